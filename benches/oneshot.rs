@@ -0,0 +1,292 @@
+//! pair, poly, and channel against tokio's and futures' oneshot channels.
+//! `single_thread` makes, resolves, and awaits a promise on one thread, with
+//! pair also on a `LocalRawMutex`, `cross_thread` hands values back and forth
+//! between two threads, one promise per leg, and `fan_out` wakes 64 consumers
+//! of a single value.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::{executor::block_on, task::noop_waker, FutureExt};
+use promise_out::{channel, lock::LocalRawMutex, pair, poly, Promise};
+use std::{
+    future::Future,
+    hint::black_box,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+const ROUNDS: usize = 1024;
+const FAN_OUT: usize = 64;
+
+/// A one-shot handoff of a `usize`, however its implementation names it.
+trait Oneshot {
+    const NAME: &'static str;
+    type Sender;
+    type Receiver: Future + Unpin;
+
+    fn new() -> (Self::Sender, Self::Receiver);
+    fn send(sender: Self::Sender, value: usize);
+    fn value(output: <Self::Receiver as Future>::Output) -> usize;
+}
+
+struct Pair;
+struct PairLocal;
+struct Poly;
+struct Channel;
+struct Tokio;
+struct Futures;
+
+impl Oneshot for Pair {
+    const NAME: &'static str = "pair";
+    type Sender = pair::Producer<usize>;
+    type Receiver = pair::Consumer<usize>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        pair::Producer::new()
+    }
+
+    fn send(sender: Self::Sender, value: usize) {
+        sender.resolve(value)
+    }
+
+    fn value(output: Result<usize, promise_out::Error>) -> usize {
+        output.unwrap()
+    }
+}
+
+impl Oneshot for PairLocal {
+    const NAME: &'static str = "pair_local";
+    type Sender = pair::Producer<usize, LocalRawMutex>;
+    type Receiver = pair::Consumer<usize, LocalRawMutex>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        pair::Producer::new()
+    }
+
+    fn send(sender: Self::Sender, value: usize) {
+        sender.resolve(value)
+    }
+
+    fn value(output: Result<usize, promise_out::Error>) -> usize {
+        output.unwrap()
+    }
+}
+
+impl Oneshot for Poly {
+    const NAME: &'static str = "poly";
+    type Sender = poly::Producer<usize>;
+    type Receiver = poly::Consumer<usize>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        poly::Producer::new()
+    }
+
+    fn send(sender: Self::Sender, value: usize) {
+        sender.resolve(value)
+    }
+
+    fn value(output: Result<std::sync::Arc<usize>, promise_out::Error>) -> usize {
+        *output.unwrap()
+    }
+}
+
+impl Oneshot for Channel {
+    const NAME: &'static str = "channel";
+    type Sender = channel::Producer<usize>;
+    type Receiver = channel::Consumer<usize>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        channel::Producer::new()
+    }
+
+    fn send(sender: Self::Sender, value: usize) {
+        sender.resolve(value)
+    }
+
+    fn value(output: Result<usize, promise_out::Error>) -> usize {
+        output.unwrap()
+    }
+}
+
+impl Oneshot for Tokio {
+    const NAME: &'static str = "tokio";
+    type Sender = tokio::sync::oneshot::Sender<usize>;
+    type Receiver = tokio::sync::oneshot::Receiver<usize>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        tokio::sync::oneshot::channel()
+    }
+
+    fn send(sender: Self::Sender, value: usize) {
+        sender.send(value).unwrap()
+    }
+
+    fn value(output: Result<usize, tokio::sync::oneshot::error::RecvError>) -> usize {
+        output.unwrap()
+    }
+}
+
+impl Oneshot for Futures {
+    const NAME: &'static str = "futures";
+    type Sender = futures::channel::oneshot::Sender<usize>;
+    type Receiver = futures::channel::oneshot::Receiver<usize>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        futures::channel::oneshot::channel()
+    }
+
+    fn send(sender: Self::Sender, value: usize) {
+        sender.send(value).unwrap()
+    }
+
+    fn value(output: Result<usize, futures::channel::oneshot::Canceled>) -> usize {
+        output.unwrap()
+    }
+}
+
+/// Poll `future` once, registering a waker that does nothing.
+fn poll_once<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+    let waker = noop_waker();
+    Pin::new(future).poll(&mut Context::from_waker(&waker))
+}
+
+fn single_thread_with<O: Oneshot>(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_thread");
+    group.bench_function(BenchmarkId::new("resolved", O::NAME), |b| {
+        b.iter(|| {
+            let (sender, receiver) = O::new();
+            O::send(sender, black_box(1));
+            O::value(receiver.now_or_never().unwrap())
+        })
+    });
+    group.bench_function(BenchmarkId::new("pending", O::NAME), |b| {
+        b.iter(|| {
+            let (sender, mut receiver) = O::new();
+            assert!(poll_once(&mut receiver).is_pending());
+            O::send(sender, black_box(1));
+            match poll_once(&mut receiver) {
+                Poll::Ready(output) => O::value(output),
+                Poll::Pending => unreachable!(),
+            }
+        })
+    });
+    group.finish();
+}
+
+fn single_thread(c: &mut Criterion) {
+    single_thread_with::<Pair>(c);
+    single_thread_with::<PairLocal>(c);
+    single_thread_with::<Poly>(c);
+    single_thread_with::<Channel>(c);
+    single_thread_with::<Tokio>(c);
+    single_thread_with::<Futures>(c);
+}
+
+fn cross_thread_with<O: Oneshot>(c: &mut Criterion)
+where
+    O::Sender: Send + 'static,
+    O::Receiver: Send + 'static,
+{
+    c.benchmark_group("cross_thread")
+        .bench_function(O::NAME, |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters.div_ceil(ROUNDS as u64) {
+                    let (pings, ping_receivers): (Vec<_>, Vec<_>) =
+                        (0..ROUNDS).map(|_| O::new()).unzip();
+                    let (pongs, pong_receivers): (Vec<_>, Vec<_>) =
+                        (0..ROUNDS).map(|_| O::new()).unzip();
+                    let start = Instant::now();
+                    let peer = thread::spawn(move || {
+                        for (ping, pong) in ping_receivers.into_iter().zip(pongs) {
+                            O::send(pong, O::value(block_on(ping)) + 1);
+                        }
+                    });
+                    for (n, (ping, pong)) in pings.into_iter().zip(pong_receivers).enumerate() {
+                        O::send(ping, n);
+                        assert_eq!(n + 1, O::value(block_on(pong)));
+                    }
+                    peer.join().unwrap();
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+}
+
+fn cross_thread(c: &mut Criterion) {
+    cross_thread_with::<Pair>(c);
+    cross_thread_with::<Poly>(c);
+    cross_thread_with::<Channel>(c);
+    cross_thread_with::<Tokio>(c);
+    cross_thread_with::<Futures>(c);
+}
+
+/// Register `FAN_OUT` pending consumers, resolve, and collect every value.
+fn fan_out(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fan_out");
+    group.bench_function("poly", |b| {
+        b.iter(|| {
+            let (promise, consumer) = poly::Producer::<usize>::new();
+            let mut consumers = vec![consumer; FAN_OUT];
+            for consumer in &mut consumers {
+                assert!(poll_once(consumer).is_pending());
+            }
+            promise.resolve(black_box(1));
+            consumers
+                .iter_mut()
+                .map(|consumer| Poly::value(consumer.now_or_never().unwrap()))
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("poly_copied", |b| {
+        b.iter(|| {
+            let (promise, consumer) = poly::Producer::<usize>::new();
+            let mut consumers = vec![consumer.copied(); FAN_OUT];
+            for consumer in &mut consumers {
+                assert!(poll_once(consumer).is_pending());
+            }
+            promise.resolve(black_box(1));
+            consumers
+                .iter_mut()
+                .map(|consumer| consumer.now_or_never().unwrap().unwrap())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("futures_shared", |b| {
+        b.iter(|| {
+            let (sender, receiver) = futures::channel::oneshot::channel::<usize>();
+            let mut receivers = vec![receiver.shared(); FAN_OUT];
+            for receiver in &mut receivers {
+                assert!(poll_once(receiver).is_pending());
+            }
+            sender.send(black_box(1)).unwrap();
+            receivers
+                .iter_mut()
+                .map(|receiver| receiver.now_or_never().unwrap().unwrap())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("tokio_broadcast", |b| {
+        b.iter(|| {
+            let (sender, _) = tokio::sync::broadcast::channel::<usize>(1);
+            let mut receivers: Vec<_> = (0..FAN_OUT).map(|_| sender.subscribe()).collect();
+            let mut pending: Vec<_> = receivers
+                .iter_mut()
+                .map(|receiver| Box::pin(receiver.recv()))
+                .collect();
+            for receiver in &mut pending {
+                assert!(poll_once(receiver).is_pending());
+            }
+            sender.send(black_box(1)).unwrap();
+            pending
+                .iter_mut()
+                .map(|receiver| receiver.now_or_never().unwrap().unwrap())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, single_thread, cross_thread, fan_out);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+//! Cross-core handoffs over pair promises. `ping_pong` passes a value back
+//! and forth between two threads, one promise per leg, and `neighbours` has
+//! two threads each hammer their own promise of two allocated back to back,
+//! which share a cache line unless the promise state is padded with the
+//! `cache-padded` feature.
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use promise_out::{pair, Promise};
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+const ROUNDS: usize = 1024;
+
+fn pairs() -> (Vec<pair::Producer<usize>>, Vec<pair::Consumer<usize>>) {
+    (0..ROUNDS).map(|_| pair::Producer::new()).unzip()
+}
+
+fn ping_pong(c: &mut Criterion) {
+    c.bench_function("ping_pong", |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters.div_ceil(ROUNDS as u64) {
+                let (pings, ping_consumers) = pairs();
+                let (pongs, pong_consumers) = pairs();
+                let start = Instant::now();
+                let peer = thread::spawn(move || {
+                    for (ping, pong) in ping_consumers.into_iter().zip(pongs) {
+                        pong.resolve(block_on(ping).unwrap() + 1);
+                    }
+                });
+                for (n, (ping, pong)) in pings.into_iter().zip(pong_consumers).enumerate() {
+                    ping.resolve(n);
+                    assert_eq!(n + 1, block_on(pong).unwrap());
+                }
+                peer.join().unwrap();
+                elapsed += start.elapsed();
+            }
+            elapsed
+        })
+    });
+}
+
+fn neighbours(c: &mut Criterion) {
+    c.bench_function("neighbours", |b| {
+        b.iter_custom(|iters| {
+            let (first, _first_consumer) = pair::Producer::<usize>::new();
+            let (second, _second_consumer) = pair::Producer::<usize>::new();
+            let start = Instant::now();
+            thread::scope(|scope| {
+                for promise in [&first, &second] {
+                    scope.spawn(move || {
+                        for _ in 0..iters {
+                            black_box(promise.is_closed());
+                        }
+                    });
+                }
+            });
+            start.elapsed()
+        })
+    });
+}
+
+criterion_group!(benches, ping_pong, neighbours);
+criterion_main!(benches);
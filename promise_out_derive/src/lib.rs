@@ -0,0 +1,207 @@
+//! Derive macros for `promise_out`, enabled by its `derive` feature.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Field, Fields, Ident, Type,
+};
+
+/// Derive the ask plumbing for a request enum whose variants carry the
+/// producer of their reply.
+///
+/// For an enum `Request` this generates a trait `RequestAsk` with one method
+/// per variant, `ask_<variant>`, taking the variant's other fields in order.
+/// It creates the reply promise, posts the request through any
+/// `promise_out::mailbox::Post<Request>`, and returns the reply's consumer.
+/// The reply field is the one marked `#[reply]`, else the one named `reply`,
+/// else the only field whose type is a `Producer`.
+///
+/// ```
+/// use promise_out::{mailbox::Post, pair, Ask, Promise};
+/// use futures::executor::block_on;
+/// use std::{collections::HashMap, sync::mpsc, thread};
+///
+/// #[derive(Ask)]
+/// enum Request {
+///     Get { key: String, reply: pair::Producer<Option<u32>> },
+///     Set(String, u32, pair::Producer<()>),
+/// }
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let actor = thread::spawn(move || {
+///     let mut map = HashMap::new();
+///     for request in receiver {
+///         match request {
+///             Request::Get { key, reply } => reply.resolve(map.get(&key).copied()),
+///             Request::Set(key, value, reply) => {
+///                 map.insert(key, value);
+///                 reply.resolve(());
+///             }
+///         }
+///     }
+/// });
+/// assert_eq!(Ok(None), block_on(sender.ask_get("a".into())));
+/// assert_eq!(Ok(()), block_on(sender.ask_set("a".into(), 1)));
+/// assert_eq!(Ok(Some(1)), block_on(sender.ask_get("a".into())));
+/// drop(sender);
+/// actor.join().unwrap();
+/// ```
+#[proc_macro_derive(Ask, attributes(reply))]
+pub fn derive_ask(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_ask(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_ask(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Ask can only be derived for enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "Ask cannot be derived for generic enums",
+        ));
+    }
+    let name = &input.ident;
+    let vis = &input.vis;
+    let ask = format_ident!("{}Ask", name);
+    let mut signatures = vec![];
+    let mut methods = vec![];
+    for variant in &data.variants {
+        let fields: Vec<&Field> = variant.fields.iter().collect();
+        let reply = reply_field(&variant.ident, &fields)?;
+        let producer = &fields[reply].ty;
+        let consumer = consumer_type(producer)?;
+        let args: Vec<Ident> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match &field.ident {
+                Some(ident) if i != reply => ident.clone(),
+                _ if i == reply => format_ident!("__reply"),
+                _ => format_ident!("arg{}", i),
+            })
+            .collect();
+        let params = fields
+            .iter()
+            .zip(&args)
+            .enumerate()
+            .filter(|(i, _)| *i != reply)
+            .map(|(_, (field, arg))| {
+                let ty = &field.ty;
+                quote!(#arg: #ty)
+            });
+        let variant_name = &variant.ident;
+        let request = match &variant.fields {
+            Fields::Named(_) => {
+                let names = fields.iter().map(|field| &field.ident);
+                quote!(#name::#variant_name { #(#names: #args),* })
+            }
+            Fields::Unnamed(_) => quote!(#name::#variant_name(#(#args),*)),
+            Fields::Unit => unreachable!("a unit variant has no reply field"),
+        };
+        let method = format_ident!("ask_{}", snake_case(&variant_name.to_string()));
+        let signature = quote! {
+            fn #method(&self, #(#params),*) -> #consumer
+        };
+        methods.push(quote! {
+            #signature {
+                let (__reply, __consumer): (#producer, #consumer) = ::promise_out::Promise::new();
+                ::promise_out::mailbox::Post::post(self, #request);
+                __consumer
+            }
+        });
+        signatures.push(signature);
+    }
+    let doc = format!("Ask methods for [`{}`], derived by `Ask`.", name);
+    Ok(quote! {
+        #[doc = #doc]
+        #vis trait #ask {
+            #(#signatures;)*
+        }
+
+        impl<P: ::promise_out::mailbox::Post<#name> + ?Sized> #ask for P {
+            #(#methods)*
+        }
+    })
+}
+
+/// Return the index of the field carrying the reply producer.
+fn reply_field(variant: &Ident, fields: &[&Field]) -> syn::Result<usize> {
+    let marked: Vec<usize> = (0..fields.len())
+        .filter(|&i| fields[i].attrs.iter().any(|a| a.path().is_ident("reply")))
+        .collect();
+    match marked[..] {
+        [i] => return Ok(i),
+        [_, second, ..] => {
+            return Err(Error::new(
+                fields[second].span(),
+                "only one field can be the #[reply]",
+            ))
+        }
+        [] => {}
+    }
+    if let Some(i) = fields
+        .iter()
+        .position(|field| field.ident.as_ref().is_some_and(|ident| ident == "reply"))
+    {
+        return Ok(i);
+    }
+    let producers: Vec<usize> = (0..fields.len())
+        .filter(|&i| last_segment(&fields[i].ty).is_some_and(|ident| ident == "Producer"))
+        .collect();
+    match producers[..] {
+        [i] => Ok(i),
+        [] => Err(Error::new_spanned(
+            variant,
+            "no reply field; add a `Producer` field or mark one #[reply]",
+        )),
+        _ => Err(Error::new_spanned(
+            variant,
+            "several `Producer` fields; mark the reply #[reply]",
+        )),
+    }
+}
+
+fn last_segment(ty: &Type) -> Option<&Ident> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|segment| &segment.ident),
+        _ => None,
+    }
+}
+
+/// Return the consumer type matching a `Producer` type of the same flavor.
+fn consumer_type(producer: &Type) -> syn::Result<Type> {
+    let mut consumer = producer.clone();
+    if let Type::Path(path) = &mut consumer {
+        if let Some(segment) = path.path.segments.last_mut() {
+            if segment.ident == "Producer" {
+                segment.ident = Ident::new("Consumer", segment.ident.span());
+                return Ok(consumer);
+            }
+        }
+    }
+    Err(Error::new_spanned(
+        producer,
+        "the reply must be a promise_out `Producer`",
+    ))
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
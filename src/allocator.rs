@@ -0,0 +1,118 @@
+//! allocator selects where the pair, poly, and channel flavors place their
+//! shared state. Their handles take an [`Allocator`] type parameter next to
+//! the [`RawMutex`](crate::lock::RawMutex) one, and their `new_in`
+//! constructors take the allocator, e.g. an arena or a pool the application
+//! controls. Allocators are a nightly-only API of the standard library, so
+//! this needs the `allocator_api` feature and a nightly compiler; without it
+//! [`Global`] is the only allocator.
+#[cfg(feature = "allocator_api")]
+pub use alloc::alloc::{Allocator, Global};
+#[cfg(feature = "allocator_api")]
+#[cfg_attr(
+    not(all(feature = "pair", feature = "poly", feature = "channel")),
+    allow(unused_imports)
+)]
+pub(crate) use alloc::sync::{Arc as Shared, Weak as WeakShared};
+
+#[cfg(not(feature = "allocator_api"))]
+pub use stable::{Allocator, Global};
+#[cfg(not(feature = "allocator_api"))]
+#[cfg_attr(
+    not(all(feature = "pair", feature = "poly", feature = "channel")),
+    allow(unused_imports)
+)]
+pub(crate) use stable::{Shared, WeakShared};
+
+/// Stand-ins for the allocator API, which always allocate globally. Not every
+/// flavor needs all of them.
+#[cfg(not(feature = "allocator_api"))]
+#[cfg_attr(
+    not(all(feature = "pair", feature = "poly", feature = "channel")),
+    allow(dead_code)
+)]
+mod stable {
+    use alloc::sync::{Arc, Weak};
+    use core::{fmt::Debug, marker::PhantomData, ops::Deref};
+
+    mod sealed {
+        pub trait Sealed {}
+    }
+
+    /// An allocator the flavors can place their state in. Without the
+    /// `allocator_api` feature it is only implemented by [`Global`].
+    pub trait Allocator: sealed::Sealed {}
+
+    /// The global allocator.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Global;
+
+    impl sealed::Sealed for Global {}
+
+    impl Allocator for Global {}
+
+    /// An `Arc<T, A>` that ignores its allocator.
+    pub(crate) struct Shared<T, A>(Arc<T>, PhantomData<A>);
+
+    impl<T, A: Allocator> Shared<T, A> {
+        pub(crate) fn new_in(value: T, _alloc: A) -> Self {
+            Shared(Arc::new(value), PhantomData)
+        }
+
+        pub(crate) fn ptr_eq(this: &Self, other: &Self) -> bool {
+            Arc::ptr_eq(&this.0, &other.0)
+        }
+
+        pub(crate) fn as_ptr(this: &Self) -> *const T {
+            Arc::as_ptr(&this.0)
+        }
+
+        pub(crate) fn get_mut(this: &mut Self) -> Option<&mut T> {
+            Arc::get_mut(&mut this.0)
+        }
+
+        pub(crate) fn downgrade(this: &Self) -> WeakShared<T, A> {
+            WeakShared(Arc::downgrade(&this.0), PhantomData)
+        }
+    }
+
+    impl<T, A> Clone for Shared<T, A> {
+        fn clone(&self) -> Self {
+            Shared(self.0.clone(), PhantomData)
+        }
+    }
+
+    impl<T, A> Deref for Shared<T, A> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    impl<T: Debug, A> Debug for Shared<T, A> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    /// A `Weak<T, A>` that ignores its allocator.
+    pub(crate) struct WeakShared<T, A>(Weak<T>, PhantomData<A>);
+
+    impl<T, A> WeakShared<T, A> {
+        pub(crate) fn upgrade(&self) -> Option<Shared<T, A>> {
+            Some(Shared(self.0.upgrade()?, PhantomData))
+        }
+    }
+
+    impl<T, A> Clone for WeakShared<T, A> {
+        fn clone(&self) -> Self {
+            WeakShared(self.0.clone(), PhantomData)
+        }
+    }
+
+    impl<T, A> Debug for WeakShared<T, A> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+}
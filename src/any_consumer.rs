@@ -0,0 +1,127 @@
+//! any_consumer unifies the consumers of every promise flavor in one enum, so
+//! consumers of different flavors can be kept in one collection and awaited
+//! alike without boxing them as trait objects.
+use crate::{
+    channel,
+    envelope::{Envelope, Metadata},
+    mpmc, pair, poly, Error,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// A consumer of any flavor. It resolves with a `T` for every flavor; the
+/// value shared by a poly or mpmc promise is cloned unless this consumer
+/// holds the last reference to it.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{AnyConsumer, Promise, channel, pair, poly};
+/// use futures::executor::block_on;
+/// let (a, pair_consumer) = pair::Producer::<u32>::new();
+/// let (b, poly_consumer) = poly::Producer::<u32>::new();
+/// let (c, channel_consumer) = channel::Producer::<u32>::new();
+/// let consumers: Vec<AnyConsumer<u32>> = vec![
+///     pair_consumer.into(),
+///     poly_consumer.into(),
+///     channel_consumer.into(),
+/// ];
+/// a.resolve(1);
+/// b.resolve(2);
+/// c.resolve(3);
+/// let values = block_on(promise_out::join_all(consumers));
+/// assert_eq!(Ok(vec![1, 2, 3]), values);
+/// ```
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub enum AnyConsumer<T> {
+    Pair(pair::Consumer<T>),
+    Poly(poly::Consumer<T>),
+    Channel(channel::Consumer<T>),
+    Mpmc(mpmc::Consumer<T>),
+}
+
+fn unwrap_or_clone<T: Clone>(value: Arc<T>) -> T {
+    Arc::try_unwrap(value).unwrap_or_else(|value| (*value).clone())
+}
+
+impl<T: Clone> Future for AnyConsumer<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.get_mut() {
+            AnyConsumer::Pair(consumer) => Pin::new(consumer).poll(cx),
+            AnyConsumer::Poly(consumer) => Pin::new(consumer)
+                .poll(cx)
+                .map(|result| result.map(unwrap_or_clone)),
+            AnyConsumer::Channel(consumer) => Pin::new(consumer).poll(cx),
+            AnyConsumer::Mpmc(consumer) => Pin::new(consumer)
+                .poll(cx)
+                .map(|result| result.map(unwrap_or_clone)),
+        }
+    }
+}
+
+impl<T> Envelope for AnyConsumer<T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        match self {
+            AnyConsumer::Pair(consumer) => consumer.metadata(),
+            AnyConsumer::Poly(consumer) => consumer.metadata(),
+            AnyConsumer::Channel(consumer) => consumer.metadata(),
+            AnyConsumer::Mpmc(consumer) => consumer.metadata(),
+        }
+    }
+}
+
+impl<T> From<pair::Consumer<T>> for AnyConsumer<T> {
+    fn from(consumer: pair::Consumer<T>) -> Self {
+        AnyConsumer::Pair(consumer)
+    }
+}
+
+impl<T> From<poly::Consumer<T>> for AnyConsumer<T> {
+    fn from(consumer: poly::Consumer<T>) -> Self {
+        AnyConsumer::Poly(consumer)
+    }
+}
+
+impl<T> From<channel::Consumer<T>> for AnyConsumer<T> {
+    fn from(consumer: channel::Consumer<T>) -> Self {
+        AnyConsumer::Channel(consumer)
+    }
+}
+
+impl<T> From<mpmc::Consumer<T>> for AnyConsumer<T> {
+    fn from(consumer: mpmc::Consumer<T>) -> Self {
+        AnyConsumer::Mpmc(consumer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnyConsumer;
+    use crate::{mpmc, poly, Error, Promise};
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_any_consumer_shared_value() {
+        let (producer, consumer) = poly::Producer::<String>::new();
+        let other = consumer.clone();
+        let any = AnyConsumer::from(consumer);
+        producer.resolve(String::from("🍓"));
+        // The other consumer still shares the value, so it is cloned.
+        assert_eq!(Ok(String::from("🍓")), block_on(any));
+        assert_eq!("🍓", *block_on(other).unwrap());
+
+        let (producer, consumer) = mpmc::Producer::<String>::new();
+        drop(producer);
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            block_on(AnyConsumer::from(consumer))
+        );
+    }
+}
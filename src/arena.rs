@@ -0,0 +1,259 @@
+//! arena implements single-producer, single-consumer promises whose state is
+//! kept in the slots of an [`Arena`] rather than in an `Arc<Mutex<..>>` each.
+//! A producer or consumer is a reference to the arena and the index of its
+//! slot, and a slot is reused once both halves of its promise are gone, so a
+//! service that creates promises at a high rate stops allocating once the
+//! arena has grown to the number of promises in flight. All slots share the
+//! arena's lock, which is held only briefly.
+use crate::{
+    lock::{DefaultRawMutex, RawMutex},
+    Error,
+};
+use alloc::vec::Vec;
+use core::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use lock_api::Mutex;
+
+/// The storage of many promises.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::arena::Arena;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let arena = Arena::<u64>::with_capacity(1024);
+/// thread::scope(|scope| {
+///     for n in 0..4 {
+///         let (promise, consumer) = arena.promise();
+///         scope.spawn(move || promise.resolve(n * n));
+///         assert_eq!(Ok(n * n), block_on(consumer));
+///     }
+/// });
+/// assert_eq!(0, arena.len());
+/// ```
+pub struct Arena<T, R: RawMutex = DefaultRawMutex> {
+    slots: Mutex<R, Slots<T>>,
+}
+
+impl<T: Debug, R: RawMutex> Debug for Arena<T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Arena").field("slots", &self.slots).finish()
+    }
+}
+
+#[derive(Debug)]
+struct Slots<T> {
+    entries: Vec<Entry<T>>,
+    // Indices of the entries no promise uses.
+    free: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    producer: bool,
+    consumer: bool,
+}
+
+impl<T> Entry<T> {
+    fn is_vacant(&self) -> bool {
+        !self.producer && !self.consumer
+    }
+}
+
+impl<T> Slots<T> {
+    /// Release the entry at `index` if neither half of its promise is left.
+    fn reclaim(&mut self, index: usize) {
+        let entry = &mut self.entries[index];
+        if entry.is_vacant() {
+            entry.value = None;
+            entry.waker = None;
+            self.free.push(index);
+        }
+    }
+}
+
+impl<T, R: RawMutex> Arena<T, R> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Return an arena with room for `capacity` promises in flight before it
+    /// allocates.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Arena {
+            slots: Mutex::new(Slots {
+                entries: Vec::with_capacity(capacity),
+                free: Vec::with_capacity(capacity),
+            }),
+        }
+    }
+
+    /// Return a (producer, consumer) pair kept in a free slot.
+    pub fn promise(&self) -> (Producer<'_, T, R>, Consumer<'_, T, R>) {
+        let mut slots = self.slots.lock();
+        let entry = Entry {
+            value: None,
+            waker: None,
+            producer: true,
+            consumer: true,
+        };
+        let index = match slots.free.pop() {
+            Some(index) => {
+                slots.entries[index] = entry;
+                index
+            }
+            None => {
+                slots.entries.push(entry);
+                slots.entries.len() - 1
+            }
+        };
+        (
+            Producer { arena: self, index },
+            Consumer { arena: self, index },
+        )
+    }
+
+    /// Return how many slots are in use.
+    pub fn len(&self) -> usize {
+        let slots = self.slots.lock();
+        slots.entries.len() - slots.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, R: RawMutex> Default for Arena<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The producer of a promise kept in an [`Arena`].
+pub struct Producer<'a, T, R: RawMutex = DefaultRawMutex> {
+    arena: &'a Arena<T, R>,
+    index: usize,
+}
+
+impl<T, R: RawMutex> Debug for Producer<'_, T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex> Producer<'_, T, R> {
+    pub fn resolve(self, value: T) {
+        let waker = {
+            let mut slots = self.arena.slots.lock();
+            let entry = &mut slots.entries[self.index];
+            if entry.consumer {
+                entry.value = Some(value);
+            }
+            entry.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake()
+        }
+    }
+
+    /// Return true if the consumer has been dropped, so resolving the promise
+    /// would go unobserved.
+    pub fn is_closed(&self) -> bool {
+        !self.arena.slots.lock().entries[self.index].consumer
+    }
+}
+
+impl<T, R: RawMutex> Drop for Producer<'_, T, R> {
+    /// Wake the consumer, which fails if the promise was not resolved.
+    fn drop(&mut self) {
+        let waker = {
+            let mut slots = self.arena.slots.lock();
+            slots.entries[self.index].producer = false;
+            let waker = slots.entries[self.index].waker.take();
+            slots.reclaim(self.index);
+            waker
+        };
+        if let Some(waker) = waker {
+            waker.wake()
+        }
+    }
+}
+
+/// The consumer of a promise kept in an [`Arena`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer<'a, T, R: RawMutex = DefaultRawMutex> {
+    arena: &'a Arena<T, R>,
+    index: usize,
+}
+
+impl<T, R: RawMutex> Debug for Consumer<'_, T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex> Future for Consumer<'_, T, R> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slots = self.arena.slots.lock();
+        let entry = &mut slots.entries[self.index];
+        if let Some(value) = entry.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if !entry.producer {
+            return Poll::Ready(Err(Error::ProducerDropped));
+        }
+        match &entry.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => entry.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T, R: RawMutex> Drop for Consumer<'_, T, R> {
+    fn drop(&mut self) {
+        let mut slots = self.arena.slots.lock();
+        slots.entries[self.index].consumer = false;
+        slots.reclaim(self.index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Arena;
+    use crate::Error;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_slots_are_reused() {
+        let arena = Arena::<u32>::new();
+        let (promise, mut consumer) = arena.promise();
+        let (dropped, failed) = arena.promise();
+        assert_eq!(2, arena.len());
+        assert_eq!(None, (&mut consumer).now_or_never());
+        drop(dropped);
+        assert_eq!(Err(Error::ProducerDropped), block_on(failed));
+        assert_eq!(1, arena.len());
+        promise.resolve(1);
+        assert_eq!(Ok(1), block_on(consumer));
+        assert!(arena.is_empty());
+        let (promise, consumer) = arena.promise();
+        drop(consumer);
+        assert!(promise.is_closed());
+        assert_eq!(2, arena.slots.lock().entries.len());
+    }
+}
@@ -0,0 +1,145 @@
+//! barrier implements a reusable async barrier. Each round is a poly promise
+//! resolved by the last task to arrive, which releases every waiting task at
+//! once and arms a fresh promise for the next round.
+use crate::{poly, Error, Promise};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+/// A barrier that releases `n` tasks together, like `std::sync::Barrier`.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::barrier::Barrier;
+/// use futures::executor::block_on;
+/// use std::{sync::Arc, thread};
+/// let barrier = Arc::new(Barrier::new(3));
+/// let tasks: Vec<_> = (0..3).map(|_| {
+///     let barrier = barrier.clone();
+///     thread::spawn(move || block_on(barrier.wait()).unwrap())
+/// }).collect();
+/// let leaders = tasks.into_iter()
+///     .map(|task| task.join().unwrap())
+///     .filter(|result| result.is_leader())
+///     .count();
+/// assert_eq!(1, leaders);
+/// ```
+#[derive(Debug)]
+pub struct Barrier {
+    n: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    arrived: usize,
+    producer: poly::Producer<()>,
+    released: poly::Consumer<()>,
+}
+
+/// What [`Barrier::wait`] resolves with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    is_leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// Return true for exactly one task per round: the one that arrived last.
+    pub fn is_leader(&self) -> bool {
+        self.is_leader
+    }
+}
+
+impl Barrier {
+    /// Return a barrier for `n` tasks. A barrier for zero tasks behaves like
+    /// a barrier for one.
+    pub fn new(n: usize) -> Self {
+        let (producer, released) = poly::Producer::new();
+        Barrier {
+            n,
+            state: Mutex::new(State {
+                arrived: 0,
+                producer,
+                released,
+            }),
+        }
+    }
+
+    /// Wait until `n` tasks are waiting. Dropping the future before it
+    /// resolves does not take back the arrival.
+    pub fn wait(&self) -> Wait {
+        let mut state = self.state.lock().unwrap();
+        state.arrived += 1;
+        if state.arrived < self.n {
+            return Wait {
+                released: Some(state.released.clone()),
+            };
+        }
+        let (producer, released) = poly::Producer::new();
+        state.arrived = 0;
+        state.released = released;
+        let producer = std::mem::replace(&mut state.producer, producer);
+        drop(state);
+        producer.resolve(());
+        Wait { released: None }
+    }
+}
+
+/// Future for [`Barrier::wait`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait {
+    // None for the leader, which is released as soon as it arrives.
+    released: Option<poly::Consumer<()>>,
+}
+
+impl Future for Wait {
+    type Output = Result<BarrierWaitResult, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.released.as_mut() {
+            None => Poll::Ready(Ok(BarrierWaitResult { is_leader: true })),
+            Some(released) => Pin::new(released)
+                .poll(cx)
+                .map_ok(|_| BarrierWaitResult { is_leader: false }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Barrier;
+    use crate::Error;
+    use futures::executor::block_on;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_barrier_is_reusable() {
+        let barrier = Arc::new(Barrier::new(2));
+        let other = barrier.clone();
+        let task1 = thread::spawn(move || {
+            (0..3)
+                .map(|_| block_on(other.wait()).unwrap().is_leader())
+                .collect::<Vec<_>>()
+        });
+        let here: Vec<_> = (0..3)
+            .map(|_| block_on(barrier.wait()).unwrap().is_leader())
+            .collect();
+        let there = task1.join().expect("The task1 thread has panicked");
+        for (here, there) in here.into_iter().zip(there) {
+            assert!(here ^ there);
+        }
+    }
+
+    #[test]
+    fn test_barrier_dropped() {
+        let barrier = Barrier::new(2);
+        let wait = barrier.wait();
+        drop(barrier);
+        assert_eq!(Err(Error::ProducerDropped), block_on(wait));
+    }
+}
@@ -0,0 +1,96 @@
+//! blocking waits for a promise from synchronous code. The thread first spins
+//! on the promise's settled flag, which costs no lock, and parks only if the
+//! promise is still pending after that. Promises resolved within microseconds
+//! are then picked up without the latency of parking and unparking a thread,
+//! while long waits still sleep. The number of spins adapts: it grows while
+//! spinning pays off and shrinks while waits outlast it.
+use std::{
+    future::Future,
+    hint,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+const MIN_SPINS: u32 = 16;
+const MAX_SPINS: u32 = 1 << 14;
+
+/// How many times a waiting thread spins before it parks.
+static SPINS: AtomicU32 = AtomicU32::new(1 << 8);
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/// Spin until `is_settled` returns true or the spin budget runs out, then
+/// poll `future` to completion, parking the thread while it is pending.
+pub(crate) fn wait<F: Future>(
+    mut future: Pin<&mut F>,
+    is_settled: impl Fn(&F) -> bool,
+) -> F::Output {
+    let budget = SPINS.load(Ordering::Relaxed);
+    match (0..budget).position(|_| {
+        let settled = is_settled(&future);
+        if !settled {
+            hint::spin_loop();
+        }
+        settled
+    }) {
+        // Leave room to settle a little later than this time.
+        Some(spins) => {
+            let grown = budget.max(spins.saturating_mul(2) as u32).min(MAX_SPINS);
+            SPINS.store(grown, Ordering::Relaxed);
+        }
+        None => SPINS.store((budget / 2).max(MIN_SPINS), Ordering::Relaxed),
+    }
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            // Wakes that came before parking leave a token, and spurious ones
+            // only cost another poll.
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "pair"))]
+mod tests {
+    use super::{wait, MIN_SPINS, SPINS};
+    use crate::{pair::Producer, Promise};
+    use std::{pin::Pin, sync::atomic::Ordering, thread, time::Duration};
+
+    #[test]
+    fn test_spins_then_parks() {
+        let (promise, mut consumer) = Producer::<u32>::new();
+        promise.resolve(1);
+        assert_eq!(
+            Ok(1),
+            wait(Pin::new(&mut consumer), |consumer| consumer.is_settled())
+        );
+        let (promise, mut consumer) = Producer::<u32>::new();
+        let resolver = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            promise.resolve(2);
+        });
+        assert_eq!(
+            Ok(2),
+            wait(Pin::new(&mut consumer), |consumer| consumer.is_settled())
+        );
+        assert!(SPINS.load(Ordering::Relaxed) >= MIN_SPINS);
+        resolver.join().unwrap();
+    }
+}
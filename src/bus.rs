@@ -0,0 +1,109 @@
+//! bus implements a rendezvous point keyed by type. A module asks for the next
+//! value of a type with [`Bus::request`] and gets a poly consumer; whichever
+//! module later publishes a value of that type resolves every consumer that
+//! asked for it. Neither side has to know about the other.
+use crate::{poly, Promise};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+type Request<T> = (poly::Producer<T>, poly::Consumer<T>);
+
+/// A type-keyed bus. Clones share the same requests.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::bus::Bus;
+/// use futures::executor::block_on;
+/// #[derive(Debug, PartialEq)]
+/// struct Config { verbose: bool }
+/// let bus = Bus::new();
+/// let logger = bus.request::<Config>();
+/// let renderer = bus.request::<Config>();
+/// assert_eq!(2, bus.publish(Config { verbose: true }));
+/// assert!(block_on(logger).unwrap().verbose);
+/// assert!(block_on(renderer).unwrap().verbose);
+/// ```
+#[derive(Clone, Default)]
+pub struct Bus {
+    requests: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return a consumer for the next value of type `T` to be published.
+    /// Every request made before that publish shares the same value.
+    pub fn request<T: Send + Sync + 'static>(&self) -> poly::Consumer<T> {
+        let mut requests = self.requests.lock().unwrap();
+        let request = requests
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(poly::Producer::<T>::new()));
+        let (_, consumer) = request
+            .downcast_ref::<Request<T>>()
+            .expect("bus entries are keyed by their type");
+        consumer.clone()
+    }
+
+    /// Resolve every pending request for `T` with `value`, and return how many
+    /// consumers were waiting for it. The value is dropped if nobody asked;
+    /// the bus does not keep it for later requests.
+    pub fn publish<T: Send + Sync + 'static>(&self, value: T) -> usize {
+        let request = self.requests.lock().unwrap().remove(&TypeId::of::<T>());
+        let Some(request) = request else {
+            return 0;
+        };
+        let (producer, consumer) = *request
+            .downcast::<Request<T>>()
+            .expect("bus entries are keyed by their type");
+        // Our own consumer was only kept to hand out clones.
+        drop(consumer);
+        let waiting = producer.consumers();
+        producer.resolve(value);
+        waiting
+    }
+
+    /// Return true if some consumer is waiting for a value of type `T`.
+    pub fn is_requested<T: 'static>(&self) -> bool {
+        self.requests
+            .lock()
+            .unwrap()
+            .contains_key(&TypeId::of::<T>())
+    }
+}
+
+impl Debug for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let requests = self.requests.lock().unwrap();
+        f.debug_struct("Bus")
+            .field("requested", &requests.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bus;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_bus_keys_by_type() {
+        let bus = Bus::new();
+        let mut number = bus.request::<u32>();
+        let text = bus.request::<String>();
+        assert_eq!(0, bus.publish(1u8));
+        assert_eq!(1, bus.publish(String::from("🍓")));
+        assert_eq!("🍓", *block_on(text).unwrap());
+        assert_eq!(None, (&mut number).now_or_never());
+        assert!(bus.is_requested::<u32>());
+        assert_eq!(1, bus.publish(7u32));
+        assert_eq!(7, *block_on(number).unwrap());
+        assert!(!bus.is_requested::<u32>());
+    }
+}
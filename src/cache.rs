@@ -0,0 +1,212 @@
+//! cache implements an async memoization cache keyed by `K`. Every entry is a
+//! poly consumer: while the value is being produced it is the in-flight
+//! promise shared by every caller that missed, and once resolved it keeps the
+//! `Arc` of the value for later hits until the eviction policy drops it.
+use crate::{poly, Promise};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// When a [`Cache`] lets go of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eviction {
+    /// Keep every entry until it is invalidated.
+    #[default]
+    Never,
+    /// Keep at most this many entries, evicting the least recently used.
+    Lru(usize),
+    /// Keep an entry for this long after it was inserted.
+    Ttl(Duration),
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    consumer: poly::Consumer<T>,
+    inserted: Instant,
+    used: u64,
+}
+
+#[derive(Debug)]
+struct State<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    clock: u64,
+}
+
+/// A keyed async cache. Clones share the same entries.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, cache::{Cache, Eviction}};
+/// use futures::executor::block_on;
+/// let cache = Cache::<&str, usize>::new(Eviction::Lru(64));
+/// let first = cache.get_or_resolve("dweb:home", |producer| producer.resolve(42));
+/// let second = cache.get_or_resolve("dweb:home", |_| unreachable!("a hit"));
+/// assert_eq!(42, *block_on(first).unwrap());
+/// assert_eq!(42, *block_on(second).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct Cache<K, T> {
+    eviction: Eviction,
+    state: Arc<Mutex<State<K, T>>>,
+}
+
+impl<K: Hash + Eq + Clone, T> Cache<K, T> {
+    pub fn new(eviction: Eviction) -> Self {
+        Cache {
+            eviction,
+            state: Arc::new(Mutex::new(State {
+                entries: HashMap::new(),
+                clock: 0,
+            })),
+        }
+    }
+
+    /// Return a consumer of the value for `key`. On a miss `resolve` is called
+    /// with the producer that should do the work; it is called outside the
+    /// cache's lock, so it may start the work right away. Every caller that
+    /// misses while the work is in flight shares the same promise. An entry
+    /// whose producer was dropped without resolving counts as a miss.
+    pub fn get_or_resolve(
+        &self,
+        key: K,
+        resolve: impl FnOnce(poly::Producer<T>),
+    ) -> poly::Consumer<T> {
+        let (producer, consumer) = {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(&key) {
+                let expired = match self.eviction {
+                    Eviction::Ttl(ttl) => entry.inserted.elapsed() >= ttl,
+                    _ => false,
+                };
+                if !expired && !entry.consumer.is_failed() {
+                    entry.used = clock;
+                    return entry.consumer.clone();
+                }
+            }
+            let (producer, consumer) = poly::Producer::new();
+            state.entries.insert(
+                key,
+                Entry {
+                    consumer: consumer.clone(),
+                    inserted: Instant::now(),
+                    used: clock,
+                },
+            );
+            self.evict(&mut state);
+            (producer, consumer)
+        };
+        resolve(producer);
+        consumer
+    }
+
+    fn evict(&self, state: &mut State<K, T>) {
+        match self.eviction {
+            Eviction::Never => {}
+            Eviction::Lru(capacity) => {
+                while state.entries.len() > capacity {
+                    let oldest = state
+                        .entries
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.used)
+                        .map(|(key, _)| key.clone())
+                        .unwrap();
+                    state.entries.remove(&oldest);
+                }
+            }
+            Eviction::Ttl(ttl) => state
+                .entries
+                .retain(|_, entry| entry.inserted.elapsed() < ttl),
+        }
+    }
+
+    /// Forget the entry for `key`. Consumers already handed out still resolve.
+    pub fn invalidate(&self, key: &K) {
+        self.state.lock().unwrap().entries.remove(key);
+    }
+
+    /// Return the number of entries, in flight or resolved.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, T> Clone for Cache<K, T> {
+    fn clone(&self) -> Self {
+        Cache {
+            eviction: self.eviction,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, Eviction};
+    use crate::poly;
+    use futures::executor::block_on;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_cache_shares_in_flight() {
+        let cache = Cache::<u32, String>::new(Eviction::Never);
+        let mut work = None;
+        let first = cache.get_or_resolve(1, |producer| work = Some(producer));
+        let second = cache.get_or_resolve(1, |_| panic!("not a miss"));
+        let task1 = thread::spawn(move || block_on(second));
+        work.unwrap().resolve(String::from("🍓"));
+        assert_eq!("🍓", *block_on(first).unwrap());
+        assert_eq!(
+            "🍓",
+            *task1
+                .join()
+                .expect("The task1 thread has panicked")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cache_retries_failed_entry() {
+        let cache = Cache::<u32, u32>::new(Eviction::Never);
+        assert!(block_on(cache.get_or_resolve(1, drop)).is_err());
+        let retried = cache.get_or_resolve(1, |producer| producer.resolve(2));
+        assert_eq!(2, *block_on(retried).unwrap());
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let resolve = |producer: poly::Producer<u32>| producer.resolve(0);
+        let lru = Cache::new(Eviction::Lru(2));
+        drop(lru.get_or_resolve(1, resolve));
+        drop(lru.get_or_resolve(2, resolve));
+        drop(lru.get_or_resolve(1, resolve));
+        drop(lru.get_or_resolve(3, resolve));
+        assert_eq!(2, lru.len());
+        let mut missed = false;
+        drop(lru.get_or_resolve(2, |producer| {
+            missed = true;
+            resolve(producer)
+        }));
+        assert!(missed);
+
+        let ttl = Cache::new(Eviction::Ttl(Duration::from_millis(10)));
+        drop(ttl.get_or_resolve(1, resolve));
+        thread::sleep(Duration::from_millis(20));
+        let mut missed = false;
+        drop(ttl.get_or_resolve(1, |producer| {
+            missed = true;
+            resolve(producer)
+        }));
+        assert!(missed);
+        assert_eq!(1, ttl.len());
+    }
+}
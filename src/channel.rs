@@ -1,80 +1,563 @@
-//! A channel promise uses a multi-producer, single-consumer channel as its
-//! backend. This allows for the Producer to be cloned but not the Consumer.
-//!
-use crate::{Error, Promise, WakerState};
-use std::{
+//! A channel promise has many producers and a single consumer: the Producer
+//! can be cloned but not the Consumer, and the first producer to resolve wins,
+//! unless a [`SettlePolicy`] decides otherwise, see [`Producer::with_policy`].
+//! The value slot and the consumer's waker share one allocation and one lock.
+use crate::{
+    allocator::{Allocator, Global, Shared, WeakShared},
+    envelope::{Envelope, Metadata},
+    lock::{DefaultRawMutex, RawMutex},
+    padded::CachePadded,
+    settle::{SettlePolicy, Verdict},
+    slot::Slot,
+    tracked::Tracked,
+    Cancel, Error, Promise, PromiseId, WakerState,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    fmt::Debug,
     future::Future,
-    sync::{
-        mpsc::{channel, Receiver, Sender, TryRecvError},
-        Arc, Mutex,
-    },
+    hash::{Hash, Hasher},
+    mem::MaybeUninit,
     task::{Poll, Waker},
 };
-#[derive(Debug, Clone)]
-pub struct Producer<T> {
-    sender: Sender<T>,
-    promise: Arc<Mutex<Inner>>,
+use lock_api::Mutex;
+pub struct Producer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
 }
 
-#[derive(Debug)]
-pub struct Consumer<T> {
-    receiver: Receiver<T>,
-    promise: Arc<Mutex<Inner>>,
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Producer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+pub struct Consumer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Consumer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("promise", &self.promise)
+            .finish()
+    }
 }
 
 #[derive(Debug)]
-struct Inner {
+struct Inner<T> {
+    id: PromiseId,
+    metadata: Option<Arc<Metadata>>,
+    tracked: Tracked,
+    value: Slot<T>,
     waker: Result<Waker, WakerState>,
+    producers: usize,
+    // Boxed, so promises without one pay a pointer for it.
+    policy: Option<Box<Policy<T>>>,
+    cancel: Cancel,
+}
+
+/// The policy of a promise made by [`Producer::with_policy`], and the value
+/// it holds until every producer is gone.
+struct Policy<T> {
+    judge: Box<dyn SettlePolicy<T> + Send>,
+    held: Option<T>,
 }
 
-impl<T> Future for Consumer<T> {
+impl<T: Debug> Debug for Policy<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Policy").field("held", &self.held).finish()
+    }
+}
+
+/// The state shared by the halves of a promise, see [`CachePadded`].
+type SharedInner<T, R, A> = Shared<CachePadded<Mutex<R, Inner<T>>>, A>;
+
+impl<T, R: RawMutex, A: Allocator + Clone> Consumer<T, R, A> {
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().id
+    }
+}
+
+/// Consumers made without a producer use the default lock.
+impl<T> Consumer<T> {
+    /// Return a consumer that is already resolved with `value`, without a
+    /// producer.
+    ///
+    /// ```
+    /// use promise_out::channel::Consumer;
+    /// use futures::executor::block_on;
+    /// assert_eq!(Ok(1), block_on(Consumer::ready(1)));
+    /// ```
+    pub fn ready(value: T) -> Self {
+        Consumer {
+            promise: Shared::new_in(
+                CachePadded::new(Mutex::new(Inner {
+                    id: PromiseId::next(),
+                    metadata: None,
+                    tracked: Tracked::new(),
+                    value: Slot::full(value),
+                    waker: Err(WakerState::Tainted),
+                    producers: 0,
+                    policy: None,
+                    cancel: Cancel::default(),
+                })),
+                Global,
+            ),
+        }
+    }
+
+    /// Return a consumer that never resolves. Unlike a consumer whose
+    /// producers were dropped, it does not fail either.
+    pub fn never() -> Self {
+        Consumer {
+            promise: Shared::new_in(
+                CachePadded::new(Mutex::new(Inner {
+                    id: PromiseId::next(),
+                    metadata: None,
+                    tracked: Tracked::new(),
+                    value: Slot::empty(),
+                    waker: Err(WakerState::Fresh),
+                    producers: 0,
+                    policy: None,
+                    cancel: Cancel::default(),
+                })),
+                Global,
+            ),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Consumer<T, R, A> {
     type Output = Result<T, Error>;
 
     fn poll(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        match self.receiver.try_recv() {
-            Ok(value) => Poll::Ready(Ok(value)),
-            Err(TryRecvError::Empty) => {
-                let mut promise = self.promise.lock().unwrap();
-                match std::mem::replace(&mut promise.waker, Ok(cx.waker().clone())) {
-                    Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
-                    _ => Poll::Pending,
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        crate::coop::cooperate(cx, |cx| {
+            let mut promise = self.promise.lock();
+            if let Some(value) = promise.value.take() {
+                return Poll::Ready(Ok(value));
+            }
+            match &promise.waker {
+                Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
+                Ok(waker) if waker.will_wake(cx.waker()) => {
+                    promise.tracked.poll_shutdown(cx).map(Err)
+                }
+                _ => {
+                    promise.waker = Ok(cx.waker().clone());
+                    promise.tracked.poll_shutdown(cx).map(Err)
                 }
             }
-            Err(TryRecvError::Disconnected) => Poll::Ready(Err(Error::ProducerDropped)),
-        }
+        })
     }
 }
 
-impl<T> Promise<T> for Producer<T> {
-    type Waiter = Consumer<T>;
+impl<T, R: RawMutex, A: Allocator + Clone + Default> Promise<T> for Producer<T, R, A> {
+    type Waiter = Consumer<T, R, A>;
     fn resolve(self, value: T) {
-        self.sender.send(value).unwrap();
-        let mut promise = self.promise.lock().unwrap();
-        if let Ok(waker) = std::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
+        Producer::resolve(self, value)
+    }
+
+    fn new() -> (Self, Self::Waiter) {
+        Self::new_in(A::default())
+    }
+
+    fn resolved(value: T) -> (Self, Self::Waiter) {
+        let (producer, consumer) = Self::new();
+        producer.clone().resolve(value);
+        (producer, consumer)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Producer<T, R, A> {
+    /// Resolve the promise's value, unless another producer already has or
+    /// the policy refuses it. Unlike [`Promise::resolve`], this also works for
+    /// allocators without a default.
+    #[inline]
+    pub fn resolve(self, value: T) {
+        self.resolve_in_place(|slot| slot.write(value))
+    }
+
+    /// Resolve the promise with the value `init` writes into its storage,
+    /// unless another producer already has, see
+    /// [`pair::Producer::resolve_in_place`](crate::pair::Producer::resolve_in_place).
+    /// `init` is not called if the promise is already resolved. With a
+    /// policy, the value is built first and then offered, see
+    /// [`offer`](Self::offer).
+    pub fn resolve_in_place(self, init: impl FnOnce(&mut MaybeUninit<T>) -> &mut T) {
+        let mut promise = self.promise.lock();
+        if let Err(WakerState::Tainted) = promise.waker {
+            return;
+        }
+        if promise.policy.is_some() {
+            // The policy judges the value, so it can not be built in place.
+            let mut value = Slot::empty();
+            value.init(init);
+            drop(promise);
+            let _ = self.offer(value.take().expect("init wrote the value"));
+            return;
+        }
+        promise.value.init(init);
+        let waker = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted));
+        // Wake with the lock released, so the consumer does not wait for it.
+        drop(promise);
+        if let Ok(waker) = waker {
             waker.wake()
         }
     }
 
-    fn new() -> (Self, Self::Waiter)
-    where
-        Self: Sized,
-    {
-        let (tx, rx) = channel();
-        let inner = Arc::new(Mutex::new(Inner {
-            waker: Err(WakerState::Fresh),
-        }));
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().id
+    }
+
+    /// Return a (producer, consumer) pair whose shared state is placed in
+    /// `alloc`, see [`allocator`](crate::allocator).
+    pub fn new_in(alloc: A) -> (Self, Consumer<T, R, A>) {
+        let inner = Shared::new_in(
+            CachePadded::new(Mutex::new(Inner {
+                id: PromiseId::next(),
+                metadata: None,
+                tracked: Tracked::new(),
+                value: Slot::empty(),
+                waker: Err(WakerState::Fresh),
+                producers: 1,
+                policy: None,
+                cancel: Cancel::default(),
+            })),
+            alloc,
+        );
         (
             Producer {
-                sender: tx,
                 promise: inner.clone(),
             },
-            Consumer {
-                receiver: rx,
-                promise: inner,
-            },
+            Consumer { promise: inner },
         )
     }
+
+    /// Return a (producer, consumer) pair carrying `metadata`, readable from
+    /// both halves through [`Envelope`].
+    pub fn with_metadata(metadata: Metadata) -> (Self, Consumer<T, R, A>)
+    where
+        A: Default,
+    {
+        let (producer, consumer) = Self::new();
+        producer.promise.lock().metadata = Some(Arc::new(metadata));
+        (producer, consumer)
+    }
+
+    /// Return a (producer, consumer) pair whose producers settle it as
+    /// `policy` decides, see [`settle`](crate::settle).
+    ///
+    /// ```
+    /// use promise_out::{channel::Producer, settle::LastWins};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u32>::with_policy(LastWins);
+    /// let replica = promise.clone();
+    /// assert_eq!(Ok(None), promise.offer(1));
+    /// assert_eq!(Ok(Some(1)), replica.offer(2));
+    /// drop((promise, replica));
+    /// assert_eq!(Ok(2), block_on(consumer));
+    /// ```
+    pub fn with_policy(policy: impl SettlePolicy<T> + Send + 'static) -> (Self, Consumer<T, R, A>)
+    where
+        A: Default,
+    {
+        let (producer, consumer) = Self::new();
+        producer.promise.lock().policy = Some(Box::new(Policy {
+            judge: Box::new(policy),
+            held: None,
+        }));
+        (producer, consumer)
+    }
+
+    /// Offer `value` to settle the promise. Returns the value it displaced,
+    /// if the policy had one held, or hands `value` back if it lost: the
+    /// promise was already settled or the policy refused it.
+    pub fn offer(&self, value: T) -> Result<Option<T>, T> {
+        let mut promise = self.promise.lock();
+        if let Err(WakerState::Tainted) = promise.waker {
+            return Err(value);
+        }
+        let verdict = match &promise.policy {
+            Some(policy) => policy.judge.judge(policy.held.as_ref(), &value),
+            None => Verdict::Settle,
+        };
+        let policy = promise.policy.as_mut();
+        let displaced = match verdict {
+            Verdict::Reject => return Err(value),
+            Verdict::Hold => {
+                let policy = policy.expect("only a policy holds values");
+                return Ok(policy.held.replace(value));
+            }
+            Verdict::Settle => policy.and_then(|policy| policy.held.take()),
+        };
+        promise.value.init(|slot| slot.write(value));
+        let waker = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted));
+        drop(promise);
+        if let Ok(waker) = waker {
+            waker.wake()
+        }
+        Ok(displaced)
+    }
+
+    /// Return true if the consumer has been dropped, so resolving the promise
+    /// would go unobserved.
+    pub fn is_closed(&self) -> bool {
+        self.promise.lock().cancel.closed
+    }
+
+    /// Return a future that resolves once the consumer has been dropped,
+    /// including a consumer dropped inside a combinator chain.
+    pub fn closed(&self) -> Closed<T, R, A> {
+        Closed {
+            promise: self.promise.clone(),
+        }
+    }
+
+    /// Return a weak handle that can be upgraded back into a producer while
+    /// some producer is alive. It does not keep the promise pending: once
+    /// every producer is dropped the consumer fails, even while weak handles
+    /// remain.
+    pub fn downgrade(&self) -> WeakProducer<T, R, A> {
+        WeakProducer {
+            promise: Shared::downgrade(&self.promise),
+        }
+    }
+}
+
+/// A handle to a producer that does not count as one. See
+/// [`Producer::downgrade`].
+pub struct WeakProducer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: WeakShared<CachePadded<Mutex<R, Inner<T>>>, A>,
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Debug for WeakProducer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WeakProducer")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> WeakProducer<T, R, A> {
+    /// Return a producer again, unless every producer has been dropped in the
+    /// meantime.
+    pub fn upgrade(&self) -> Option<Producer<T, R, A>> {
+        let promise = self.promise.upgrade()?;
+        {
+            let mut inner = promise.lock();
+            if inner.producers == 0 {
+                return None;
+            }
+            inner.producers += 1;
+        }
+        Some(Producer { promise })
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Clone for WeakProducer<T, R, A> {
+    fn clone(&self) -> Self {
+        WeakProducer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Clone for Producer<T, R, A> {
+    fn clone(&self) -> Self {
+        self.promise.lock().producers += 1;
+        Producer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Producer<T, R, A> {
+    /// If this was the last producer, settle the promise with the value its
+    /// policy holds, or if there is none, wake the consumer with an error.
+    fn drop(&mut self) {
+        let mut promise = self.promise.lock();
+        promise.producers -= 1;
+        if promise.producers == 0 {
+            if let Some(held) = promise
+                .policy
+                .as_mut()
+                .and_then(|policy| policy.held.take())
+            {
+                promise.value.init(|slot| slot.write(held));
+            }
+            let waker = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted));
+            drop(promise);
+            if let Ok(waker) = waker {
+                waker.wake()
+            }
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Consumer<T, R, A> {
+    /// Let the producers know nobody is waiting for the value anymore.
+    fn drop(&mut self) {
+        self.promise.lock().cancel.close();
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Envelope for Producer<T, R, A> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().metadata.clone()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Envelope for Consumer<T, R, A> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().metadata.clone()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> PartialEq for Producer<T, R, A> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Eq for Producer<T, R, A> {}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Hash for Producer<T, R, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.promise).hash(state)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> PartialEq for Consumer<T, R, A> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Eq for Consumer<T, R, A> {}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Hash for Consumer<T, R, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.promise).hash(state)
+    }
+}
+
+/// Future for [`Producer::closed`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Closed<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Closed<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Closed")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Closed<T, R, A> {
+    type Output = ();
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<()> {
+        self.promise.lock().cancel.poll_closed(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Producer;
+    use crate::{Error, Promise};
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_promise_out_clones_dropped() {
+        let (op, op_a) = Producer::<String>::new();
+        let op2 = op.clone();
+        let task1 = thread::spawn(move || block_on(op_a));
+        std::mem::drop(op);
+        thread::spawn(move || std::mem::drop(op2))
+            .join()
+            .expect("The task2 thread has panicked");
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            task1.join().expect("The task1 thread has panicked")
+        );
+    }
+
+    #[test]
+    fn test_promise_out_closed() {
+        let (op, op_a) = Producer::<String>::new();
+        let op2 = op.clone();
+        let task1 = thread::spawn(move || block_on(op2.closed()));
+        std::mem::drop(op_a);
+        task1.join().expect("The task1 thread has panicked");
+        assert!(op.is_closed());
+    }
+
+    #[test]
+    fn test_weak_producer_does_not_keep_promise() {
+        let (op, op_a) = Producer::<String>::new();
+        let weak = op.downgrade();
+        drop(op);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(Err(Error::ProducerDropped), block_on(op_a));
+    }
+
+    #[test]
+    fn test_ready_and_never() {
+        use super::Consumer;
+        use futures::future::{select, Either};
+        match block_on(select(Consumer::<u8>::never(), Consumer::ready(1))) {
+            Either::Right((value, _)) => assert_eq!(Ok(1), value),
+            Either::Left(_) => panic!("never resolved"),
+        }
+    }
+
+    #[test]
+    fn test_resolved_and_rejected() {
+        let (op, op_a) = Producer::<&str>::resolved("🍓");
+        op.clone().resolve("🍌");
+        drop(op);
+        assert_eq!(Ok("🍓"), block_on(op_a));
+        let (_, op_a) = Producer::<Result<u8, &str>>::rejected("reject!");
+        assert_eq!(Ok(Err("reject!")), block_on(op_a));
+    }
+
+    #[test]
+    fn test_first_error_wins_across_clones() {
+        use crate::settle::FirstErrorWins;
+        let (op, op_a) = Producer::<Result<u32, &str>>::with_policy(FirstErrorWins);
+        let op2 = op.clone();
+        assert_eq!(Ok(None), op.offer(Ok(1)));
+        assert_eq!(Err(Ok(2)), op2.offer(Ok(2)));
+        op2.clone().resolve(Err("disk full"));
+        assert_eq!(Err(Err("late")), op.offer(Err("late")));
+        assert_eq!(Ok(Err("disk full")), block_on(op_a));
+        let (op, op_a) = Producer::<Result<u32, &str>>::with_policy(FirstErrorWins);
+        op.clone().resolve_in_place(|slot| slot.write(Ok(3)));
+        drop(op);
+        assert_eq!(Ok(Ok(3)), block_on(op_a));
+    }
+
+    #[test]
+    fn test_consumer_is_one_pointer() {
+        use super::Consumer;
+        assert_eq!(
+            std::mem::size_of::<usize>(),
+            std::mem::size_of::<Consumer<[u8; 64]>>()
+        );
+        let (op, op_a) = Producer::<u8>::new();
+        op.clone().resolve(1);
+        op.resolve(2);
+        assert_eq!(Ok(1), block_on(op_a));
+    }
 }
@@ -0,0 +1,185 @@
+//! codec defines how a promise's settlement travels over the wire. A [`Frame`]
+//! pairs a correlation id with a resolve or reject [`Payload`], and a
+//! [`Codec`] turns frames into bytes and back. [`Bincode`] and [`Json`] are
+//! provided behind the `bincode` and `json` features; [`Wire`] plugs any codec
+//! into the [`rpc`](crate::rpc) layer so it can run over sockets and message
+//! buses.
+use crate::rpc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt::Debug, marker::PhantomData};
+
+/// A correlation id and what it settles to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame<T> {
+    pub id: u64,
+    pub payload: Payload<T>,
+}
+
+/// The settlement carried by a [`Frame`]. A rejection carries its reason as
+/// text, since the error types of either end need not agree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload<T> {
+    Resolve(T),
+    Reject(String),
+}
+
+impl<T> From<Payload<T>> for Result<T, String> {
+    fn from(payload: Payload<T>) -> Self {
+        match payload {
+            Payload::Resolve(value) => Ok(value),
+            Payload::Reject(reason) => Err(reason),
+        }
+    }
+}
+
+impl<T, E: ToString> From<Result<T, E>> for Payload<T> {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Payload::Resolve(value),
+            Err(reason) => Payload::Reject(reason.to_string()),
+        }
+    }
+}
+
+/// A wire format for frames.
+pub trait Codec {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn encode<T: Serialize>(&self, frame: &Frame<T>) -> Result<Vec<u8>, Self::Error>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<Frame<T>, Self::Error>;
+}
+
+/// The compact binary format of the `bincode` crate.
+///
+/// ```
+/// use promise_out::codec::{Bincode, Codec, Frame, Payload};
+/// let frame = Frame { id: 7, payload: Payload::Resolve(String::from("🍓")) };
+/// let bytes = Bincode.encode(&frame).unwrap();
+/// assert_eq!(frame, Bincode.decode(&bytes).unwrap());
+/// ```
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    type Error = bincode::Error;
+
+    fn encode<T: Serialize>(&self, frame: &Frame<T>) -> Result<Vec<u8>, Self::Error> {
+        bincode::serialize(frame)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<Frame<T>, Self::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+/// JSON, for peers that are not written in Rust or for readable traffic.
+///
+/// ```
+/// use promise_out::codec::{Codec, Frame, Json, Payload};
+/// let frame = Frame::<u32> { id: 7, payload: Payload::Reject(String::from("no")) };
+/// let bytes = Json.encode(&frame).unwrap();
+/// assert_eq!(r#"{"id":7,"payload":{"Reject":"no"}}"#, String::from_utf8_lossy(&bytes));
+/// assert_eq!(frame, Json.decode(&bytes).unwrap());
+/// ```
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize>(&self, frame: &Frame<T>) -> Result<Vec<u8>, Self::Error> {
+        serde_json::to_vec(frame)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<Frame<T>, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// Adapts a [`Codec`] to an [`rpc::Codec`] over byte frames. A request of
+/// type `Req` travels as a resolved frame; the peer answers with a frame that
+/// resolves to a `Resp` or rejects with a reason. Frames that fail to decode
+/// are skipped.
+///
+/// # Panics
+///
+/// Sending a request panics if it fails to serialize, which for the provided
+/// codecs only happens for types their format cannot express.
+pub struct Wire<C, Req, Resp> {
+    codec: C,
+    phantom: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<C, Req, Resp> Wire<C, Req, Resp> {
+    pub fn new(codec: C) -> Self {
+        Wire {
+            codec,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<C, Req, Resp> rpc::Codec for Wire<C, Req, Resp>
+where
+    C: Codec,
+    Req: Serialize,
+    Resp: DeserializeOwned,
+{
+    type Frame = Vec<u8>;
+    type Request = Req;
+    type Response = Result<Resp, String>;
+
+    fn encode(&self, id: u64, request: Req) -> Vec<u8> {
+        let frame = Frame {
+            id,
+            payload: Payload::Resolve(request),
+        };
+        self.codec
+            .encode(&frame)
+            .expect("rpc request must serialize")
+    }
+
+    fn decode(&self, frame: Vec<u8>) -> Option<(u64, Result<Resp, String>)> {
+        let frame = self.codec.decode::<Resp>(&frame).ok()?;
+        Some((frame.id, frame.payload.into()))
+    }
+}
+
+impl<C: Debug, Req, Resp> Debug for Wire<C, Req, Resp> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wire").field("codec", &self.codec).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_wire_round_trip() {
+        use super::{Codec, Frame, Json, Payload, Wire};
+        use crate::rpc::Codec as _;
+        let wire = Wire::<_, String, u32>::new(Json);
+        let request: Frame<String> = Json.decode(&wire.encode(3, String::from("len"))).unwrap();
+        assert_eq!(3, request.id);
+        assert_eq!(Payload::Resolve(String::from("len")), request.payload);
+        let resolved = Json.encode(&Frame {
+            id: 3,
+            payload: Payload::Resolve(3u32),
+        });
+        assert_eq!(Some((3, Ok(3))), wire.decode(resolved.unwrap()));
+        let rejected = Json.encode(&Frame::<u32> {
+            id: 4,
+            payload: Err("nope").into(),
+        });
+        assert_eq!(
+            Some((4, Err(String::from("nope")))),
+            wire.decode(rejected.unwrap())
+        );
+        assert_eq!(None, wire.decode(b"garbage".to_vec()));
+    }
+}
@@ -0,0 +1,141 @@
+//! collector implements dynamic scatter/gather. Producers are minted from a
+//! [`Collector`] one at a time, and a pair promise resolves with every value
+//! they settled once the collector and all of its producers are gone.
+use crate::{pair, Promise};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+struct State<T> {
+    // One slot per minted producer, in minting order.
+    values: Vec<Option<T>>,
+    // Minted producers that have not settled, plus the collector itself.
+    outstanding: usize,
+    gathered: Option<pair::Producer<Vec<T>>>,
+}
+
+impl<T> State<T> {
+    fn settle(state: &Mutex<Self>, slot: usize, value: Option<T>) {
+        let gathered = {
+            let mut state = state.lock().unwrap();
+            if slot < state.values.len() {
+                state.values[slot] = value;
+            }
+            state.outstanding -= 1;
+            if state.outstanding > 0 {
+                return;
+            }
+            let values = std::mem::take(&mut state.values);
+            state.gathered.take().map(|gathered| (gathered, values))
+        };
+        if let Some((gathered, values)) = gathered {
+            gathered.resolve(values.into_iter().flatten().collect());
+        }
+    }
+}
+
+/// Mints producers whose values are gathered into one `Vec`.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::collector::Collector;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let (collector, gathered) = Collector::new();
+/// for shard in 0..3 {
+///     let producer = collector.producer();
+///     thread::spawn(move || producer.resolve(shard * 10));
+/// }
+/// drop(collector);
+/// assert_eq!(Ok(vec![0, 10, 20]), block_on(gathered));
+/// ```
+#[derive(Debug)]
+pub struct Collector<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// A producer minted by a [`Collector`]. Dropping it without resolving leaves
+/// its value out of the result.
+#[derive(Debug)]
+pub struct Producer<T> {
+    state: Arc<Mutex<State<T>>>,
+    slot: usize,
+    settled: bool,
+}
+
+impl<T> Collector<T> {
+    /// Return a collector and the consumer of the gathered values. The
+    /// consumer resolves with the values in the order their producers were
+    /// minted, once the collector has been dropped and every producer has
+    /// resolved or been dropped. It does not fail.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Self, pair::Consumer<Vec<T>>) {
+        let (gathered, consumer) = pair::Producer::new();
+        let state = Arc::new(Mutex::new(State {
+            values: vec![],
+            outstanding: 1,
+            gathered: Some(gathered),
+        }));
+        (Collector { state }, consumer)
+    }
+
+    /// Mint another producer.
+    pub fn producer(&self) -> Producer<T> {
+        let mut state = self.state.lock().unwrap();
+        state.values.push(None);
+        state.outstanding += 1;
+        Producer {
+            state: self.state.clone(),
+            slot: state.values.len() - 1,
+            settled: false,
+        }
+    }
+
+    /// Return how many minted producers have not settled yet.
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().outstanding - 1
+    }
+}
+
+impl<T> Drop for Collector<T> {
+    /// Stop minting; the values are gathered once the producers settle.
+    fn drop(&mut self) {
+        State::settle(&self.state, usize::MAX, None);
+    }
+}
+
+impl<T> Producer<T> {
+    pub fn resolve(mut self, value: T) {
+        self.settled = true;
+        State::settle(&self.state, self.slot, Some(value));
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        if !self.settled {
+            State::settle(&self.state, self.slot, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Collector;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_collector_waits_for_every_producer() {
+        let (collector, mut gathered) = Collector::new();
+        let first = collector.producer();
+        let dropped = collector.producer();
+        let last = collector.producer();
+        assert_eq!(3, collector.pending());
+        drop(collector);
+        last.resolve("c");
+        drop(dropped);
+        assert_eq!(None, (&mut gathered).now_or_never());
+        first.resolve("a");
+        assert_eq!(Ok(vec!["a", "c"]), block_on(gathered));
+    }
+}
@@ -0,0 +1,687 @@
+//! combinator implements adapters over any consumer. They work for every
+//! flavor since each consumer is a `Future<Output = Result<T, E>>`; for a poly
+//! consumer the `T` is an `Arc<T>` and the adapters see that `Arc`. It also
+//! holds [`Contramap`], the one adapter on the producer side. Every adapter
+//! passes on the [`Envelope`] metadata of the promise it wraps.
+use crate::{
+    envelope::{Envelope, Metadata},
+    Error, Promise,
+};
+use alloc::sync::Arc;
+use core::{
+    fmt::{Debug, Display},
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(feature = "futures")]
+use futures_core::Stream;
+
+/// Adapters for consumers, or any future that returns a `Result<T, E>`.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, ConsumerExt, pair::Producer};
+/// use futures::executor::block_on;
+/// let (promise, consumer) = Producer::<u32>::new();
+/// promise.resolve(41);
+/// assert_eq!(Ok(42), block_on(consumer.map(|x| x + 1)));
+/// ```
+pub trait ConsumerExt<T, E>: Future<Output = Result<T, E>> + Sized {
+    /// Convert the resolved value with `f`. Errors pass through untouched.
+    fn map<U, F>(self, f: F) -> Map<Self, F>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Map {
+            future: self,
+            f: Some(f),
+        }
+    }
+
+    /// Convert the error with `f`. Resolved values pass through untouched.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u32>::new();
+    /// drop(promise);
+    /// assert_eq!(Err("gone".to_string()),
+    ///            block_on(consumer.map_err(|_| "gone".to_string())));
+    /// ```
+    fn map_err<E2, F>(self, f: F) -> MapErr<Self, F>
+    where
+        F: FnOnce(E) -> E2,
+    {
+        MapErr {
+            future: self,
+            f: Some(f),
+        }
+    }
+
+    /// Run `f` on a reference to the resolved value as it passes through.
+    /// Nothing is run if the consumer returns an error.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<String>::new();
+    /// promise.resolve("🍓".into());
+    /// let value = block_on(consumer.inspect(|v| println!("Received {v}")));
+    /// assert_eq!("🍓", value.unwrap());
+    /// ```
+    fn inspect<F>(self, f: F) -> Inspect<Self, F>
+    where
+        F: FnOnce(&T),
+    {
+        Inspect {
+            future: self,
+            f: Some(f),
+        }
+    }
+
+    /// Wait for either this consumer or `other`, whichever settles first.
+    ///
+    /// The winner's output is returned along with the loser, which has not
+    /// been polled to completion. Keep the loser to await it later, e.g. to
+    /// fall back on a secondary source, or drop it to give up on it. Dropping
+    /// the loser closes its producer, so the work behind it can stop. If both
+    /// are ready at the same time this consumer wins.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, Either, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (primary, consumer) = Producer::<&str>::new();
+    /// let (_fallback, fallback_consumer) = Producer::<&str>::new();
+    /// primary.resolve("🍓");
+    /// match block_on(consumer.select(fallback_consumer)) {
+    ///     Either::Left((value, _loser)) => assert_eq!(Ok("🍓"), value),
+    ///     Either::Right(_) => unreachable!(),
+    /// }
+    /// ```
+    fn select<B>(self, other: B) -> Select<Self, B>
+    where
+        B: Future,
+    {
+        Select {
+            inner: Some((self, other)),
+        }
+    }
+
+    /// Return the resolved value, or compute one from the error with `f`.
+    /// Useful where losing the producer should fall back to a default.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u32>::new();
+    /// drop(promise);
+    /// assert_eq!(0, block_on(consumer.unwrap_or_else(|_| 0)));
+    /// ```
+    fn unwrap_or_else<F>(self, f: F) -> UnwrapOrElse<Self, F>
+    where
+        F: FnOnce(E) -> T,
+    {
+        UnwrapOrElse {
+            future: self,
+            f: Some(f),
+        }
+    }
+
+    /// Merge a rejection encoded in the value, as in `Promise<Result<T, E>>`,
+    /// with the consumer's own error, so both can be handled with one `?`.
+    /// Poly consumers are supported too; their value is unwrapped from the
+    /// `Arc`, or cloned if it is shared.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, combinator::CombinedError, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<Result<u32, &str>>::new();
+    /// promise.resolve(Err("reject!"));
+    /// assert_eq!(Err(CombinedError::Rejected("reject!")), block_on(consumer.flatten()));
+    /// ```
+    fn flatten(self) -> Flatten<Self>
+    where
+        T: IntoResult,
+        E: Into<Error>,
+    {
+        Flatten { future: self }
+    }
+
+    /// Turn the consumer into a `Stream` that yields its result once and then
+    /// ends, so it can be merged with other streams.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, pair::Producer};
+    /// use futures::{executor::block_on, stream, StreamExt};
+    /// let (first, first_consumer) = Producer::<u32>::new();
+    /// let (second, second_consumer) = Producer::<u32>::new();
+    /// first.resolve(1);
+    /// second.resolve(2);
+    /// let merged = stream::select(first_consumer.into_stream(), second_consumer.into_stream());
+    /// let mut values: Vec<_> = block_on(merged.map(Result::unwrap).collect());
+    /// values.sort();
+    /// assert_eq!(vec![1, 2], values);
+    /// ```
+    #[cfg(feature = "futures")]
+    fn into_stream(self) -> IntoStream<Self> {
+        IntoStream { future: Some(self) }
+    }
+}
+
+impl<Fut, T, E> ConsumerExt<T, E> for Fut where Fut: Future<Output = Result<T, E>> {}
+
+/// Future for [`ConsumerExt::map`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Map<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+// The closure is never pinned; it is only ever moved out.
+impl<Fut: Unpin, F> Unpin for Map<Fut, F> {}
+
+impl<Fut, F, T, E, U> Future for Map<Fut, F>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    F: FnOnce(T) -> U,
+{
+    type Output = Result<U, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(result) => {
+                let f = this
+                    .f
+                    .take()
+                    .expect("Map must not be polled after it returned Ready");
+                Poll::Ready(result.map(f))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future for [`ConsumerExt::map_err`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct MapErr<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+impl<Fut: Unpin, F> Unpin for MapErr<Fut, F> {}
+
+impl<Fut, F, T, E, E2> Future for MapErr<Fut, F>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    F: FnOnce(E) -> E2,
+{
+    type Output = Result<T, E2>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(result) => {
+                let f = this
+                    .f
+                    .take()
+                    .expect("MapErr must not be polled after it returned Ready");
+                Poll::Ready(result.map_err(f))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future for [`ConsumerExt::inspect`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Inspect<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+impl<Fut: Unpin, F> Unpin for Inspect<Fut, F> {}
+
+impl<Fut, F, T, E> Future for Inspect<Fut, F>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    F: FnOnce(&T),
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(result) => {
+                let f = this
+                    .f
+                    .take()
+                    .expect("Inspect must not be polled after it returned Ready");
+                if let Ok(value) = &result {
+                    f(value);
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future for [`ConsumerExt::unwrap_or_else`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct UnwrapOrElse<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+impl<Fut: Unpin, F> Unpin for UnwrapOrElse<Fut, F> {}
+
+impl<Fut, F, T, E> Future for UnwrapOrElse<Fut, F>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    F: FnOnce(E) -> T,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(result) => {
+                let f = this
+                    .f
+                    .take()
+                    .expect("UnwrapOrElse must not be polled after it returned Ready");
+                Poll::Ready(result.unwrap_or_else(f))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A value that holds a `Result`, as resolved by a promise that encodes its
+/// rejection in its value. See [`ConsumerExt::flatten`].
+pub trait IntoResult {
+    type Value;
+    type Reason;
+
+    fn into_result(self) -> Result<Self::Value, Self::Reason>;
+}
+
+impl<T, E> IntoResult for Result<T, E> {
+    type Value = T;
+    type Reason = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
+impl<T: Clone, E: Clone> IntoResult for Arc<Result<T, E>> {
+    type Value = T;
+    type Reason = E;
+
+    fn into_result(self) -> Result<T, E> {
+        Arc::try_unwrap(self).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+/// The error of a flattened consumer: either the promise itself failed or it
+/// was resolved with a rejection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CombinedError<E> {
+    Promise(Error),
+    Rejected(E),
+}
+
+impl<E> From<Error> for CombinedError<E> {
+    fn from(error: Error) -> Self {
+        CombinedError::Promise(error)
+    }
+}
+
+impl<E: Display> Display for CombinedError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CombinedError::Promise(error) => Display::fmt(error, f),
+            CombinedError::Rejected(reason) => write!(f, "rejected: {reason}"),
+        }
+    }
+}
+
+impl<E: Debug + Display> core::error::Error for CombinedError<E> {}
+
+/// Future for [`ConsumerExt::flatten`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Flatten<Fut> {
+    future: Fut,
+}
+
+impl<Fut, T, E> Future for Flatten<Fut>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    T: IntoResult,
+    E: Into<Error>,
+{
+    type Output = Result<T::Value, CombinedError<T::Reason>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.future).poll(cx).map(|result| {
+            result
+                .map_err(|error| CombinedError::Promise(error.into()))?
+                .into_result()
+                .map_err(CombinedError::Rejected)
+        })
+    }
+}
+
+/// Stream for [`ConsumerExt::into_stream`].
+#[cfg(feature = "futures")]
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct IntoStream<Fut> {
+    future: Option<Fut>,
+}
+
+#[cfg(feature = "futures")]
+impl<Fut: Future + Unpin> Stream for IntoStream<Fut> {
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(future) = self.future.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let output = core::task::ready!(Pin::new(future).poll(cx));
+        self.future = None;
+        Poll::Ready(Some(output))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = usize::from(self.future.is_some());
+        (remaining, Some(remaining))
+    }
+}
+
+/// A producer resolved with a different type than its consumer expects. See
+/// [`Promise::contramap`].
+pub struct Contramap<P, F, T> {
+    producer: P,
+    f: F,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<P, F, T> Contramap<P, F, T> {
+    pub(crate) fn new(producer: P, f: F) -> Self {
+        Contramap {
+            producer,
+            f,
+            _value: PhantomData,
+        }
+    }
+
+    /// Return the original producer.
+    pub fn into_inner(self) -> P {
+        self.producer
+    }
+}
+
+impl<P, F, T> Contramap<P, F, T>
+where
+    P: Promise<T>,
+{
+    /// Convert `value` and resolve the original producer with it.
+    pub fn resolve<U>(self, value: U)
+    where
+        F: FnOnce(U) -> T,
+    {
+        self.producer.resolve((self.f)(value))
+    }
+}
+
+impl<P: Debug, F, T> Debug for Contramap<P, F, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Contramap")
+            .field("producer", &self.producer)
+            .finish()
+    }
+}
+
+/// One of two values, e.g. the winner of [`ConsumerExt::select`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Future for [`ConsumerExt::select`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Select<A, B> {
+    inner: Option<(A, B)>,
+}
+
+impl<A, B> Future for Select<A, B>
+where
+    A: Future + Unpin,
+    B: Future + Unpin,
+{
+    type Output = Either<(A::Output, B), (B::Output, A)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (a, b) = self
+            .inner
+            .as_mut()
+            .expect("Select must not be polled after it returned Ready");
+        if let Poll::Ready(value) = Pin::new(a).poll(cx) {
+            let (_, b) = self.inner.take().unwrap();
+            return Poll::Ready(Either::Left((value, b)));
+        }
+        if let Poll::Ready(value) = Pin::new(b).poll(cx) {
+            let (a, _) = self.inner.take().unwrap();
+            return Poll::Ready(Either::Right((value, a)));
+        }
+        Poll::Pending
+    }
+}
+
+impl<Fut: Envelope, F> Envelope for Map<Fut, F> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.future.metadata()
+    }
+}
+
+impl<Fut: Envelope, F> Envelope for MapErr<Fut, F> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.future.metadata()
+    }
+}
+
+impl<Fut: Envelope, F> Envelope for Inspect<Fut, F> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.future.metadata()
+    }
+}
+
+impl<Fut: Envelope, F> Envelope for UnwrapOrElse<Fut, F> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.future.metadata()
+    }
+}
+
+impl<Fut: Envelope> Envelope for Flatten<Fut> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.future.metadata()
+    }
+}
+
+impl<P: Envelope, F, T> Envelope for Contramap<P, F, T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.producer.metadata()
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "std",
+    feature = "pair",
+    feature = "poly",
+    feature = "channel"
+))]
+mod tests {
+    use super::{CombinedError, ConsumerExt, Either};
+    use crate::{channel, pair, poly, Error, Promise};
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_map_pair() {
+        let (op, op_a) = pair::Producer::<String>::new();
+        let task1 = thread::spawn(move || block_on(op_a.map(|s| s.len())));
+        op.resolve(String::from("🍓"));
+        assert_eq!(Ok(4), task1.join().expect("The task1 thread has panicked"));
+    }
+
+    #[test]
+    fn test_map_poly() {
+        let (op, op_a) = poly::Producer::<String>::new();
+        let op_b = op_a.clone();
+        op.resolve(String::from("hi"));
+        assert_eq!(Ok(2), block_on(op_a.map(|s| s.len())));
+        assert_eq!(
+            Ok(String::from("hi!")),
+            block_on(op_b.map(|s| format!("{s}!")))
+        );
+    }
+
+    #[test]
+    fn test_map_err_channel() {
+        let (op, op_a) = channel::Producer::<u8>::new();
+        drop(op);
+        let result = block_on(op_a.map(|x| x + 1).map_err(|e| e.to_string()));
+        assert_eq!(Err(Error::ProducerDropped.to_string()), result);
+    }
+
+    #[test]
+    fn test_inspect() {
+        let (op, op_a) = pair::Producer::<u32>::new();
+        let mut seen = None;
+        op.resolve(7);
+        let result = block_on(op_a.inspect(|v| seen = Some(*v)).map(|v| v * 2));
+        assert_eq!(Ok(14), result);
+        assert_eq!(Some(7), seen);
+
+        let (op, op_a) = pair::Producer::<u32>::new();
+        let mut called = false;
+        drop(op);
+        assert!(block_on(op_a.inspect(|_| called = true)).is_err());
+        assert!(!called);
+    }
+
+    #[test]
+    fn test_select_keeps_loser() {
+        let (op, op_a) = pair::Producer::<u32>::new();
+        let (op2, op_b) = pair::Producer::<u32>::new();
+        let task1 = thread::spawn(move || block_on(op_a.select(op_b)));
+        op2.resolve(2);
+        match task1.join().expect("The task1 thread has panicked") {
+            Either::Right((value, loser)) => {
+                assert_eq!(Ok(2), value);
+                op.resolve(1);
+                assert_eq!(Ok(1), block_on(loser));
+            }
+            Either::Left(_) => panic!("the first consumer was never resolved"),
+        }
+    }
+
+    #[test]
+    fn test_select_dropped_loser_closes_its_producer() {
+        let (op, op_a) = pair::Producer::<u32>::new();
+        let (op2, op_b) = pair::Producer::<u32>::new();
+        op.resolve(1);
+        match block_on(op_a.select(op_b)) {
+            Either::Left((value, loser)) => {
+                assert_eq!(Ok(1), value);
+                assert!(!op2.is_closed());
+                drop(loser);
+            }
+            Either::Right(_) => panic!("the second consumer was never resolved"),
+        }
+        block_on(op2.closed());
+    }
+
+    #[test]
+    fn test_select_dropped_producer_settles() {
+        let (op, op_a) = pair::Producer::<u32>::new();
+        let (_op2, op_b) = poly::Producer::<u32>::new();
+        drop(op);
+        match block_on(op_a.select(op_b)) {
+            Either::Left((value, _)) => assert_eq!(Err(Error::ProducerDropped), value),
+            Either::Right(_) => panic!("the second consumer was never resolved"),
+        }
+    }
+
+    #[test]
+    fn test_unwrap_or_else() {
+        let (op, op_a) = poly::Producer::<String>::new();
+        op.resolve(String::from("🍓"));
+        assert_eq!("🍓", *block_on(op_a.unwrap_or_else(|_| Default::default())));
+
+        let (op, op_a) = channel::Producer::<String>::new();
+        drop(op);
+        let value = block_on(op_a.unwrap_or_else(|e| format!("fallback: {e}")));
+        assert_eq!("fallback: producer dropped", value);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let (op, op_a) = pair::Producer::<Result<u8, String>>::new();
+        op.resolve(Ok(1));
+        assert_eq!(Ok(1), block_on(op_a.flatten()));
+
+        let (op, op_a) = pair::Producer::<Result<u8, String>>::new();
+        drop(op);
+        assert_eq!(
+            Err(CombinedError::Promise(Error::ProducerDropped)),
+            block_on(op_a.flatten())
+        );
+
+        let (op, op_a) = poly::Producer::<Result<u8, String>>::new();
+        let op_b = op_a.clone();
+        op.resolve(Err("reject!!".into()));
+        let rejected = CombinedError::Rejected(String::from("reject!!"));
+        assert_eq!(Err(rejected.clone()), block_on(op_a.flatten()));
+        assert_eq!(Err(rejected.clone()), block_on(op_b.flatten()));
+        assert_eq!("rejected: reject!!", rejected.to_string());
+    }
+
+    #[test]
+    fn test_contramap() {
+        let (op, op_a) = poly::Producer::<Result<u8, String>>::new();
+        let op = op.contramap(|x: u8| x.checked_mul(2).ok_or_else(|| String::from("overflow")));
+        op.resolve(200);
+        assert_eq!(Err(String::from("overflow")), *block_on(op_a).unwrap());
+
+        let (op, op_a) = channel::Producer::<u8>::new();
+        let op = op.contramap(|s: &str| s.len() as u8);
+        let task1 = thread::spawn(move || op.resolve("🍓"));
+        task1.join().expect("The task1 thread has panicked");
+        assert_eq!(Ok(4), block_on(op_a));
+    }
+
+    #[test]
+    fn test_into_stream_yields_once() {
+        use futures::StreamExt;
+        let (op, op_a) = pair::Producer::<u8>::new();
+        let mut stream = op_a.into_stream();
+        drop(op);
+        assert_eq!(Some(Err(Error::ProducerDropped)), block_on(stream.next()));
+        assert_eq!(None, block_on(stream.next()));
+    }
+}
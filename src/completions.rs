@@ -0,0 +1,159 @@
+//! completions implements a growable set of tagged consumers that yields each
+//! result as soon as its consumer settles. All consumers share one ready queue,
+//! so only the consumers that were woken are polled again, and the slots of
+//! settled consumers are reused.
+use crate::ready::ReadyQueue;
+use futures_core::Stream;
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A set of consumers, each with a tag, yielding `(tag, output)` in the order
+/// the consumers settle. The stream ends whenever the set is empty; pushing
+/// more consumers afterwards starts it again.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, completions::Completions, pair::Producer};
+/// use futures::{executor::block_on, StreamExt};
+/// let mut completions = Completions::new();
+/// let (first, consumer) = Producer::<u32>::new();
+/// completions.push("first", consumer);
+/// let (second, consumer) = Producer::<u32>::new();
+/// completions.push("second", consumer);
+/// second.resolve(2);
+/// assert_eq!(Some(("second", Ok(2))), block_on(completions.next()));
+/// first.resolve(1);
+/// assert_eq!(Some(("first", Ok(1))), block_on(completions.next()));
+/// assert_eq!(None, block_on(completions.next()));
+/// ```
+pub struct Completions<Tag, Fut> {
+    slots: Vec<Option<(Tag, Fut)>>,
+    free: Vec<usize>,
+    ready: ReadyQueue,
+}
+
+impl<Tag, Fut: Future + Unpin> Completions<Tag, Fut> {
+    pub fn new() -> Self {
+        Completions {
+            slots: vec![],
+            free: vec![],
+            ready: ReadyQueue::default(),
+        }
+    }
+
+    /// Add a consumer; its output is yielded with `tag`.
+    pub fn push(&mut self, tag: Tag, consumer: Fut) {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some((tag, consumer));
+                index
+            }
+            None => {
+                self.slots.push(Some((tag, consumer)));
+                self.slots.len() - 1
+            }
+        };
+        self.ready.insert(index);
+    }
+
+    /// Return the number of consumers that have not settled.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<Tag, Fut: Future + Unpin> Default for Completions<Tag, Fut> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Tag, Fut> Unpin for Completions<Tag, Fut> {}
+
+impl<Tag: Debug, Fut: Debug> Debug for Completions<Tag, Fut> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.slots.iter().flatten()).finish()
+    }
+}
+
+impl<Tag, Fut: Future + Unpin> Stream for Completions<Tag, Fut> {
+    type Item = (Tag, Fut::Output);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.is_empty() {
+            return Poll::Ready(None);
+        }
+        this.ready.register(cx.waker());
+        while let Some(index) = this.ready.pop() {
+            // The slot may have settled since it was queued.
+            let Some((_, consumer)) = this.slots[index].as_mut() else {
+                continue;
+            };
+            let mut child = Context::from_waker(this.ready.waker(index));
+            if let Poll::Ready(output) = Pin::new(consumer).poll(&mut child) {
+                let (tag, _) = this.slots[index].take().unwrap();
+                this.free.push(index);
+                return Poll::Ready(Some((tag, output)));
+            }
+        }
+        Poll::Pending
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), Some(self.len()))
+    }
+}
+
+#[cfg(all(test, feature = "pair"))]
+mod tests {
+    use super::Completions;
+    use crate::{pair, Error, Promise};
+    use futures::{executor::block_on, StreamExt};
+    use std::thread;
+
+    #[test]
+    fn test_completions_many_pending() {
+        let mut completions = Completions::new();
+        let producers: Vec<_> = (0..1000)
+            .map(|i| {
+                let (producer, consumer) = pair::Producer::<usize>::new();
+                completions.push(i, consumer);
+                producer
+            })
+            .collect();
+        let task1 = thread::spawn(move || {
+            for (i, producer) in producers.into_iter().enumerate().rev() {
+                if i % 2 == 0 {
+                    producer.resolve(i * 2);
+                }
+            }
+        });
+        let settled: Vec<_> = block_on(completions.by_ref().collect());
+        task1.join().expect("The task1 thread has panicked");
+        assert_eq!(1000, settled.len());
+        for (i, result) in settled {
+            if i % 2 == 0 {
+                assert_eq!(Ok(i * 2), result);
+            } else {
+                assert_eq!(Err(Error::ProducerDropped), result);
+            }
+        }
+
+        // Settled slots are reused.
+        let (producer, consumer) = pair::Producer::<usize>::new();
+        completions.push(0, consumer);
+        producer.resolve(7);
+        assert_eq!(Some((0, Ok(7))), block_on(completions.next()));
+        assert_eq!(1000, completions.slots.len());
+    }
+}
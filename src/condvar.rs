@@ -0,0 +1,203 @@
+//! condvar implements an async condition variable for state kept in a
+//! `std::sync::Mutex`. Every waiter owns a slot driven by the same waker state
+//! machine as a pair promise: notifying a waiter taints its slot and wakes it.
+use crate::WakerState;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+type Slot = Arc<Mutex<Result<Waker, WakerState>>>;
+
+/// An async condition variable.
+///
+/// Waiters are notified in the order they started waiting. There are no
+/// spurious wakeups, but as with any condition variable the condition should
+/// still be checked in a loop, since another task may change it between the
+/// notification and the re-lock.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::condvar::Condvar;
+/// use futures::executor::block_on;
+/// use std::{sync::{Arc, Mutex}, thread};
+/// let pair = Arc::new((Mutex::new(false), Condvar::new()));
+/// let other = pair.clone();
+/// thread::spawn(move || {
+///     let (ready, condvar) = &*other;
+///     *ready.lock().unwrap() = true;
+///     condvar.notify_one();
+/// });
+/// let (ready, condvar) = &*pair;
+/// block_on(async {
+///     let mut guard = ready.lock().unwrap();
+///     while !*guard {
+///         guard = condvar.wait(ready, guard).await;
+///     }
+/// });
+/// ```
+#[derive(Debug, Default)]
+pub struct Condvar {
+    waiters: Mutex<VecDeque<Slot>>,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Release `guard` and wait for a notification, then lock `mutex` again.
+    /// `guard` must belong to `mutex`. The waiter is queued before the guard
+    /// is released, so a notification sent after this call is never lost.
+    ///
+    /// The re-lock blocks the current thread until the mutex is free, like
+    /// any use of a `std::sync::Mutex` from async code.
+    pub fn wait<'a, T>(&'a self, mutex: &'a Mutex<T>, guard: MutexGuard<'a, T>) -> Wait<'a, T> {
+        let slot: Slot = Arc::new(Mutex::new(Err(WakerState::Fresh)));
+        self.waiters.lock().unwrap().push_back(slot.clone());
+        drop(guard);
+        Wait {
+            condvar: self,
+            mutex,
+            slot,
+            done: false,
+        }
+    }
+
+    /// Wake the longest waiting task, if any.
+    pub fn notify_one(&self) {
+        let mut waiters = self.waiters.lock().unwrap();
+        if let Some(slot) = waiters.pop_front() {
+            notify(&slot);
+        }
+    }
+
+    /// Wake every waiting task.
+    pub fn notify_all(&self) {
+        let mut waiters = self.waiters.lock().unwrap();
+        for slot in waiters.drain(..) {
+            notify(&slot);
+        }
+    }
+}
+
+fn notify(slot: &Slot) {
+    if let Ok(waker) = std::mem::replace(&mut *slot.lock().unwrap(), Err(WakerState::Tainted)) {
+        waker.wake()
+    }
+}
+
+/// Future for [`Condvar::wait`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait<'a, T> {
+    condvar: &'a Condvar,
+    mutex: &'a Mutex<T>,
+    slot: Slot,
+    done: bool,
+}
+
+impl<T: Debug> Debug for Wait<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wait")
+            .field("slot", &self.slot)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl<'a, T> Future for Wait<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        {
+            let mut slot = self.slot.lock().unwrap();
+            match std::mem::replace(&mut *slot, Ok(cx.waker().clone())) {
+                Err(WakerState::Tainted) => *slot = Err(WakerState::Tainted),
+                _ => return Poll::Pending,
+            }
+        }
+        self.done = true;
+        Poll::Ready(self.mutex.lock().unwrap())
+    }
+}
+
+impl<T> Drop for Wait<'_, T> {
+    /// Leave the queue. A notification this waiter received but never acted
+    /// on is handed to the next waiter, so `notify_one` is not lost.
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut waiters = self.condvar.waiters.lock().unwrap();
+        match waiters
+            .iter()
+            .position(|slot| Arc::ptr_eq(slot, &self.slot))
+        {
+            Some(index) => {
+                waiters.remove(index);
+            }
+            None => {
+                if let Some(slot) = waiters.pop_front() {
+                    notify(&slot);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condvar;
+    use futures::executor::block_on;
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    // The guard is handed to `wait`, which releases it before suspending.
+    #[allow(clippy::await_holding_lock)]
+    fn test_condvar_notify_all() {
+        let state = Arc::new((Mutex::new(0), Condvar::new()));
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    let (count, condvar) = &*state;
+                    block_on(async {
+                        let mut guard = count.lock().unwrap();
+                        while *guard < 3 {
+                            guard = condvar.wait(count, guard).await;
+                        }
+                        *guard
+                    })
+                })
+            })
+            .collect();
+        let (count, condvar) = &*state;
+        for _ in 0..3 {
+            *count.lock().unwrap() += 1;
+            condvar.notify_all();
+        }
+        for task in tasks {
+            assert_eq!(3, task.join().expect("The task thread has panicked"));
+        }
+    }
+
+    #[test]
+    fn test_condvar_dropped_waiter_passes_notification() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let wait1 = condvar.wait(&mutex, mutex.lock().unwrap());
+        let wait2 = condvar.wait(&mutex, mutex.lock().unwrap());
+        condvar.notify_one();
+        drop(wait1);
+        drop(block_on(wait2));
+        assert!(condvar.waiters.lock().unwrap().is_empty());
+    }
+}
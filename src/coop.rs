@@ -0,0 +1,154 @@
+//! coop keeps a task that drains many ready consumers from hogging its
+//! executor. When a wake storm makes thousands of consumers ready at once, a
+//! task awaiting them one after another never sees `Poll::Pending`, so it
+//! runs until the last one while every other task on its thread waits.
+//!
+//! A [`Budget`] makes such a task yield after a number of ready values on any
+//! executor. With the `tokio` feature, consumers of the pair, poly, and
+//! channel flavors also spend tokio's own per-task budget, the way tokio's
+//! channels do, so inside a tokio runtime they yield without a `Budget`.
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Yields to the executor after every `limit` ready values.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, coop::Budget, pair::Producer};
+/// use futures::executor::block_on;
+/// let consumers: Vec<_> = (0..100)
+///     .map(|n| Producer::<u32>::resolved(n).1)
+///     .collect();
+/// let sum = block_on(async {
+///     let mut budget = Budget::new(16);
+///     let mut sum = 0;
+///     for consumer in consumers {
+///         budget.proceed().await;
+///         sum += consumer.await.unwrap();
+///     }
+///     sum
+/// });
+/// assert_eq!(4950, sum);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Budget {
+    limit: u32,
+    remaining: u32,
+}
+
+impl Budget {
+    /// Return a budget of `limit` values between yields. A limit of zero is
+    /// taken as one.
+    pub const fn new(limit: u32) -> Self {
+        let limit = if limit == 0 { 1 } else { limit };
+        Budget {
+            limit,
+            remaining: limit,
+        }
+    }
+
+    /// Spend one unit of the budget, or, once it is spent, refill it, wake the
+    /// task, and return `Poll::Pending` so the executor can run others first.
+    pub fn poll_proceed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.remaining == 0 {
+            self.remaining = self.limit;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.remaining -= 1;
+        Poll::Ready(())
+    }
+
+    /// Return a future that spends one unit of the budget, see
+    /// [`Budget::poll_proceed`].
+    pub fn proceed(&mut self) -> Proceed<'_> {
+        Proceed { budget: self }
+    }
+}
+
+/// Future for [`Budget::proceed`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Proceed<'a> {
+    budget: &'a mut Budget,
+}
+
+impl Future for Proceed<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.budget.poll_proceed(cx)
+    }
+}
+
+/// Poll a consumer with `poll`, spending a unit of tokio's task budget if it
+/// is ready, or yielding if the budget is spent. Without the `tokio` feature
+/// this only calls `poll`.
+#[cfg(any(feature = "pair", feature = "poly", feature = "channel"))]
+pub(crate) fn cooperate<O>(
+    cx: &mut Context<'_>,
+    poll: impl FnOnce(&mut Context<'_>) -> Poll<O>,
+) -> Poll<O> {
+    #[cfg(feature = "tokio")]
+    {
+        let progress = core::task::ready!(tokio::task::coop::poll_proceed(cx));
+        let output = poll(cx);
+        if output.is_ready() {
+            progress.made_progress();
+        }
+        output
+    }
+    #[cfg(not(feature = "tokio"))]
+    poll(cx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Budget;
+    use core::task::{Context, Poll};
+    use futures::task::noop_waker;
+
+    #[test]
+    fn test_budget_yields_once_spent() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut budget = Budget::new(3);
+        for _ in 0..3 {
+            assert_eq!(Poll::Ready(()), budget.poll_proceed(&mut cx));
+        }
+        assert_eq!(Poll::Pending, budget.poll_proceed(&mut cx));
+        assert_eq!(Poll::Ready(()), budget.poll_proceed(&mut cx));
+    }
+
+    #[cfg(all(feature = "tokio", feature = "pair"))]
+    #[test]
+    fn test_tokio_budget_interleaves_tasks() {
+        use crate::{pair::Producer, Promise};
+        use std::{
+            rc::Rc,
+            sync::atomic::{AtomicBool, Ordering},
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        let ran = Rc::new(AtomicBool::new(false));
+        let other = ran.clone();
+        let interleaved = local.block_on(&runtime, async move {
+            let consumers: Vec<_> = (0..1024).map(|n| Producer::<u32>::resolved(n).1).collect();
+            tokio::task::spawn_local(async move { other.store(true, Ordering::Relaxed) });
+            for consumer in consumers {
+                consumer.await.unwrap();
+                if ran.load(Ordering::Relaxed) {
+                    return true;
+                }
+            }
+            false
+        });
+        assert!(interleaved);
+    }
+}
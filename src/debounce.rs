@@ -0,0 +1,300 @@
+//! debounce shapes a burst of signals from a re-armable source, such as
+//! [`Notify::notified`](crate::notify::Notify::notified) or
+//! [`reusable::Producer::rearm`](crate::reusable::Producer::rearm), into fewer
+//! resolutions. The source is a factory that is called again after every
+//! signal to re-arm it, and the shaped signals are yielded as a `Stream`.
+//!
+//! - [`debounce`] waits until the source has been quiet for a period.
+//! - [`throttle`] lets at most one signal through per period.
+//!
+//! Both take an [`Edge`] that picks whether the first signal of a burst, the
+//! last one, or both are let through.
+use crate::{pair, timer};
+use futures_core::Stream;
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How many signals are taken from a source that is always ready before
+/// yielding to the executor.
+const BUDGET: usize = 64;
+
+/// Which signals of a burst are let through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// The first signal, as soon as it arrives.
+    Leading,
+    /// The last signal, once the period is over.
+    Trailing,
+    /// The first signal, and the last one too if there were more.
+    Both,
+}
+
+impl Edge {
+    fn leading(self) -> bool {
+        self != Edge::Trailing
+    }
+
+    fn trailing(self) -> bool {
+        self != Edge::Leading
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Debounce,
+    Throttle,
+}
+
+/// Yield a signal from `factory` once no other signal has arrived for
+/// `period`. The stream does not end.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, debounce::{debounce, Edge}, pair::{Consumer, Producer}};
+/// use futures::{executor::block_on, StreamExt};
+/// use std::time::Duration;
+/// let (producers, consumers): (Vec<_>, Vec<_>) = (0..3).map(|_| Producer::<u32>::new()).unzip();
+/// let mut consumers = consumers.into_iter();
+/// let mut saves = debounce(
+///     move || consumers.next().unwrap_or_else(Consumer::never),
+///     Duration::from_millis(10),
+///     Edge::Trailing,
+/// );
+/// for (keystroke, producer) in producers.into_iter().enumerate() {
+///     producer.resolve(keystroke as u32);
+/// }
+/// assert_eq!(Some(Ok(2)), block_on(saves.next()));
+/// ```
+pub fn debounce<F, Fut>(factory: F, period: Duration, edge: Edge) -> Debounce<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future + Unpin,
+{
+    Debounce(Shaper::new(Kind::Debounce, factory, period, edge))
+}
+
+/// Yield at most one signal from `factory` per `period`. The stream does not
+/// end.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, debounce::{throttle, Edge}, pair::{Consumer, Producer}};
+/// use futures::{executor::block_on, StreamExt};
+/// use std::time::Duration;
+/// let (producers, consumers): (Vec<_>, Vec<_>) = (0..3).map(|_| Producer::<u32>::new()).unzip();
+/// let mut consumers = consumers.into_iter();
+/// let mut scrolls = throttle(
+///     move || consumers.next().unwrap_or_else(Consumer::never),
+///     Duration::from_millis(10),
+///     Edge::Both,
+/// );
+/// for (offset, producer) in producers.into_iter().enumerate() {
+///     producer.resolve(offset as u32);
+/// }
+/// assert_eq!(Some(Ok(0)), block_on(scrolls.next()));
+/// assert_eq!(Some(Ok(2)), block_on(scrolls.next()));
+/// ```
+pub fn throttle<F, Fut>(factory: F, period: Duration, edge: Edge) -> Throttle<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future + Unpin,
+{
+    Throttle(Shaper::new(Kind::Throttle, factory, period, edge))
+}
+
+struct Shaper<F, Fut: Future> {
+    kind: Kind,
+    factory: F,
+    source: Fut,
+    period: Duration,
+    edge: Edge,
+    // The last signal of the current window, kept for the trailing edge.
+    latest: Option<Fut::Output>,
+    window: Option<pair::Consumer<()>>,
+}
+
+impl<F, Fut> Shaper<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future + Unpin,
+{
+    fn new(kind: Kind, mut factory: F, period: Duration, edge: Edge) -> Self {
+        Shaper {
+            kind,
+            source: factory(),
+            factory,
+            period,
+            edge,
+            latest: None,
+            window: None,
+        }
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Fut::Output>> {
+        for _ in 0..BUDGET {
+            if let Some(window) = self.window.as_mut() {
+                if Pin::new(window).poll(cx).is_ready() {
+                    self.window = None;
+                    if let Some(signal) = self.latest.take() {
+                        if self.kind == Kind::Throttle {
+                            // The trailing signal opens the next window.
+                            self.window = Some(timer::delay(self.period));
+                        }
+                        return Poll::Ready(Some(signal));
+                    }
+                }
+            }
+            let signal = match Pin::new(&mut self.source).poll(cx) {
+                Poll::Ready(signal) => signal,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.source = (self.factory)();
+            let idle = self.window.is_none();
+            if idle || self.kind == Kind::Debounce {
+                self.window = Some(timer::delay(self.period));
+            }
+            if idle && self.edge.leading() {
+                return Poll::Ready(Some(signal));
+            }
+            if self.edge.trailing() {
+                self.latest = Some(signal);
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<F, Fut: Future + Debug> Debug for Shaper<F, Fut>
+where
+    Fut::Output: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shaper")
+            .field("kind", &self.kind)
+            .field("source", &self.source)
+            .field("period", &self.period)
+            .field("edge", &self.edge)
+            .field("latest", &self.latest)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+/// Stream for [`debounce`].
+#[must_use = "streams do nothing unless polled"]
+pub struct Debounce<F, Fut: Future>(Shaper<F, Fut>);
+
+/// Stream for [`throttle`].
+#[must_use = "streams do nothing unless polled"]
+pub struct Throttle<F, Fut: Future>(Shaper<F, Fut>);
+
+impl<F, Fut: Future + Debug> Debug for Debounce<F, Fut>
+where
+    Fut::Output: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Debounce").field(&self.0).finish()
+    }
+}
+
+impl<F, Fut: Future + Debug> Debug for Throttle<F, Fut>
+where
+    Fut::Output: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Throttle").field(&self.0).finish()
+    }
+}
+
+impl<F, Fut: Future + Unpin> Unpin for Debounce<F, Fut> {}
+
+impl<F, Fut: Future + Unpin> Unpin for Throttle<F, Fut> {}
+
+impl<F, Fut> Stream for Debounce<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future + Unpin,
+{
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_next(cx)
+    }
+}
+
+impl<F, Fut> Stream for Throttle<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future + Unpin,
+{
+    type Item = Fut::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{debounce, throttle, Edge};
+    use crate::{pair, Promise};
+    use futures::{executor::block_on, FutureExt, StreamExt};
+    use std::time::Duration;
+
+    fn source(
+        n: usize,
+    ) -> (
+        Vec<pair::Producer<usize>>,
+        impl FnMut() -> pair::Consumer<usize>,
+    ) {
+        let (producers, consumers): (Vec<_>, Vec<_>) =
+            (0..n).map(|_| pair::Producer::new()).unzip();
+        let mut consumers = consumers.into_iter();
+        (producers, move || {
+            consumers.next().unwrap_or_else(pair::Consumer::never)
+        })
+    }
+
+    #[test]
+    fn test_debounce_leading_edge() {
+        let (producers, factory) = source(3);
+        let mut stream = debounce(factory, Duration::from_millis(10), Edge::Both);
+        let mut producers = producers.into_iter();
+        producers.next().unwrap().resolve(0);
+        assert_eq!(Some(Some(Ok(0))), stream.next().now_or_never());
+        producers.next().unwrap().resolve(1);
+        producers.next().unwrap().resolve(2);
+        assert_eq!(None, stream.next().now_or_never());
+        assert_eq!(Some(Ok(2)), block_on(stream.next()));
+
+        // A lone signal is not repeated on the trailing edge.
+        let (producers, factory) = source(1);
+        let mut stream = debounce(factory, Duration::from_millis(1), Edge::Both);
+        producers.into_iter().next().unwrap().resolve(7);
+        assert_eq!(Some(Ok(7)), block_on(stream.next()));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(None, stream.next().now_or_never());
+    }
+
+    #[test]
+    fn test_throttle_leading_drops_burst() {
+        let (producers, factory) = source(3);
+        let mut stream = throttle(factory, Duration::from_millis(20), Edge::Leading);
+        for (i, producer) in producers.into_iter().enumerate() {
+            producer.resolve(i);
+        }
+        assert_eq!(Some(Ok(0)), block_on(stream.next()));
+        // The rest of the burst is taken within the window and dropped.
+        assert_eq!(None, stream.next().now_or_never());
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(None, stream.next().now_or_never());
+    }
+}
@@ -0,0 +1,334 @@
+//! delay_queue settles many producers at their own deadlines, and
+//! [`TaskQueue`] runs many tasks at theirs. The entries are kept in deadline
+//! order, and only the earliest one is scheduled on the crate's timer thread
+//! at a time, so thousands of per-request timeouts cost one timer entry rather
+//! than one each.
+use crate::{
+    timer::{self, Task},
+    Promise,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    marker::PhantomData,
+    sync::{Arc, Mutex, Weak},
+    time::Instant,
+};
+
+/// What a queue does with an entry at its deadline.
+trait Due: Send + 'static {
+    fn run(self);
+}
+
+impl<P, T> Due for (P, T)
+where
+    P: Promise<T> + Send + 'static,
+    T: Send + 'static,
+{
+    fn run(self) {
+        self.0.resolve(self.1)
+    }
+}
+
+impl Due for Task {
+    fn run(self) {
+        self()
+    }
+}
+
+struct State<E> {
+    entries: BTreeMap<Key, E>,
+    next_id: u64,
+    // The deadline the timer thread will wake us for, if any.
+    armed: Option<Instant>,
+}
+
+/// Identifies an entry of a [`DelayQueue`] or [`TaskQueue`], to take it back
+/// out with `remove`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    deadline: Instant,
+    id: u64,
+}
+
+impl Key {
+    /// Return when the entry is settled.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+/// A queue of producers, each resolved with its own value at its deadline.
+/// To reject at the deadline, resolve with an `Err`. Dropping every handle to
+/// the queue drops the producers still in it.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Error, Promise, delay_queue::DelayQueue, pair::Producer};
+/// use futures::executor::block_on;
+/// use std::time::{Duration, Instant};
+/// let timeouts = DelayQueue::new();
+/// let (slow, slow_reply) = Producer::<Result<&str, Error>>::new();
+/// let (fast, fast_reply) = Producer::<Result<&str, Error>>::new();
+/// let deadline = Instant::now() + Duration::from_millis(10);
+/// timeouts.insert(slow, deadline, Err(Error::Timeout));
+/// let key = timeouts.insert(fast, deadline, Err(Error::Timeout));
+/// // The fast response arrives in time.
+/// let (fast, _) = timeouts.remove(key).unwrap();
+/// fast.resolve(Ok("pong"));
+/// assert_eq!(Ok(Ok("pong")), block_on(fast_reply));
+/// assert_eq!(Ok(Err(Error::Timeout)), block_on(slow_reply));
+/// ```
+pub struct DelayQueue<P, T> {
+    state: Arc<Mutex<State<(P, T)>>>,
+    _promise: PhantomData<fn(T)>,
+}
+
+impl<P, T> DelayQueue<P, T>
+where
+    P: Promise<T> + Send + 'static,
+    T: Send + 'static,
+{
+    pub fn new() -> Self {
+        DelayQueue {
+            state: new_state(),
+            _promise: PhantomData,
+        }
+    }
+
+    /// Resolve `producer` with `value` once `deadline` has passed. Entries
+    /// with the same deadline are settled in the order they were inserted.
+    pub fn insert(&self, producer: P, deadline: Instant, value: T) -> Key {
+        insert(&self.state, deadline, (producer, value))
+    }
+
+    /// Take an entry out of the queue before its deadline, returning its
+    /// producer and value. Returns `None` if the entry was already settled.
+    pub fn remove(&self, key: Key) -> Option<(P, T)> {
+        self.state.lock().unwrap().entries.remove(&key)
+    }
+
+    /// Return the number of entries waiting for their deadline.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A queue of tasks, each run at its deadline on the crate's timer. Unlike
+/// scheduling on the timer directly, a task taken out with
+/// [`remove`](Self::remove) is dropped at once rather than at its deadline.
+/// Dropping every handle to the queue drops the tasks still in it.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::delay_queue::TaskQueue;
+/// use std::{sync::mpsc, time::{Duration, Instant}};
+/// let tasks = TaskQueue::new();
+/// let (sender, ran) = mpsc::channel();
+/// let deadline = Instant::now() + Duration::from_millis(10);
+/// let canceled = tasks.insert(deadline, {
+///     let sender = sender.clone();
+///     move || sender.send("canceled").unwrap()
+/// });
+/// tasks.insert(deadline, move || sender.send("ran").unwrap());
+/// assert!(tasks.remove(canceled));
+/// assert_eq!(Ok("ran"), ran.recv());
+/// assert!(ran.recv().is_err());
+/// ```
+pub struct TaskQueue {
+    state: Arc<Mutex<State<Task>>>,
+}
+
+impl TaskQueue {
+    pub fn new() -> Self {
+        TaskQueue { state: new_state() }
+    }
+
+    /// Run `task` once `deadline` has passed. Tasks with the same deadline
+    /// run in the order they were inserted.
+    pub fn insert(&self, deadline: Instant, task: impl FnOnce() + Send + 'static) -> Key {
+        insert(&self.state, deadline, Box::new(task) as Task)
+    }
+
+    /// Take a task out of the queue before its deadline and drop it. Returns
+    /// false if the task has already run.
+    pub fn remove(&self, key: Key) -> bool {
+        self.state.lock().unwrap().entries.remove(&key).is_some()
+    }
+
+    /// Return the number of tasks waiting for their deadline.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn new_state<E>() -> Arc<Mutex<State<E>>> {
+    Arc::new(Mutex::new(State {
+        entries: BTreeMap::new(),
+        next_id: 0,
+        armed: None,
+    }))
+}
+
+fn insert<E: Due>(shared: &Arc<Mutex<State<E>>>, deadline: Instant, entry: E) -> Key {
+    let mut state = shared.lock().unwrap();
+    let key = Key {
+        deadline,
+        id: state.next_id,
+    };
+    state.next_id += 1;
+    state.entries.insert(key, entry);
+    arm(&mut state, shared);
+    key
+}
+
+/// Make sure the timer thread wakes us for the earliest entry. A timer entry
+/// that fires after an earlier one was armed finds nothing due and re-arms,
+/// which is harmless.
+fn arm<E: Due>(state: &mut State<E>, shared: &Arc<Mutex<State<E>>>) {
+    let Some(next) = state.entries.keys().next().map(Key::deadline) else {
+        return;
+    };
+    if state.armed.is_some_and(|armed| armed <= next) {
+        return;
+    }
+    state.armed = Some(next);
+    let weak = Arc::downgrade(shared);
+    timer::schedule(next, move || fire(next, weak));
+}
+
+fn fire<E: Due>(deadline: Instant, weak: Weak<Mutex<State<E>>>) {
+    let Some(shared) = weak.upgrade() else {
+        return;
+    };
+    let due = {
+        let mut state = shared.lock().unwrap();
+        if state.armed == Some(deadline) {
+            state.armed = None;
+        }
+        let now = Instant::now();
+        let pending = match state.entries.keys().find(|key| key.deadline > now) {
+            Some(&first_pending) => state.entries.split_off(&first_pending),
+            None => BTreeMap::new(),
+        };
+        let due = std::mem::replace(&mut state.entries, pending);
+        arm(&mut state, &shared);
+        due
+    };
+    for entry in due.into_values() {
+        entry.run();
+    }
+}
+
+impl<P, T> Clone for DelayQueue<P, T> {
+    fn clone(&self) -> Self {
+        DelayQueue {
+            state: self.state.clone(),
+            _promise: PhantomData,
+        }
+    }
+}
+
+impl<P, T> Default for DelayQueue<P, T>
+where
+    P: Promise<T> + Send + 'static,
+    T: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P, T> Debug for DelayQueue<P, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("DelayQueue")
+            .field("len", &state.entries.len())
+            .field("armed", &state.armed)
+            .finish()
+    }
+}
+
+impl Clone for TaskQueue {
+    fn clone(&self) -> Self {
+        TaskQueue {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for TaskQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("TaskQueue")
+            .field("len", &state.entries.len())
+            .field("armed", &state.armed)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "pair"))]
+mod tests {
+    use super::{DelayQueue, TaskQueue};
+    use crate::{pair, Promise};
+    use futures::executor::block_on;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_delay_queue_many_entries() {
+        let queue = DelayQueue::new();
+        let start = Instant::now();
+        let consumers: Vec<_> = (0..1000u64)
+            .map(|i| {
+                let (producer, consumer) = pair::Producer::<u64>::new();
+                // Inserted latest deadline first, so the timer is re-armed
+                // earlier and earlier.
+                let deadline = start + Duration::from_micros(20_000 - i * 10);
+                queue.insert(producer, deadline, i);
+                (deadline, consumer)
+            })
+            .collect();
+        for (i, (deadline, consumer)) in consumers.into_iter().enumerate() {
+            assert_eq!(Ok(i as u64), block_on(consumer));
+            assert!(Instant::now() >= deadline);
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_delay_queue_dropped() {
+        let queue = DelayQueue::new();
+        let (producer, consumer) = pair::Producer::<()>::new();
+        queue.insert(producer, Instant::now() + Duration::from_secs(60), ());
+        drop(queue);
+        assert_eq!(Err(crate::Error::ProducerDropped), block_on(consumer));
+    }
+
+    #[test]
+    fn test_task_queue_drops_removed_tasks() {
+        let tasks = TaskQueue::new();
+        let (producer, consumer) = pair::Producer::<()>::new();
+        let key = tasks.insert(Instant::now() + Duration::from_secs(60), move || {
+            producer.resolve(())
+        });
+        assert!(tasks.remove(key));
+        assert!(!tasks.remove(key));
+        assert_eq!(Err(crate::Error::ProducerDropped), block_on(consumer));
+    }
+}
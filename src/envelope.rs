@@ -0,0 +1,102 @@
+//! envelope attaches request context to a promise. [`Metadata`] such as a
+//! trace id, the caller, and a deadline is given to a promise when it is
+//! created, e.g. with [`pair::Producer::with_metadata`](crate::pair::Producer::with_metadata),
+//! and can be read through [`Envelope`] from either half before the promise
+//! settles. Combinators and registry consumers pass on the metadata of the
+//! promise they wrap, so the context travels with the request. Deadlines
+//! need the `std` feature.
+use alloc::{string::String, sync::Arc};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// The context of a request.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{envelope::{Envelope, Metadata}, ConsumerExt, Promise, pair::Producer};
+/// use std::time::{Duration, Instant};
+/// let metadata = Metadata::new()
+///     .with_trace_id("4bf92f35")
+///     .with_caller("checkout")
+///     .with_deadline(Instant::now() + Duration::from_secs(5));
+/// let (promise, consumer) = Producer::<u32>::with_metadata(metadata);
+/// let consumer = consumer.map(|n| n * 2);
+/// assert_eq!(Some("checkout"), promise.metadata().unwrap().caller());
+/// assert_eq!(Some("4bf92f35"), consumer.metadata().unwrap().trace_id());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    trace_id: Option<String>,
+    caller: Option<String>,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    pub fn with_caller(mut self, caller: impl Into<String>) -> Self {
+        self.caller = Some(caller.into());
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn trace_id(&self) -> Option<&str> {
+        self.trace_id.as_deref()
+    }
+
+    pub fn caller(&self) -> Option<&str> {
+        self.caller.as_deref()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Return the time left until the deadline, zero once it has passed.
+    #[cfg(feature = "std")]
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// A promise half, or a wrapper around one, that can carry [`Metadata`].
+pub trait Envelope {
+    /// Return the metadata given to the promise at creation, if any.
+    fn metadata(&self) -> Option<Arc<Metadata>>;
+}
+
+#[cfg(all(test, feature = "std", feature = "poly"))]
+mod tests {
+    use super::{Envelope, Metadata};
+    use crate::{poly, Promise};
+
+    #[test]
+    fn test_metadata_shared_by_clones() {
+        let (promise, consumer) = poly::Producer::<u32>::with_metadata(Metadata::new());
+        let other = consumer.clone();
+        assert!(std::sync::Arc::ptr_eq(
+            &consumer.metadata().unwrap(),
+            &other.metadata().unwrap()
+        ));
+        assert_eq!(None, other.metadata().unwrap().remaining());
+        let (_, plain) = poly::Producer::<u32>::new();
+        assert_eq!(None, plain.metadata());
+        drop(promise);
+    }
+}
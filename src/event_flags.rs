@@ -0,0 +1,139 @@
+//! event_flags implements the event-flags pattern: producers set bits of a
+//! mask, and consumers wait until any or all bits of a mask of theirs are set.
+//! Setting bits wakes every waiter through a [`Notify`], and each waiter checks
+//! its own condition again.
+use crate::notify::{Notified, Notify};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+/// A set of 32 event flags.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::event_flags::EventFlags;
+/// use futures::executor::block_on;
+/// use std::{sync::Arc, thread};
+/// const CONNECTED: u32 = 0b01;
+/// const AUTHENTICATED: u32 = 0b10;
+/// let flags = Arc::new(EventFlags::new());
+/// let other = flags.clone();
+/// let task1 = thread::spawn(move || block_on(other.wait_all(CONNECTED | AUTHENTICATED)));
+/// flags.set(CONNECTED);
+/// flags.set(AUTHENTICATED);
+/// assert_eq!(CONNECTED | AUTHENTICATED, task1.join().expect("The task1 thread has panicked."));
+/// ```
+#[derive(Debug, Default)]
+pub struct EventFlags {
+    bits: AtomicU32,
+    notify: Notify,
+}
+
+impl EventFlags {
+    /// Return flags with every bit clear. It is a `const fn`, so the flags
+    /// can be a `static`.
+    pub const fn new() -> Self {
+        EventFlags {
+            bits: AtomicU32::new(0),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Set the bits of `mask` and wake the waiters.
+    pub fn set(&self, mask: u32) {
+        self.bits.fetch_or(mask, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Clear the bits of `mask`. Waiters are not woken, since clearing bits
+    /// can not satisfy them.
+    pub fn clear(&self, mask: u32) {
+        self.bits.fetch_and(!mask, Ordering::SeqCst);
+    }
+
+    /// Return the bits that are set.
+    pub fn bits(&self) -> u32 {
+        self.bits.load(Ordering::SeqCst)
+    }
+
+    /// Return a future that resolves once any bit of `mask` is set, with the
+    /// bits of `mask` that are set at that moment.
+    pub fn wait_any(&self, mask: u32) -> Wait<'_> {
+        self.wait(mask, false)
+    }
+
+    /// Return a future that resolves once every bit of `mask` is set, with
+    /// `mask`.
+    pub fn wait_all(&self, mask: u32) -> Wait<'_> {
+        self.wait(mask, true)
+    }
+
+    fn wait(&self, mask: u32, all: bool) -> Wait<'_> {
+        Wait {
+            flags: self,
+            mask,
+            all,
+            notified: None,
+        }
+    }
+}
+
+/// Future for [`EventFlags::wait_any`] and [`EventFlags::wait_all`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait<'a> {
+    flags: &'a EventFlags,
+    mask: u32,
+    all: bool,
+    notified: Option<Notified<'a>>,
+}
+
+impl Future for Wait<'_> {
+    type Output = u32;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+        let this = &mut *self;
+        loop {
+            if this.notified.is_none() {
+                // Armed before the bits are read, so a `set` in between is
+                // not missed.
+                this.notified = Some(this.flags.notify.notified());
+                let set = this.flags.bits() & this.mask;
+                let done = if this.all { set == this.mask } else { set != 0 };
+                if done {
+                    this.notified = None;
+                    return Poll::Ready(set);
+                }
+            }
+            match Pin::new(this.notified.as_mut().unwrap()).poll(cx) {
+                Poll::Ready(()) => this.notified = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventFlags;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_event_flags_any_and_all() {
+        let flags = EventFlags::new();
+        let mut any = flags.wait_any(0b110);
+        let mut all = flags.wait_all(0b110);
+        assert_eq!(None, (&mut any).now_or_never());
+        flags.set(0b011);
+        assert_eq!(Some(0b010), (&mut any).now_or_never());
+        assert_eq!(None, (&mut all).now_or_never());
+        flags.clear(0b001);
+        flags.set(0b100);
+        assert_eq!(0b110, block_on(all));
+        assert_eq!(0b110, flags.bits());
+    }
+}
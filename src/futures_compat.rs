@@ -0,0 +1,188 @@
+//! futures_compat bridges pair promises and `futures::channel::oneshot`, for
+//! libraries that already speak futures-channel. Neither side can drive the
+//! other, so each conversion returns the new handle along with a forwarding
+//! future, which the caller spawns on whatever executor it uses. Cancellation
+//! crosses the bridge both ways: dropping the receiving end closes the
+//! sending end on the other side, and dropping the sending end fails the
+//! receiving end, so the forwarder then finishes too.
+//!
+//! ```
+//! use promise_out::{futures_compat, pair, Promise};
+//! use futures::{channel::oneshot, executor::block_on, future::join};
+//! let (sender, receiver) = oneshot::channel();
+//! let (consumer, forward) = futures_compat::consumer_from_receiver::<u32>(receiver);
+//! sender.send(1).unwrap();
+//! assert_eq!(((), Ok(1)), block_on(join(forward, consumer)));
+//! let (promise, consumer) = pair::Producer::<u32>::new();
+//! let (receiver, forward) = futures_compat::receiver_from_consumer(consumer);
+//! promise.resolve(2);
+//! assert_eq!(((), Ok(2)), block_on(join(forward, receiver)));
+//! ```
+use crate::{
+    pair::{Closed, Consumer, Producer},
+    Promise,
+};
+use core::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures_channel::oneshot::{self, Receiver, Sender};
+
+/// Return a consumer of what is sent on `receiver`, and the future that
+/// forwards it. The consumer fails with
+/// [`Error::ProducerDropped`](crate::Error::ProducerDropped) if the sender is
+/// dropped without sending.
+pub fn consumer_from_receiver<T>(receiver: Receiver<T>) -> (Consumer<T>, ToProducer<T>) {
+    let (producer, consumer) = Producer::new();
+    (consumer, ToProducer::new(receiver, producer))
+}
+
+/// Return a sender whose value resolves `producer`, and the future that
+/// forwards it. Dropping the sender without sending drops the producer.
+pub fn sender_from_producer<T>(producer: Producer<T>) -> (Sender<T>, ToProducer<T>) {
+    let (sender, receiver) = oneshot::channel();
+    (sender, ToProducer::new(receiver, producer))
+}
+
+/// Return a receiver of the value `consumer` resolves with, and the future
+/// that forwards it. The receiver is canceled if the promise fails.
+pub fn receiver_from_consumer<T>(consumer: Consumer<T>) -> (Receiver<T>, ToSender<T>) {
+    let (sender, receiver) = oneshot::channel();
+    (receiver, ToSender::new(consumer, sender))
+}
+
+/// Return a producer whose value is sent on `sender`, and the future that
+/// forwards it. The producer sees the promise closed once the receiver is
+/// canceled.
+pub fn producer_from_sender<T>(sender: Sender<T>) -> (Producer<T>, ToSender<T>) {
+    let (producer, consumer) = Producer::new();
+    (producer, ToSender::new(consumer, sender))
+}
+
+/// Future that forwards from a oneshot [`Receiver`] to a [`Producer`]. It
+/// finishes once the value is forwarded, the sender is dropped, or the
+/// producer's consumer is.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ToProducer<T> {
+    // `None` once finished.
+    inner: Option<(Receiver<T>, Producer<T>, Closed<T>)>,
+}
+
+impl<T> ToProducer<T> {
+    fn new(receiver: Receiver<T>, producer: Producer<T>) -> Self {
+        let closed = producer.closed();
+        ToProducer {
+            inner: Some((receiver, producer, closed)),
+        }
+    }
+}
+
+impl<T: Debug> Debug for ToProducer<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ToProducer")
+            .field("finished", &self.inner.is_none())
+            .finish()
+    }
+}
+
+impl<T> Future for ToProducer<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some((receiver, _, closed)) = &mut self.inner else {
+            return Poll::Ready(());
+        };
+        // Dropping the receiver cancels the sender.
+        if Pin::new(closed).poll(cx).is_ready() {
+            self.inner = None;
+            return Poll::Ready(());
+        }
+        match Pin::new(receiver).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            // A canceled receiver drops the producer, which fails the consumer.
+            Poll::Ready(value) => {
+                let (_, producer, _) = self.inner.take().unwrap();
+                if let Ok(value) = value {
+                    producer.resolve(value)
+                }
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+/// Future that forwards from a [`Consumer`] to a oneshot [`Sender`]. It
+/// finishes once the value is forwarded, the producer is dropped, or the
+/// receiver is.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ToSender<T> {
+    // `None` once finished.
+    inner: Option<(Consumer<T>, Sender<T>)>,
+}
+
+impl<T> ToSender<T> {
+    fn new(consumer: Consumer<T>, sender: Sender<T>) -> Self {
+        ToSender {
+            inner: Some((consumer, sender)),
+        }
+    }
+}
+
+impl<T: Debug> Debug for ToSender<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ToSender")
+            .field("finished", &self.inner.is_none())
+            .finish()
+    }
+}
+
+impl<T> Future for ToSender<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some((consumer, sender)) = &mut self.inner else {
+            return Poll::Ready(());
+        };
+        // Dropping the consumer closes the producer.
+        if sender.poll_canceled(cx).is_ready() {
+            self.inner = None;
+            return Poll::Ready(());
+        }
+        match Pin::new(consumer).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            // A failed promise drops the sender, which cancels the receiver.
+            Poll::Ready(value) => {
+                let (_, sender) = self.inner.take().unwrap();
+                if let Ok(value) = value {
+                    let _ = sender.send(value);
+                }
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{producer_from_sender, sender_from_producer};
+    use crate::{pair::Producer, Error, Promise};
+    use futures::{channel::oneshot, executor::block_on, future::join, FutureExt};
+
+    #[test]
+    fn test_cancellation_crosses_the_bridge() {
+        let (sender, receiver) = oneshot::channel::<u32>();
+        let (promise, mut forward) = producer_from_sender(sender);
+        assert_eq!(None, (&mut forward).now_or_never());
+        drop(receiver);
+        assert_eq!(((), ()), block_on(join(forward, promise.closed())));
+        let (promise, consumer) = Producer::<u32>::new();
+        let (sender, forward) = sender_from_producer(promise);
+        drop(sender);
+        assert_eq!(
+            ((), Err(Error::ProducerDropped)),
+            block_on(join(forward, consumer))
+        );
+    }
+}
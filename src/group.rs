@@ -0,0 +1,224 @@
+//! group implements structured aggregation for fan-out jobs. Children are
+//! minted from a [`Group`] one at a time, and the group's consumer resolves
+//! with how each of them settled, once all of them have, or as soon as a
+//! configured number have. Unlike a [`Collector`](crate::collector::Collector),
+//! a child can also fail, and its failure is reported rather than left out.
+use crate::{join::Settled, pair, Error, Promise};
+use std::sync::{Arc, Mutex};
+
+/// How each child settled, by the index of the child in minting order.
+pub type Results<T> = Vec<(usize, Settled<T>)>;
+
+#[derive(Debug)]
+struct State<T> {
+    // One slot per minted child, in minting order.
+    results: Vec<Option<Settled<T>>>,
+    settled: usize,
+    // False once the group is dropped and no more children can be minted.
+    minting: bool,
+    count: Option<usize>,
+    parent: Option<pair::Producer<Results<T>>>,
+}
+
+impl<T> State<T> {
+    fn settle(state: &Mutex<Self>, index: usize, settled: Settled<T>) {
+        let finished = {
+            let mut state = state.lock().unwrap();
+            state.results[index] = Some(settled);
+            state.settled += 1;
+            state.finish()
+        };
+        Self::resolve(finished);
+    }
+
+    /// Take the parent and the results if the group is complete.
+    fn finish(&mut self) -> Option<(pair::Producer<Results<T>>, Results<T>)> {
+        let reached = self.count.is_some_and(|count| self.settled >= count);
+        let exhausted = !self.minting && self.settled == self.results.len();
+        if !reached && !exhausted {
+            return None;
+        }
+        let parent = self.parent.take()?;
+        let results = self
+            .results
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, result)| Some((index, result.take()?)))
+            .collect();
+        Some((parent, results))
+    }
+
+    fn resolve(finished: Option<(pair::Producer<Results<T>>, Results<T>)>) {
+        if let Some((parent, results)) = finished {
+            parent.resolve(results);
+        }
+    }
+}
+
+/// Mints child promises whose results are reported together.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{group::Group, join::Settled, Error};
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let (group, parent) = Group::new();
+/// for job in 0..3 {
+///     let child = group.child();
+///     thread::spawn(move || match job {
+///         1 => child.reject(Error::Timeout),
+///         _ => child.resolve(job * 10),
+///     });
+/// }
+/// drop(group);
+/// assert_eq!(
+///     vec![
+///         (0, Settled::Fulfilled(0)),
+///         (1, Settled::Rejected(Error::Timeout)),
+///         (2, Settled::Fulfilled(20)),
+///     ],
+///     block_on(parent).unwrap()
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Group<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// A child promise minted by a [`Group`]. Dropping it without settling
+/// reports it as [`Settled::Dropped`].
+#[derive(Debug)]
+pub struct Child<T> {
+    state: Arc<Mutex<State<T>>>,
+    index: usize,
+    settled: bool,
+}
+
+impl<T> Group<T> {
+    /// Return a group and the consumer of its results, which resolves once
+    /// the group has been dropped and every child has settled.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Self, pair::Consumer<Results<T>>) {
+        Self::with_state(None)
+    }
+
+    /// Return a group whose consumer resolves as soon as `count` children
+    /// have settled, with the results of those. It resolves with fewer if the
+    /// group is dropped and every child settles before `count` is reached.
+    ///
+    /// ```
+    /// use promise_out::{group::Group, join::Settled};
+    /// use futures::executor::block_on;
+    /// let (group, parent) = Group::with_count(2);
+    /// let children: Vec<_> = (0..4).map(|_| group.child()).collect();
+    /// for (index, child) in children.into_iter().enumerate().rev().take(2) {
+    ///     child.resolve(index);
+    /// }
+    /// assert_eq!(
+    ///     vec![(2, Settled::Fulfilled(2)), (3, Settled::Fulfilled(3))],
+    ///     block_on(parent).unwrap()
+    /// );
+    /// ```
+    pub fn with_count(count: usize) -> (Self, pair::Consumer<Results<T>>) {
+        Self::with_state(Some(count))
+    }
+
+    fn with_state(count: Option<usize>) -> (Self, pair::Consumer<Results<T>>) {
+        let (parent, consumer) = pair::Producer::new();
+        let mut state = State {
+            results: vec![],
+            settled: 0,
+            minting: true,
+            count,
+            parent: Some(parent),
+        };
+        State::resolve(state.finish());
+        let group = Group {
+            state: Arc::new(Mutex::new(state)),
+        };
+        (group, consumer)
+    }
+
+    /// Mint another child.
+    pub fn child(&self) -> Child<T> {
+        let mut state = self.state.lock().unwrap();
+        state.results.push(None);
+        Child {
+            state: self.state.clone(),
+            index: state.results.len() - 1,
+            settled: false,
+        }
+    }
+
+    /// Return how many minted children have not settled yet.
+    pub fn pending(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.results.len() - state.settled
+    }
+}
+
+impl<T> Drop for Group<T> {
+    /// Stop minting; the results are reported once the children settle.
+    fn drop(&mut self) {
+        let finished = {
+            let mut state = self.state.lock().unwrap();
+            state.minting = false;
+            state.finish()
+        };
+        State::resolve(finished);
+    }
+}
+
+impl<T> Child<T> {
+    /// Return the index of the child in minting order, as in the results.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn resolve(self, value: T) {
+        self.settle(Settled::Fulfilled(value))
+    }
+
+    pub fn reject(self, error: Error) {
+        self.settle(Settled::from(Err(error)))
+    }
+
+    fn settle(mut self, settled: Settled<T>) {
+        self.settled = true;
+        State::settle(&self.state, self.index, settled);
+    }
+}
+
+impl<T> Drop for Child<T> {
+    fn drop(&mut self) {
+        if !self.settled {
+            State::settle(&self.state, self.index, Settled::Dropped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Group;
+    use crate::join::Settled;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_group_waits_for_every_child() {
+        let (group, mut parent) = Group::<&str>::new();
+        let first = group.child();
+        let dropped = group.child();
+        assert_eq!(2, group.pending());
+        drop(dropped);
+        drop(group);
+        assert_eq!(None, (&mut parent).now_or_never());
+        first.resolve("a");
+        assert_eq!(
+            Ok(vec![(0, Settled::Fulfilled("a")), (1, Settled::Dropped)]),
+            block_on(parent)
+        );
+        let (_, empty) = Group::<()>::with_count(0);
+        assert_eq!(Ok(vec![]), block_on(empty));
+    }
+}
@@ -0,0 +1,220 @@
+//! heapless implements a variant of the [`poly`](crate::poly) flavor that needs
+//! no heap, for microcontrollers. Its state lives in a [`Slot`] owned by the
+//! caller, typically a `static`, and the wakers of its consumers are kept in an
+//! inline array, so at most `N` consumers can exist at a time. Consumers return
+//! a clone of the value rather than an `Arc`.
+use crate::{
+    lock::{DefaultRawMutex, RawMutex},
+    Error,
+};
+use core::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use lock_api::Mutex;
+
+/// The storage of a promise with at most `N` consumers. A slot is split into
+/// a producer and a first consumer, and can be split again once every handle
+/// of the previous promise is gone.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::heapless::Slot;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// static READING: Slot<u16, 2> = Slot::new();
+/// let (promise, consumer) = READING.split().unwrap();
+/// let consumer2 = consumer.try_clone().unwrap();
+/// assert!(consumer.try_clone().is_none());
+/// thread::spawn(move || promise.resolve(512));
+/// assert_eq!(Ok(512), block_on(consumer));
+/// assert_eq!(Ok(512), block_on(consumer2));
+/// ```
+pub struct Slot<T, const N: usize, R: RawMutex = DefaultRawMutex> {
+    state: Mutex<R, State<T, N>>,
+}
+
+impl<T: Debug, const N: usize, R: RawMutex> Debug for Slot<T, N, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Slot").field("state", &self.state).finish()
+    }
+}
+
+#[derive(Debug)]
+struct State<T, const N: usize> {
+    value: Option<T>,
+    failed: bool,
+    producer: bool,
+    // One entry per consumer; `None` is free, `Some(None)` a consumer that has
+    // not been polled.
+    wakers: [Option<Option<Waker>>; N],
+}
+
+impl<T, const N: usize> State<T, N> {
+    fn is_idle(&self) -> bool {
+        !self.producer && self.wakers.iter().all(Option::is_none)
+    }
+
+    fn claim(&mut self) -> Option<usize> {
+        let index = self.wakers.iter().position(Option::is_none)?;
+        self.wakers[index] = Some(None);
+        Some(index)
+    }
+
+    fn wake(&mut self) {
+        for waker in self.wakers.iter_mut().flatten() {
+            if let Some(waker) = waker.take() {
+                waker.wake()
+            }
+        }
+    }
+}
+
+impl<T, const N: usize, R: RawMutex> Slot<T, N, R> {
+    pub const fn new() -> Self {
+        Slot {
+            state: Mutex::const_new(
+                R::INIT,
+                State {
+                    value: None,
+                    failed: false,
+                    producer: false,
+                    wakers: [const { None }; N],
+                },
+            ),
+        }
+    }
+
+    /// Return the producer and first consumer of a new promise, or `None` if
+    /// a handle of the previous promise is still alive or `N` is zero.
+    pub fn split(&self) -> Option<(Producer<'_, T, N, R>, Consumer<'_, T, N, R>)> {
+        let mut state = self.state.lock();
+        if !state.is_idle() {
+            return None;
+        }
+        state.value = None;
+        state.failed = false;
+        let index = state.claim()?;
+        state.producer = true;
+        Some((Producer { slot: self }, Consumer { slot: self, index }))
+    }
+}
+
+impl<T, const N: usize, R: RawMutex> Default for Slot<T, N, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The producer of a promise kept in a [`Slot`].
+pub struct Producer<'a, T, const N: usize, R: RawMutex = DefaultRawMutex> {
+    slot: &'a Slot<T, N, R>,
+}
+
+impl<T: Debug, const N: usize, R: RawMutex> Debug for Producer<'_, T, N, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("slot", &self.slot)
+            .finish()
+    }
+}
+
+impl<T, const N: usize, R: RawMutex> Producer<'_, T, N, R> {
+    /// Resolve the promise, waking every consumer.
+    pub fn resolve(self, value: T) {
+        let mut state = self.slot.state.lock();
+        state.value = Some(value);
+        state.wake();
+    }
+}
+
+impl<T, const N: usize, R: RawMutex> Drop for Producer<'_, T, N, R> {
+    fn drop(&mut self) {
+        let mut state = self.slot.state.lock();
+        state.producer = false;
+        if state.value.is_none() {
+            state.failed = true;
+            state.wake();
+        }
+    }
+}
+
+/// A consumer of a promise kept in a [`Slot`]. It returns a clone of the
+/// value.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer<'a, T, const N: usize, R: RawMutex = DefaultRawMutex> {
+    slot: &'a Slot<T, N, R>,
+    index: usize,
+}
+
+impl<T: Debug, const N: usize, R: RawMutex> Debug for Consumer<'_, T, N, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("slot", &self.slot)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<T, const N: usize, R: RawMutex> Consumer<'_, T, N, R> {
+    /// Return another consumer of the promise, or `None` if `N` consumers
+    /// already exist.
+    pub fn try_clone(&self) -> Option<Self> {
+        let index = self.slot.state.lock().claim()?;
+        Some(Consumer {
+            slot: self.slot,
+            index,
+        })
+    }
+}
+
+impl<T: Clone, const N: usize, R: RawMutex> Future for Consumer<'_, T, N, R> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock();
+        if let Some(value) = &state.value {
+            return Poll::Ready(Ok(value.clone()));
+        }
+        if state.failed {
+            return Poll::Ready(Err(Error::ProducerDropped));
+        }
+        let waker = state.wakers[self.index].get_or_insert(None);
+        match waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => *waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T, const N: usize, R: RawMutex> Drop for Consumer<'_, T, N, R> {
+    fn drop(&mut self) {
+        self.slot.state.lock().wakers[self.index] = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slot;
+    use crate::Error;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_slot_is_reused_once_idle() {
+        let slot = Slot::<u32, 1>::new();
+        let (promise, mut consumer) = slot.split().unwrap();
+        assert!(slot.split().is_none());
+        assert_eq!(None, (&mut consumer).now_or_never());
+        drop(promise);
+        assert_eq!(Err(Error::ProducerDropped), block_on(&mut consumer));
+        drop(consumer);
+        let (promise, consumer) = slot.split().unwrap();
+        promise.resolve(7);
+        assert_eq!(Ok(7), block_on(consumer));
+        assert!(Slot::<u32, 0>::new().split().is_none());
+    }
+}
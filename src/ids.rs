@@ -0,0 +1,107 @@
+//! ids allocates request ids, e.g. for the keys of a
+//! [`Registry`](crate::registry::Registry) that hands them out itself with
+//! [`register_new`](crate::registry::Registry::register_new). [`Sequential`]
+//! counts up and wraps around at a chosen maximum, so it also fits protocols
+//! with narrow id fields; [`Uuids`] draws random UUIDs and needs the `uuid`
+//! feature. Both can be shared between threads.
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A source of ids. An id may come around again, e.g. after a counter wraps,
+/// so users check that it is not still in use.
+pub trait IdGenerator {
+    type Id;
+
+    fn next_id(&self) -> Self::Id;
+}
+
+/// Counts up from zero and wraps around to zero after `max`.
+///
+/// ```
+/// use promise_out::ids::{IdGenerator, Sequential};
+/// let ids = Sequential::bounded(2);
+/// let drawn: Vec<u64> = (0..4).map(|_| ids.next_id()).collect();
+/// assert_eq!(vec![0, 1, 2, 0], drawn);
+/// ```
+#[derive(Debug)]
+pub struct Sequential {
+    next: AtomicU64,
+    max: u64,
+}
+
+impl Sequential {
+    pub fn new() -> Self {
+        Self::bounded(u64::MAX)
+    }
+
+    /// Wrap around after `max`, e.g. `u32::MAX as u64` for 32-bit ids.
+    pub fn bounded(max: u64) -> Self {
+        Sequential {
+            next: AtomicU64::new(0),
+            max,
+        }
+    }
+}
+
+impl Default for Sequential {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdGenerator for Sequential {
+    type Id = u64;
+
+    fn next_id(&self) -> u64 {
+        let max = self.max;
+        self.next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| {
+                Some(if id >= max { 0 } else { id + 1 })
+            })
+            .expect("the update always succeeds")
+    }
+}
+
+/// Draws random (version 4) UUIDs.
+///
+/// ```
+/// use promise_out::ids::{IdGenerator, Uuids};
+/// assert_ne!(Uuids.next_id(), Uuids.next_id());
+/// ```
+#[cfg(feature = "uuid")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uuids;
+
+#[cfg(feature = "uuid")]
+impl IdGenerator for Uuids {
+    type Id = uuid::Uuid;
+
+    fn next_id(&self) -> uuid::Uuid {
+        uuid::Uuid::new_v4()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdGenerator, Sequential};
+    use std::{collections::HashSet, sync::Arc, thread};
+
+    #[test]
+    fn test_sequential_unique_across_threads() {
+        let ids = Arc::new(Sequential::new());
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let ids = ids.clone();
+                thread::spawn(move || (0..1000).map(|_| ids.next_id()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut drawn = HashSet::new();
+        for task in tasks {
+            drawn.extend(task.join().expect("The task thread has panicked"));
+        }
+        assert_eq!(4000, drawn.len());
+        assert_eq!(4000, ids.next_id());
+    }
+}
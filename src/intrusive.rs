@@ -0,0 +1,145 @@
+//! intrusive keeps the wakers of waiting futures in a doubly linked list whose
+//! nodes live inside the futures themselves, so registering a waiter never
+//! allocates however many there are. A future links its [`Node`] on its first
+//! pending poll, once it is pinned, and unlinks it when dropped. The list and
+//! every linked node are only touched with the lock of the promise held.
+use core::{marker::PhantomPinned, ptr::NonNull, task::Waker};
+
+/// The entry of a waiting future in a [`WaitList`].
+#[derive(Debug, Default)]
+pub(crate) struct Node {
+    waker: Option<Waker>,
+    prev: Option<NonNull<Node>>,
+    next: Option<NonNull<Node>>,
+    linked: bool,
+    _pinned: PhantomPinned,
+}
+
+impl Node {
+    pub(crate) const fn new() -> Self {
+        Node {
+            waker: None,
+            prev: None,
+            next: None,
+            linked: false,
+            _pinned: PhantomPinned,
+        }
+    }
+
+    /// Store the waker to wake, keeping the current one if it would wake the
+    /// same task.
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        match &self.waker {
+            Some(current) if current.will_wake(waker) => {}
+            _ => self.waker = Some(waker.clone()),
+        }
+    }
+
+    pub(crate) fn is_linked(&self) -> bool {
+        self.linked
+    }
+}
+
+/// A list of the nodes of waiting futures.
+#[derive(Debug, Default)]
+pub(crate) struct WaitList {
+    head: Option<NonNull<Node>>,
+    tail: Option<NonNull<Node>>,
+}
+
+// The nodes are only reached through the list with the lock that guards it
+// held, as are the wakers they own.
+unsafe impl Send for WaitList {}
+unsafe impl Sync for WaitList {}
+
+impl WaitList {
+    pub(crate) const fn new() -> Self {
+        WaitList {
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Link `node` at the back of the list.
+    ///
+    /// # Safety
+    ///
+    /// `node` must not be linked, must stay in place until it is removed or
+    /// woken, and must only be accessed with the list's lock held meanwhile.
+    pub(crate) unsafe fn push_back(&mut self, mut node: NonNull<Node>) {
+        let entry = unsafe { node.as_mut() };
+        debug_assert!(!entry.linked);
+        entry.prev = self.tail;
+        entry.next = None;
+        entry.linked = true;
+        match self.tail {
+            Some(mut tail) => unsafe { tail.as_mut().next = Some(node) },
+            None => self.head = Some(node),
+        }
+        self.tail = Some(node);
+    }
+
+    /// Unlink `node` if it is linked.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be valid, and linked into this list if it is linked at all.
+    pub(crate) unsafe fn remove(&mut self, mut node: NonNull<Node>) {
+        let entry = unsafe { node.as_mut() };
+        if !entry.linked {
+            return;
+        }
+        match entry.prev {
+            Some(mut prev) => unsafe { prev.as_mut().next = entry.next },
+            None => self.head = entry.next,
+        }
+        match entry.next {
+            Some(mut next) => unsafe { next.as_mut().prev = entry.prev },
+            None => self.tail = entry.prev,
+        }
+        entry.prev = None;
+        entry.next = None;
+        entry.linked = false;
+    }
+
+    /// Unlink every node and pass its waker to `wake`.
+    pub(crate) fn drain(&mut self, mut wake: impl FnMut(Waker)) {
+        while let Some(mut node) = self.head {
+            // Safety: linked nodes stay valid until they are unlinked, which
+            // needs the lock the caller holds.
+            let entry = unsafe { node.as_mut() };
+            self.head = entry.next;
+            entry.prev = None;
+            entry.next = None;
+            entry.linked = false;
+            if let Some(waker) = entry.waker.take() {
+                wake(waker)
+            }
+        }
+        self.tail = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Node, WaitList};
+    use core::ptr::NonNull;
+    use futures::task::noop_waker;
+
+    #[test]
+    fn test_remove_and_drain() {
+        let mut nodes = [Node::new(), Node::new(), Node::new()];
+        let mut list = WaitList::new();
+        for node in nodes.iter_mut() {
+            node.register(&noop_waker());
+            unsafe { list.push_back(NonNull::from(node)) };
+        }
+        unsafe { list.remove(NonNull::from(&mut nodes[1])) };
+        assert!(!nodes[1].is_linked());
+        assert!(nodes[1].waker.is_some());
+        list.drain(|waker| waker.wake());
+        assert!(list.head.is_none() && list.tail.is_none());
+        assert!(nodes.iter().all(|node| !node.is_linked()));
+        assert!(nodes[0].waker.is_none() && nodes[2].waker.is_none());
+    }
+}
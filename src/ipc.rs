@@ -0,0 +1,450 @@
+//! ipc settles promises across processes over a Unix domain socket. The
+//! awaiting process holds a [`Client`] and gets a registry consumer for every
+//! request it sends; the resolving process accepts [`Connection`]s from a
+//! [`Listener`] and settles each request through its [`Reply`], which sends
+//! the response back by id. Frames are length-prefixed and encoded with a
+//! [`Codec`].
+//!
+//! A client reconnects on the next request after losing its connection, and
+//! every request still pending on the lost connection is rejected rather than
+//! left waiting. Only Unix domain sockets are supported; the module is not
+//! built on other platforms.
+use crate::{
+    codec::{Codec, Frame, Payload},
+    registry::{Consumer, Registry},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    io::{self, Read, Write},
+    marker::PhantomData,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// The reason given to requests pending on a lost connection.
+pub const DISCONNECTED: &str = "disconnected";
+
+/// The largest frame, in bytes, that is sent or accepted. The length prefix
+/// comes from the peer, so a larger one is an error rather than an
+/// allocation of up to 4 GiB.
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+
+fn write_frame(stream: &mut UnixStream, bytes: &[u8]) -> io::Result<()> {
+    if bytes.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame too large",
+        ));
+    }
+    let len = bytes.len() as u32;
+    let mut buf = Vec::with_capacity(4 + bytes.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(bytes);
+    stream.write_all(&buf)
+}
+
+/// Return the next frame, or `None` once the peer has closed the stream.
+fn read_frame(stream: &mut UnixStream) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+fn invalid_data(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// One connection of a client, and the ids of the requests sent on it. The
+/// ids are `None` once the connection is lost.
+#[derive(Debug)]
+struct Link {
+    stream: Mutex<UnixStream>,
+    pending: Mutex<Option<HashSet<u64>>>,
+}
+
+struct ClientInner<C, Resp> {
+    path: PathBuf,
+    codec: C,
+    registry: Registry<u64, Result<Resp, String>>,
+    link: Mutex<Option<Arc<Link>>>,
+    next_id: Mutex<u64>,
+}
+
+/// Sends requests of type `Req` to a [`Listener`] and awaits its responses of
+/// type `Resp`. Clones share the connection.
+pub struct Client<Req, Resp, C> {
+    inner: Arc<ClientInner<C, Resp>>,
+    phantom: PhantomData<fn(Req)>,
+}
+
+impl<Req, Resp, C> Client<Req, Resp, C>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + Send + Sync + 'static,
+    C: Codec + Send + Sync + 'static,
+{
+    /// Connect to the listener at `path`.
+    pub fn connect(path: impl AsRef<Path>, codec: C) -> io::Result<Self> {
+        let client = Client {
+            inner: Arc::new(ClientInner {
+                path: path.as_ref().to_owned(),
+                codec,
+                registry: Registry::new(),
+                link: Mutex::new(None),
+                next_id: Mutex::new(0),
+            }),
+            phantom: PhantomData,
+        };
+        client.link()?;
+        Ok(client)
+    }
+
+    /// Send `request` and return the consumer of its response: the value the
+    /// peer resolved, or the reason it rejected. The reason is
+    /// [`DISCONNECTED`] if the connection is lost first. Reconnects if the
+    /// previous connection was lost, and fails if that or sending fails.
+    pub fn call(&self, request: &Req) -> io::Result<Consumer<Result<Resp, String>>> {
+        let id = {
+            let mut next_id = self.inner.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        let frame = Frame {
+            id,
+            payload: Payload::Resolve(request),
+        };
+        let bytes = self.inner.codec.encode(&frame).map_err(invalid_data)?;
+        let link = self.link()?;
+        let consumer = self
+            .inner
+            .registry
+            .register(id)
+            .expect("client ids are unique");
+        match link.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id),
+            None => {
+                self.inner.registry.remove(&id);
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+        };
+        if let Err(e) = write_frame(&mut link.stream.lock().unwrap(), &bytes) {
+            // The reader notices the broken stream and rejects the rest.
+            if let Some(pending) = link.pending.lock().unwrap().as_mut() {
+                pending.remove(&id);
+            }
+            self.inner.registry.remove(&id);
+            return Err(e);
+        }
+        Ok(consumer)
+    }
+
+    /// Return the number of requests waiting for their response.
+    pub fn pending(&self) -> usize {
+        self.inner.registry.len()
+    }
+
+    /// Return the current connection, connecting if there is none.
+    fn link(&self) -> io::Result<Arc<Link>> {
+        let mut current = self.inner.link.lock().unwrap();
+        if let Some(link) = current.as_ref() {
+            if link.pending.lock().unwrap().is_some() {
+                return Ok(link.clone());
+            }
+        }
+        let stream = UnixStream::connect(&self.inner.path)?;
+        let mut reader = stream.try_clone()?;
+        let link = Arc::new(Link {
+            stream: Mutex::new(stream),
+            pending: Mutex::new(Some(HashSet::new())),
+        });
+        *current = Some(link.clone());
+        let inner = Arc::downgrade(&self.inner);
+        let weak = Arc::downgrade(&link);
+        thread::spawn(move || {
+            while let Ok(Some(bytes)) = read_frame(&mut reader) {
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let Ok(frame) = inner.codec.decode::<Resp>(&bytes) else {
+                    continue;
+                };
+                let Some(link) = weak.upgrade() else {
+                    return;
+                };
+                let sent = match link.pending.lock().unwrap().as_mut() {
+                    Some(pending) => pending.remove(&frame.id),
+                    None => false,
+                };
+                if sent {
+                    let _ = inner.registry.resolve(&frame.id, frame.payload.into());
+                }
+            }
+            let (Some(inner), Some(link)) = (inner.upgrade(), weak.upgrade()) else {
+                return;
+            };
+            let pending = link.pending.lock().unwrap().take().unwrap_or_default();
+            for id in pending {
+                let _ = inner.registry.resolve(&id, Err(DISCONNECTED.into()));
+            }
+        });
+        Ok(link)
+    }
+}
+
+impl<Req, Resp, C> Clone for Client<Req, Resp, C> {
+    fn clone(&self) -> Self {
+        Client {
+            inner: self.inner.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<C, Resp> Drop for ClientInner<C, Resp> {
+    fn drop(&mut self) {
+        if let Some(link) = self.link.get_mut().unwrap().take() {
+            // Unblocks the reader thread.
+            let _ = link
+                .stream
+                .lock()
+                .unwrap()
+                .shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+impl<Req, Resp, C: Debug> Debug for Client<Req, Resp, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("path", &self.inner.path)
+            .field("codec", &self.inner.codec)
+            .finish()
+    }
+}
+
+/// Accepts connections from [`Client`]s.
+pub struct Listener<Req, Resp, C> {
+    listener: UnixListener,
+    codec: Arc<C>,
+    phantom: PhantomData<fn(Resp) -> Req>,
+}
+
+impl<Req, Resp, C> Listener<Req, Resp, C>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    C: Codec,
+{
+    /// Listen at `path`, which must not exist yet.
+    pub fn bind(path: impl AsRef<Path>, codec: C) -> io::Result<Self> {
+        Ok(Listener {
+            listener: UnixListener::bind(path)?,
+            codec: Arc::new(codec),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Block until a client connects.
+    pub fn accept(&self) -> io::Result<Connection<Req, Resp, C>> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(Connection {
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
+            reader: stream,
+            codec: self.codec.clone(),
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<Req, Resp, C: Debug> Debug for Listener<Req, Resp, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener")
+            .field("listener", &self.listener)
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
+
+/// A connected client. Iterating yields its requests, each with the reply
+/// that settles it, until the client disconnects.
+pub struct Connection<Req, Resp, C> {
+    reader: UnixStream,
+    writer: Arc<Mutex<UnixStream>>,
+    codec: Arc<C>,
+    phantom: PhantomData<fn(Resp) -> Req>,
+}
+
+impl<Req, Resp, C> Iterator for Connection<Req, Resp, C>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    C: Codec,
+{
+    type Item = io::Result<(Req, Reply<Resp, C>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = match read_frame(&mut self.reader) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+        let frame = match self.codec.decode::<Req>(&bytes) {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(invalid_data(e))),
+        };
+        let Payload::Resolve(request) = frame.payload else {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a request must be a resolved frame",
+            )));
+        };
+        let reply = Reply {
+            id: frame.id,
+            writer: Some(self.writer.clone()),
+            codec: self.codec.clone(),
+            phantom: PhantomData,
+        };
+        Some(Ok((request, reply)))
+    }
+}
+
+impl<Req, Resp, C: Debug> Debug for Connection<Req, Resp, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("reader", &self.reader)
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
+
+/// Settles one request of a [`Connection`]. A reply dropped unsettled rejects
+/// the request, like a dropped producer.
+pub struct Reply<Resp, C: Codec> {
+    id: u64,
+    writer: Option<Arc<Mutex<UnixStream>>>,
+    codec: Arc<C>,
+    phantom: PhantomData<fn(Resp)>,
+}
+
+impl<Resp: Serialize, C: Codec> Reply<Resp, C> {
+    /// Return the id the client gave the request.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Send `value` as the response.
+    pub fn resolve(mut self, value: Resp) -> io::Result<()> {
+        self.send(Payload::Resolve(value))
+    }
+
+    /// Reject the request with `reason`.
+    pub fn reject(mut self, reason: impl ToString) -> io::Result<()> {
+        self.send(Payload::Reject(reason.to_string()))
+    }
+
+    fn send(&mut self, payload: Payload<Resp>) -> io::Result<()> {
+        let writer = self.writer.take().expect("a reply is sent only once");
+        let frame = Frame {
+            id: self.id,
+            payload,
+        };
+        let bytes = self.codec.encode(&frame).map_err(invalid_data)?;
+        let mut writer = writer.lock().unwrap();
+        write_frame(&mut writer, &bytes)
+    }
+}
+
+impl<Resp, C: Codec> Drop for Reply<Resp, C> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            let frame = Frame::<()> {
+                id: self.id,
+                payload: Payload::Reject(String::from("reply dropped")),
+            };
+            if let Ok(bytes) = self.codec.encode(&frame) {
+                let _ = write_frame(&mut writer.lock().unwrap(), &bytes);
+            }
+        }
+    }
+}
+
+impl<Resp, C: Codec> Debug for Reply<Resp, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reply")
+            .field("id", &self.id)
+            .field("sent", &self.writer.is_none())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::{read_frame, Client, Listener, DISCONNECTED, MAX_FRAME_LEN};
+    use crate::codec::Json;
+    use futures::executor::block_on;
+    use std::{
+        io::{ErrorKind, Write},
+        net::Shutdown,
+        os::unix::net::UnixStream,
+        thread,
+    };
+
+    #[test]
+    fn test_ipc_rejects_oversized_frames() {
+        let (mut peer, mut stream) = UnixStream::pair().unwrap();
+        peer.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        let error = read_frame(&mut stream).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+        let len = MAX_FRAME_LEN as u32;
+        peer.write_all(&len.to_be_bytes()).unwrap();
+        drop(peer);
+        // A frame at the limit is read, and here cut short.
+        let error = read_frame(&mut stream).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+    }
+
+    #[test]
+    fn test_ipc_rejects_on_disconnect_and_reconnects() {
+        let path = std::env::temp_dir().join(format!("promise_out-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = Listener::<String, usize, _>::bind(&path, Json).unwrap();
+        let server = thread::spawn(move || {
+            let mut connection = listener.accept().unwrap();
+            let (request, reply) = connection.next().unwrap().unwrap();
+            reply.resolve(request.len()).unwrap();
+            // Hang up with the second request pending.
+            let (_, _reply) = connection.next().unwrap().unwrap();
+            connection.reader.shutdown(Shutdown::Both).unwrap();
+            let mut connection = listener.accept().unwrap();
+            let (_, reply) = connection.next().unwrap().unwrap();
+            reply.reject("no thanks").unwrap();
+        });
+        let client = Client::<String, usize, _>::connect(&path, Json).unwrap();
+        let first = client.call(&String::from("four")).unwrap();
+        assert_eq!(Ok(Ok(4)), block_on(first));
+        let second = client.call(&String::from("lost")).unwrap();
+        assert_eq!(Ok(Err(String::from(DISCONNECTED))), block_on(second));
+        let third = client.call(&String::from("again")).unwrap();
+        assert_eq!(Ok(Err(String::from("no thanks"))), block_on(third));
+        server.join().expect("The server thread has panicked");
+        assert_eq!(0, client.pending());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
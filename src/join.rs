@@ -0,0 +1,657 @@
+//! join waits on many consumers at once. Each consumer is polled with its own
+//! waker, so settling one consumer only polls that consumer again rather than
+//! every consumer in the collection.
+use crate::{ready::ReadyQueue, Error};
+use futures_core::Stream;
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The consumers being driven by one of the futures in this module.
+struct Many<Fut> {
+    pending: Vec<Option<Fut>>,
+    remaining: usize,
+    ready: ReadyQueue,
+}
+
+impl<Fut: Future + Unpin> Many<Fut> {
+    fn new(consumers: impl IntoIterator<Item = Fut>) -> Self {
+        let pending: Vec<_> = consumers.into_iter().map(Some).collect();
+        let mut ready = ReadyQueue::default();
+        for index in 0..pending.len() {
+            ready.insert(index);
+        }
+        Many {
+            remaining: pending.len(),
+            pending,
+            ready,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Poll the woken consumers until one settles. Returns `Ready(None)` once
+    /// every consumer has settled.
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<(usize, Fut::Output)>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        self.ready.register(cx.waker());
+        while let Some(index) = self.ready.pop() {
+            let Some(future) = self.pending[index].as_mut() else {
+                continue;
+            };
+            let mut child = Context::from_waker(self.ready.waker(index));
+            if let Poll::Ready(output) = Pin::new(future).poll(&mut child) {
+                self.pending[index] = None;
+                self.remaining -= 1;
+                return Poll::Ready(Some((index, output)));
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Drop every consumer that has not settled.
+    fn clear(&mut self) {
+        self.pending.clear();
+        self.remaining = 0;
+    }
+}
+
+impl<Fut: Debug> Debug for Many<Fut> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.pending.iter().flatten())
+            .finish()
+    }
+}
+
+/// Wait for every consumer and return their values in the given order. The
+/// first error is returned as soon as it happens, and the remaining consumers
+/// are dropped.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, join_all, pair::Producer};
+/// use futures::executor::block_on;
+/// let (promises, consumers): (Vec<_>, Vec<_>) = (0..3).map(|_| Producer::<u32>::new()).unzip();
+/// for (i, promise) in promises.into_iter().enumerate() {
+///     promise.resolve(i as u32);
+/// }
+/// assert_eq!(Ok(vec![0, 1, 2]), block_on(join_all(consumers)));
+/// ```
+pub fn join_all<I, Fut, T, E>(consumers: I) -> JoinAll<Fut, T>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future<Output = Result<T, E>> + Unpin,
+{
+    let many = Many::new(consumers);
+    JoinAll {
+        values: (0..many.len()).map(|_| None).collect(),
+        many,
+    }
+}
+
+/// Future for [`join_all`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct JoinAll<Fut, T> {
+    many: Many<Fut>,
+    values: Vec<Option<T>>,
+}
+
+impl<Fut: Debug, T: Debug> Debug for JoinAll<Fut, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinAll")
+            .field("pending", &self.many)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl<Fut: Unpin, T> Unpin for JoinAll<Fut, T> {}
+
+impl<Fut, T, E> Future for JoinAll<Fut, T>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+{
+    type Output = Result<Vec<T>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.many.poll_next(cx) {
+                Poll::Ready(Some((index, Ok(value)))) => this.values[index] = Some(value),
+                Poll::Ready(Some((_, Err(error)))) => {
+                    this.many.clear();
+                    return Poll::Ready(Err(error));
+                }
+                Poll::Ready(None) => {
+                    let values = std::mem::take(&mut this.values);
+                    return Poll::Ready(Ok(values.into_iter().map(Option::unwrap).collect()));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Return whatever the first consumer to settle returns, successful or not.
+/// The remaining consumers are dropped. Like JavaScript's `Promise.race`,
+/// racing no consumers never settles.
+///
+/// ```
+/// use promise_out::{Promise, race, pair::Producer};
+/// use futures::executor::block_on;
+/// let (_slow, slow_consumer) = Producer::<&str>::new();
+/// let (fast, fast_consumer) = Producer::<&str>::new();
+/// fast.resolve("🐇");
+/// assert_eq!(Ok("🐇"), block_on(race([slow_consumer, fast_consumer])));
+/// ```
+pub fn race<I, Fut>(consumers: I) -> Race<Fut>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future + Unpin,
+{
+    Race {
+        many: Many::new(consumers),
+    }
+}
+
+/// Future for [`race`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Race<Fut> {
+    many: Many<Fut>,
+}
+
+impl<Fut: Unpin> Unpin for Race<Fut> {}
+
+impl<Fut> Future for Race<Fut>
+where
+    Fut: Future + Unpin,
+{
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.many.poll_next(cx) {
+            Poll::Ready(Some((_, output))) => {
+                this.many.clear();
+                Poll::Ready(output)
+            }
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Return the value of the first consumer to succeed. The remaining consumers
+/// are dropped. If every consumer fails, all the errors are returned in the
+/// given order, so no consumers fail with an empty `Vec`.
+///
+/// ```
+/// use promise_out::{Promise, any, pair::Producer};
+/// use futures::executor::block_on;
+/// let (broken, broken_consumer) = Producer::<&str>::new();
+/// let (working, working_consumer) = Producer::<&str>::new();
+/// drop(broken);
+/// working.resolve("🍓");
+/// assert_eq!(Ok("🍓"), block_on(any([broken_consumer, working_consumer])));
+/// ```
+pub fn any<I, Fut, T, E>(consumers: I) -> Any<Fut, E>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future<Output = Result<T, E>> + Unpin,
+{
+    let many = Many::new(consumers);
+    Any {
+        errors: (0..many.len()).map(|_| None).collect(),
+        many,
+    }
+}
+
+/// Future for [`any`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Any<Fut, E> {
+    many: Many<Fut>,
+    errors: Vec<Option<E>>,
+}
+
+impl<Fut: Debug, E: Debug> Debug for Any<Fut, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Any")
+            .field("pending", &self.many)
+            .field("errors", &self.errors)
+            .finish()
+    }
+}
+
+impl<Fut: Unpin, E> Unpin for Any<Fut, E> {}
+
+impl<Fut, T, E> Future for Any<Fut, E>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+{
+    type Output = Result<T, Vec<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.many.poll_next(cx) {
+                Poll::Ready(Some((_, Ok(value)))) => {
+                    this.many.clear();
+                    return Poll::Ready(Ok(value));
+                }
+                Poll::Ready(Some((index, Err(error)))) => this.errors[index] = Some(error),
+                Poll::Ready(None) => {
+                    let errors = std::mem::take(&mut this.errors);
+                    return Poll::Ready(Err(errors.into_iter().map(Option::unwrap).collect()));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// How a consumer passed to [`all_settled`] settled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Settled<T> {
+    /// The promise was resolved with a value.
+    Fulfilled(T),
+    /// The promise failed with an error other than its producer being dropped.
+    Rejected(Error),
+    /// The producer was dropped without resolving the promise.
+    Dropped,
+}
+
+impl<T> From<Result<T, Error>> for Settled<T> {
+    fn from(result: Result<T, Error>) -> Self {
+        match result {
+            Ok(value) => Settled::Fulfilled(value),
+            Err(Error::ProducerDropped) => Settled::Dropped,
+            Err(error) => Settled::Rejected(error),
+        }
+    }
+}
+
+impl<T> Settled<T> {
+    /// Return the value if the promise was fulfilled.
+    pub fn fulfilled(self) -> Option<T> {
+        match self {
+            Settled::Fulfilled(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Wait for every consumer and report how each one settled, in the given
+/// order. Unlike [`join_all`] this never stops early, like JavaScript's
+/// `Promise.allSettled`.
+///
+/// ```
+/// use promise_out::{Promise, all_settled, join::Settled, pair::Producer};
+/// use futures::executor::block_on;
+/// let (good, good_consumer) = Producer::<u32>::new();
+/// let (lost, lost_consumer) = Producer::<u32>::new();
+/// good.resolve(1);
+/// drop(lost);
+/// assert_eq!(vec![Settled::Fulfilled(1), Settled::Dropped],
+///            block_on(all_settled([good_consumer, lost_consumer])));
+/// ```
+pub fn all_settled<I, Fut, T>(consumers: I) -> AllSettled<Fut, T>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future<Output = Result<T, Error>> + Unpin,
+{
+    let many = Many::new(consumers);
+    AllSettled {
+        outcomes: (0..many.len()).map(|_| None).collect(),
+        many,
+    }
+}
+
+/// Future for [`all_settled`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct AllSettled<Fut, T> {
+    many: Many<Fut>,
+    outcomes: Vec<Option<Settled<T>>>,
+}
+
+impl<Fut: Debug, T: Debug> Debug for AllSettled<Fut, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AllSettled")
+            .field("pending", &self.many)
+            .field("outcomes", &self.outcomes)
+            .finish()
+    }
+}
+
+impl<Fut: Unpin, T> Unpin for AllSettled<Fut, T> {}
+
+impl<Fut, T> Future for AllSettled<Fut, T>
+where
+    Fut: Future<Output = Result<T, Error>> + Unpin,
+{
+    type Output = Vec<Settled<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.many.poll_next(cx) {
+                Poll::Ready(Some((index, result))) => this.outcomes[index] = Some(result.into()),
+                Poll::Ready(None) => {
+                    let outcomes = std::mem::take(&mut this.outcomes);
+                    return Poll::Ready(outcomes.into_iter().map(Option::unwrap).collect());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Wait until `k` of the consumers have succeeded and return their values in
+/// the order they settled; the remaining consumers are dropped. Once so many
+/// consumers have failed that `k` successes are no longer possible, the errors
+/// collected so far are returned instead.
+///
+/// ```
+/// use promise_out::{Promise, quorum, pair::Producer};
+/// use futures::executor::block_on;
+/// let (replicas, consumers): (Vec<_>, Vec<_>) = (0..3).map(|_| Producer::<u32>::new()).unzip();
+/// let mut replicas = replicas.into_iter();
+/// replicas.next().unwrap().resolve(7);
+/// drop(replicas.next());
+/// replicas.next().unwrap().resolve(7);
+/// assert_eq!(Ok(vec![7, 7]), block_on(quorum(2, consumers)));
+/// ```
+pub fn quorum<I, Fut, T, E>(k: usize, consumers: I) -> Quorum<Fut, T, E>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future<Output = Result<T, E>> + Unpin,
+{
+    Quorum {
+        many: Many::new(consumers),
+        k,
+        values: Vec::new(),
+        errors: Vec::new(),
+    }
+}
+
+/// Future for [`quorum`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Quorum<Fut, T, E> {
+    many: Many<Fut>,
+    k: usize,
+    values: Vec<T>,
+    errors: Vec<E>,
+}
+
+impl<Fut: Debug, T: Debug, E: Debug> Debug for Quorum<Fut, T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Quorum")
+            .field("pending", &self.many)
+            .field("k", &self.k)
+            .field("values", &self.values)
+            .field("errors", &self.errors)
+            .finish()
+    }
+}
+
+impl<Fut: Unpin, T, E> Unpin for Quorum<Fut, T, E> {}
+
+impl<Fut, T, E> Future for Quorum<Fut, T, E>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+{
+    type Output = Result<Vec<T>, Vec<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            if this.values.len() >= this.k {
+                this.many.clear();
+                return Poll::Ready(Ok(std::mem::take(&mut this.values)));
+            }
+            if this.many.len() - this.errors.len() < this.k {
+                this.many.clear();
+                return Poll::Ready(Err(std::mem::take(&mut this.errors)));
+            }
+            match this.many.poll_next(cx) {
+                Poll::Ready(Some((_, Ok(value)))) => this.values.push(value),
+                Poll::Ready(Some((_, Err(error)))) => this.errors.push(error),
+                Poll::Ready(None) => {
+                    unreachable!("quorum is decided before every consumer settles")
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Return a stream of the consumers' outputs in the given order. Outputs of
+/// consumers that settle early are buffered until every consumer before them
+/// has been yielded.
+///
+/// ```
+/// use promise_out::{Promise, in_order, pair::Producer};
+/// use futures::{executor::block_on, StreamExt};
+/// let (promises, consumers): (Vec<_>, Vec<_>) = (0..3).map(|_| Producer::<u32>::new()).unzip();
+/// for (i, promise) in promises.into_iter().enumerate().rev() {
+///     promise.resolve(i as u32);
+/// }
+/// let outputs: Vec<_> = block_on(in_order(consumers).collect());
+/// assert_eq!(vec![Ok(0), Ok(1), Ok(2)], outputs);
+/// ```
+pub fn in_order<I, Fut>(consumers: I) -> InOrder<Fut>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future + Unpin,
+{
+    let many = Many::new(consumers);
+    InOrder {
+        buffered: (0..many.len()).map(|_| None).collect(),
+        next: 0,
+        many,
+    }
+}
+
+/// Stream for [`in_order`].
+#[must_use = "streams do nothing unless polled"]
+pub struct InOrder<Fut: Future> {
+    many: Many<Fut>,
+    buffered: Vec<Option<Fut::Output>>,
+    next: usize,
+}
+
+impl<Fut: Future + Debug> Debug for InOrder<Fut>
+where
+    Fut::Output: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InOrder")
+            .field("pending", &self.many)
+            .field("buffered", &self.buffered)
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+impl<Fut: Future + Unpin> Unpin for InOrder<Fut> {}
+
+impl<Fut: Future + Unpin> Stream for InOrder<Fut> {
+    type Item = Fut::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.next == this.buffered.len() {
+                return Poll::Ready(None);
+            }
+            if let Some(output) = this.buffered[this.next].take() {
+                this.next += 1;
+                return Poll::Ready(Some(output));
+            }
+            match this.many.poll_next(cx) {
+                Poll::Ready(Some((index, output))) => this.buffered[index] = Some(output),
+                Poll::Ready(None) => unreachable!("the next output is still pending"),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.buffered.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(all(test, feature = "pair", feature = "poly"))]
+mod tests {
+    use super::{all_settled, any, in_order, join_all, quorum, race, Settled};
+    use crate::{pair, poly, Error, Promise};
+    use futures::executor::block_on;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_join_all_out_of_order() {
+        let (ops, consumers): (Vec<_>, Vec<_>) =
+            (0..10).map(|_| pair::Producer::<usize>::new()).unzip();
+        let task1 = thread::spawn(move || block_on(join_all(consumers)));
+        for (i, op) in ops.into_iter().enumerate().rev() {
+            op.resolve(i * 10);
+        }
+        let values = task1.join().expect("The task1 thread has panicked");
+        assert_eq!(Ok((0..10).map(|i| i * 10).collect()), values);
+    }
+
+    #[test]
+    fn test_join_all_error() {
+        let (op, op_a) = pair::Producer::<u8>::new();
+        let (op2, op_b) = pair::Producer::<u8>::new();
+        let task1 = thread::spawn(move || block_on(join_all([op_a, op_b])));
+        drop(op2);
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            task1.join().expect("The task1 thread has panicked")
+        );
+        op.resolve(1);
+    }
+
+    #[test]
+    fn test_join_all_empty() {
+        let empty: Vec<poly::Consumer<u8>> = Vec::new();
+        assert_eq!(Ok(Vec::<Arc<u8>>::new()), block_on(join_all(empty)));
+    }
+
+    #[test]
+    fn test_race() {
+        let (op, op_a) = pair::Producer::<u8>::new();
+        let (op2, op_b) = pair::Producer::<u8>::new();
+        let task1 = thread::spawn(move || block_on(race([op_a, op_b])));
+        drop(op2);
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            task1.join().expect("The task1 thread has panicked")
+        );
+        op.resolve(1);
+    }
+
+    #[test]
+    fn test_any() {
+        let (ops, consumers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| pair::Producer::<u8>::new()).unzip();
+        let task1 = thread::spawn(move || block_on(any(consumers)));
+        let mut ops = ops.into_iter();
+        drop(ops.next());
+        ops.next().unwrap().resolve(1);
+        assert_eq!(Ok(1), task1.join().expect("The task1 thread has panicked"));
+    }
+
+    #[test]
+    fn test_any_all_fail() {
+        let (ops, consumers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| pair::Producer::<u8>::new()).unzip();
+        drop(ops);
+        assert_eq!(
+            Err(vec![
+                Error::ProducerDropped,
+                Error::ProducerDropped,
+                Error::ProducerDropped
+            ]),
+            block_on(any(consumers))
+        );
+        let empty: Vec<pair::Consumer<u8>> = Vec::new();
+        assert_eq!(Err(vec![]), block_on(any(empty)));
+    }
+
+    #[test]
+    fn test_all_settled() {
+        let (ops, consumers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| pair::Producer::<u8>::new()).unzip();
+        let task1 = thread::spawn(move || block_on(all_settled(consumers)));
+        let mut ops = ops.into_iter();
+        let first = ops.next().unwrap();
+        drop(ops.next());
+        ops.next().unwrap().resolve(3);
+        first.resolve(1);
+        assert_eq!(
+            vec![
+                Settled::Fulfilled(1),
+                Settled::Dropped,
+                Settled::Fulfilled(3)
+            ],
+            task1.join().expect("The task1 thread has panicked")
+        );
+        assert_eq!(
+            Settled::<u8>::Rejected(Error::Timeout),
+            Err(Error::Timeout).into()
+        );
+    }
+
+    #[test]
+    fn test_quorum_fails_early() {
+        let (ops, consumers): (Vec<_>, Vec<_>) =
+            (0..4).map(|_| pair::Producer::<u8>::new()).unzip();
+        let task1 = thread::spawn(move || block_on(quorum(3, consumers)));
+        let mut ops = ops.into_iter();
+        ops.next().unwrap().resolve(1);
+        drop(ops.next());
+        drop(ops.next());
+        // The last producer is still alive, but three successes are impossible.
+        assert_eq!(
+            Err(vec![Error::ProducerDropped, Error::ProducerDropped]),
+            task1.join().expect("The task1 thread has panicked")
+        );
+    }
+
+    #[test]
+    fn test_quorum_bounds() {
+        let empty: Vec<pair::Consumer<u8>> = Vec::new();
+        assert_eq!(Ok(vec![]), block_on(quorum(0, empty)));
+        let (_op, op_a) = pair::Producer::<u8>::new();
+        assert_eq!(Err(vec![]), block_on(quorum(2, [op_a])));
+    }
+
+    #[test]
+    fn test_in_order_buffers_early_outputs() {
+        use futures::{FutureExt, StreamExt};
+        let (ops, consumers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| pair::Producer::<u8>::new()).unzip();
+        let mut stream = in_order(consumers);
+        let mut ops = ops.into_iter();
+        let (first, second, third) = (ops.next().unwrap(), ops.next().unwrap(), ops.next());
+        second.resolve(2);
+        drop(third);
+        assert_eq!(None, stream.next().now_or_never());
+        first.resolve(1);
+        assert_eq!(
+            vec![Ok(1), Ok(2), Err(Error::ProducerDropped)],
+            block_on(stream.collect::<Vec<_>>())
+        );
+    }
+}
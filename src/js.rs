@@ -0,0 +1,155 @@
+//! js offers a JavaScript flavored facade over the consumers, to ease porting
+//! promise code from the dweb JavaScript side. `then`, `catch`, and `finally`
+//! read like their JavaScript namesakes but are plain Rust futures underneath.
+//!
+//! ```
+//! use promise_out::{Promise, Error, js::Thenable, pair::Producer};
+//! use futures::executor::block_on;
+//! let (promise, consumer) = Producer::<u32>::new();
+//! let chain = consumer
+//!     .then(|value| value * 2)
+//!     .catch(|_error| Ok::<_, Error>(0))
+//!     .finally(|| println!("done"));
+//! promise.resolve(21);
+//! assert_eq!(Ok(42), block_on(chain));
+//! ```
+use crate::combinator::Map;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// `then`, `catch`, and `finally` for consumers, or any future that returns a
+/// `Result<T, E>`.
+pub trait Thenable<T, E>: Future<Output = Result<T, E>> + Sized {
+    /// Run `on_fulfilled` with the resolved value and resolve with its result.
+    fn then<U, F>(self, on_fulfilled: F) -> Map<Self, F>
+    where
+        F: FnOnce(T) -> U,
+    {
+        crate::ConsumerExt::map(self, on_fulfilled)
+    }
+
+    /// Run `on_rejected` with the error. Returning `Ok` recovers, returning
+    /// `Err` rethrows.
+    fn catch<E2, F>(self, on_rejected: F) -> Catch<Self, F>
+    where
+        F: FnOnce(E) -> Result<T, E2>,
+    {
+        Catch {
+            future: self,
+            f: Some(on_rejected),
+        }
+    }
+
+    /// Run `on_finally` once the promise settles either way, passing the
+    /// result through.
+    fn finally<F>(self, on_finally: F) -> Finally<Self, F>
+    where
+        F: FnOnce(),
+    {
+        Finally {
+            future: self,
+            f: Some(on_finally),
+        }
+    }
+}
+
+impl<Fut, T, E> Thenable<T, E> for Fut where Fut: Future<Output = Result<T, E>> {}
+
+/// Future for [`Thenable::catch`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Catch<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+impl<Fut: Unpin, F> Unpin for Catch<Fut, F> {}
+
+impl<Fut, F, T, E, E2> Future for Catch<Fut, F>
+where
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    F: FnOnce(E) -> Result<T, E2>,
+{
+    type Output = Result<T, E2>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(result) => {
+                let f = this
+                    .f
+                    .take()
+                    .expect("Catch must not be polled after it returned Ready");
+                Poll::Ready(result.or_else(f))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future for [`Thenable::finally`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Finally<Fut, F> {
+    future: Fut,
+    f: Option<F>,
+}
+
+impl<Fut: Unpin, F> Unpin for Finally<Fut, F> {}
+
+impl<Fut, F> Future for Finally<Fut, F>
+where
+    Fut: Future + Unpin,
+    F: FnOnce(),
+{
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(output) => {
+                let f = this
+                    .f
+                    .take()
+                    .expect("Finally must not be polled after it returned Ready");
+                f();
+                Poll::Ready(output)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "pair"))]
+mod tests {
+    use super::Thenable;
+    use crate::{pair::Producer, Error, Promise};
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_catch_recovers_and_rethrows() {
+        let (op, op_a) = Producer::<u32>::new();
+        let mut cleaned_up = false;
+        let task1 = thread::spawn(move || std::mem::drop(op));
+        let value = block_on(
+            op_a.then(|v| v + 1)
+                .catch(|e| match e {
+                    Error::ProducerDropped => Ok(0),
+                    e => Err(e),
+                })
+                .finally(|| cleaned_up = true),
+        );
+        task1.join().expect("The task1 thread has panicked");
+        assert_eq!(Ok(0), value);
+        assert!(cleaned_up);
+
+        let (op, op_a) = Producer::<u32>::new();
+        std::mem::drop(op);
+        let value = block_on(op_a.catch(|e| Err::<u32, _>(e.to_string())));
+        assert_eq!(Err(String::from("producer dropped")), value);
+    }
+}
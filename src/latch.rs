@@ -0,0 +1,141 @@
+//! latch implements a countdown latch on top of a poly promise: the promise is
+//! resolved once the count reaches zero, waking every waiter.
+use crate::{poly, Error, Promise};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+/// A latch that opens once [`count_down`](Latch::count_down) has been called
+/// `n` times. Dropping an unopened latch fails its waiters.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::latch::Latch;
+/// use futures::executor::block_on;
+/// use std::{sync::Arc, thread};
+/// let latch = Arc::new(Latch::new(3));
+/// let tasks: Vec<_> = (0..3).map(|_| {
+///     let latch = latch.clone();
+///     thread::spawn(move || latch.count_down())
+/// }).collect();
+/// block_on(latch.wait()).unwrap();
+/// assert_eq!(0, latch.count());
+/// # for task in tasks { task.join().unwrap(); }
+/// ```
+#[derive(Debug)]
+pub struct Latch {
+    state: Mutex<State>,
+    opened: poly::Consumer<()>,
+}
+
+#[derive(Debug)]
+struct State {
+    count: usize,
+    producer: Option<poly::Producer<()>>,
+}
+
+impl Latch {
+    pub fn new(count: usize) -> Self {
+        let (producer, opened) = poly::Producer::new();
+        let latch = Latch {
+            state: Mutex::new(State {
+                count,
+                producer: Some(producer),
+            }),
+            opened,
+        };
+        if count == 0 {
+            latch.open();
+        }
+        latch
+    }
+
+    fn open(&self) {
+        let producer = self.state.lock().unwrap().producer.take();
+        if let Some(producer) = producer {
+            producer.resolve(());
+        }
+    }
+
+    /// Decrement the count, opening the latch when it reaches zero. Counting
+    /// down an open latch does nothing.
+    pub fn count_down(&self) {
+        let producer = {
+            let mut state = self.state.lock().unwrap();
+            state.count = state.count.saturating_sub(1);
+            if state.count > 0 {
+                return;
+            }
+            state.producer.take()
+        };
+        if let Some(producer) = producer {
+            producer.resolve(());
+        }
+    }
+
+    /// Return the remaining count.
+    pub fn count(&self) -> usize {
+        self.state.lock().unwrap().count
+    }
+
+    /// Return a future that resolves once the latch opens.
+    pub fn wait(&self) -> Wait {
+        Wait {
+            opened: self.opened.clone(),
+        }
+    }
+}
+
+/// Future for [`Latch::wait`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait {
+    opened: poly::Consumer<()>,
+}
+
+impl Future for Wait {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.opened).poll(cx).map_ok(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Latch;
+    use crate::Error;
+    use futures::executor::block_on;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_latch_opens_for_every_waiter() {
+        let latch = Arc::new(Latch::new(2));
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let latch = latch.clone();
+                thread::spawn(move || block_on(latch.wait()))
+            })
+            .collect();
+        latch.count_down();
+        assert_eq!(1, latch.count());
+        latch.count_down();
+        latch.count_down();
+        for task in tasks {
+            assert_eq!(Ok(()), task.join().expect("The task thread has panicked"));
+        }
+        assert_eq!(Ok(()), block_on(Latch::new(0).wait()));
+    }
+
+    #[test]
+    fn test_latch_dropped() {
+        let latch = Latch::new(1);
+        let wait = latch.wait();
+        drop(latch);
+        assert_eq!(Err(Error::ProducerDropped), block_on(wait));
+    }
+}
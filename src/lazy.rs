@@ -0,0 +1,189 @@
+//! lazy implements a promise whose computation is registered up front but only
+//! starts when a consumer first polls it. The computation is shared: whichever
+//! consumer polls drives it, and it is polled with a waker that wakes every
+//! waiting consumer, so all of them are resolved with the same `Arc`.
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Wake, Waker},
+};
+
+type Computation<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+struct Shared<T> {
+    // None while a consumer is polling it, or once it has finished.
+    computation: Option<Computation<T>>,
+    started: bool,
+    value: Option<Arc<T>>,
+}
+
+type Wakers = Mutex<Vec<Waker>>;
+
+fn wake_all(wakers: &Wakers) {
+    let wakers = std::mem::take(&mut *wakers.lock().unwrap());
+    for waker in wakers {
+        waker.wake()
+    }
+}
+
+/// Wakes every consumer waiting on the computation. The wakers are kept apart
+/// from the value so this waker is `Send` whatever `T` is.
+struct WakeAll {
+    wakers: Weak<Wakers>,
+}
+
+impl Wake for WakeAll {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(wakers) = self.wakers.upgrade() {
+            wake_all(&wakers)
+        }
+    }
+}
+
+/// A consumer of a lazily evaluated promise. It may be cloned; every clone
+/// resolves with the same value.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::lazy::Lazy;
+/// use futures::executor::block_on;
+/// let lazy = Lazy::from_fn(|| 6 * 7);
+/// assert!(!lazy.has_started());
+/// let other = lazy.clone();
+/// assert_eq!(42, *block_on(lazy));
+/// assert!(other.has_started());
+/// assert_eq!(42, *block_on(other));
+/// ```
+pub struct Lazy<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    wakers: Arc<Wakers>,
+}
+
+impl<T: Send + 'static> Lazy<T> {
+    /// Return a consumer that runs `future` once it is first polled.
+    pub fn new(future: impl Future<Output = T> + Send + 'static) -> Self {
+        Lazy {
+            shared: Arc::new(Mutex::new(Shared {
+                computation: Some(Box::pin(future)),
+                started: false,
+                value: None,
+            })),
+            wakers: Arc::default(),
+        }
+    }
+
+    /// Return a consumer that calls `f` once it is first polled.
+    pub fn from_fn(f: impl FnOnce() -> T + Send + 'static) -> Self {
+        Self::new(async move { f() })
+    }
+}
+
+impl<T> Lazy<T> {
+    /// Return true once a consumer has polled the computation.
+    pub fn has_started(&self) -> bool {
+        self.shared.lock().unwrap().started
+    }
+}
+
+impl<T> Clone for Lazy<T> {
+    fn clone(&self) -> Self {
+        Lazy {
+            shared: self.shared.clone(),
+            wakers: self.wakers.clone(),
+        }
+    }
+}
+
+impl<T: Debug> Debug for Lazy<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("Lazy")
+            .field("started", &shared.started)
+            .field("value", &shared.value)
+            .finish()
+    }
+}
+
+impl<T> Future for Lazy<T> {
+    type Output = Arc<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut computation = {
+            let mut shared = self.shared.lock().unwrap();
+            if let Some(value) = &shared.value {
+                return Poll::Ready(value.clone());
+            }
+            self.wakers.lock().unwrap().push(cx.waker().clone());
+            match shared.computation.take() {
+                Some(computation) => computation,
+                // Another consumer is polling it and will wake us.
+                None => return Poll::Pending,
+            }
+        };
+        // Polled without the lock, so other consumers can register meanwhile.
+        let waker = Waker::from(Arc::new(WakeAll {
+            wakers: Arc::downgrade(&self.wakers),
+        }));
+        let poll = computation.as_mut().poll(&mut Context::from_waker(&waker));
+        let mut shared = self.shared.lock().unwrap();
+        shared.started = true;
+        match poll {
+            Poll::Ready(value) => {
+                let value = Arc::new(value);
+                shared.value = Some(value.clone());
+                drop(shared);
+                wake_all(&self.wakers);
+                Poll::Ready(value)
+            }
+            Poll::Pending => {
+                shared.computation = Some(computation);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Lazy<T> {
+    /// Wake the other consumers if the computation is unfinished, since the
+    /// wake-up meant for this consumer may have been its last one.
+    fn drop(&mut self) {
+        let finished = self.shared.lock().unwrap().value.is_some();
+        if !finished {
+            wake_all(&self.wakers);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "pair"))]
+mod tests {
+    use super::Lazy;
+    use crate::{pair, Promise};
+    use futures::{executor::block_on, FutureExt};
+    use std::thread;
+
+    #[test]
+    fn test_lazy_starts_on_first_poll() {
+        let (producer, consumer) = pair::Producer::<u32>::new();
+        let mut lazy = Lazy::new(async move { consumer.await.unwrap() + 1 });
+        let others: Vec<_> = (0..3).map(|_| lazy.clone()).collect();
+        assert!(!lazy.has_started());
+        assert_eq!(None, (&mut lazy).now_or_never());
+        assert!(lazy.has_started());
+        let tasks: Vec<_> = others
+            .into_iter()
+            .map(|other| thread::spawn(move || block_on(other)))
+            .collect();
+        producer.resolve(1);
+        assert_eq!(2, *block_on(lazy));
+        for task in tasks {
+            assert_eq!(2, *task.join().expect("The task thread has panicked"));
+        }
+    }
+}
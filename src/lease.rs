@@ -0,0 +1,210 @@
+//! lease implements a single-consumer promise whose producer holds a lease
+//! that runs out at a deadline. Once the lease expires, the consumer fails
+//! with [`Error::Timeout`] at once, and a late [`Producer::resolve`], e.g.
+//! from a slow worker, is refused with [`LeaseExpired`], so nobody resolves a
+//! request that has already been given up on. A worker that is still making
+//! progress can [`renew`](Producer::renew) its lease.
+use crate::{timer, Error};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// The error returned by [`Producer::resolve`] after the lease expired. It
+/// hands back the value.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("lease expired")]
+pub struct LeaseExpired<T>(pub T);
+
+#[derive(Debug)]
+enum State<T> {
+    Pending,
+    Resolved(T),
+    Taken,
+    Expired,
+    Dropped,
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    state: State<T>,
+    deadline: Instant,
+    waker: Option<Waker>,
+}
+
+impl<T> Inner<T> {
+    /// True once the deadline has passed, even if the timer has yet to run.
+    fn expired(&self) -> bool {
+        matches!(self.state, State::Expired) || self.deadline <= Instant::now()
+    }
+
+    fn settle(&mut self, state: State<T>) {
+        self.state = state;
+        if let Some(waker) = self.waker.take() {
+            waker.wake()
+        }
+    }
+}
+
+/// The producer of a leased promise.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{lease::{LeaseExpired, Producer}, Error};
+/// use futures::executor::block_on;
+/// use std::{thread, time::Duration};
+/// let (promise, consumer) = Producer::<u32>::new(Duration::from_millis(10));
+/// let worker = thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(50));
+///     promise.resolve(42)
+/// });
+/// assert_eq!(Err(Error::Timeout), block_on(consumer));
+/// assert_eq!(Err(LeaseExpired(42)), worker.join().unwrap());
+/// ```
+#[derive(Debug)]
+pub struct Producer<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+}
+
+/// The consumer of a leased promise.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T: Send + 'static> Producer<T> {
+    /// Return a (producer, consumer) pair whose lease lasts for `ttl`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(ttl: Duration) -> (Self, Consumer<T>) {
+        Self::with_deadline(Instant::now() + ttl)
+    }
+
+    /// Return a (producer, consumer) pair whose lease expires at `deadline`.
+    pub fn with_deadline(deadline: Instant) -> (Self, Consumer<T>) {
+        let promise = Arc::new(Mutex::new(Inner {
+            state: State::Pending,
+            deadline,
+            waker: None,
+        }));
+        schedule(&promise, deadline);
+        (
+            Producer {
+                promise: promise.clone(),
+            },
+            Consumer { promise },
+        )
+    }
+
+    /// Extend the lease to `ttl` from now. Returns false if it has already
+    /// expired, which renewing can not undo.
+    pub fn renew(&self, ttl: Duration) -> bool {
+        let deadline = Instant::now() + ttl;
+        let mut promise = self.promise.lock().unwrap();
+        if promise.expired() {
+            return false;
+        }
+        promise.deadline = deadline;
+        drop(promise);
+        schedule(&self.promise, deadline);
+        true
+    }
+}
+
+impl<T> Producer<T> {
+    /// Resolve the promise, unless the lease has expired; the value is handed
+    /// back then.
+    pub fn resolve(self, value: T) -> Result<(), LeaseExpired<T>> {
+        let mut promise = self.promise.lock().unwrap();
+        if promise.expired() {
+            promise.settle(State::Expired);
+            return Err(LeaseExpired(value));
+        }
+        promise.settle(State::Resolved(value));
+        Ok(())
+    }
+
+    /// Return when the lease expires.
+    pub fn deadline(&self) -> Instant {
+        self.promise.lock().unwrap().deadline
+    }
+
+    /// Return true once the lease has expired.
+    pub fn is_expired(&self) -> bool {
+        self.promise.lock().unwrap().expired()
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        let mut promise = self.promise.lock().unwrap();
+        if matches!(promise.state, State::Pending) {
+            promise.settle(State::Dropped);
+        }
+    }
+}
+
+/// Expire the lease at `deadline`, unless it has been renewed since.
+fn schedule<T: Send + 'static>(promise: &Arc<Mutex<Inner<T>>>, deadline: Instant) {
+    let weak = Arc::downgrade(promise);
+    timer::schedule(deadline, move || expire(weak, deadline));
+}
+
+fn expire<T>(promise: Weak<Mutex<Inner<T>>>, deadline: Instant) {
+    let Some(promise) = promise.upgrade() else {
+        return;
+    };
+    let mut promise = promise.lock().unwrap();
+    if matches!(promise.state, State::Pending) && promise.deadline == deadline {
+        promise.settle(State::Expired);
+    }
+}
+
+impl<T> Future for Consumer<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut promise = self.promise.lock().unwrap();
+        match promise.state {
+            State::Pending => {
+                promise.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Expired => Poll::Ready(Err(Error::Timeout)),
+            State::Dropped => Poll::Ready(Err(Error::ProducerDropped)),
+            State::Taken => panic!("Consumer polled after completion"),
+            State::Resolved(_) => match std::mem::replace(&mut promise.state, State::Taken) {
+                State::Resolved(value) => Poll::Ready(Ok(value)),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LeaseExpired, Producer};
+    use futures::executor::block_on;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_lease_renewed() {
+        let (promise, consumer) = Producer::<u32>::new(Duration::from_millis(200));
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(20));
+            assert!(promise.renew(Duration::from_millis(200)));
+        }
+        assert!(!promise.is_expired());
+        promise.resolve(7).unwrap();
+        assert_eq!(Ok(7), block_on(consumer));
+
+        let (promise, consumer) = Producer::<u32>::new(Duration::ZERO);
+        assert!(block_on(consumer).is_err());
+        assert!(!promise.renew(Duration::from_secs(1)));
+        assert_eq!(Err(LeaseExpired(7)), promise.resolve(7));
+    }
+}
@@ -1,6 +1,26 @@
 #![doc = include_str!("../README.md")]
-use std::future::Future;
-use thiserror::Error;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+extern crate alloc;
+
+use alloc::string::String;
+use combinator::IntoResult;
+use core::future::Future;
+#[cfg(any(feature = "pair", feature = "poly"))]
+use core::{
+    ops::{Deref, DerefMut},
+    sync::atomic::AtomicBool,
+};
+#[cfg(any(
+    feature = "pair",
+    feature = "poly",
+    feature = "channel",
+    feature = "std"
+))]
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
 
 /// The trait for a promise.
 pub trait Promise<T> {
@@ -17,20 +37,416 @@ pub trait Promise<T> {
     fn new() -> (Self, Self::Waiter)
     where
         Self: Sized;
+
+    /// Return a producer that is resolved with a `U`, converted by `f` into
+    /// the `T` its consumer expects.
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<String>::new();
+    /// let promise = promise.contramap(|n: u32| n.to_string());
+    /// promise.resolve(42);
+    /// assert_eq!("42", block_on(consumer).unwrap());
+    /// ```
+    fn contramap<U, F>(self, f: F) -> Contramap<Self, F, T>
+    where
+        Self: Sized,
+        F: FnOnce(U) -> T,
+    {
+        Contramap::new(self, f)
+    }
+
+    /// Return a (producer, consumer) pair that has already been resolved with
+    /// `value`, e.g. for a cache that sometimes answers synchronously but must
+    /// always hand out both halves. Resolving the producer again does nothing.
+    ///
+    /// ```
+    /// use promise_out::{Promise, poly::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<&str>::resolved("🍓");
+    /// promise.resolve("🍋");
+    /// assert_eq!("🍓", *block_on(consumer).unwrap());
+    /// ```
+    fn resolved(value: T) -> (Self, Self::Waiter)
+    where
+        Self: Sized;
+
+    /// Return a (producer, consumer) pair that has already been rejected with
+    /// `reason`, for promises that encode their rejection in a `Result`.
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (_, consumer) = Producer::<Result<u32, &str>>::rejected("reject!");
+    /// assert_eq!(Ok(Err("reject!")), block_on(consumer));
+    /// ```
+    fn rejected(reason: <T as IntoResult>::Reason) -> (Self, Self::Waiter)
+    where
+        Self: Sized,
+        T: IntoResult + From<Result<<T as IntoResult>::Value, <T as IntoResult>::Reason>>,
+    {
+        Self::resolved(T::from(Err(reason)))
+    }
+
+    /// Resolve the promise with `value` once `duration` has elapsed. The
+    /// resolution happens on the crate's [timer](timer::set_timer).
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// use futures::executor::block_on;
+    /// use std::time::{Duration, Instant};
+    /// let start = Instant::now();
+    /// let (promise, consumer) = Producer::<&str>::new();
+    /// promise.resolve_after(Duration::from_millis(10), "late");
+    /// assert_eq!(Ok("late"), block_on(consumer));
+    /// assert!(start.elapsed() >= Duration::from_millis(10));
+    /// ```
+    #[cfg(feature = "timer")]
+    fn resolve_after(self, duration: std::time::Duration, value: T)
+    where
+        Self: Sized + Send + 'static,
+        T: Send + 'static,
+    {
+        timer::schedule(std::time::Instant::now() + duration, move || {
+            self.resolve(value)
+        });
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Error)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("producer dropped")]
     ProducerDropped,
+    Timeout,
+    Stale,
+    ConsumerDropped,
+    Shutdown(String),
+    Corrupted,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::ProducerDropped => f.write_str("producer dropped"),
+            Error::Timeout => f.write_str("timed out"),
+            Error::Stale => f.write_str("stale generation"),
+            Error::ConsumerDropped => f.write_str("consumer dropped"),
+            Error::Shutdown(reason) => write!(f, "shut down: {reason}"),
+            Error::Corrupted => f.write_str("corrupted shared state"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Identifies a promise of the pair, poly, channel, or mpmc flavor. Both
+/// halves of a promise, and all clones of them, report the same id, and no
+/// two promises share one, so it can correlate a promise across log lines.
+///
+/// ```
+/// use promise_out::{Promise, pair::Producer};
+/// let (promise, consumer) = Producer::<u32>::new();
+/// assert_eq!(promise.id(), consumer.id());
+/// let (other, _) = Producer::<u32>::new();
+/// assert_ne!(promise.id(), other.id());
+/// println!("waiting for promise {}", consumer.id());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PromiseId(u64);
+
+#[cfg(any(
+    feature = "pair",
+    feature = "poly",
+    feature = "channel",
+    feature = "std"
+))]
+impl PromiseId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        PromiseId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl core::fmt::Display for PromiseId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
 }
 
+#[cfg(any(
+    feature = "pair",
+    feature = "poly",
+    feature = "channel",
+    feature = "bytes",
+    feature = "std"
+))]
 #[derive(Debug)]
 enum WakerState {
     Fresh,
     Tainted,
 }
 
+/// Whether every consumer of a promise has gone away, and the producer's waker
+/// to wake when they do.
+#[cfg(any(
+    feature = "pair",
+    feature = "poly",
+    feature = "channel",
+    feature = "std"
+))]
+#[derive(Debug, Default)]
+struct Cancel {
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+#[cfg(any(
+    feature = "pair",
+    feature = "poly",
+    feature = "channel",
+    feature = "std"
+))]
+impl Cancel {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake()
+        }
+    }
+
+    fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.closed {
+            Poll::Ready(())
+        } else {
+            self.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// The lock around the inner state of a promise, next to a flag that tells
+/// whether the promise has settled without taking the lock.
+///
+/// The flag is set with release ordering while the lock is held, after the
+/// value has been stored or the producer has gone, and read with acquire
+/// ordering. So everything that happened before a promise was settled
+/// happens before a reader sees the flag, and a reader that sees it and then
+/// takes the lock finds the promise settled.
+#[cfg(any(feature = "pair", feature = "poly"))]
+struct Guarded<R: lock::RawMutex, I> {
+    settled: AtomicBool,
+    lock: lock_api::Mutex<R, I>,
+}
+
+#[cfg(any(feature = "pair", feature = "poly"))]
+impl<R: lock::RawMutex, I> Guarded<R, I> {
+    fn new(inner: I) -> Self {
+        Guarded {
+            settled: AtomicBool::new(false),
+            lock: lock_api::Mutex::new(inner),
+        }
+    }
+
+    fn new_settled(inner: I) -> Self {
+        Guarded {
+            settled: AtomicBool::new(true),
+            lock: lock_api::Mutex::new(inner),
+        }
+    }
+
+    /// Publish that the promise has settled. Call it with the lock held.
+    #[inline]
+    fn settle(&self) {
+        self.settled.store(true, Ordering::Release);
+    }
+
+    #[inline]
+    fn is_settled(&self) -> bool {
+        self.settled.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(any(feature = "pair", feature = "poly"))]
+impl<R: lock::RawMutex, I: core::fmt::Debug> core::fmt::Debug for Guarded<R, I> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Guarded")
+            .field("settled", &self.settled)
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
+
+#[cfg(any(feature = "pair", feature = "poly"))]
+impl<R: lock::RawMutex, I> Deref for Guarded<R, I> {
+    type Target = lock_api::Mutex<R, I>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.lock
+    }
+}
+
+#[cfg(any(feature = "pair", feature = "poly"))]
+impl<R: lock::RawMutex, I> DerefMut for Guarded<R, I> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.lock
+    }
+}
+
+pub mod allocator;
+#[cfg(all(
+    feature = "std",
+    feature = "pair",
+    feature = "poly",
+    feature = "channel"
+))]
+pub mod any_consumer;
+pub mod arena;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod barrier;
+#[cfg(all(feature = "std", any(feature = "pair", feature = "poly")))]
+mod blocking;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod bus;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod cache;
+#[cfg(feature = "channel")]
 pub mod channel;
+#[cfg(feature = "serde")]
+pub mod codec;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod collector;
+pub mod combinator;
+#[cfg(feature = "std")]
+pub mod completions;
+#[cfg(feature = "std")]
+pub mod condvar;
+pub mod coop;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod debounce;
+#[cfg(feature = "std")]
+pub mod delay_queue;
+pub mod envelope;
+#[cfg(feature = "std")]
+pub mod event_flags;
+#[cfg(feature = "futures-channel")]
+pub mod futures_compat;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod group;
+pub mod heapless;
+#[cfg(feature = "std")]
+pub mod ids;
+#[cfg(feature = "poly")]
+mod intrusive;
+#[cfg(all(unix, feature = "ipc"))]
+pub mod ipc;
+#[cfg(feature = "std")]
+pub mod join;
+#[cfg(feature = "std")]
+pub mod js;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod latch;
+#[cfg(feature = "std")]
+pub mod lazy;
+#[cfg(feature = "std")]
+pub mod lease;
+pub mod lock;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod mailbox;
+#[cfg(feature = "std")]
+pub mod mpmc;
+#[cfg(feature = "std")]
+pub mod notify;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod once_cell;
+#[cfg(any(feature = "pair", feature = "poly", feature = "channel"))]
+mod padded;
+#[cfg(feature = "pair")]
 pub mod pair;
+#[cfg(feature = "bytes")]
+pub mod payload;
+#[cfg(feature = "poly")]
 pub mod poly;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod port;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod rate_limit;
+#[cfg(feature = "std")]
+mod ready;
+#[cfg(all(feature = "std", feature = "pair", feature = "poly"))]
+pub mod registry;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod retry;
+#[cfg(feature = "std")]
+pub mod reusable;
+#[cfg(all(feature = "std", feature = "pair", feature = "poly"))]
+pub mod rpc;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod semaphore;
+pub mod settle;
+#[cfg(all(target_os = "linux", feature = "shm"))]
+pub mod shm;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod singleflight;
+#[cfg(any(feature = "pair", feature = "channel"))]
+mod slot;
+pub mod stack;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod staged;
+pub mod static_promise;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod streaming;
+#[cfg(feature = "std")]
+pub mod timer;
+#[cfg(all(feature = "tokio", feature = "pair", feature = "poly"))]
+pub mod tokio_compat;
+#[cfg(any(
+    feature = "pair",
+    feature = "poly",
+    feature = "channel",
+    feature = "std"
+))]
+mod tracked;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod wait_group;
+#[cfg(feature = "poly")]
+pub mod wake;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(all(feature = "std", feature = "poly"))]
+pub mod watch;
+#[cfg(feature = "std")]
+pub mod watchdog;
+#[cfg(all(feature = "std", feature = "pair"))]
+pub mod waterfall;
+
+#[cfg(all(
+    feature = "std",
+    feature = "pair",
+    feature = "poly",
+    feature = "channel"
+))]
+pub use any_consumer::AnyConsumer;
+pub use combinator::{ConsumerExt, Contramap, Either};
+#[cfg(feature = "std")]
+pub use join::{all_settled, any, in_order, join_all, quorum, race};
+#[cfg(feature = "derive")]
+pub use promise_out_derive::Ask;
+#[cfg(feature = "tracked")]
+pub use tracked::{is_shut_down, shutdown};
+
+/// Return a consumer that resolves once `duration` has elapsed, driven by the
+/// crate's [timer](timer::set_timer).
+///
+/// ```
+/// use promise_out::delay;
+/// use futures::executor::block_on;
+/// use std::time::{Duration, Instant};
+/// let start = Instant::now();
+/// assert_eq!(Ok(()), block_on(delay(Duration::from_millis(10))));
+/// assert!(start.elapsed() >= Duration::from_millis(10));
+/// ```
+#[cfg(feature = "timer")]
+pub fn delay(duration: std::time::Duration) -> pair::Consumer<()> {
+    timer::delay(duration)
+}
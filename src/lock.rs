@@ -0,0 +1,330 @@
+//! lock selects the mutex guarding the inner state of the pair, poly, and
+//! channel flavors. Their handles take a [`RawMutex`] type parameter, so a
+//! downstream crate can plug in any raw mutex, such as
+//! `parking_lot::RawMutex` or, with spin's `lock_api` feature,
+//! `spin::Mutex<()>`. It defaults to [`DefaultRawMutex`], which needs nothing
+//! beyond std, or with the `parking_lot` feature is parking_lot's, which is
+//! cheaper under contention. The `critical-section` feature picks
+//! `CriticalSectionRawMutex`, which masks interrupts while locked so that a
+//! promise can be resolved from an interrupt handler, and the `spin` feature
+//! a spin lock, for targets without std locks. When several are enabled,
+//! `parking_lot` wins over `critical-section`, which wins over `spin`.
+//! [`LocalRawMutex`] is never the default: it is chosen explicitly for a
+//! promise whose halves stay on one thread, as in wasm or on a tokio
+//! `LocalSet`, and locks with a plain flag instead of atomics.
+//! Without the `std` feature there is no [`StdRawMutex`], so `spin` or
+//! `critical-section` is required.
+//!
+//! ```
+//! use promise_out::{lock::StdRawMutex, pair, Promise};
+//! use futures::executor::block_on;
+//! let (promise, consumer) = pair::Producer::<u32, StdRawMutex>::new();
+//! promise.resolve(1);
+//! assert_eq!(Ok(1), block_on(consumer));
+//! ```
+use core::cell::Cell;
+#[cfg(feature = "critical-section")]
+use critical_section::RestoreState;
+use lock_api::GuardNoSend;
+#[cfg(feature = "std")]
+use lock_api::GuardSend;
+pub use lock_api::RawMutex;
+#[cfg(feature = "std")]
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Condvar, Mutex, PoisonError,
+};
+
+#[cfg(not(any(feature = "std", feature = "spin", feature = "critical-section")))]
+compile_error!("promise_out needs the spin or critical-section feature without std");
+
+/// The raw mutex used when none is chosen: [`StdRawMutex`], or
+/// `parking_lot::RawMutex` with the `parking_lot` feature, or else
+/// `CriticalSectionRawMutex` with the `critical-section` feature, or else
+/// `spin::mutex::SpinMutex<()>` with the `spin` feature.
+#[cfg(all(
+    feature = "std",
+    not(any(
+        feature = "parking_lot",
+        feature = "critical-section",
+        feature = "spin"
+    ))
+))]
+pub type DefaultRawMutex = StdRawMutex;
+
+/// The raw mutex used when none is chosen: [`StdRawMutex`], or
+/// `parking_lot::RawMutex` with the `parking_lot` feature, or else
+/// `CriticalSectionRawMutex` with the `critical-section` feature, or else
+/// `spin::mutex::SpinMutex<()>` with the `spin` feature.
+#[cfg(feature = "parking_lot")]
+pub type DefaultRawMutex = parking_lot::RawMutex;
+
+/// The raw mutex used when none is chosen: [`StdRawMutex`], or
+/// `parking_lot::RawMutex` with the `parking_lot` feature, or else
+/// `CriticalSectionRawMutex` with the `critical-section` feature, or else
+/// `spin::mutex::SpinMutex<()>` with the `spin` feature.
+#[cfg(all(feature = "critical-section", not(feature = "parking_lot")))]
+pub type DefaultRawMutex = CriticalSectionRawMutex;
+
+/// The raw mutex used when none is chosen: [`StdRawMutex`], or
+/// `parking_lot::RawMutex` with the `parking_lot` feature, or else
+/// `CriticalSectionRawMutex` with the `critical-section` feature, or else
+/// `spin::mutex::SpinMutex<()>` with the `spin` feature.
+#[cfg(all(
+    feature = "spin",
+    not(any(feature = "parking_lot", feature = "critical-section"))
+))]
+pub type DefaultRawMutex = spin::mutex::SpinMutex<()>;
+
+/// A [`RawMutex`] that holds a critical section while locked. Nothing else,
+/// not even an interrupt handler, runs in one, so locking never waits on a
+/// holder that it preempted. Guards must be released in the reverse order
+/// they were taken, as critical sections are left, so a guard can not be
+/// sent to another thread. A consumer whose promise is resolved in an
+/// interrupt handler is woken there, and polled once its executor runs again
+/// in thread mode.
+///
+/// Critical sections nest, so the mutex also keeps a flag to refuse a second
+/// lock from inside the first:
+///
+/// ```
+/// use promise_out::lock::{CriticalSectionRawMutex, RawMutex};
+/// let mutex = CriticalSectionRawMutex::INIT;
+/// mutex.lock();
+/// assert!(!mutex.try_lock());
+/// unsafe { mutex.unlock() };
+/// assert!(mutex.try_lock());
+/// unsafe { mutex.unlock() };
+/// ```
+#[cfg(feature = "critical-section")]
+#[derive(Debug)]
+pub struct CriticalSectionRawMutex {
+    restore: Cell<RestoreState>,
+    locked: Cell<bool>,
+}
+
+// The cells are only touched inside the critical section.
+#[cfg(feature = "critical-section")]
+unsafe impl Sync for CriticalSectionRawMutex {}
+
+#[cfg(feature = "critical-section")]
+unsafe impl RawMutex for CriticalSectionRawMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = CriticalSectionRawMutex {
+        restore: Cell::new(RestoreState::invalid()),
+        locked: Cell::new(false),
+    };
+
+    type GuardMarker = GuardNoSend;
+
+    /// # Panics
+    ///
+    /// Panics if the lock is already held. Nothing can release it while this
+    /// critical section runs, so waiting would never end.
+    fn lock(&self) {
+        assert!(
+            self.try_lock(),
+            "CriticalSectionRawMutex locked twice in one critical section"
+        );
+    }
+
+    fn try_lock(&self) -> bool {
+        let restore = unsafe { critical_section::acquire() };
+        if self.locked.replace(true) {
+            unsafe { critical_section::release(restore) };
+            return false;
+        }
+        self.restore.set(restore);
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.set(false);
+        critical_section::release(self.restore.replace(RestoreState::invalid()));
+    }
+}
+
+/// A [`RawMutex`] for promises whose producer and consumer live on the same
+/// thread. It is neither `Send` nor `Sync`, so neither are the halves of a
+/// promise that uses it, and the compiler keeps them on their thread. Since
+/// no other thread can hold it, locking only checks and sets a flag.
+///
+/// ```
+/// use promise_out::{lock::LocalRawMutex, pair, Promise};
+/// use futures::executor::block_on;
+/// let (promise, consumer) = pair::Producer::<u32, LocalRawMutex>::new();
+/// promise.resolve(1);
+/// assert_eq!(Ok(1), block_on(consumer));
+/// ```
+///
+/// ```compile_fail
+/// use promise_out::{lock::LocalRawMutex, pair, Promise};
+/// let (promise, consumer) = pair::Producer::<u32, LocalRawMutex>::new();
+/// std::thread::spawn(move || promise.resolve(1));
+/// ```
+#[derive(Debug)]
+pub struct LocalRawMutex {
+    locked: Cell<bool>,
+}
+
+unsafe impl RawMutex for LocalRawMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = LocalRawMutex {
+        locked: Cell::new(false),
+    };
+
+    type GuardMarker = GuardNoSend;
+
+    /// # Panics
+    ///
+    /// Panics if the lock is already held, which on a single thread would
+    /// otherwise never be released.
+    #[inline]
+    fn lock(&self) {
+        assert!(self.try_lock(), "LocalRawMutex locked twice on one thread");
+    }
+
+    #[inline]
+    fn try_lock(&self) -> bool {
+        !self.locked.replace(true)
+    }
+
+    #[inline]
+    unsafe fn unlock(&self) {
+        self.locked.set(false);
+    }
+}
+
+/// A [`RawMutex`] built from std's `Mutex` and `Condvar`. Uncontended, it
+/// locks and unlocks with one atomic operation each, and only a thread that
+/// finds it locked takes the `Mutex` to sleep on the `Condvar`.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdRawMutex {
+    state: AtomicU8,
+    parked: Mutex<()>,
+    unlocked: Condvar,
+}
+
+#[cfg(feature = "std")]
+const UNLOCKED: u8 = 0;
+#[cfg(feature = "std")]
+const LOCKED: u8 = 1;
+/// Locked, and a thread may be waiting on the condvar.
+#[cfg(feature = "std")]
+const CONTENDED: u8 = 2;
+
+#[cfg(feature = "std")]
+unsafe impl RawMutex for StdRawMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = StdRawMutex {
+        state: AtomicU8::new(UNLOCKED),
+        parked: Mutex::new(()),
+        unlocked: Condvar::new(),
+    };
+
+    type GuardMarker = GuardSend;
+
+    #[inline]
+    fn lock(&self) {
+        if !self.try_lock() {
+            self.lock_contended()
+        }
+    }
+
+    #[inline]
+    fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline]
+    unsafe fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            // Taking the mutex waits for a waiter that saw the lock held to
+            // be asleep on the condvar, so the notification is not lost.
+            drop(self.parked.lock().unwrap_or_else(PoisonError::into_inner));
+            self.unlocked.notify_one();
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl StdRawMutex {
+    #[cold]
+    fn lock_contended(&self) {
+        // No user code runs while the mutex is held, so poisoning can not
+        // leave anything inconsistent.
+        let mut parked = self.parked.lock().unwrap_or_else(PoisonError::into_inner);
+        // Marking the lock contended makes the holder notify on unlock. A
+        // thread that wins it this way leaves it marked, which at worst
+        // costs one needless notification.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            parked = self
+                .unlocked
+                .wait(parked)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{RawMutex, StdRawMutex};
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_std_raw_mutex_excludes() {
+        let counter = Arc::new(lock_api::Mutex::<StdRawMutex, u32>::new(0));
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *counter.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.join().expect("The task thread has panicked");
+        }
+        assert_eq!(4000, *counter.lock());
+        let raw = StdRawMutex::INIT;
+        assert!(raw.try_lock());
+        assert!(!raw.try_lock());
+        unsafe { raw.unlock() };
+        assert!(raw.try_lock());
+    }
+
+    #[test]
+    #[cfg(feature = "poly")]
+    fn test_local_raw_mutex_resolves_on_its_thread() {
+        use super::LocalRawMutex;
+        use crate::{poly, Promise};
+        use futures::executor::block_on;
+        let raw = LocalRawMutex::INIT;
+        assert!(raw.try_lock());
+        assert!(!raw.try_lock());
+        unsafe { raw.unlock() };
+        let (promise, consumer) = poly::Producer::<u32, LocalRawMutex>::new();
+        let copy = consumer.clone();
+        promise.resolve(2);
+        assert_eq!(2, *block_on(consumer).unwrap());
+        assert_eq!(2, *block_on(copy).unwrap());
+    }
+
+    #[cfg(feature = "critical-section")]
+    #[test]
+    fn test_critical_section_resolves() {
+        use super::CriticalSectionRawMutex;
+        use crate::{pair, Promise};
+        use futures::executor::block_on;
+        let (promise, consumer) = pair::Producer::<u32, CriticalSectionRawMutex>::new();
+        // Stands in for an interrupt handler, which runs in a critical section.
+        let task = thread::spawn(move || critical_section::with(|_| promise.resolve(1)));
+        assert_eq!(Ok(1), block_on(consumer));
+        task.join().expect("The task thread has panicked");
+    }
+}
@@ -0,0 +1,220 @@
+//! mailbox implements the ask pattern for actors. Any number of [`Mailbox`]
+//! handles queue requests for a single [`Inbox`], and each request travels
+//! with the pair producer of its reply: the asker awaits the consumer, and the
+//! handler resolves the producer once it has an answer. A request the handler
+//! never answers, or that is still queued when the inbox is dropped, fails
+//! with [`Error::ProducerDropped`](crate::Error::ProducerDropped).
+//!
+//! Requests that carry their reply producer themselves, such as an enum with
+//! a producer in each variant, are sent through [`Post`] instead; with the
+//! `derive` feature, `#[derive(Ask)]` generates the typed `ask_*` methods.
+use crate::{pair, Promise};
+use futures_core::Stream;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    pin::Pin,
+    sync::{
+        mpsc::{Sender, SyncSender},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+type Letter<Req, Resp> = (Req, pair::Producer<Resp>);
+
+struct State<Req, Resp> {
+    queue: VecDeque<Letter<Req, Resp>>,
+    waker: Option<Waker>,
+    mailboxes: usize,
+    /// False once the inbox is gone.
+    open: bool,
+}
+
+/// Sends requests to the inbox. Clones share the inbox.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{mailbox, Promise};
+/// use futures::{executor::block_on, StreamExt};
+/// use std::thread;
+/// let (mailbox, mut inbox) = mailbox::new::<u32, u32>();
+/// let actor = thread::spawn(move || {
+///     let mut total = 0;
+///     while let Some((n, reply)) = block_on(inbox.next()) {
+///         total += n;
+///         reply.resolve(total);
+///     }
+/// });
+/// assert_eq!(Ok(2), block_on(mailbox.ask(2)));
+/// assert_eq!(Ok(5), block_on(mailbox.ask(3)));
+/// drop(mailbox);
+/// actor.join().unwrap();
+/// ```
+pub struct Mailbox<Req, Resp> {
+    state: Arc<Mutex<State<Req, Resp>>>,
+}
+
+/// Receives the requests sent to its mailboxes, each with its reply producer.
+/// The stream ends once every mailbox is dropped and the queue is drained.
+#[must_use = "streams do nothing unless polled"]
+pub struct Inbox<Req, Resp> {
+    state: Arc<Mutex<State<Req, Resp>>>,
+}
+
+/// Return a mailbox and the inbox it delivers to.
+pub fn new<Req, Resp>() -> (Mailbox<Req, Resp>, Inbox<Req, Resp>) {
+    let state = Arc::new(Mutex::new(State {
+        queue: VecDeque::new(),
+        waker: None,
+        mailboxes: 1,
+        open: true,
+    }));
+    (
+        Mailbox {
+            state: state.clone(),
+        },
+        Inbox { state },
+    )
+}
+
+impl<Req, Resp> Mailbox<Req, Resp> {
+    /// Queue `request` and return the consumer of its reply.
+    pub fn ask(&self, request: Req) -> pair::Consumer<Resp> {
+        let (producer, consumer) = pair::Producer::new();
+        let mut state = self.state.lock().unwrap();
+        // With the inbox gone, the producer is dropped here instead.
+        if state.open {
+            state.queue.push_back((request, producer));
+            if let Some(waker) = state.waker.take() {
+                waker.wake()
+            }
+        }
+        consumer
+    }
+
+    /// Return false once the inbox has been dropped.
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open
+    }
+
+    /// Return the number of requests waiting for the handler.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<Req, Resp> Clone for Mailbox<Req, Resp> {
+    fn clone(&self) -> Self {
+        self.state.lock().unwrap().mailboxes += 1;
+        Mailbox {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Drop for Mailbox<Req, Resp> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.mailboxes -= 1;
+        if state.mailboxes == 0 {
+            if let Some(waker) = state.waker.take() {
+                waker.wake()
+            }
+        }
+    }
+}
+
+impl<Req, Resp> Debug for Mailbox<Req, Resp> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Mailbox")
+            .field("queued", &state.queue.len())
+            .field("open", &state.open)
+            .finish()
+    }
+}
+
+impl<Req, Resp> Stream for Inbox<Req, Resp> {
+    type Item = Letter<Req, Resp>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(letter) = state.queue.pop_front() {
+            return Poll::Ready(Some(letter));
+        }
+        if state.mailboxes == 0 {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<Req, Resp> Drop for Inbox<Req, Resp> {
+    fn drop(&mut self) {
+        let queue = {
+            let mut state = self.state.lock().unwrap();
+            state.open = false;
+            std::mem::take(&mut state.queue)
+        };
+        // Rejects the queued requests outside the lock.
+        drop(queue);
+    }
+}
+
+impl<Req, Resp> Debug for Inbox<Req, Resp> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Inbox")
+            .field("queued", &state.queue.len())
+            .field("mailboxes", &state.mailboxes)
+            .finish()
+    }
+}
+
+/// Delivers a message that carries its own reply producer. A message that
+/// cannot be delivered is dropped, which rejects its reply.
+pub trait Post<M> {
+    fn post(&self, message: M);
+}
+
+impl<M> Post<M> for Sender<M> {
+    fn post(&self, message: M) {
+        let _ = self.send(message);
+    }
+}
+
+impl<M> Post<M> for SyncSender<M> {
+    /// Blocks while the channel is full.
+    fn post(&self, message: M) {
+        let _ = self.send(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mailbox, Error};
+    use futures::{executor::block_on, StreamExt};
+
+    #[test]
+    fn test_mailbox_rejects_unanswered() {
+        let (mailbox, mut inbox) = mailbox::new::<&str, usize>();
+        let ignored = mailbox.ask("ignored");
+        let queued = mailbox.ask("queued");
+        assert_eq!(2, mailbox.len());
+        let (request, reply) = block_on(inbox.next()).unwrap();
+        assert_eq!("ignored", request);
+        drop(reply);
+        assert_eq!(Err(Error::ProducerDropped), block_on(ignored));
+        drop(inbox);
+        assert_eq!(Err(Error::ProducerDropped), block_on(queued));
+        assert!(!mailbox.is_open());
+        assert_eq!(Err(Error::ProducerDropped), block_on(mailbox.ask("late")));
+    }
+}
@@ -0,0 +1,519 @@
+//! mpmc implements a multi-producer, multi-consumer promise. Both halves may be
+//! cloned; by default the first producer to resolve wins, and every consumer
+//! observes the winning value. A [`SettlePolicy`] can pick the winner
+//! differently, see [`Producer::with_policy`].
+use crate::{
+    envelope::{Envelope, Metadata},
+    settle::{SettlePolicy, Verdict},
+    tracked::Tracked,
+    Cancel, Error, Promise, PromiseId, WakerState,
+};
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, Weak};
+use std::{
+    future::Future,
+    task::{Poll, Waker},
+};
+
+/// This `mpmc::Producer` promise can have many producers and many consumers.
+/// The consumers return a `Result<Arc<T>, Error>`; an error is returned if
+/// every producer has been dropped without resolving.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, mpmc::Producer};
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let (promise, consumer) = Producer::<&str>::new();
+/// let peers: Vec<_> = ["alice", "bob"].into_iter().map(|name| {
+///     let promise = promise.clone();
+///     thread::spawn(move || promise.resolve(name))
+/// }).collect();
+/// let winner = block_on(consumer.clone()).unwrap();
+/// assert_eq!(winner, block_on(consumer).unwrap());
+/// # for peer in peers { peer.join().unwrap(); }
+/// ```
+#[derive(Debug)]
+pub struct Producer<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+}
+
+#[derive(Debug)]
+pub struct Consumer<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    id: PromiseId,
+    metadata: Option<Arc<Metadata>>,
+    tracked: Tracked,
+    value: Option<Arc<T>>,
+    /// A value that will settle the promise once every producer is gone,
+    /// unless another attempt replaces it first.
+    held: Option<T>,
+    policy: Option<Policy<T>>,
+    waker: Result<Vec<Waker>, WakerState>,
+    producers: usize,
+    consumers: usize,
+    cancel: Cancel,
+}
+
+struct Policy<T>(Box<dyn SettlePolicy<T> + Send>);
+
+impl<T> Debug for Policy<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Policy")
+    }
+}
+
+impl<T> Inner<T> {
+    fn settle(&mut self, value: T) {
+        self.value = Some(Arc::new(value));
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        if let Ok(wakers) = std::mem::replace(&mut self.waker, Err(WakerState::Tainted)) {
+            for waker in wakers {
+                waker.wake()
+            }
+        }
+    }
+}
+
+impl<T> Promise<T> for Producer<T> {
+    type Waiter = Consumer<T>;
+
+    /// Resolve the promise unless another producer already has or the policy
+    /// refuses it, in which case `value` is dropped.
+    fn resolve(self, value: T) {
+        let _ = self.try_resolve(value);
+    }
+
+    fn new() -> (Self, Consumer<T>) {
+        let promise = Arc::new(Mutex::new(Inner {
+            id: PromiseId::next(),
+            metadata: None,
+            tracked: Tracked::new(),
+            value: None,
+            held: None,
+            policy: None,
+            waker: Err(WakerState::Fresh),
+            producers: 1,
+            consumers: 1,
+            cancel: Cancel::default(),
+        }));
+        (
+            Producer {
+                promise: promise.clone(),
+            },
+            Consumer { promise },
+        )
+    }
+
+    fn resolved(value: T) -> (Self, Consumer<T>) {
+        let (producer, consumer) = Self::new();
+        let _ = producer.try_resolve(value);
+        (producer, consumer)
+    }
+}
+
+impl<T> Producer<T> {
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().unwrap().id
+    }
+
+    /// Return a (producer, consumer) pair carrying `metadata`, readable from
+    /// both halves through [`Envelope`].
+    pub fn with_metadata(metadata: Metadata) -> (Self, Consumer<T>) {
+        let (producer, consumer) = Self::new();
+        producer.promise.lock().unwrap().metadata = Some(Arc::new(metadata));
+        (producer, consumer)
+    }
+
+    /// Return a (producer, consumer) pair whose producers settle it as
+    /// `policy` decides.
+    ///
+    /// ```
+    /// use promise_out::{Promise, mpmc::Producer, settle::LastWins};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u32>::with_policy(LastWins);
+    /// let replica = promise.clone();
+    /// assert_eq!(Ok(None), promise.offer(1));
+    /// assert_eq!(Ok(Some(1)), replica.offer(2));
+    /// drop(promise);
+    /// assert!(!replica.is_resolved());
+    /// drop(replica);
+    /// assert_eq!(2, *block_on(consumer).unwrap());
+    /// ```
+    pub fn with_policy(policy: impl SettlePolicy<T> + Send + 'static) -> (Self, Consumer<T>) {
+        let (producer, consumer) = Self::new();
+        producer.promise.lock().unwrap().policy = Some(Policy(Box::new(policy)));
+        (producer, consumer)
+    }
+
+    /// Offer `value` to settle the promise. Returns the value it displaced,
+    /// if the policy had one held, or hands `value` back if it lost: the
+    /// promise was already resolved or the policy refused it.
+    pub fn offer(&self, value: T) -> Result<Option<T>, T> {
+        let mut promise = self.promise.lock().unwrap();
+        if promise.value.is_some() {
+            return Err(value);
+        }
+        let verdict = match &promise.policy {
+            Some(policy) => policy.0.judge(promise.held.as_ref(), &value),
+            None => Verdict::Settle,
+        };
+        match verdict {
+            Verdict::Reject => Err(value),
+            Verdict::Hold => Ok(promise.held.replace(value)),
+            Verdict::Settle => {
+                let displaced = promise.held.take();
+                promise.settle(value);
+                Ok(displaced)
+            }
+        }
+    }
+
+    /// Resolve the promise, or hand `value` back if another producer already
+    /// resolved it or the policy refused it. A value displaced later by
+    /// another attempt is dropped.
+    pub fn try_resolve(&self, value: T) -> Result<(), T> {
+        self.offer(value).map(drop)
+    }
+
+    /// Return true if the promise has been resolved.
+    pub fn is_resolved(&self) -> bool {
+        self.promise.lock().unwrap().value.is_some()
+    }
+
+    /// Return true if every consumer has been dropped, so resolving the
+    /// promise would go unobserved.
+    pub fn is_closed(&self) -> bool {
+        self.promise.lock().unwrap().cancel.closed
+    }
+
+    /// Return a future that resolves once every consumer has been dropped.
+    pub fn closed(&self) -> Closed<T> {
+        Closed {
+            promise: self.promise.clone(),
+        }
+    }
+
+    /// Return a weak handle that can be upgraded back into a producer while
+    /// some producer is alive. It does not keep the promise pending: once
+    /// every producer is dropped the consumers fail, even while weak handles
+    /// remain.
+    ///
+    /// ```
+    /// use promise_out::{Promise, mpmc::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u32>::new();
+    /// let weak = promise.downgrade();
+    /// weak.upgrade().unwrap().resolve(1);
+    /// assert_eq!(1, *block_on(consumer.clone()).unwrap());
+    /// drop(promise);
+    /// assert!(weak.upgrade().is_none());
+    /// ```
+    pub fn downgrade(&self) -> WeakProducer<T> {
+        WeakProducer {
+            promise: Arc::downgrade(&self.promise),
+        }
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().unwrap().id
+    }
+
+    /// Return a weak handle that can be upgraded back into a consumer while
+    /// some consumer is alive, without keeping the promise open itself.
+    pub fn downgrade(&self) -> WeakConsumer<T> {
+        WeakConsumer {
+            promise: Arc::downgrade(&self.promise),
+        }
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.promise.lock().unwrap().producers += 1;
+        Producer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    /// If this is the last producer, settle the promise with the held value,
+    /// or if there is none and the promise is unresolved, wake every consumer
+    /// with an error.
+    fn drop(&mut self) {
+        let mut promise = self.promise.lock().unwrap();
+        promise.producers -= 1;
+        if promise.producers == 0 {
+            match promise.held.take() {
+                Some(value) => promise.settle(value),
+                None => promise.wake(),
+            }
+        }
+    }
+}
+
+impl<T> Clone for Consumer<T> {
+    fn clone(&self) -> Self {
+        self.promise.lock().unwrap().consumers += 1;
+        Consumer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    /// Let the producers know once the last consumer is gone.
+    fn drop(&mut self) {
+        let mut promise = self.promise.lock().unwrap();
+        promise.consumers -= 1;
+        if promise.consumers == 0 {
+            promise.cancel.close();
+        }
+    }
+}
+
+impl<T> Future for Consumer<T> {
+    type Output = Result<Arc<T>, Error>;
+
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let mut promise = self.promise.lock().unwrap();
+        if let Some(ref value) = promise.value {
+            return Poll::Ready(Ok(value.clone()));
+        }
+        match &mut promise.waker {
+            Err(WakerState::Tainted) => return Poll::Ready(Err(Error::ProducerDropped)),
+            Err(WakerState::Fresh) => promise.waker = Ok(vec![cx.waker().clone()]),
+            Ok(wakers) => {
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone())
+                }
+            }
+        }
+        promise.tracked.poll_shutdown(cx).map(Err)
+    }
+}
+
+/// A handle to a producer that does not count as one. See
+/// [`Producer::downgrade`].
+#[derive(Debug)]
+pub struct WeakProducer<T> {
+    promise: Weak<Mutex<Inner<T>>>,
+}
+
+impl<T> WeakProducer<T> {
+    /// Return a producer again, unless every producer has been dropped in the
+    /// meantime.
+    pub fn upgrade(&self) -> Option<Producer<T>> {
+        let promise = self.promise.upgrade()?;
+        {
+            let mut inner = promise.lock().unwrap();
+            if inner.producers == 0 {
+                return None;
+            }
+            inner.producers += 1;
+        }
+        Some(Producer { promise })
+    }
+}
+
+impl<T> Clone for WeakProducer<T> {
+    fn clone(&self) -> Self {
+        WeakProducer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+/// A handle to a consumer that does not count as one: once every consumer
+/// has been dropped the producers see the promise as closed, even while
+/// weak handles remain. See [`Consumer::downgrade`].
+#[derive(Debug)]
+pub struct WeakConsumer<T> {
+    promise: Weak<Mutex<Inner<T>>>,
+}
+
+impl<T> WeakConsumer<T> {
+    /// Return a consumer again, unless every consumer has been dropped in the
+    /// meantime.
+    pub fn upgrade(&self) -> Option<Consumer<T>> {
+        let promise = self.promise.upgrade()?;
+        {
+            let mut inner = promise.lock().unwrap();
+            if inner.consumers == 0 {
+                return None;
+            }
+            inner.consumers += 1;
+        }
+        Some(Consumer { promise })
+    }
+}
+
+impl<T> Clone for WeakConsumer<T> {
+    fn clone(&self) -> Self {
+        WeakConsumer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T> Envelope for Producer<T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().unwrap().metadata.clone()
+    }
+}
+
+impl<T> Envelope for Consumer<T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().unwrap().metadata.clone()
+    }
+}
+
+impl<T> PartialEq for Producer<T> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T> Eq for Producer<T> {}
+
+impl<T> Hash for Producer<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.promise).hash(state)
+    }
+}
+
+impl<T> PartialEq for Consumer<T> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T> Eq for Consumer<T> {}
+
+impl<T> Hash for Consumer<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.promise).hash(state)
+    }
+}
+
+/// Future for [`Producer::closed`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Closed<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Future for Closed<T> {
+    type Output = ();
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<()> {
+        self.promise.lock().unwrap().cancel.poll_closed(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Producer;
+    use crate::{settle::FirstErrorWins, Error, Promise};
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_first_resolve_wins() {
+        let (op, op_a) = Producer::<String>::new();
+        let op2 = op.clone();
+        let op_b = op_a.clone();
+        let task1 = thread::spawn(move || block_on(op_a));
+        assert_eq!(Ok(()), op.try_resolve(String::from("🍓")));
+        assert_eq!(Err(String::from("🍌")), op2.try_resolve(String::from("🍌")));
+        assert!(op2.is_resolved());
+        assert_eq!(
+            "🍓",
+            *task1
+                .join()
+                .expect("The task1 thread has panicked")
+                .unwrap()
+        );
+        assert_eq!("🍓", *block_on(op_b).unwrap());
+    }
+
+    #[test]
+    fn test_resolved_and_rejected() {
+        let (op, op_a) = Producer::<&str>::resolved("🍓");
+        assert!(op.is_resolved());
+        assert_eq!(Err("🍌"), op.try_resolve("🍌"));
+        assert_eq!("🍓", *block_on(op_a).unwrap());
+        let (_, op_a) = Producer::<Result<u8, &str>>::rejected("reject!");
+        assert_eq!(Err("reject!"), *block_on(op_a).unwrap());
+    }
+
+    #[test]
+    fn test_first_error_wins_all_ok() {
+        let (op, op_a) = Producer::<Result<u32, ()>>::with_policy(FirstErrorWins);
+        let tasks: Vec<_> = (0..4)
+            .map(|n| {
+                let op = op.clone();
+                thread::spawn(move || op.resolve(Ok(n)))
+            })
+            .collect();
+        for task in tasks {
+            task.join().expect("The task thread has panicked");
+        }
+        assert!(!op.is_resolved());
+        drop(op);
+        assert!(block_on(op_a).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_all_producers_dropped() {
+        let (op, op_a) = Producer::<String>::new();
+        let op2 = op.clone();
+        let task1 = thread::spawn(move || block_on(op_a));
+        drop(op);
+        drop(op2);
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            task1.join().expect("The task1 thread has panicked")
+        );
+    }
+
+    #[test]
+    fn test_all_consumers_dropped() {
+        let (op, op_a) = Producer::<String>::new();
+        let op_b = op_a.clone();
+        drop(op_a);
+        assert!(!op.is_closed());
+        drop(op_b);
+        block_on(op.closed());
+    }
+
+    #[test]
+    fn test_repolling_keeps_one_waker() {
+        use futures::FutureExt;
+        let (_op, mut op_a) = Producer::<u8>::new();
+        for _ in 0..10 {
+            assert_eq!(None, (&mut op_a).now_or_never());
+        }
+        let promise = op_a.promise.lock().unwrap();
+        assert_eq!(Some(1), promise.waker.as_ref().ok().map(Vec::len));
+    }
+}
@@ -0,0 +1,192 @@
+//! notify implements a unit valued, re-armable signal. Every call to
+//! [`Notify::notified`] returns a future for the next signal, and
+//! [`Notify::notify_waiters`] wakes all of them at once, so one `Notify` serves
+//! round after round instead of recreating a `Producer<()>` pair each time.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug, Default)]
+struct State {
+    generation: u64,
+    wakers: Vec<Option<Waker>>,
+    // Slots of `wakers` left by dropped futures, reused before it grows.
+    free: Vec<usize>,
+}
+
+/// A re-armable signal.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::notify::Notify;
+/// use futures::executor::block_on;
+/// use std::{sync::Arc, thread};
+/// let notify = Arc::new(Notify::new());
+/// for _ in 0..2 {
+///     let waiter = notify.clone();
+///     let task1 = thread::spawn(move || block_on(waiter.notified()));
+///     while notify.waiters() == 0 {
+///         thread::yield_now();
+///     }
+///     notify.notify_waiters();
+///     task1.join().expect("The task1 thread has panicked.");
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+impl Notify {
+    /// Return a signal with no waiters. It is a `const fn`, so a `Notify` can
+    /// be a `static`.
+    pub const fn new() -> Self {
+        Notify {
+            state: Mutex::new(State {
+                generation: 0,
+                wakers: Vec::new(),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    /// Return a future that resolves on the next call to
+    /// [`notify_waiters`](Self::notify_waiters). Signals sent before this
+    /// call are not seen.
+    pub fn notified(&self) -> Notified<'_> {
+        let generation = self.state.lock().unwrap().generation;
+        Notified {
+            notify: self,
+            generation,
+            slot: None,
+        }
+    }
+
+    /// Wake every pending [`Notified`] future.
+    pub fn notify_waiters(&self) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.generation = state.generation.wrapping_add(1);
+            state.free.clear();
+            std::mem::take(&mut state.wakers)
+        };
+        for waker in wakers.into_iter().flatten() {
+            waker.wake()
+        }
+    }
+
+    /// Return how many futures are waiting for the next signal.
+    pub fn waiters(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.wakers.iter().flatten().count()
+    }
+}
+
+/// Future for [`Notify::notified`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: u64,
+    slot: Option<usize>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.notify.state.lock().unwrap();
+        if state.generation != self.generation {
+            return Poll::Ready(());
+        }
+        match self.slot {
+            Some(slot) => state.wakers[slot] = Some(cx.waker().clone()),
+            None => {
+                let waker = Some(cx.waker().clone());
+                let slot = match state.free.pop() {
+                    Some(slot) => {
+                        state.wakers[slot] = waker;
+                        slot
+                    }
+                    None => {
+                        state.wakers.push(waker);
+                        state.wakers.len() - 1
+                    }
+                };
+                drop(state);
+                self.slot = Some(slot);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            let mut state = self.notify.state.lock().unwrap();
+            if state.generation == self.generation {
+                state.wakers[slot] = None;
+                state.free.push(slot);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notify;
+    use futures::{executor::block_on, future::poll_fn};
+    use std::{future::Future, pin::Pin, sync::Arc, task::Poll, thread};
+
+    #[test]
+    fn test_notify_wakes_all_waiters() {
+        let notify = Arc::new(Notify::new());
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let notify = notify.clone();
+                thread::spawn(move || block_on(notify.notified()))
+            })
+            .collect();
+        while notify.waiters() < 3 {
+            thread::yield_now();
+        }
+        notify.notify_waiters();
+        for task in tasks {
+            task.join().expect("The task thread has panicked");
+        }
+    }
+
+    #[test]
+    fn test_notify_is_not_sticky() {
+        let notify = Notify::new();
+        notify.notify_waiters();
+        let mut notified = notify.notified();
+        block_on(poll_fn(|cx| {
+            assert!(Pin::new(&mut notified).poll(cx).is_pending());
+            Poll::Ready(())
+        }));
+        drop(notified);
+        assert_eq!(0, notify.waiters());
+    }
+
+    #[test]
+    fn test_dropped_waiters_free_their_slots() {
+        let notify = Notify::new();
+        let _kept = block_on(poll_fn(|cx| {
+            let mut kept = Box::pin(notify.notified());
+            assert!(kept.as_mut().poll(cx).is_pending());
+            for _ in 0..100 {
+                let mut notified = notify.notified();
+                assert!(Pin::new(&mut notified).poll(cx).is_pending());
+            }
+            Poll::Ready(kept)
+        }));
+        assert_eq!(1, notify.waiters());
+        assert_eq!(2, notify.state.lock().unwrap().wakers.len());
+    }
+}
@@ -0,0 +1,206 @@
+//! once_cell implements a cell that is initialized asynchronously at most once.
+//! The first caller runs the initializer and resolves a poly promise that every
+//! concurrent caller awaits; once it is resolved the value can be read without
+//! waiting. If the caller running the initializer gives up, the next waiting
+//! caller takes over.
+use crate::{poly, Promise};
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// A cell initialized once by an async initializer.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::once_cell::OnceCell;
+/// use futures::executor::block_on;
+/// let cell = OnceCell::new();
+/// assert_eq!(None, cell.get());
+/// let value = block_on(cell.get_or_init(|| async { String::from("config") }));
+/// assert_eq!("config", *value);
+/// let value = block_on(cell.get_or_init(|| async { unreachable!() }));
+/// assert_eq!("config", *value);
+/// assert_eq!("config", *cell.get().unwrap());
+/// ```
+#[derive(Debug)]
+pub struct OnceCell<T> {
+    // The promise of the current attempt to initialize the cell.
+    current: Mutex<Option<poly::Consumer<T>>>,
+}
+
+impl<T> OnceCell<T> {
+    /// Return an empty cell. It is a `const fn`, so the cell can be a
+    /// `static`.
+    pub const fn new() -> Self {
+        OnceCell {
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Return the value if the cell has been initialized.
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.current.lock().unwrap().as_ref().and_then(|c| c.peek())
+    }
+
+    /// Initialize the cell with `value`, unless it is initialized or being
+    /// initialized, in which case the value is handed back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref().is_some_and(|c| !c.is_failed()) {
+            return Err(value);
+        }
+        *current = Some(poly::Consumer::ready(value));
+        Ok(())
+    }
+
+    /// Return the value, running `init` to produce it if nobody has. Callers
+    /// that arrive while another caller's initializer runs wait for its
+    /// result; if that caller is dropped first, one of them runs its own
+    /// `init` instead.
+    pub fn get_or_init<F, Fut>(&self, init: F) -> GetOrInit<'_, T, F, Fut>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        GetOrInit {
+            cell: self,
+            init: Some(init),
+            running: None,
+            waiting: None,
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Running<T, Fut> = (Pin<Box<Fut>>, Option<poly::Producer<T>>, poly::Consumer<T>);
+
+/// Future for [`OnceCell::get_or_init`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct GetOrInit<'a, T, F, Fut> {
+    cell: &'a OnceCell<T>,
+    init: Option<F>,
+    // Set while this caller runs the initializer.
+    running: Option<Running<T, Fut>>,
+    // Set while this caller waits for another caller's initializer.
+    waiting: Option<poly::Consumer<T>>,
+}
+
+impl<T, F, Fut> Unpin for GetOrInit<'_, T, F, Fut> {}
+
+impl<T: Debug, F, Fut> Debug for GetOrInit<'_, T, F, Fut> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GetOrInit")
+            .field("cell", &self.cell)
+            .field("running", &self.running.is_some())
+            .field("waiting", &self.waiting)
+            .finish()
+    }
+}
+
+impl<T, F, Fut> Future for GetOrInit<'_, T, F, Fut>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    type Output = Arc<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            if let Some((init, producer, consumer)) = this.running.as_mut() {
+                let value = match init.as_mut().poll(cx) {
+                    Poll::Ready(value) => value,
+                    Poll::Pending => return Poll::Pending,
+                };
+                producer
+                    .take()
+                    .expect("GetOrInit must not be polled after it returned Ready")
+                    .resolve(value);
+                return Poll::Ready(consumer.peek().unwrap());
+            }
+            if let Some(waiting) = this.waiting.as_mut() {
+                match Pin::new(waiting).poll(cx) {
+                    Poll::Ready(Ok(value)) => return Poll::Ready(value),
+                    // The initializing caller was dropped; try again.
+                    Poll::Ready(Err(_)) => this.waiting = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let mut current = this.cell.current.lock().unwrap();
+            match current.as_ref() {
+                Some(consumer) if !consumer.is_failed() => this.waiting = Some(consumer.clone()),
+                _ => {
+                    let init = this
+                        .init
+                        .take()
+                        .expect("GetOrInit must not be polled after it returned Ready");
+                    let (producer, consumer) = poly::Producer::new();
+                    *current = Some(consumer.clone());
+                    drop(current);
+                    this.running = Some((Box::pin(init()), Some(producer), consumer));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OnceCell;
+    use futures::{executor::block_on, FutureExt};
+    use std::{
+        future::pending,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    #[test]
+    fn test_once_cell_initializes_once() {
+        let cell = Arc::new(OnceCell::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..4)
+            .map(|i| {
+                let cell = cell.clone();
+                let calls = calls.clone();
+                thread::spawn(move || {
+                    block_on(cell.get_or_init(|| async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        i
+                    }))
+                })
+            })
+            .collect();
+        let values: Vec<_> = tasks
+            .into_iter()
+            .map(|task| *task.join().expect("The task thread has panicked"))
+            .collect();
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert!(values.iter().all(|value| *value == values[0]));
+        assert_eq!(Err(5), cell.set(5));
+    }
+
+    #[test]
+    fn test_once_cell_leader_dropped() {
+        let cell = OnceCell::new();
+        let mut leader = cell.get_or_init(pending::<u32>);
+        assert_eq!(None, (&mut leader).now_or_never());
+        let mut follower = cell.get_or_init(|| async { 2 });
+        assert_eq!(None, (&mut follower).now_or_never());
+        drop(leader);
+        assert_eq!(2, *block_on(follower));
+        assert_eq!(Some(2), cell.get().as_deref().copied());
+    }
+}
@@ -0,0 +1,72 @@
+//! padded aligns the shared state of a promise to a cache line with the
+//! `cache-padded` feature. Producer and consumer usually run on different
+//! cores, and states allocated back to back would otherwise share lines, so
+//! locking one promise would evict its neighbours from the other cores'
+//! caches. The state sits behind a single lock, so there are no producer and
+//! consumer fields to split, and the lock and every field are padded as one.
+//! That makes every promise at least a line long, so it is opt-in: without
+//! the feature [`CachePadded`] adds nothing.
+use core::ops::{Deref, DerefMut};
+
+/// Pads and aligns a value to the length of a cache line, or of the pair of
+/// lines the prefetcher moves together on x86-64 and aarch64, with the
+/// `cache-padded` feature.
+#[cfg_attr(
+    all(
+        feature = "cache-padded",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "powerpc64"
+        )
+    ),
+    repr(align(128))
+)]
+#[cfg_attr(
+    all(
+        feature = "cache-padded",
+        not(any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "powerpc64"
+        ))
+    ),
+    repr(align(64))
+)]
+#[derive(Debug, Default)]
+pub(crate) struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        CachePadded(value)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[cfg(all(test, feature = "cache-padded"))]
+mod tests {
+    use super::CachePadded;
+    use core::mem::{align_of, size_of};
+
+    #[test]
+    fn test_padded_to_a_line() {
+        assert!(align_of::<CachePadded<u8>>() >= 64);
+        assert_eq!(align_of::<CachePadded<u8>>(), size_of::<CachePadded<u8>>());
+        let values = [CachePadded::new(1u8), CachePadded::new(2u8)];
+        let distance = &*values[1] as *const u8 as usize - &*values[0] as *const u8 as usize;
+        assert!(distance >= 64);
+    }
+}
@@ -1,12 +1,26 @@
 //! pair implements a single-producer, single-consumer promise. Neither the producer
 //! nor the consumer can be cloned.
-use crate::{Error, Promise, WakerState};
-use std::fmt::Debug;
-use std::sync::{Arc, Mutex};
-use std::{
+use crate::{
+    allocator::{Allocator, Global, Shared},
+    envelope::{Envelope, Metadata},
+    lock::{DefaultRawMutex, RawMutex},
+    padded::CachePadded,
+    slot::Slot,
+    tracked::Tracked,
+    Cancel, Error, Guarded, Promise, PromiseId, WakerState,
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::fmt::Debug;
+use core::hash::{Hash, Hasher};
+use core::mem::MaybeUninit;
+#[cfg(feature = "futures")]
+use core::pin::Pin;
+use core::{
     future::Future,
     task::{Poll, Waker},
 };
+#[cfg(feature = "futures")]
+use futures_core::Stream;
 
 /// This `pair::Producer` promise can only have one consumer. The consumer
 /// returns a `Result<T,Error>`. An error is returned if the only producer has
@@ -26,24 +40,59 @@ use std::{
 /// promise.resolve("Hi".into());
 /// task1.join().expect("The task1 thread has panicked.");
 /// ```
-#[derive(Debug)]
-pub struct Producer<T> {
-    promise: Arc<Mutex<Inner<T>>>,
+pub struct Producer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
 }
 
-#[derive(Debug)]
-pub struct Consumer<T> {
-    promise: Arc<Mutex<Inner<T>>>,
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Producer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+pub struct Consumer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Consumer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("promise", &self.promise)
+            .finish()
+    }
 }
 
 #[derive(Debug)]
 struct Inner<T> {
-    value: Option<T>,
+    id: PromiseId,
+    metadata: Option<Arc<Metadata>>,
+    tracked: Tracked,
+    value: Slot<T>,
     waker: Result<Waker, WakerState>,
+    cancel: Cancel,
+}
+
+/// The state shared by the halves of a promise, see [`CachePadded`].
+type SharedInner<T, R, A> = Shared<CachePadded<Guarded<R, Inner<T>>>, A>;
+
+impl<T> Inner<T> {
+    /// Return the state of a new promise that has not been resolved.
+    fn pending() -> Self {
+        Inner {
+            id: PromiseId::next(),
+            metadata: None,
+            tracked: Tracked::new(),
+            value: Slot::empty(),
+            waker: Err(WakerState::Fresh),
+            cancel: Cancel::default(),
+        }
+    }
 }
 
-impl<T> Promise<T> for Producer<T> {
-    type Waiter = Consumer<T>;
+impl<T, R: RawMutex, A: Allocator + Clone + Default> Promise<T> for Producer<T, R, A> {
+    type Waiter = Consumer<T, R, A>;
     #[allow(dead_code)]
     ///promiseOut.resolve
     ///
@@ -65,18 +114,76 @@ impl<T> Promise<T> for Producer<T> {
     /// task2.join().expect("The task2 thread has panicked");
     /// ```
     fn resolve(self, value: T) {
-        let mut promise = self.promise.lock().unwrap();
-        promise.value = Some(value);
-        if let Ok(waker) = std::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
+        Producer::resolve(self, value)
+    }
+
+    fn new() -> (Self, Consumer<T, R, A>) {
+        Self::new_in(A::default())
+    }
+
+    fn resolved(value: T) -> (Self, Consumer<T, R, A>) {
+        let (producer, consumer) = Self::new();
+        producer.settle_in_place(|slot| slot.write(value));
+        (producer, consumer)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Producer<T, R, A> {
+    /// Resolve the promise's value. Unlike [`Promise::resolve`], this also
+    /// works for allocators without a default.
+    #[inline]
+    pub fn resolve(self, value: T) {
+        self.resolve_in_place(|slot| slot.write(value))
+    }
+
+    /// Resolve the promise with the value `init` writes into its storage, so
+    /// a value of several kilobytes is built where the consumer reads it
+    /// instead of on this thread's stack, from where it would be moved twice.
+    /// `init` returns the reference [`MaybeUninit::write`] gives back, which
+    /// shows that it wrote the value. It runs with the promise's lock held.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `init` returns any other reference. The consumer then fails
+    /// as if the producer had been dropped.
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<[u8; 8192]>::new();
+    /// promise.resolve_in_place(|slot| slot.write([7; 8192]));
+    /// assert_eq!(7, block_on(consumer).unwrap()[8191]);
+    /// ```
+    pub fn resolve_in_place(self, init: impl FnOnce(&mut MaybeUninit<T>) -> &mut T) {
+        self.settle_in_place(init)
+    }
+
+    /// Store the value `init` writes and wake the consumer, unless the promise
+    /// was made by [`Promise::resolved`] and so is already settled.
+    fn settle_in_place(&self, init: impl FnOnce(&mut MaybeUninit<T>) -> &mut T) {
+        let mut promise = self.promise.lock();
+        if self.promise.is_settled() {
+            return;
+        }
+        promise.value.init(init);
+        self.promise.settle();
+        let waker = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted));
+        // Wake with the lock released, so the consumer does not wait for it.
+        drop(promise);
+        if let Ok(waker) = waker {
             waker.wake()
         }
     }
 
-    fn new() -> (Self, Consumer<T>) {
-        let inner = Arc::new(Mutex::new(Inner {
-            value: None,
-            waker: Err(WakerState::Fresh),
-        }));
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().id
+    }
+
+    /// Return a (producer, consumer) pair whose shared state is placed in
+    /// `alloc`, see [`allocator`](crate::allocator).
+    pub fn new_in(alloc: A) -> (Self, Consumer<T, R, A>) {
+        let inner = Shared::new_in(CachePadded::new(Guarded::new(Inner::pending())), alloc);
         (
             Self {
                 promise: inner.clone(),
@@ -84,33 +191,533 @@ impl<T> Promise<T> for Producer<T> {
             Consumer { promise: inner },
         )
     }
+
+    /// Return a (producer, consumer) pair carrying `metadata`, readable from
+    /// both halves through [`Envelope`].
+    pub fn with_metadata(metadata: Metadata) -> (Self, Consumer<T, R, A>)
+    where
+        A: Default,
+    {
+        let (producer, consumer) = Self::new();
+        producer.promise.lock().metadata = Some(Arc::new(metadata));
+        (producer, consumer)
+    }
+
+    /// Return true if the consumer has been dropped, so resolving the promise
+    /// would go unobserved.
+    pub fn is_closed(&self) -> bool {
+        self.promise.lock().cancel.closed
+    }
+
+    /// Return a future that resolves once the consumer has been dropped. This
+    /// includes the consumer being dropped inside a combinator chain, so the
+    /// work behind a promise can stop once nobody is waiting for it.
+    ///
+    /// ```
+    /// use promise_out::{Promise, ConsumerExt, pair::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u32>::new();
+    /// let chain = consumer.map(|x| x + 1).map_err(|e| e.to_string());
+    /// drop(chain);
+    /// block_on(promise.closed());
+    /// assert!(promise.is_closed());
+    /// ```
+    pub fn closed(&self) -> Closed<T, R, A> {
+        Closed {
+            promise: self.promise.clone(),
+        }
+    }
+
+    /// Return a future that drives `stream` and resolves the promise with its
+    /// first item. If the stream ends first the producer is dropped, failing
+    /// the consumer; if the consumer is dropped first the driver stops
+    /// without polling the stream again.
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// use futures::{executor::block_on, stream};
+    /// let (promise, consumer) = Producer::<&str>::new();
+    /// block_on(promise.pipe_from(stream::iter(["first", "second"])));
+    /// assert_eq!(Ok("first"), block_on(consumer));
+    /// ```
+    #[cfg(feature = "futures")]
+    pub fn pipe_from<S>(self, stream: S) -> PipeFrom<T, S, R, A>
+    where
+        S: Stream<Item = T> + Unpin,
+    {
+        PipeFrom {
+            producer: Some(self),
+            stream,
+        }
+    }
 }
 
-impl<T> Drop for Producer<T> {
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Producer<T, R, A> {
     /// If this is an unresolved producer, wake with an error.
     fn drop(&mut self) {
-        let mut promise = self.promise.lock().unwrap();
-        if let Ok(waker) = std::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
+        // A resolved producer has nothing left to do, and need not lock again.
+        if self.promise.is_settled() {
+            return;
+        }
+        let mut promise = self.promise.lock();
+        self.promise.settle();
+        let waker = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted));
+        drop(promise);
+        if let Ok(waker) = waker {
             waker.wake()
         }
     }
 }
 
-impl<T> Future for Consumer<T> {
+impl<T, R: RawMutex, A: Allocator + Clone> Consumer<T, R, A> {
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().id
+    }
+
+    /// Return true if the promise has been resolved or its producer dropped,
+    /// so polling the consumer would return at once. This does not take the
+    /// lock. Everything the producer's thread did before settling the promise
+    /// happens before a call that returns true, as if the consumer had been
+    /// polled to completion.
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// let (promise, consumer) = Producer::<u8>::new();
+    /// assert!(!consumer.is_settled());
+    /// promise.resolve(1);
+    /// assert!(consumer.is_settled());
+    /// ```
+    pub fn is_settled(&self) -> bool {
+        self.promise.is_settled()
+    }
+
+    /// Block the thread until the promise settles. The thread spins briefly
+    /// before it parks, so a promise resolved within microseconds is picked
+    /// up without a round trip through the scheduler.
+    ///
+    /// ```
+    /// use promise_out::{Promise, pair::Producer};
+    /// use std::thread;
+    /// let (promise, consumer) = Producer::<u8>::new();
+    /// thread::spawn(move || promise.resolve(3));
+    /// assert_eq!(Ok(3), consumer.wait_blocking());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn wait_blocking(self) -> Result<T, Error> {
+        crate::blocking::wait(core::pin::pin!(self), Self::is_settled)
+    }
+}
+
+/// Consumers made without a producer use the default lock.
+impl<T> Consumer<T> {
+    /// Return a consumer that is already resolved with `value`, without a
+    /// producer.
+    ///
+    /// ```
+    /// use promise_out::pair::Consumer;
+    /// use futures::executor::block_on;
+    /// assert_eq!(Ok(1), block_on(Consumer::ready(1)));
+    /// ```
+    pub fn ready(value: T) -> Self {
+        Consumer {
+            promise: Shared::new_in(
+                CachePadded::new(Guarded::new_settled(Inner {
+                    id: PromiseId::next(),
+                    metadata: None,
+                    tracked: Tracked::new(),
+                    value: Slot::full(value),
+                    waker: Err(WakerState::Tainted),
+                    cancel: Cancel::default(),
+                })),
+                Global,
+            ),
+        }
+    }
+
+    /// Return a consumer that never resolves. Unlike a consumer whose producer
+    /// was dropped, it does not fail either.
+    pub fn never() -> Self {
+        Consumer {
+            promise: Shared::new_in(CachePadded::new(Guarded::new(Inner::pending())), Global),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Consumer<T, R, A> {
     type Output = Result<T, Error>;
 
     fn poll(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        let mut promise = self.promise.lock().unwrap();
-        match promise.value.take() {
-            Some(value) => Poll::Ready(Ok(value)),
-            None => match std::mem::replace(&mut promise.waker, Ok(cx.waker().clone())) {
-                Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
-                _ => Poll::Pending,
-            },
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        crate::coop::cooperate(cx, |cx| {
+            let mut promise = self.promise.lock();
+            match promise.value.take() {
+                Some(value) => Poll::Ready(Ok(value)),
+                None => match &promise.waker {
+                    Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
+                    // Polled again by the same task, so keep the waker.
+                    Ok(waker) if waker.will_wake(cx.waker()) => {
+                        promise.tracked.poll_shutdown(cx).map(Err)
+                    }
+                    _ => {
+                        promise.waker = Ok(cx.waker().clone());
+                        promise.tracked.poll_shutdown(cx).map(Err)
+                    }
+                },
+            }
+        })
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Consumer<T, R, A> {
+    /// Let the producer know nobody is waiting for the value anymore.
+    fn drop(&mut self) {
+        self.promise.lock().cancel.close();
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Envelope for Producer<T, R, A> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().metadata.clone()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Envelope for Consumer<T, R, A> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().metadata.clone()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> PartialEq for Producer<T, R, A> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Eq for Producer<T, R, A> {}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Hash for Producer<T, R, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.promise).hash(state)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> PartialEq for Consumer<T, R, A> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Eq for Consumer<T, R, A> {}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Hash for Consumer<T, R, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.promise).hash(state)
+    }
+}
+
+/// Future for [`Producer::closed`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Closed<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Closed<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Closed")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Closed<T, R, A> {
+    type Output = ();
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<()> {
+        self.promise.lock().cancel.poll_closed(cx)
+    }
+}
+
+/// Future for [`Producer::pipe_from`].
+#[cfg(feature = "futures")]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct PipeFrom<T, S, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    producer: Option<Producer<T, R, A>>,
+    stream: S,
+}
+
+#[cfg(feature = "futures")]
+impl<T: Debug, S: Debug, R: RawMutex, A: Allocator + Clone> Debug for PipeFrom<T, S, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PipeFrom")
+            .field("producer", &self.producer)
+            .field("stream", &self.stream)
+            .finish()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, S: Unpin, R: RawMutex, A: Allocator + Clone> Unpin for PipeFrom<T, S, R, A> {}
+
+#[cfg(feature = "futures")]
+impl<T, S, R: RawMutex, A: Allocator + Clone> Future for PipeFrom<T, S, R, A>
+where
+    S: Stream<Item = T> + Unpin,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let producer = this
+            .producer
+            .as_ref()
+            .expect("PipeFrom must not be polled after it returned Ready");
+        if producer.promise.lock().cancel.poll_closed(cx).is_ready() {
+            this.producer = None;
+            return Poll::Ready(());
         }
+        match Pin::new(&mut this.stream).poll_next(cx) {
+            Poll::Ready(Some(value)) => this.producer.take().unwrap().resolve(value),
+            Poll::Ready(None) => this.producer = None,
+            Poll::Pending => return Poll::Pending,
+        }
+        Poll::Ready(())
+    }
+}
+
+/// A group of producers settled all at once. Committing stores every value
+/// while holding every promise's lock, so no consumer can see one value of
+/// the transaction before all of them are in place; the consumers are then
+/// woken in the order the producers were added. Dropping an uncommitted
+/// transaction drops its producers, failing their consumers.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, pair::{Producer, Transaction}};
+/// use futures::executor::block_on;
+/// let (balance_a, a) = Producer::<i64>::new();
+/// let (balance_b, b) = Producer::<i64>::new();
+/// let mut transfer = Transaction::new();
+/// transfer.add(balance_a, 90);
+/// transfer.add(balance_b, 110);
+/// transfer.commit();
+/// assert_eq!(Ok(90), block_on(a));
+/// assert_eq!(Ok(110), block_on(b));
+/// ```
+pub struct Transaction<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    entries: Vec<(Producer<T, R, A>, T)>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Transaction<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Transaction")
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Transaction<T, R, A> {
+    pub fn new() -> Self {
+        Transaction { entries: vec![] }
+    }
+
+    /// Resolve `producer` with `value` when the transaction commits.
+    pub fn add(&mut self, producer: Producer<T, R, A>, value: T) {
+        self.entries.push((producer, value));
+    }
+
+    /// Return the number of producers in the transaction.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Settle every producer, except those made by [`Promise::resolved`],
+    /// which keep their value.
+    pub fn commit(self) {
+        let (producers, values): (Vec<_>, Vec<_>) = self.entries.into_iter().unzip();
+        // Lock in address order, so concurrent commits can not deadlock.
+        let mut order: Vec<_> = (0..producers.len()).collect();
+        order.sort_by_key(|&i| Shared::as_ptr(&producers[i].promise));
+        let mut guards: Vec<_> = producers.iter().map(|_| None).collect();
+        for &i in &order {
+            guards[i] = Some(producers[i].promise.lock());
+        }
+        let wakers: Vec<_> = guards
+            .iter_mut()
+            .zip(&producers)
+            .zip(values)
+            .filter_map(|((guard, producer), value)| {
+                if producer.promise.is_settled() {
+                    return None;
+                }
+                let promise = guard.as_mut().unwrap();
+                promise.value.put(value);
+                core::mem::replace(&mut promise.waker, Err(WakerState::Tainted)).ok()
+            })
+            .collect();
+        for producer in &producers {
+            producer.promise.settle();
+        }
+        // Unlock in reverse, as a critical section must be left.
+        for i in order.into_iter().rev() {
+            guards[i] = None;
+        }
+        for waker in wakers {
+            waker.wake()
+        }
+    }
+
+    /// Give up on the transaction and hand back its producers and values, so
+    /// none of them is settled.
+    pub fn abort(self) -> Vec<(Producer<T, R, A>, T)> {
+        self.entries
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Default for Transaction<T, R, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolve every producer with a clone of `value`, and return how many of
+/// them were resolved. Producers whose consumer is gone, or that were made by
+/// [`Promise::resolved`] and so are already settled, are not sent a clone,
+/// the last live producer gets `value` itself, and the consumers are woken
+/// together once every value is in place.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, pair::{fan_out, Producer}};
+/// use futures::executor::block_on;
+/// let (producers, consumers): (Vec<_>, Vec<_>) =
+///     (0..3).map(|_| Producer::<String>::new()).unzip();
+/// assert_eq!(3, fan_out(producers, String::from("shutdown")));
+/// for consumer in consumers {
+///     assert_eq!(Ok(String::from("shutdown")), block_on(consumer));
+/// }
+/// ```
+pub fn fan_out<T: Clone, R: RawMutex, A: Allocator + Clone>(
+    producers: impl IntoIterator<Item = Producer<T, R, A>>,
+    value: T,
+) -> usize {
+    let live: Vec<_> = producers
+        .into_iter()
+        .filter(|producer| !producer.promise.is_settled() && !producer.is_closed())
+        .collect();
+    let mut value = Some(value);
+    let mut wakers = Vec::with_capacity(live.len());
+    for (i, producer) in live.iter().enumerate() {
+        let value = if i + 1 == live.len() {
+            value.take().unwrap()
+        } else {
+            value.clone().unwrap()
+        };
+        let mut promise = producer.promise.lock();
+        promise.value.put(value);
+        producer.promise.settle();
+        if let Ok(waker) = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
+            wakers.push(waker);
+        }
+    }
+    for waker in wakers {
+        waker.wake()
+    }
+    live.len()
+}
+
+/// Recycles the allocations of settled promises. [`Pool::take`] hands out a
+/// (producer, consumer) pair like [`Promise::new`], but reuses the shared
+/// state of an earlier pair once both of its halves are gone. The pool
+/// retains at most `capacity` allocations; beyond that it allocates as usual.
+/// A value that was resolved but never consumed is dropped when its
+/// allocation is reused.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::pair::Pool;
+/// use futures::executor::block_on;
+/// let mut pool = Pool::<u32>::new(16);
+/// for request in 0..100 {
+///     let (promise, consumer) = pool.take();
+///     promise.resolve(request);
+///     assert_eq!(Ok(request), block_on(consumer));
+/// }
+/// assert_eq!(1, pool.len());
+/// ```
+pub struct Pool<T, R: RawMutex = DefaultRawMutex> {
+    retained: Vec<SharedInner<T, R, Global>>,
+    capacity: usize,
+    // Where the next search for a free allocation starts.
+    cursor: usize,
+}
+
+impl<T, R: RawMutex> Debug for Pool<T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Pool")
+            .field("retained", &self.retained.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex> Pool<T, R> {
+    pub fn new(capacity: usize) -> Self {
+        Pool {
+            retained: Vec::with_capacity(capacity),
+            capacity,
+            cursor: 0,
+        }
+    }
+
+    /// Return a (producer, consumer) pair, reusing a free allocation if the
+    /// pool has one.
+    pub fn take(&mut self) -> (Producer<T, R>, Consumer<T, R>) {
+        let len = self.retained.len();
+        for step in 0..len {
+            let index = (self.cursor + step) % len;
+            if let Some(inner) = Shared::get_mut(&mut self.retained[index]) {
+                **inner = Guarded::new(Inner::pending());
+                self.cursor = index + 1;
+                let inner = self.retained[index].clone();
+                return (
+                    Producer {
+                        promise: inner.clone(),
+                    },
+                    Consumer { promise: inner },
+                );
+            }
+        }
+        let inner = Shared::new_in(CachePadded::new(Guarded::new(Inner::pending())), Global);
+        if len < self.capacity {
+            self.retained.push(inner.clone());
+        }
+        (
+            Producer {
+                promise: inner.clone(),
+            },
+            Consumer { promise: inner },
+        )
+    }
+
+    /// Return how many allocations the pool retains, whether in use or free.
+    pub fn len(&self) -> usize {
+        self.retained.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.retained.is_empty()
     }
 }
 
@@ -249,4 +856,208 @@ mod tests {
         task1.join().expect("The task1 thread has panicked");
         task2.join().expect("The task2 thread has panicked");
     }
+
+    #[test]
+    fn test_promise_out_closed() {
+        let (op, op_a) = Producer::<String>::new();
+        assert!(!op.is_closed());
+        let closed = op.closed();
+        let task1 = thread::spawn(move || block_on(closed));
+        thread::spawn(move || std::mem::drop(op_a))
+            .join()
+            .expect("The task2 thread has panicked");
+        task1.join().expect("The task1 thread has panicked");
+        assert!(op.is_closed());
+    }
+
+    #[test]
+    fn test_ready_and_never() {
+        use super::Consumer;
+        use futures::future::{select, Either};
+        assert_eq!(Ok("🍓"), block_on(Consumer::ready("🍓")));
+        let never = Consumer::<u8>::never();
+        match block_on(select(never, Consumer::ready(1))) {
+            Either::Right((value, _)) => assert_eq!(Ok(1), value),
+            Either::Left(_) => panic!("never resolved"),
+        }
+    }
+
+    #[test]
+    fn test_resolved_and_rejected() {
+        let (op, op_a) = Producer::<&str>::resolved("🍓");
+        assert!(op_a.is_settled());
+        op.resolve("🍌");
+        assert_eq!(Ok("🍓"), block_on(op_a));
+        let (op, op_a) = Producer::<Result<u8, &str>>::rejected("reject!");
+        drop(op);
+        assert_eq!(Ok(Err("reject!")), block_on(op_a));
+    }
+
+    #[test]
+    fn test_transaction_is_atomic() {
+        use super::Transaction;
+        use futures::FutureExt;
+        let (op1, mut op1_a) = Producer::<u32>::new();
+        let (op2, op2_a) = Producer::<u32>::new();
+        let task1 = thread::spawn(move || {
+            let second = block_on(op2_a);
+            // The first value is in place by the time the second is seen.
+            (second, (&mut op1_a).now_or_never())
+        });
+        let mut transaction = Transaction::new();
+        transaction.add(op1, 1);
+        transaction.add(op2, 2);
+        transaction.commit();
+        assert_eq!(
+            (Ok(2), Some(Ok(1))),
+            task1.join().expect("The task1 thread has panicked")
+        );
+
+        let (op3, op3_a) = Producer::<u32>::new();
+        let mut transaction = Transaction::new();
+        transaction.add(op3, 3);
+        let mut entries = transaction.abort();
+        assert_eq!(1, entries.len());
+        let (op3, value) = entries.pop().unwrap();
+        op3.resolve(value + 1);
+        assert_eq!(Ok(4), block_on(op3_a));
+
+        let (op4, op4_a) = Producer::<u32>::resolved(4);
+        let (op5, op5_a) = Producer::<u32>::new();
+        let mut transaction = Transaction::new();
+        transaction.add(op4, 40);
+        transaction.add(op5, 5);
+        transaction.commit();
+        assert_eq!((Ok(4), Ok(5)), (block_on(op4_a), block_on(op5_a)));
+    }
+
+    #[test]
+    fn test_fan_out_skips_closed() {
+        use super::fan_out;
+        let (op, op_a) = Producer::<String>::new();
+        let (closed, closed_a) = Producer::<String>::new();
+        drop(closed_a);
+        let task1 = thread::spawn(move || block_on(op_a));
+        assert_eq!(1, fan_out([op, closed], String::from("🍓")));
+        assert_eq!(
+            Ok(String::from("🍓")),
+            task1.join().expect("The task1 thread has panicked")
+        );
+    }
+
+    #[test]
+    fn test_fan_out_skips_settled() {
+        use super::fan_out;
+        let (op, op_a) = Producer::<String>::new();
+        let (settled, settled_a) = Producer::<String>::resolved(String::from("🍌"));
+        assert_eq!(1, fan_out([op, settled], String::from("🍓")));
+        assert_eq!(Ok(String::from("🍓")), block_on(op_a));
+        assert_eq!(Ok(String::from("🍌")), block_on(settled_a));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_pipe_from_stream_ends() {
+        use futures::{stream, FutureExt};
+        let (promise, consumer) = Producer::<u8>::new();
+        block_on(promise.pipe_from(stream::empty()));
+        assert_eq!(Err(crate::Error::ProducerDropped), block_on(consumer));
+
+        let (promise, consumer) = Producer::<u8>::new();
+        let mut driver = promise.pipe_from(stream::pending());
+        assert_eq!(None, (&mut driver).now_or_never());
+        drop(consumer);
+        assert_eq!(Some(()), driver.now_or_never());
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn test_new_in_allocator() {
+        use crate::lock::DefaultRawMutex;
+        use std::{
+            alloc::{AllocError, Allocator, Global, Layout},
+            ptr::NonNull,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        #[derive(Clone, Copy)]
+        struct Counting<'a>(&'a AtomicUsize);
+
+        unsafe impl Allocator for Counting<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let live = AtomicUsize::new(0);
+        let (promise, consumer) = Producer::<u32, DefaultRawMutex, _>::new_in(Counting(&live));
+        assert_eq!(1, live.load(Ordering::Relaxed));
+        promise.resolve(1);
+        assert_eq!(Ok(1), block_on(consumer));
+        assert_eq!(0, live.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_pool_reuses_free_allocations() {
+        use super::Pool;
+        let mut pool = Pool::<u32>::new(2);
+        let (first, first_a) = pool.take();
+        let (second, second_a) = pool.take();
+        let (third, third_a) = pool.take();
+        assert_eq!(2, pool.len());
+        first.resolve(1);
+        drop(first_a);
+        drop((second, third, third_a));
+        let (promise, consumer) = pool.take();
+        assert!(!promise.is_closed());
+        assert_eq!(2, pool.len());
+        promise.resolve(4);
+        assert_eq!(Ok(4), block_on(consumer));
+        assert_eq!(Err(crate::Error::ProducerDropped), block_on(second_a));
+    }
+
+    #[test]
+    fn test_settled_happens_after_resolve() {
+        use futures::FutureExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+        for round in 1..=100 {
+            let (promise, consumer) = Producer::<usize>::new();
+            let writer = thread::spawn(move || {
+                WRITTEN.store(round, Ordering::Relaxed);
+                promise.resolve(round);
+            });
+            while !consumer.is_settled() {
+                std::hint::spin_loop();
+            }
+            // The relaxed store is ordered before the flag by the release.
+            assert_eq!(round, WRITTEN.load(Ordering::Relaxed));
+            assert_eq!(Some(Ok(round)), consumer.now_or_never());
+            writer.join().unwrap();
+        }
+        let (promise, consumer) = Producer::<usize>::new();
+        drop(promise);
+        assert!(consumer.is_settled());
+        assert!(crate::pair::Consumer::ready(1).is_settled());
+        assert!(!crate::pair::Consumer::<u8>::never().is_settled());
+    }
+
+    #[test]
+    fn test_resolve_in_place_checks_the_slot() {
+        let (promise, consumer) = Producer::<Vec<u8>>::new();
+        promise.resolve_in_place(|slot| slot.write(vec![1; 4096]));
+        assert_eq!(Ok(vec![1; 4096]), block_on(consumer));
+        let (promise, consumer) = Producer::<Vec<u8>>::new();
+        let elsewhere = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            promise.resolve_in_place(|_| Box::leak(Box::new(vec![2])))
+        }));
+        assert!(elsewhere.is_err());
+        assert_eq!(Err(crate::Error::ProducerDropped), block_on(consumer));
+    }
 }
@@ -0,0 +1,204 @@
+//! payload implements a single-producer, multi-consumer promise for byte
+//! buffers, built on the `bytes` crate. Where a [`poly`](crate::poly) consumer
+//! returns an `Arc<T>`, which would wrap a `Bytes` in a second reference count,
+//! a payload consumer returns the [`Bytes`] itself, and can be narrowed with
+//! [`Consumer::slice`] to a range of the payload. Resolving with a `BytesMut`
+//! or `Vec<u8>` freezes it in place, so the buffer read off a socket reaches
+//! every consumer without being copied.
+use crate::{
+    lock::{DefaultRawMutex, RawMutex},
+    Error, WakerState,
+};
+use ::bytes::Bytes;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    fmt::Debug,
+    future::Future,
+    ops::{Bound, RangeBounds},
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use lock_api::Mutex;
+
+/// The producer of a payload.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::payload::Producer;
+/// use bytes::BytesMut;
+/// use futures::executor::block_on;
+/// let (promise, consumer): (Producer, _) = Producer::new();
+/// let header = consumer.slice(..4);
+/// let body = consumer.slice(4..);
+/// let mut frame = BytesMut::with_capacity(64);
+/// frame.extend_from_slice(b"HEADbody");
+/// promise.resolve(frame);
+/// assert_eq!(&b"HEAD"[..], block_on(header).unwrap());
+/// assert_eq!(&b"body"[..], block_on(body).unwrap());
+/// assert_eq!(8, block_on(consumer).unwrap().len());
+/// ```
+pub struct Producer<R: RawMutex = DefaultRawMutex> {
+    promise: Arc<Mutex<R, Inner>>,
+}
+
+impl<R: RawMutex> Debug for Producer<R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    value: Option<Bytes>,
+    waker: Result<Vec<Waker>, WakerState>,
+}
+
+impl<R: RawMutex> Producer<R> {
+    /// Return a producer and its first consumer, which sees the whole payload.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Self, Consumer<R>) {
+        let promise = Arc::new(Mutex::new(Inner {
+            value: None,
+            waker: Err(WakerState::Fresh),
+        }));
+        (
+            Producer {
+                promise: promise.clone(),
+            },
+            Consumer {
+                promise,
+                start: 0,
+                end: None,
+            },
+        )
+    }
+
+    /// Resolve the payload, waking every consumer. A `BytesMut` or `Vec<u8>`
+    /// is frozen without copying.
+    pub fn resolve(self, value: impl Into<Bytes>) {
+        let mut promise = self.promise.lock();
+        promise.value = Some(value.into());
+        if let Ok(wakers) = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
+            for waker in wakers {
+                waker.wake()
+            }
+        }
+    }
+}
+
+impl<R: RawMutex> Drop for Producer<R> {
+    /// If this is an unresolved producer, wake every consumer with an error.
+    fn drop(&mut self) {
+        let mut promise = self.promise.lock();
+        if let Ok(wakers) = core::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
+            for waker in wakers {
+                waker.wake()
+            }
+        }
+    }
+}
+
+/// A consumer of a payload, or of a range of it. It returns a [`Bytes`] that
+/// shares the payload's buffer.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer<R: RawMutex = DefaultRawMutex> {
+    promise: Arc<Mutex<R, Inner>>,
+    start: usize,
+    // `None` runs to the end of the payload.
+    end: Option<usize>,
+}
+
+impl<R: RawMutex> Debug for Consumer<R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("promise", &self.promise)
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .finish()
+    }
+}
+
+impl<R: RawMutex> Clone for Consumer<R> {
+    fn clone(&self) -> Self {
+        Consumer {
+            promise: self.promise.clone(),
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl<R: RawMutex> Consumer<R> {
+    /// Return a consumer of `range` of what this consumer returns. Like
+    /// [`Bytes::slice`], polling it panics if the range is out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => self.start + n,
+            Bound::Excluded(&n) => self.start + n + 1,
+            Bound::Unbounded => self.start,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => Some(self.start + n + 1),
+            Bound::Excluded(&n) => Some(self.start + n),
+            Bound::Unbounded => self.end,
+        };
+        Consumer {
+            promise: self.promise.clone(),
+            start,
+            end,
+        }
+    }
+}
+
+impl<R: RawMutex> Future for Consumer<R> {
+    type Output = Result<Bytes, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut promise = self.promise.lock();
+        if let Some(value) = &promise.value {
+            let end = self.end.unwrap_or(value.len());
+            return Poll::Ready(Ok(value.slice(self.start..end)));
+        }
+        match &mut promise.waker {
+            Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
+            Err(WakerState::Fresh) => {
+                promise.waker = Ok(alloc::vec![cx.waker().clone()]);
+                Poll::Pending
+            }
+            Ok(wakers) => {
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Producer;
+    use crate::{lock::DefaultRawMutex, Error};
+    use bytes::Bytes;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_slices_share_the_payload() {
+        let (promise, mut consumer) = Producer::<DefaultRawMutex>::new();
+        let middle = consumer.slice(2..).slice(..=1);
+        assert_eq!(None, (&mut consumer).now_or_never());
+        let payload = Bytes::from_static(b"0123456789");
+        let base = payload.as_ptr();
+        promise.resolve(payload);
+        let middle = block_on(middle).unwrap();
+        assert_eq!(&b"23"[..], middle);
+        assert_eq!(base.wrapping_add(2), middle.as_ptr());
+        assert_eq!(base, block_on(consumer).unwrap().as_ptr());
+        let (promise, consumer) = Producer::<DefaultRawMutex>::new();
+        drop(promise);
+        assert_eq!(Err(Error::ProducerDropped), block_on(consumer));
+    }
+}
@@ -1,9 +1,19 @@
 //! poly implements a single-producer, multi-consumer promise. The producer
 //! may be cloned but the consumer can not be cloned.
-use crate::{Error, Promise, WakerState};
-use std::fmt::Debug;
-use std::sync::{Arc, Mutex};
-use std::{
+use crate::{
+    allocator::{Allocator, Global, Shared, WeakShared},
+    envelope::{Envelope, Metadata},
+    intrusive::{Node, WaitList},
+    lock::{DefaultRawMutex, RawMutex},
+    padded::CachePadded,
+    tracked::Tracked,
+    wake::WakeStrategy,
+    Cancel, Error, Guarded, Promise, PromiseId, WakerState,
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::hash::{Hash, Hasher};
+use core::{cell::UnsafeCell, fmt::Debug, mem::MaybeUninit, ptr::NonNull};
+use core::{
     future::Future,
     task::{Poll, Waker},
 };
@@ -29,27 +39,139 @@ use std::{
 /// task1.join().expect("The task1 thread has panicked.");
 /// task2.join().expect("The task2 thread has panicked.");
 /// ```
-#[derive(Debug)]
-pub struct Producer<T> {
-    promise: Arc<Mutex<Inner<T>>>,
+pub struct Producer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Producer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+pub struct Consumer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
 }
 
-#[derive(Clone)]
-pub struct Consumer<T> {
-    promise: Arc<Mutex<Inner<T>>>,
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Consumer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("promise", &self.promise)
+            .finish()
+    }
 }
 
 #[derive(Debug)]
 struct Inner<T> {
-    value: Option<Arc<T>>,
-    waker: Result<Vec<Waker>, WakerState>, // This was failing the two promise when only one waker
-                                           // was kept. Even though many docs insist you only need
-                                           // to wake the last waker. I don't get it.
-                                           // https://rust-lang.github.io/async-book/02_execution/03_wakeups.html
+    id: PromiseId,
+    metadata: Option<Arc<Metadata>>,
+    tracked: Tracked,
+    value: Option<Value<T>>,
+    // This was failing the two promise when only one waker was kept. Even
+    // though many docs insist you only need to wake the last waker. I don't
+    // get it.
+    // https://rust-lang.github.io/async-book/02_execution/03_wakeups.html
+    waker: Result<Vec<Waker>, WakerState>,
+    consumers: usize,
+    // Set once a consumer asked for the value by copy, see `Consumer::copied`.
+    by_value: bool,
+    // Futures returned by `Consumer::wait`, which hold their own nodes.
+    waiters: WaitList,
+    wake: WakeStrategy,
+    cancel: Cancel,
+}
+
+/// The value of a resolved promise. It is kept inline if a consumer from
+/// [`Consumer::copied`] asked for it, and moved into an `Arc` once a consumer
+/// that returns one polls.
+#[derive(Debug)]
+enum Value<T> {
+    Inline(T),
+    Shared(Arc<T>),
+}
+
+impl<T> Inner<T> {
+    /// Return the value in an `Arc`, moving an inline value into one.
+    fn shared(&mut self) -> Option<Arc<T>> {
+        let value = match self.value.take()? {
+            Value::Inline(value) => Arc::new(value),
+            Value::Shared(value) => value,
+        };
+        self.value = Some(Value::Shared(value.clone()));
+        Some(value)
+    }
+
+    fn copied(&mut self) -> Option<T>
+    where
+        T: Copy,
+    {
+        match self.value.as_ref()? {
+            Value::Inline(value) => Some(*value),
+            Value::Shared(value) => Some(**value),
+        }
+    }
+
+    /// Return what `read` finds, or else fail if the producer is gone, or else
+    /// register the waker.
+    fn poll_value<O>(
+        &mut self,
+        cx: &mut core::task::Context<'_>,
+        read: impl FnOnce(&mut Self) -> Option<O>,
+    ) -> Poll<Result<O, Error>> {
+        crate::coop::cooperate(cx, |cx| {
+            if let Some(value) = read(self) {
+                return Poll::Ready(Ok(value));
+            }
+            match &mut self.waker {
+                Err(WakerState::Tainted) => return Poll::Ready(Err(Error::ProducerDropped)),
+                Err(WakerState::Fresh) => self.waker = Ok(vec![cx.waker().clone()]),
+                Ok(wakers) => {
+                    if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                        wakers.push(cx.waker().clone())
+                    }
+                }
+            }
+            self.tracked.poll_shutdown(cx).map(Err)
+        })
+    }
+
+    /// Let the producer know once the last consumer is gone.
+    fn release(&mut self) {
+        self.consumers -= 1;
+        if self.consumers == 0 {
+            self.cancel.close();
+        }
+    }
+
+    /// Wake every consumer of a promise that has just settled. Wakes that the
+    /// strategy defers are returned, to be dispatched once the lock is
+    /// released.
+    fn take_wakers(&mut self) -> Option<(WakeStrategy, Vec<Waker>)> {
+        let mut wakers: Vec<Waker> =
+            core::mem::replace(&mut self.waker, Err(WakerState::Tainted)).unwrap_or_default();
+        match &self.wake {
+            WakeStrategy::Inline => {
+                for waker in wakers {
+                    waker.wake()
+                }
+                self.waiters.drain(Waker::wake);
+                None
+            }
+            strategy => {
+                self.waiters.drain(|waker| wakers.push(waker));
+                Some((strategy.clone(), wakers))
+            }
+        }
+    }
 }
 
-impl<T> Promise<T> for Producer<T> {
-    type Waiter = Consumer<T>;
+/// The state shared by the halves of a promise, see [`CachePadded`].
+type SharedInner<T, R, A> = Shared<CachePadded<Guarded<R, Inner<T>>>, A>;
+
+impl<T, R: RawMutex, A: Allocator + Clone + Default> Promise<T> for Producer<T, R, A> {
+    type Waiter = Consumer<T, R, A>;
     #[allow(dead_code)]
     ///promiseOut.resolve
     ///
@@ -70,13 +192,7 @@ impl<T> Promise<T> for Producer<T> {
     /// task2.join().expect("The task2 thread has panicked");
     /// ```
     fn resolve(self, value: T) {
-        let mut promise = self.promise.lock().unwrap();
-        promise.value = Some(Arc::new(value));
-        if let Ok(mut wakers) = std::mem::replace(&mut promise.waker, Err(WakerState::Tainted)) {
-            for waker in wakers.drain(..) {
-                waker.wake()
-            }
-        }
+        Producer::resolve(self, value)
     }
 
     /// promise.new
@@ -85,44 +201,543 @@ impl<T> Promise<T> for Producer<T> {
     /// doing that because we're not returning Self. We're returning a
     /// Consumer<T, E> which you can wait on.
     fn new() -> (Self, Self::Waiter) {
+        Self::new_in(A::default())
+    }
+
+    fn resolved(value: T) -> (Self, Self::Waiter) {
+        let (producer, consumer) = Self::new();
+        producer.settle_with(|_| Value::Shared(Arc::new(value)));
+        (producer, consumer)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Producer<T, R, A> {
+    /// Resolve the promise's value. Unlike [`Promise::resolve`], this also
+    /// works for allocators without a default.
+    #[inline]
+    pub fn resolve(self, value: T) {
+        self.settle_with(|by_value| {
+            if by_value {
+                Value::Inline(value)
+            } else {
+                Value::Shared(Arc::new(value))
+            }
+        })
+    }
+
+    /// Resolve the promise with the value `init` writes into the allocation
+    /// the consumers' `Arc` points to, see
+    /// [`pair::Producer::resolve_in_place`](crate::pair::Producer::resolve_in_place).
+    /// Unlike there, `init` runs before the lock is taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `init` returns any other reference than the one
+    /// [`MaybeUninit::write`] gives back.
+    pub fn resolve_in_place(self, init: impl FnOnce(&mut MaybeUninit<T>) -> &mut T) {
+        let mut value = Arc::<T>::new_uninit();
+        let storage = Arc::get_mut(&mut value).expect("a new Arc is not shared");
+        let start = storage.as_mut_ptr();
+        let written = init(storage) as *mut T;
+        assert!(
+            core::ptr::eq(start, written),
+            "resolve_in_place must return the reference MaybeUninit::write returns"
+        );
+        // Safety: `init` returned the reference to the storage that `write`
+        // gives back, so the value is initialized.
+        let value = unsafe { value.assume_init() };
+        self.settle_with(|_| Value::Shared(value))
+    }
+
+    /// Store the value `value` makes, given whether it may be kept inline,
+    /// and wake the consumers, unless the promise was made by
+    /// [`Promise::resolved`] and so is already settled.
+    fn settle_with(&self, value: impl FnOnce(bool) -> Value<T>) {
+        let mut promise = self.promise.lock();
+        if self.promise.is_settled() {
+            return;
+        }
+        promise.value = Some(value(promise.by_value));
+        self.promise.settle();
+        if let Some((strategy, wakers)) = promise.take_wakers() {
+            drop(promise);
+            strategy.dispatch(wakers);
+        }
+    }
+
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().id
+    }
+
+    /// Return a (producer, consumer) pair whose shared state is placed in
+    /// `alloc`, see [`allocator`](crate::allocator).
+    pub fn new_in(alloc: A) -> (Self, Consumer<T, R, A>) {
         let producer = Self {
-            promise: Arc::new(Mutex::new(Inner {
-                value: None,
-                waker: Err(WakerState::Fresh),
-            })),
+            promise: Shared::new_in(
+                CachePadded::new(Guarded::new(Inner {
+                    id: PromiseId::next(),
+                    metadata: None,
+                    tracked: Tracked::new(),
+                    value: None,
+                    waker: Err(WakerState::Fresh),
+                    consumers: 1,
+                    by_value: false,
+                    waiters: WaitList::new(),
+                    wake: WakeStrategy::Inline,
+                    cancel: Cancel::default(),
+                })),
+                alloc,
+            ),
         };
         let consumer = Consumer {
             promise: producer.promise.clone(),
         };
         (producer, consumer)
     }
+
+    /// Return a (producer, consumer) pair carrying `metadata`, readable from
+    /// both halves through [`Envelope`].
+    pub fn with_metadata(metadata: Metadata) -> (Self, Consumer<T, R, A>)
+    where
+        A: Default,
+    {
+        let (producer, consumer) = Self::new();
+        producer.promise.lock().metadata = Some(Arc::new(metadata));
+        (producer, consumer)
+    }
+
+    /// Return true if every consumer has been dropped, so resolving the
+    /// promise would go unobserved.
+    pub fn is_closed(&self) -> bool {
+        self.promise.lock().cancel.closed
+    }
+
+    /// Choose how consumers are woken when the promise settles, see
+    /// [`wake`](crate::wake).
+    pub fn set_wake_strategy(&self, strategy: WakeStrategy) {
+        self.promise.lock().wake = strategy;
+    }
+
+    /// Return a future that resolves once every consumer has been dropped,
+    /// including consumers dropped inside combinator chains.
+    pub fn closed(&self) -> Closed<T, R, A> {
+        Closed {
+            promise: self.promise.clone(),
+        }
+    }
+
+    /// Return how many consumers are alive.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn consumers(&self) -> usize {
+        self.promise.lock().consumers
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Producer<T, R, A> {
+    /// If this is an unresolved producer, wake every consumer with an error.
+    fn drop(&mut self) {
+        // A resolved producer has nothing left to do, and need not lock again.
+        if self.promise.is_settled() {
+            return;
+        }
+        let mut promise = self.promise.lock();
+        self.promise.settle();
+        if let Some((strategy, wakers)) = promise.take_wakers() {
+            drop(promise);
+            strategy.dispatch(wakers);
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Clone for Consumer<T, R, A> {
+    fn clone(&self) -> Self {
+        self.promise.lock().consumers += 1;
+        Consumer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Consumer<T, R, A> {
+    /// Let the producer know once the last consumer is gone.
+    fn drop(&mut self) {
+        self.promise.lock().release();
+    }
+}
+
+/// A handle to a consumer that does not count as one: once every consumer
+/// has been dropped the producer sees the promise as closed, even while weak
+/// handles remain. See [`Consumer::downgrade`].
+pub struct WeakConsumer<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: WeakShared<CachePadded<Guarded<R, Inner<T>>>, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for WeakConsumer<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WeakConsumer")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> WeakConsumer<T, R, A> {
+    /// Return a consumer again, unless every consumer has been dropped in the
+    /// meantime.
+    pub fn upgrade(&self) -> Option<Consumer<T, R, A>> {
+        let promise = self.promise.upgrade()?;
+        {
+            let mut inner = promise.lock();
+            if inner.consumers == 0 {
+                return None;
+            }
+            inner.consumers += 1;
+        }
+        Some(Consumer { promise })
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Clone for WeakConsumer<T, R, A> {
+    fn clone(&self) -> Self {
+        WeakConsumer {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Envelope for Producer<T, R, A> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().metadata.clone()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Envelope for Consumer<T, R, A> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.promise.lock().metadata.clone()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> PartialEq for Producer<T, R, A> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Eq for Producer<T, R, A> {}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Hash for Producer<T, R, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.promise).hash(state)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> PartialEq for Consumer<T, R, A> {
+    /// Handles are equal if they belong to the same promise.
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.promise, &other.promise)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Eq for Consumer<T, R, A> {}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Hash for Consumer<T, R, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.promise).hash(state)
+    }
+}
+
+/// Future for [`Producer::closed`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Closed<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Closed<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Closed")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Closed<T, R, A> {
+    type Output = ();
+
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<()> {
+        self.promise.lock().cancel.poll_closed(cx)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Consumer<T, R, A> {
+    /// Return the id of the promise, shared by both of its halves.
+    pub fn id(&self) -> PromiseId {
+        self.promise.lock().id
+    }
+
+    /// Return true if the promise has been resolved or its producer dropped,
+    /// without taking the lock. Everything the producer's thread did before
+    /// settling the promise happens before a call that returns true.
+    pub fn is_settled(&self) -> bool {
+        self.promise.is_settled()
+    }
+
+    /// Block the thread until the promise settles, spinning briefly before it
+    /// parks, see [`pair::Consumer::wait_blocking`](crate::pair::Consumer::wait_blocking).
+    #[cfg(feature = "std")]
+    pub fn wait_blocking(self) -> Result<Arc<T>, Error> {
+        crate::blocking::wait(core::pin::pin!(self), Self::is_settled)
+    }
+}
+
+/// Consumers made without a producer use the default lock.
+impl<T> Consumer<T> {
+    /// Return a consumer that is already resolved with `value`, without a
+    /// producer.
+    ///
+    /// ```
+    /// use promise_out::poly::Consumer;
+    /// use futures::executor::block_on;
+    /// let consumer = Consumer::ready("🍓");
+    /// assert_eq!("🍓", *block_on(consumer.clone()).unwrap());
+    /// ```
+    pub fn ready(value: T) -> Self {
+        Consumer {
+            promise: Shared::new_in(
+                CachePadded::new(Guarded::new_settled(Inner {
+                    id: PromiseId::next(),
+                    metadata: None,
+                    tracked: Tracked::new(),
+                    value: Some(Value::Shared(Arc::new(value))),
+                    waker: Err(WakerState::Tainted),
+                    consumers: 1,
+                    by_value: false,
+                    waiters: WaitList::new(),
+                    wake: WakeStrategy::Inline,
+                    cancel: Cancel::default(),
+                })),
+                Global,
+            ),
+        }
+    }
+
+    /// Return a consumer that never resolves. Unlike a consumer whose producer
+    /// was dropped, it does not fail either.
+    pub fn never() -> Self {
+        Consumer {
+            promise: Shared::new_in(
+                CachePadded::new(Guarded::new(Inner {
+                    id: PromiseId::next(),
+                    metadata: None,
+                    tracked: Tracked::new(),
+                    value: None,
+                    waker: Err(WakerState::Fresh),
+                    consumers: 1,
+                    by_value: false,
+                    waiters: WaitList::new(),
+                    wake: WakeStrategy::Inline,
+                    cancel: Cancel::default(),
+                })),
+                Global,
+            ),
+        }
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Consumer<T, R, A> {
+    /// Return a weak handle that can be upgraded back into a consumer while
+    /// some consumer is alive, without keeping the promise open itself.
+    ///
+    /// ```
+    /// use promise_out::{Promise, poly::Producer};
+    /// let (promise, consumer) = Producer::<u32>::new();
+    /// let weak = consumer.downgrade();
+    /// assert!(weak.upgrade().is_some());
+    /// drop(consumer);
+    /// assert!(promise.is_closed());
+    /// assert!(weak.upgrade().is_none());
+    /// ```
+    pub fn downgrade(&self) -> WeakConsumer<T, R, A> {
+        WeakConsumer {
+            promise: Shared::downgrade(&self.promise),
+        }
+    }
+
+    /// Return true if the producer was dropped without resolving the promise.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn is_failed(&self) -> bool {
+        let promise = self.promise.lock();
+        promise.value.is_none() && matches!(promise.waker, Err(WakerState::Tainted))
+    }
+
+    /// Return the value if the promise has been resolved, without waiting.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn peek(&self) -> Option<Arc<T>> {
+        self.promise.lock().shared()
+    }
 }
 
-impl<T> Future for Consumer<T> {
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Consumer<T, R, A> {
     type Output = Result<Arc<T>, Error>;
 
     fn poll(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        let mut promise = self.promise.lock().unwrap();
-        match promise.value {
-            Some(ref value) => Poll::Ready(Ok(value.clone())),
-            None => match &mut promise.waker {
-                Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
-                Err(WakerState::Fresh) => {
-                    promise.waker = Ok(vec![cx.waker().clone()]);
-                    Poll::Pending
-                }
-                Ok(wakers) => {
-                    wakers.push(cx.waker().clone());
-                    Poll::Pending
-                }
-            },
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        self.promise.lock().poll_value(cx, Inner::shared)
+    }
+}
+
+impl<T: Copy, R: RawMutex, A: Allocator + Clone> Consumer<T, R, A> {
+    /// Return a consumer that returns the value itself rather than an `Arc`.
+    /// Unless a consumer that returns an `Arc` polls the resolved promise,
+    /// the value is never put in one, which saves an allocation per promise
+    /// for small values such as ids and status codes.
+    ///
+    /// ```
+    /// use promise_out::{Promise, poly::Producer};
+    /// use futures::executor::block_on;
+    /// let (promise, consumer) = Producer::<u16>::new();
+    /// let status = consumer.copied();
+    /// let again = status.clone();
+    /// promise.resolve(204);
+    /// assert_eq!(Ok(204), block_on(status));
+    /// assert_eq!(Ok(204), block_on(again));
+    /// ```
+    pub fn copied(self) -> Copied<T, R, A> {
+        let mut promise = self.promise.lock();
+        promise.by_value = true;
+        promise.consumers += 1;
+        drop(promise);
+        Copied {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+/// A consumer that returns a copy of the value, see [`Consumer::copied`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Copied<T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    promise: SharedInner<T, R, A>,
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Copied<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Copied")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Clone for Copied<T, R, A> {
+    fn clone(&self) -> Self {
+        self.promise.lock().consumers += 1;
+        Copied {
+            promise: self.promise.clone(),
         }
     }
 }
 
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Copied<T, R, A> {
+    fn drop(&mut self) {
+        self.promise.lock().release();
+    }
+}
+
+impl<T: Copy, R: RawMutex, A: Allocator + Clone> Future for Copied<T, R, A> {
+    type Output = Result<T, Error>;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        self.promise.lock().poll_value(cx, Inner::copied)
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Consumer<T, R, A> {
+    /// Return a future that resolves like this consumer, but whose waker is
+    /// kept in a node inside the future rather than in a list the promise
+    /// allocates, so waiting never allocates however many tasks wait.
+    ///
+    /// ```
+    /// use promise_out::{Promise, poly::Producer};
+    /// use futures::{executor::block_on, future::join_all};
+    /// use std::thread;
+    /// let (promise, consumer) = Producer::<u32>::new();
+    /// let waiting = thread::spawn(move || {
+    ///     block_on(join_all((0..64).map(|_| consumer.wait())))
+    /// });
+    /// promise.resolve(8);
+    /// for value in waiting.join().unwrap() {
+    ///     assert_eq!(8, *value.unwrap());
+    /// }
+    /// ```
+    pub fn wait(&self) -> Wait<'_, T, R, A> {
+        Wait {
+            consumer: self,
+            node: UnsafeCell::new(Node::new()),
+        }
+    }
+}
+
+/// The future returned by [`Consumer::wait`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait<'a, T, R: RawMutex = DefaultRawMutex, A: Allocator + Clone = Global> {
+    consumer: &'a Consumer<T, R, A>,
+    // Linked into the promise's waiters once polled, so the future is pinned.
+    node: UnsafeCell<Node>,
+}
+
+// The node is only accessed with the promise's lock held.
+unsafe impl<T, R: RawMutex, A: Allocator + Clone> Send for Wait<'_, T, R, A> where
+    Consumer<T, R, A>: Sync
+{
+}
+
+impl<T: Debug, R: RawMutex, A: Allocator + Clone> Debug for Wait<'_, T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Wait")
+            .field("consumer", &self.consumer)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Future for Wait<'_, T, R, A> {
+    type Output = Result<Arc<T>, Error>;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        crate::coop::cooperate(cx, |cx| {
+            let mut promise = self.consumer.promise.lock();
+            if let Some(value) = promise.shared() {
+                return Poll::Ready(Ok(value));
+            }
+            if let Err(WakerState::Tainted) = promise.waker {
+                return Poll::Ready(Err(Error::ProducerDropped));
+            }
+            // Safety: the lock is held, and the future is pinned, so the node
+            // stays in place until `drop` unlinks it.
+            let node = unsafe { &mut *self.node.get() };
+            node.register(cx.waker());
+            if !node.is_linked() {
+                unsafe { promise.waiters.push_back(NonNull::from(node)) };
+            }
+            promise.tracked.poll_shutdown(cx).map(Err)
+        })
+    }
+}
+
+impl<T, R: RawMutex, A: Allocator + Clone> Drop for Wait<'_, T, R, A> {
+    fn drop(&mut self) {
+        let mut promise = self.consumer.promise.lock();
+        // Safety: the node is only linked into this promise's waiters.
+        unsafe { promise.waiters.remove(NonNull::from(self.node.get_mut())) };
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Producer;
@@ -202,4 +817,108 @@ mod tests {
         // Not possible. a is consumed. I love rust.
         // a.resolve("hi".into());
     }
+
+    #[test]
+    fn test_promise_out_unresolved() {
+        let (op, op_a) = Producer::<String>::new();
+        let op_b = op_a.clone();
+        let task1 = thread::spawn(move || block_on(op_a));
+        let task2 = thread::spawn(move || block_on(op_b));
+        std::mem::drop(op);
+        assert!(task1
+            .join()
+            .expect("The task1 thread has panicked")
+            .is_err());
+        assert!(task2
+            .join()
+            .expect("The task2 thread has panicked")
+            .is_err());
+    }
+
+    #[test]
+    fn test_promise_out_closed() {
+        let (op, op_a) = Producer::<String>::new();
+        let op_b = op_a.clone();
+        std::mem::drop(op_a);
+        assert!(!op.is_closed());
+        let task1 = thread::spawn(move || std::mem::drop(op_b));
+        block_on(op.closed());
+        task1.join().expect("The task1 thread has panicked");
+        assert!(op.is_closed());
+    }
+
+    #[test]
+    fn test_ready_and_never() {
+        use super::Consumer;
+        use futures::future::{select, Either};
+        let ready = Consumer::ready(1);
+        assert_eq!(1, *block_on(ready.clone()).unwrap());
+        match block_on(select(Consumer::<u8>::never(), ready)) {
+            Either::Right((value, _)) => assert_eq!(1, *value.unwrap()),
+            Either::Left(_) => panic!("never resolved"),
+        }
+    }
+
+    #[test]
+    fn test_resolved_and_rejected() {
+        let (op, op_a) = Producer::<&str>::resolved("🍓");
+        let op_b = op_a.clone();
+        op.resolve("🍌");
+        assert_eq!("🍓", *block_on(op_a).unwrap());
+        assert_eq!("🍓", *block_on(op_b).unwrap());
+        let (op, op_a) = Producer::<Result<u8, &str>>::rejected("reject!");
+        drop(op);
+        assert_eq!(Err("reject!"), *block_on(op_a).unwrap());
+    }
+
+    // Handles hash by identity, so their interior mutability does not matter.
+    #[allow(clippy::mutable_key_type)]
+    #[test]
+    fn test_consumer_identity() {
+        use std::collections::HashSet;
+        let (op, op_a) = Producer::<u8>::new();
+        let (_, other) = Producer::<u8>::new();
+        let consumers: HashSet<_> = [op_a.clone(), op_a.clone(), other].into_iter().collect();
+        assert_eq!(2, consumers.len());
+        assert!(consumers.contains(&op_a));
+        assert_eq!(op.id(), op_a.id());
+    }
+
+    #[test]
+    fn test_wait_unlinks_dropped_waiters() {
+        use futures::FutureExt;
+        let (op, op_a) = Producer::<u8>::new();
+        let mut waits: Vec<_> = (0..4).map(|_| Box::pin(op_a.wait())).collect();
+        for wait in waits.iter_mut() {
+            assert_eq!(None, wait.as_mut().now_or_never());
+        }
+        drop(waits.remove(1));
+        drop(waits.remove(2));
+        op.resolve(5);
+        for wait in waits {
+            assert_eq!(Arc::new(5), block_on(wait).unwrap());
+        }
+        let (op, op_a) = Producer::<u8>::new();
+        let mut wait = Box::pin(op_a.wait());
+        assert_eq!(None, wait.as_mut().now_or_never());
+        drop(op);
+        assert!(block_on(wait).is_err());
+    }
+
+    #[test]
+    fn test_copied_keeps_the_value_inline() {
+        use super::Value;
+        let (op, op_a) = Producer::<u64>::new();
+        let plain = op_a.clone();
+        let copied = op_a.copied();
+        assert!(!op.is_closed());
+        op.resolve(42);
+        assert!(matches!(
+            copied.promise.lock().value,
+            Some(Value::Inline(42))
+        ));
+        assert_eq!(Ok(42), block_on(copied.clone()));
+        assert_eq!(Arc::new(42), block_on(plain).unwrap());
+        assert_eq!(Ok(42), block_on(copied));
+    }
 }
@@ -0,0 +1,123 @@
+//! port is modeled on the Web `MessageChannel` of the dweb JavaScript side: a
+//! [`MessageChannel`] holds two connected [`Port`]s, and a message posted on
+//! one is received by the other. Unlike `postMessage`, [`Port::post`] returns
+//! the consumer of the reply, which the receiving side resolves through the
+//! producer that comes with each message. Both directions work the same way,
+//! on top of a [`mailbox`](crate::mailbox) each.
+use crate::{
+    mailbox::{self, Inbox, Mailbox},
+    pair,
+};
+use futures_core::Stream;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Two connected ports.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{port::MessageChannel, Promise};
+/// use futures::{executor::block_on, StreamExt};
+/// use std::thread;
+/// let MessageChannel { port1, mut port2 } = MessageChannel::<String>::new();
+/// let peer = thread::spawn(move || {
+///     while let Some((message, reply)) = block_on(port2.next()) {
+///         reply.resolve(message.to_uppercase());
+///     }
+/// });
+/// assert_eq!("PING", block_on(port1.post("ping".into())).unwrap());
+/// drop(port1);
+/// peer.join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MessageChannel<M> {
+    pub port1: Port<M>,
+    pub port2: Port<M>,
+}
+
+impl<M> MessageChannel<M> {
+    pub fn new() -> Self {
+        let (to_port2, inbox2) = mailbox::new();
+        let (to_port1, inbox1) = mailbox::new();
+        MessageChannel {
+            port1: Port {
+                peer: to_port2,
+                inbox: inbox1,
+            },
+            port2: Port {
+                peer: to_port1,
+                inbox: inbox2,
+            },
+        }
+    }
+}
+
+impl<M> Default for MessageChannel<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One end of a [`MessageChannel`]. As a stream it yields the messages posted
+/// on the other end, each with the producer of its reply, and ends once the
+/// other end is dropped. Dropping a port fails the replies of the messages
+/// posted to it that it has not received yet.
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct Port<M> {
+    peer: Mailbox<M, M>,
+    inbox: Inbox<M, M>,
+}
+
+impl<M> Port<M> {
+    /// Post `message` to the other end and return the consumer of its reply.
+    /// The reply fails with
+    /// [`Error::ProducerDropped`](crate::Error::ProducerDropped) if the other
+    /// end drops the message unanswered or has been dropped itself.
+    pub fn post(&self, message: M) -> pair::Consumer<M> {
+        self.peer.ask(message)
+    }
+
+    /// Return false once the other end has been dropped.
+    pub fn is_open(&self) -> bool {
+        self.peer.is_open()
+    }
+}
+
+impl<M> Stream for Port<M> {
+    type Item = (M, pair::Producer<M>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().inbox).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageChannel;
+    use crate::Error;
+    use futures::{executor::block_on, StreamExt};
+
+    #[test]
+    fn test_both_directions() {
+        let MessageChannel {
+            mut port1,
+            mut port2,
+        } = MessageChannel::<u32>::new();
+        let from1 = port1.post(1);
+        let from2 = port2.post(2);
+        let (message, reply) = block_on(port2.next()).unwrap();
+        reply.resolve(message + 10);
+        let (message, reply) = block_on(port1.next()).unwrap();
+        reply.resolve(message + 20);
+        assert_eq!(Ok(11), block_on(from1));
+        assert_eq!(Ok(22), block_on(from2));
+        drop(port2);
+        assert!(!port1.is_open());
+        assert_eq!(Err(Error::ProducerDropped), block_on(port1.post(3)));
+        assert!(block_on(port1.next()).is_none());
+    }
+}
@@ -0,0 +1,237 @@
+//! rate_limit implements a token-bucket rate limiter. Tasks that find the
+//! bucket short of tokens queue a pair producer, and the timer thread refills
+//! the bucket and resolves the queue in FIFO order once enough tokens have
+//! accumulated for the task at its front.
+use crate::{pair, timer, Error, Promise};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct State {
+    capacity: usize,
+    interval: Duration,
+    tokens: usize,
+    refilled: Instant,
+    waiters: VecDeque<(usize, pair::Producer<()>)>,
+    // Whether the timer thread will look at the queue again.
+    scheduled: bool,
+}
+
+impl State {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        let added = (elapsed.as_nanos() / self.interval.as_nanos().max(1)) as usize;
+        self.tokens = self.tokens.saturating_add(added);
+        if self.tokens >= self.capacity {
+            self.tokens = self.capacity;
+            self.refilled = now;
+        } else {
+            // At most `elapsed`, so it does not pass `now`.
+            self.refilled = self
+                .refilled
+                .checked_add(intervals(self.interval, added))
+                .unwrap_or(now);
+        }
+    }
+
+    /// Take tokens for the queued tasks that can go now, oldest first.
+    fn serve(&mut self) -> Vec<pair::Producer<()>> {
+        let mut ready = vec![];
+        while let Some((n, producer)) = self.waiters.front() {
+            if producer.is_closed() {
+                self.waiters.pop_front();
+            } else if *n <= self.tokens {
+                self.tokens -= n;
+                ready.push(self.waiters.pop_front().unwrap().1);
+            } else {
+                break;
+            }
+        }
+        ready
+    }
+}
+
+/// A token-bucket rate limiter. The bucket starts full with `capacity` tokens
+/// and gains one token every `interval`. Clones share the same bucket.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::rate_limit::RateLimiter;
+/// use futures::executor::block_on;
+/// use std::time::{Duration, Instant};
+/// let limiter = RateLimiter::new(2, Duration::from_millis(10));
+/// let start = Instant::now();
+/// block_on(async {
+///     for _ in 0..4 {
+///         limiter.acquire().await.unwrap();
+///     }
+/// });
+/// assert!(start.elapsed() >= Duration::from_millis(20));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize, interval: Duration) -> Self {
+        assert!(capacity > 0, "a rate limiter needs a capacity");
+        RateLimiter {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                interval,
+                tokens: capacity,
+                refilled: Instant::now(),
+                waiters: VecDeque::new(),
+                scheduled: false,
+            })),
+        }
+    }
+
+    /// Return a future that resolves once a token has been taken.
+    pub fn acquire(&self) -> Acquire {
+        self.acquire_n(1)
+    }
+
+    /// Return a future that resolves once `n` tokens have been taken at once.
+    /// Tasks are served in the order they asked, so a large request is not
+    /// starved by smaller ones behind it. Panics if `n` exceeds the capacity,
+    /// since the bucket could never hold that many tokens.
+    pub fn acquire_n(&self, n: usize) -> Acquire {
+        let mut state = self.state.lock().unwrap();
+        assert!(
+            n <= state.capacity,
+            "acquire_n({n}) exceeds the capacity of {}",
+            state.capacity
+        );
+        state.refill(Instant::now());
+        if state.waiters.is_empty() && n <= state.tokens {
+            state.tokens -= n;
+            return Acquire { waiting: None };
+        }
+        let (producer, consumer) = pair::Producer::new();
+        state.waiters.push_back((n, producer));
+        if !state.scheduled {
+            state.scheduled = true;
+            schedule(&state, Arc::downgrade(&self.state));
+        }
+        Acquire {
+            waiting: Some(consumer),
+        }
+    }
+
+    /// Return the number of tokens in the bucket right now.
+    pub fn available(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.tokens
+    }
+}
+
+/// Return `interval` times `n`, saturating at `Duration::MAX`.
+fn intervals(interval: Duration, n: usize) -> Duration {
+    u32::try_from(n)
+        .ok()
+        .and_then(|n| interval.checked_mul(n))
+        .unwrap_or(Duration::MAX)
+}
+
+/// Ask the timer thread to look at the queue once the task at its front has
+/// enough tokens. The timer only holds a weak reference, so dropping every
+/// limiter fails the queued tasks.
+fn schedule(state: &State, weak: Weak<Mutex<State>>) {
+    let needed = state.waiters.front().map_or(1, |(n, _)| *n);
+    let missing = needed.saturating_sub(state.tokens).max(1);
+    // A deadline past what an `Instant` can hold is checked again after one
+    // interval instead.
+    let deadline = state
+        .refilled
+        .checked_add(intervals(state.interval, missing))
+        .or_else(|| state.refilled.checked_add(state.interval))
+        .unwrap_or(state.refilled);
+    timer::schedule(deadline, move || {
+        let Some(shared) = weak.upgrade() else {
+            return;
+        };
+        let ready = {
+            let mut state = shared.lock().unwrap();
+            state.refill(Instant::now());
+            let ready = state.serve();
+            state.scheduled = !state.waiters.is_empty();
+            if state.scheduled {
+                schedule(&state, weak);
+            }
+            ready
+        };
+        for producer in ready {
+            producer.resolve(());
+        }
+    });
+}
+
+/// Future for [`RateLimiter::acquire`] and [`RateLimiter::acquire_n`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Acquire {
+    // None if the tokens were taken right away.
+    waiting: Option<pair::Consumer<()>>,
+}
+
+impl Future for Acquire {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.waiting.as_mut() {
+            None => Poll::Ready(Ok(())),
+            Some(waiting) => Pin::new(waiting).poll(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{intervals, RateLimiter};
+    use crate::Error;
+    use futures::{executor::block_on, FutureExt};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rate_limiter_refill() {
+        let limiter = RateLimiter::new(3, Duration::from_millis(20));
+        assert_eq!(Some(Ok(())), limiter.acquire_n(3).now_or_never());
+        assert_eq!(0, limiter.available());
+        let start = Instant::now();
+        let two = limiter.acquire_n(2);
+        let one = limiter.acquire();
+        assert_eq!(Ok(()), block_on(two));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(Ok(()), block_on(one));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_intervals_saturate() {
+        let second = Duration::from_secs(1);
+        assert_eq!(Duration::from_secs(3), intervals(second, 3));
+        assert_eq!(Duration::MAX, intervals(Duration::MAX, 2));
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(Duration::MAX, intervals(second, u32::MAX as usize + 1));
+    }
+
+    #[test]
+    fn test_rate_limiter_dropped() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let _ = limiter.acquire().now_or_never();
+        let waiting = limiter.acquire();
+        drop(limiter);
+        assert_eq!(Err(Error::ProducerDropped), block_on(waiting));
+    }
+}
@@ -0,0 +1,95 @@
+//! ready tracks which of many child futures have been woken, so a parent that
+//! drives them only polls the children that can make progress. Every child is
+//! polled with its own waker; waking it queues the child's index and wakes the
+//! parent.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    task::{Wake, Waker},
+};
+
+#[derive(Debug, Default)]
+struct State {
+    queue: VecDeque<usize>,
+    queued: Vec<bool>,
+    parent: Option<Waker>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn push(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        if index >= state.queued.len() {
+            state.queued.resize(index + 1, false);
+        }
+        if !state.queued[index] {
+            state.queued[index] = true;
+            state.queue.push_back(index);
+        }
+        if let Some(parent) = state.parent.take() {
+            drop(state);
+            parent.wake();
+        }
+    }
+}
+
+struct ChildWaker {
+    index: usize,
+    shared: Arc<Shared>,
+}
+
+impl Wake for ChildWaker {
+    fn wake(self: Arc<Self>) {
+        self.shared.push(self.index);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.shared.push(self.index);
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ReadyQueue {
+    shared: Arc<Shared>,
+    wakers: Vec<Waker>,
+}
+
+impl ReadyQueue {
+    /// Make room for a child at `index` and queue it so it is polled once.
+    pub(crate) fn insert(&mut self, index: usize) {
+        while self.wakers.len() <= index {
+            let waker = Arc::new(ChildWaker {
+                index: self.wakers.len(),
+                shared: self.shared.clone(),
+            });
+            self.wakers.push(Waker::from(waker));
+        }
+        self.shared.push(index);
+    }
+
+    /// The waker to poll the child at `index` with.
+    pub(crate) fn waker(&self, index: usize) -> &Waker {
+        &self.wakers[index]
+    }
+
+    /// Remember the parent's waker, to be woken when any child is.
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut state = self.shared.state.lock().unwrap();
+        match &state.parent {
+            Some(parent) if parent.will_wake(waker) => {}
+            _ => state.parent = Some(waker.clone()),
+        }
+    }
+
+    /// Take the index of the next child that was woken.
+    pub(crate) fn pop(&self) -> Option<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        let index = state.queue.pop_front()?;
+        state.queued[index] = false;
+        Some(index)
+    }
+}
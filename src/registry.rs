@@ -0,0 +1,1278 @@
+//! registry correlates responses with requests by key. A request registers its
+//! key and awaits the returned consumer; whoever receives the response, e.g.
+//! the read half of a transport, resolves the entry by the same key. A key can
+//! also be awaited before anyone registers it, by any number of waiters that
+//! share a poly consumer, which makes the registry a keyed pub/sub. Entries
+//! may be given a time-to-live, after which they are rejected with
+//! [`Error::Timeout`], so a lost response can not leak its entry. Shutting the
+//! registry down rejects every pending entry at once. Every settled entry can
+//! be observed through [`Registry::completions`], and the pending ones
+//! inspected with [`Registry::snapshot`]. With the `serde` feature the pending
+//! entries can be persisted and re-issued after a restart. A retry can
+//! supersede the pending entry of its key, and a [`Version`] then tells the
+//! retry's response apart from a late one to the original request.
+use crate::{
+    delay_queue::{self, TaskQueue},
+    envelope::{Envelope, Metadata},
+    ids::IdGenerator,
+    join::JoinAll,
+    join_all, pair, poly, Error, Promise,
+};
+use futures_core::Stream;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// The error returned by [`Registry`] operations.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RegistryError {
+    #[error("unknown key")]
+    UnknownKey,
+    #[error("duplicate key")]
+    DuplicateKey,
+    #[error("registry shut down")]
+    Shutdown,
+    /// The version does not match the pending entry of the key.
+    #[error("stale version")]
+    Stale,
+}
+
+/// Identifies one registration of a key, from
+/// [`Registry::register_versioned`] or [`Registry::supersede`]. Sent along
+/// with the request, it lets [`Registry::resolve_versioned`] reject a
+/// response meant for an earlier registration of the same key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Version(u64);
+
+impl Version {
+    /// Return the version as a number, to be echoed back by the peer.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_u64(version: u64) -> Self {
+        Version(version)
+    }
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    // Tells a later entry for the same key apart from this one.
+    id: u64,
+    waiters: Waiters<T>,
+    created: Instant,
+    deadline: Option<Instant>,
+    // Only held to be dropped with the entry.
+    _expiry: Option<Expiry>,
+}
+
+/// An entry's place in the registry's [`TaskQueue`], taken out when the entry
+/// is dropped, so a settled entry leaves nothing behind to expire.
+#[derive(Debug)]
+struct Expiry {
+    queue: TaskQueue,
+    key: delay_queue::Key,
+}
+
+impl Drop for Expiry {
+    fn drop(&mut self) {
+        self.queue.remove(self.key);
+    }
+}
+
+#[derive(Debug)]
+enum Waiters<T> {
+    /// Made by [`Registry::register`], for its one consumer.
+    Registered(pair::Producer<Result<T, Error>>),
+    /// Made by [`Registry::wait_for`]. The consumer and the waiting guard are
+    /// kept weakly to hand out clones, so the entry is removed once the last
+    /// waiter is dropped.
+    Awaited(
+        poly::Producer<Result<Arc<T>, Error>>,
+        poly::WeakConsumer<Result<Arc<T>, Error>>,
+        Weak<Waiting>,
+    ),
+}
+
+/// Shared by the [`Shared`] consumers of an entry, and removes the entry once
+/// the last of them is dropped.
+struct Waiting(Mutex<Option<Box<dyn FnOnce() + Send>>>);
+
+impl Debug for Waiting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Waiting").finish_non_exhaustive()
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if let Some(abandon) = self.0.get_mut().unwrap().take() {
+            abandon()
+        }
+    }
+}
+
+impl<T> Entry<T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        match &self.waiters {
+            Waiters::Registered(producer) => producer.metadata(),
+            Waiters::Awaited(producer, ..) => producer.metadata(),
+        }
+    }
+
+    fn state(&self) -> EntryState {
+        match &self.waiters {
+            Waiters::Registered(producer) if producer.is_closed() => EntryState::Abandoned,
+            Waiters::Registered(_) => EntryState::Registered,
+            Waiters::Awaited(producer, ..) => match producer.consumers() {
+                0 => EntryState::Abandoned,
+                waiters => EntryState::Awaited { waiters },
+            },
+        }
+    }
+
+    fn settle(self, result: Result<T, Error>) {
+        match self.waiters {
+            Waiters::Registered(producer) => producer.resolve(result),
+            Waiters::Awaited(producer, ..) => producer.resolve(result.map(Arc::new)),
+        }
+    }
+}
+
+/// What a pending entry is waiting with, as reported by
+/// [`Registry::snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    /// Made by [`Registry::register`], and its consumer is waiting.
+    Registered,
+    /// Made by [`Registry::wait_for`], with this many consumers waiting.
+    Awaited { waiters: usize },
+    /// Every consumer has been dropped, so resolving the entry would go
+    /// unobserved. An entry made by [`Registry::wait_for`] is removed once its
+    /// last consumer is dropped, so this is mostly seen for
+    /// [`Registry::register`].
+    Abandoned,
+}
+
+/// A pending entry, as reported by [`Registry::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntrySnapshot<K> {
+    pub key: K,
+    /// How long ago the entry was made.
+    pub age: Duration,
+    /// How long until the entry expires, if it has a time-to-live.
+    pub expires_in: Option<Duration>,
+    pub state: EntryState,
+    /// The metadata the entry was registered with.
+    pub metadata: Option<Arc<Metadata>>,
+}
+
+/// A pending entry as saved by [`Registry::persist`], with its times on the
+/// wall clock so they stay meaningful in another process.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PersistedEntry<K> {
+    pub key: K,
+    pub registered_at: std::time::SystemTime,
+    pub deadline: Option<std::time::SystemTime>,
+    pub trace_id: Option<String>,
+    pub caller: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+impl<K> PersistedEntry<K> {
+    /// Return true if the entry's deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= std::time::SystemTime::now())
+    }
+
+    /// Return the metadata the entry is re-issued with.
+    pub fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        if let Some(trace_id) = &self.trace_id {
+            metadata = metadata.with_trace_id(trace_id.clone());
+        }
+        if let Some(caller) = &self.caller {
+            metadata = metadata.with_caller(caller.clone());
+        }
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline
+                .duration_since(std::time::SystemTime::now())
+                .unwrap_or_default();
+            metadata = metadata.with_deadline(Instant::now() + remaining);
+        }
+        metadata
+    }
+}
+
+impl<K: Clone, T> Inner<K, T> {
+    /// Return every pending entry, oldest first.
+    fn snapshot(&self) -> Vec<EntrySnapshot<K>> {
+        let now = Instant::now();
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.id);
+        entries
+            .into_iter()
+            .map(|(key, entry)| EntrySnapshot {
+                key: key.clone(),
+                age: now.saturating_duration_since(entry.created),
+                expires_in: entry
+                    .deadline
+                    .map(|deadline| deadline.saturating_duration_since(now)),
+                state: entry.state(),
+                metadata: entry.metadata(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Inner<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    next_id: u64,
+    ttl: Option<Duration>,
+    // The deadlines of the entries with a time-to-live.
+    expiries: TaskQueue,
+    shut_down: bool,
+    observers: Vec<Observer<K, T>>,
+    ids: Option<Ids<K>>,
+}
+
+/// The id generator of [`Registry::with_ids`].
+struct Ids<K>(Box<dyn IdGenerator<Id = K> + Send>);
+
+impl<K> Debug for Ids<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Ids")
+    }
+}
+
+impl<K: Clone, T> Inner<K, T> {
+    /// Tell every observer that the entry for `key` settled with `result`.
+    fn observe(&mut self, key: &K, result: &Result<T, Error>) {
+        self.observers.retain(|observer| observer.push(key, result));
+    }
+}
+
+#[derive(Debug)]
+struct Completed<K, T> {
+    settled: VecDeque<(K, Result<T, Error>)>,
+    waker: Option<Waker>,
+    // Set once the registry can settle no more entries.
+    closed: bool,
+    // Set once the stream has been dropped.
+    dropped: bool,
+}
+
+/// The registry's end of a [`Completions`] stream.
+#[derive(Debug)]
+struct Observer<K, T> {
+    completed: Arc<Mutex<Completed<K, T>>>,
+    // Captured where `T: Clone` is known, so the registry itself does not
+    // require it.
+    clone: fn(&T) -> T,
+}
+
+impl<K: Clone, T> Observer<K, T> {
+    /// Queue a copy of the settled entry. Returns false if the stream is gone.
+    fn push(&self, key: &K, result: &Result<T, Error>) -> bool {
+        let mut completed = self.completed.lock().unwrap();
+        if completed.dropped {
+            return false;
+        }
+        let result = result.as_ref().map(self.clone).map_err(Error::clone);
+        completed.settled.push_back((key.clone(), result));
+        if let Some(waker) = completed.waker.take() {
+            waker.wake()
+        }
+        true
+    }
+}
+
+impl<K, T> Drop for Observer<K, T> {
+    /// End the stream once it has taken what was queued.
+    fn drop(&mut self) {
+        let mut completed = self.completed.lock().unwrap();
+        completed.closed = true;
+        if let Some(waker) = completed.waker.take() {
+            waker.wake()
+        }
+    }
+}
+
+/// A set of pending entries keyed by `K`. Clones share the same entries, and
+/// dropping every clone fails the consumers still waiting.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::registry::{Registry, RegistryError};
+/// use futures::executor::block_on;
+/// let registry = Registry::<u64, String>::new();
+/// let reply = registry.register(1).unwrap();
+/// assert_eq!(Err(RegistryError::DuplicateKey), registry.register(1).map(drop));
+/// // ... send request #1 and receive its response ...
+/// registry.resolve(&1, String::from("pong")).unwrap();
+/// assert_eq!("pong", block_on(reply).unwrap());
+/// assert_eq!(Err(RegistryError::UnknownKey), registry.resolve(&1, String::new()));
+/// ```
+///
+/// An entry whose response never arrives is rejected once its time-to-live
+/// is over.
+///
+/// ```
+/// use promise_out::{Error, registry::Registry};
+/// use futures::executor::block_on;
+/// use std::time::Duration;
+/// let registry = Registry::<u64, String>::new().with_ttl(Duration::from_millis(10));
+/// let reply = registry.register(1).unwrap();
+/// assert_eq!(Err(Error::Timeout), block_on(reply));
+/// assert!(registry.is_empty());
+/// ```
+pub struct Registry<K, T> {
+    inner: Arc<Mutex<Inner<K, T>>>,
+}
+
+/// The consumer of a registry entry.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer<T> {
+    consumer: pair::Consumer<Result<T, Error>>,
+}
+
+/// A consumer of an entry made by [`Registry::wait_for`], shared with the
+/// other waiters for the same key.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Shared<T> {
+    consumer: poly::Consumer<Result<Arc<T>, Error>>,
+    waiting: Arc<Waiting>,
+}
+
+impl<K, T> Registry<K, T>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    T: Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Registry {
+            inner: Arc::new(Mutex::new(Inner {
+                entries: HashMap::new(),
+                next_id: 0,
+                ttl: None,
+                expiries: TaskQueue::new(),
+                shut_down: false,
+                observers: vec![],
+                ids: None,
+            })),
+        }
+    }
+
+    /// Give every entry registered from now on a time-to-live of `ttl`.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        self.inner.lock().unwrap().ttl = Some(ttl);
+        self
+    }
+
+    /// Draw the keys of [`register_new`](Self::register_new) from `ids`.
+    pub fn with_ids(self, ids: impl IdGenerator<Id = K> + Send + 'static) -> Self {
+        self.inner.lock().unwrap().ids = Some(Ids(Box::new(ids)));
+        self
+    }
+
+    /// Add an entry under a fresh key from the registry's id generator, and
+    /// return the key with the consumer. Keys still pending, e.g. after the
+    /// generator wrapped around, are skipped. Fails if the registry has been
+    /// shut down, or if every key the generator produced in a full round is
+    /// pending.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the registry was not given an id generator with
+    /// [`with_ids`](Self::with_ids).
+    ///
+    /// ```
+    /// use promise_out::{ids::Sequential, registry::Registry};
+    /// use futures::executor::block_on;
+    /// let registry = Registry::<u64, String>::new().with_ids(Sequential::bounded(1));
+    /// let (first, reply) = registry.register_new().unwrap();
+    /// let (second, _) = registry.register_new().unwrap();
+    /// assert_eq!((0, 1), (first, second));
+    /// // ... send request `first` and receive its response ...
+    /// registry.resolve(&first, String::from("pong")).unwrap();
+    /// assert_eq!("pong", block_on(reply).unwrap());
+    /// // The generator wrapped around to 0, which is free again.
+    /// assert_eq!(0, registry.register_new().unwrap().0);
+    /// ```
+    pub fn register_new(&self) -> Result<(K, Consumer<T>), RegistryError> {
+        let ttl = self.inner.lock().unwrap().ttl;
+        let attempts = self.len() + 1;
+        for _ in 0..attempts {
+            let key = {
+                let inner = self.inner.lock().unwrap();
+                let ids = inner
+                    .ids
+                    .as_ref()
+                    .expect("register_new needs an id generator; see Registry::with_ids");
+                ids.0.next_id()
+            };
+            match self.insert(key.clone(), ttl, None) {
+                Ok((_, consumer)) => return Ok((key, consumer)),
+                Err(RegistryError::DuplicateKey) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(RegistryError::DuplicateKey)
+    }
+
+    /// Add an entry for `key` and return its consumer. Fails if `key` is
+    /// already pending or the registry has been shut down. The entry expires
+    /// after the registry's time-to-live, if it has one.
+    pub fn register(&self, key: K) -> Result<Consumer<T>, RegistryError> {
+        self.register_versioned(key).map(|(_, consumer)| consumer)
+    }
+
+    /// Add an entry for `key` as with [`register`](Self::register), and
+    /// return the version of the registration along with its consumer.
+    pub fn register_versioned(&self, key: K) -> Result<(Version, Consumer<T>), RegistryError> {
+        let ttl = self.inner.lock().unwrap().ttl;
+        self.insert(key, ttl, None)
+    }
+
+    /// Add an entry for `key` that is rejected with [`Error::Timeout`] and
+    /// removed unless it is resolved within `ttl`.
+    pub fn register_with_ttl(&self, key: K, ttl: Duration) -> Result<Consumer<T>, RegistryError> {
+        self.insert(key, Some(ttl), None)
+            .map(|(_, consumer)| consumer)
+    }
+
+    /// Add an entry for `key` carrying `metadata`, which its consumer and
+    /// [`snapshot`](Self::snapshot) report. The entry expires at the
+    /// metadata's deadline if it has one, else after the registry's
+    /// time-to-live.
+    ///
+    /// ```
+    /// use promise_out::{envelope::{Envelope, Metadata}, registry::Registry, Error};
+    /// use futures::executor::block_on;
+    /// use std::time::{Duration, Instant};
+    /// let registry = Registry::<u64, String>::new();
+    /// let metadata = Metadata::new()
+    ///     .with_trace_id("4bf92f35")
+    ///     .with_deadline(Instant::now() + Duration::from_millis(10));
+    /// let reply = registry.register_with_metadata(1, metadata).unwrap();
+    /// assert_eq!(Some("4bf92f35"), reply.metadata().unwrap().trace_id());
+    /// assert_eq!(Err(Error::Timeout), block_on(reply));
+    /// ```
+    pub fn register_with_metadata(
+        &self,
+        key: K,
+        metadata: Metadata,
+    ) -> Result<Consumer<T>, RegistryError> {
+        let ttl = match metadata.remaining() {
+            Some(remaining) => Some(remaining),
+            None => self.inner.lock().unwrap().ttl,
+        };
+        self.insert(key, ttl, Some(metadata))
+            .map(|(_, consumer)| consumer)
+    }
+
+    /// Replace the pending entry for `key`, if any, with a new registration,
+    /// e.g. when the request is retried. The consumers of the replaced entry
+    /// fail with [`Error::Stale`], and a response carrying its version is
+    /// refused by [`resolve_versioned`](Self::resolve_versioned). Fails if
+    /// the registry has been shut down.
+    ///
+    /// ```
+    /// use promise_out::{registry::{Registry, RegistryError}, Error};
+    /// use futures::executor::block_on;
+    /// let registry = Registry::<u64, String>::new();
+    /// let (first, original) = registry.register_versioned(7).unwrap();
+    /// // ... the request times out on the caller's side and is sent again ...
+    /// let (second, retry) = registry.supersede(7).unwrap();
+    /// assert_eq!(Err(Error::Stale), block_on(original));
+    /// // The response to the original request arrives late.
+    /// assert_eq!(
+    ///     Err(RegistryError::Stale),
+    ///     registry.resolve_versioned(&7, first, String::from("late"))
+    /// );
+    /// registry.resolve_versioned(&7, second, String::from("pong")).unwrap();
+    /// assert_eq!("pong", block_on(retry).unwrap());
+    /// ```
+    pub fn supersede(&self, key: K) -> Result<(Version, Consumer<T>), RegistryError> {
+        let (superseded, registered) = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.shut_down {
+                return Err(RegistryError::Shutdown);
+            }
+            let superseded = inner.entries.remove(&key);
+            if superseded.is_some() {
+                inner.observe(&key, &Err(Error::Stale));
+            }
+            let ttl = inner.ttl;
+            (superseded, self.registered(&mut inner, key, ttl, None))
+        };
+        if let Some(entry) = superseded {
+            entry.settle(Err(Error::Stale));
+        }
+        Ok(registered)
+    }
+
+    fn insert(
+        &self,
+        key: K,
+        ttl: Option<Duration>,
+        metadata: Option<Metadata>,
+    ) -> Result<(Version, Consumer<T>), RegistryError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.shut_down {
+            return Err(RegistryError::Shutdown);
+        }
+        if inner.entries.contains_key(&key) {
+            return Err(RegistryError::DuplicateKey);
+        }
+        Ok(self.registered(&mut inner, key, ttl, metadata))
+    }
+
+    /// Add an entry for `key` with a single consumer.
+    fn registered(
+        &self,
+        inner: &mut Inner<K, T>,
+        key: K,
+        ttl: Option<Duration>,
+        metadata: Option<Metadata>,
+    ) -> (Version, Consumer<T>) {
+        let (producer, consumer) = match metadata {
+            Some(metadata) => pair::Producer::with_metadata(metadata),
+            None => pair::Producer::new(),
+        };
+        let entry = self.entry(inner, &key, ttl, Waiters::Registered(producer));
+        let version = Version(entry.id);
+        inner.entries.insert(key, entry);
+        (version, Consumer { consumer })
+    }
+
+    /// Return a consumer for the value of `key`, whether or not anyone has
+    /// registered it yet. The first waiter creates the entry and every later
+    /// one shares it, until the value is resolved. Fails if `key` was
+    /// registered with [`register`](Self::register), since that entry has a
+    /// single consumer, or if the registry has been shut down.
+    ///
+    /// ```
+    /// use promise_out::registry::Registry;
+    /// use futures::executor::block_on;
+    /// let registry = Registry::<&str, u32>::new();
+    /// let first = registry.wait_for("answer").unwrap();
+    /// let second = registry.wait_for("answer").unwrap();
+    /// registry.resolve(&"answer", 42).unwrap();
+    /// assert_eq!(42, *block_on(first).unwrap());
+    /// assert_eq!(42, *block_on(second).unwrap());
+    /// ```
+    pub fn wait_for(&self, key: K) -> Result<Shared<T>, RegistryError> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::check_awaitable(&inner, &key)?;
+        Ok(self.shared(&mut inner, key))
+    }
+
+    /// Return a future for the values of every key in `keys`, each awaited as
+    /// with [`wait_for`](Self::wait_for). It resolves with a map from key to
+    /// value once all of them are resolved, and fails as soon as any of them
+    /// is rejected. Fails without creating any entry if one of the keys can
+    /// not be awaited.
+    ///
+    /// ```
+    /// use promise_out::registry::Registry;
+    /// use futures::executor::block_on;
+    /// let registry = Registry::<&str, u32>::new();
+    /// let shards = registry.wait_all(["east", "west"]).unwrap();
+    /// registry.resolve(&"west", 2).unwrap();
+    /// registry.resolve(&"east", 1).unwrap();
+    /// let totals = block_on(shards).unwrap();
+    /// assert_eq!(3, *totals["east"] + *totals["west"]);
+    /// ```
+    pub fn wait_all<I>(&self, keys: I) -> Result<WaitAll<K, T>, RegistryError>
+    where
+        I: IntoIterator<Item = K>,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let mut inner = self.inner.lock().unwrap();
+        for key in &keys {
+            Self::check_awaitable(&inner, key)?;
+        }
+        let consumers: Vec<_> = keys
+            .iter()
+            .map(|key| self.shared(&mut inner, key.clone()))
+            .collect();
+        Ok(WaitAll {
+            keys: Some(keys),
+            values: join_all(consumers),
+        })
+    }
+
+    fn check_awaitable(inner: &Inner<K, T>, key: &K) -> Result<(), RegistryError> {
+        if inner.shut_down {
+            return Err(RegistryError::Shutdown);
+        }
+        match inner.entries.get(key).map(|entry| &entry.waiters) {
+            Some(Waiters::Registered(_)) => Err(RegistryError::DuplicateKey),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return a shared consumer for `key`, creating its entry if needed.
+    fn shared(&self, inner: &mut Inner<K, T>, key: K) -> Shared<T> {
+        if let Some(Entry {
+            waiters: Waiters::Awaited(_, consumer, waiting),
+            ..
+        }) = inner.entries.get(&key)
+        {
+            // Both are gone once the last waiter is, and the entry is about to
+            // be removed; it is replaced below then.
+            if let (Some(consumer), Some(waiting)) = (consumer.upgrade(), waiting.upgrade()) {
+                return Shared { consumer, waiting };
+            }
+        }
+        let (producer, consumer) = poly::Producer::new();
+        let ttl = inner.ttl;
+        let id = inner.next_id;
+        let weak = Arc::downgrade(&self.inner);
+        let abandoned = key.clone();
+        let waiting = Arc::new(Waiting(Mutex::new(Some(Box::new(move || {
+            abandon(weak, abandoned, id)
+        })))));
+        let waiters = Waiters::Awaited(producer, consumer.downgrade(), Arc::downgrade(&waiting));
+        let entry = self.entry(inner, &key, ttl, waiters);
+        inner.entries.insert(key, entry);
+        Shared { consumer, waiting }
+    }
+
+    /// Return a new entry, scheduling its expiry if it has a time-to-live.
+    fn entry(
+        &self,
+        inner: &mut Inner<K, T>,
+        key: &K,
+        ttl: Option<Duration>,
+        waiters: Waiters<T>,
+    ) -> Entry<T> {
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let created = Instant::now();
+        let deadline = ttl.map(|ttl| created + ttl);
+        let expiry = deadline.map(|deadline| {
+            let weak = Arc::downgrade(&self.inner);
+            let key = key.clone();
+            Expiry {
+                queue: inner.expiries.clone(),
+                key: inner
+                    .expiries
+                    .insert(deadline, move || expire(weak, key, id)),
+            }
+        });
+        Entry {
+            id,
+            waiters,
+            created,
+            deadline,
+            _expiry: expiry,
+        }
+    }
+
+    /// Settle the entry for `key` with `value` and remove it. Fails if no
+    /// entry for `key` is pending; `value` is dropped then.
+    pub fn resolve(&self, key: &K, value: T) -> Result<(), RegistryError> {
+        let (entry, result) = {
+            let mut inner = self.inner.lock().unwrap();
+            let entry = inner.entries.remove(key);
+            let entry = entry.ok_or(RegistryError::UnknownKey)?;
+            let result = Ok(value);
+            inner.observe(key, &result);
+            (entry, result)
+        };
+        entry.settle(result);
+        Ok(())
+    }
+
+    /// Settle the entry for `key` with `value` as with
+    /// [`resolve`](Self::resolve), but only if it is the registration that
+    /// returned `version`. Fails with [`RegistryError::Stale`] if the key has
+    /// since been registered again, leaving the newer entry pending.
+    pub fn resolve_versioned(
+        &self,
+        key: &K,
+        version: Version,
+        value: T,
+    ) -> Result<(), RegistryError> {
+        let (entry, result) = {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.entries.get(key) {
+                None => return Err(RegistryError::UnknownKey),
+                Some(entry) if entry.id != version.0 => return Err(RegistryError::Stale),
+                Some(_) => {}
+            }
+            let entry = inner.entries.remove(key).expect("the entry was just found");
+            let result = Ok(value);
+            inner.observe(key, &result);
+            (entry, result)
+        };
+        entry.settle(result);
+        Ok(())
+    }
+
+    /// Remove the entry for `key` without settling it, failing its consumers
+    /// with [`Error::ProducerDropped`]. Returns false if there was none.
+    pub fn remove(&self, key: &K) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.entries.remove(key);
+        if entry.is_some() {
+            inner.observe(key, &Err(Error::ProducerDropped));
+        }
+        entry.is_some()
+    }
+
+    /// Return a stream of every entry settled from now on, with its key and
+    /// result. An entry removed with [`remove`](Self::remove) is reported as
+    /// [`Error::ProducerDropped`]. The stream ends once the registry is shut
+    /// down or dropped.
+    ///
+    /// ```
+    /// use promise_out::{Error, registry::Registry};
+    /// use futures::{executor::block_on, StreamExt};
+    /// use std::time::Duration;
+    /// let registry = Registry::<u64, String>::new();
+    /// let mut log = registry.completions();
+    /// let _fast = registry.register(1).unwrap();
+    /// let _slow = registry.register_with_ttl(2, Duration::from_millis(10)).unwrap();
+    /// registry.resolve(&1, String::from("pong")).unwrap();
+    /// assert_eq!(Some((1, Ok(String::from("pong")))), block_on(log.next()));
+    /// assert_eq!(Some((2, Err(Error::Timeout))), block_on(log.next()));
+    /// drop(registry);
+    /// assert_eq!(None, block_on(log.next()));
+    /// ```
+    pub fn completions(&self) -> Completions<K, T>
+    where
+        T: Clone,
+    {
+        let completed = Arc::new(Mutex::new(Completed {
+            settled: VecDeque::new(),
+            waker: None,
+            closed: false,
+            dropped: false,
+        }));
+        let observer = Observer {
+            completed: completed.clone(),
+            clone: T::clone,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.shut_down {
+            inner.observers.push(observer);
+        }
+        Completions { completed }
+    }
+
+    /// Return every pending entry, oldest first, to diagnose stuck requests.
+    /// The [`Debug`] output of the registry lists the same.
+    ///
+    /// ```
+    /// use promise_out::registry::{EntryState, Registry};
+    /// let registry = Registry::<u64, String>::new();
+    /// let _reply = registry.register(1).unwrap();
+    /// drop(registry.register(2).unwrap());
+    /// let states: Vec<_> = registry.snapshot().into_iter().map(|e| (e.key, e.state)).collect();
+    /// assert_eq!(vec![(1, EntryState::Registered), (2, EntryState::Abandoned)], states);
+    /// ```
+    pub fn snapshot(&self) -> Vec<EntrySnapshot<K>> {
+        self.inner.lock().unwrap().snapshot()
+    }
+
+    /// Return every pending entry, oldest first, in a form that can be
+    /// serialized and restored by a later process with
+    /// [`reissue`](Self::reissue). Entries made by
+    /// [`wait_for`](Self::wait_for) are included too.
+    ///
+    /// ```
+    /// use promise_out::{envelope::Metadata, registry::Registry};
+    /// let registry = Registry::<u64, String>::new();
+    /// let _reply = registry
+    ///     .register_with_metadata(1, Metadata::new().with_caller("checkout"))
+    ///     .unwrap();
+    /// let saved = registry.persist();
+    /// // ... serialize `saved`, restart, and deserialize it ...
+    /// let restarted = Registry::<u64, String>::new();
+    /// for entry in saved {
+    ///     assert!(!entry.is_expired());
+    ///     let _reply = restarted.reissue(entry).unwrap();
+    ///     // ... send request #1 again ...
+    /// }
+    /// assert!(restarted.contains(&1));
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn persist(&self) -> Vec<PersistedEntry<K>> {
+        use std::time::SystemTime;
+        let inner = self.inner.lock().unwrap();
+        let (now, wall) = (Instant::now(), SystemTime::now());
+        // Instants only make sense within this process.
+        let to_wall = |instant: Instant| match instant.checked_duration_since(now) {
+            Some(ahead) => wall + ahead,
+            None => wall - now.duration_since(instant),
+        };
+        let mut entries: Vec<_> = inner.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.id);
+        entries
+            .into_iter()
+            .map(|(key, entry)| {
+                let metadata = entry.metadata();
+                PersistedEntry {
+                    key: key.clone(),
+                    registered_at: to_wall(entry.created),
+                    deadline: entry.deadline.map(to_wall),
+                    trace_id: metadata
+                        .as_ref()
+                        .and_then(|m| m.trace_id().map(String::from)),
+                    caller: metadata.as_ref().and_then(|m| m.caller().map(String::from)),
+                }
+            })
+            .collect()
+    }
+
+    /// Register a persisted entry again, with its metadata and remaining
+    /// time. An entry whose deadline has passed is registered all the same
+    /// and fails with [`Error::Timeout`] at once, so its request is failed
+    /// explicitly rather than forgotten.
+    #[cfg(feature = "serde")]
+    pub fn reissue(&self, entry: PersistedEntry<K>) -> Result<Consumer<T>, RegistryError> {
+        let metadata = entry.metadata();
+        self.register_with_metadata(entry.key, metadata)
+    }
+
+    /// Return true if the registry has been shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.inner.lock().unwrap().shut_down
+    }
+
+    /// Return true if an entry for `key` is pending.
+    pub fn contains(&self, key: &K) -> bool {
+        self.inner.lock().unwrap().entries.contains_key(key)
+    }
+
+    /// Return the number of pending entries.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Shutting down needs no more of the key than settling does, so a registry
+/// can be shut down from a `Drop` impl that can not add bounds.
+impl<K: Clone, T> Registry<K, T> {
+    /// Reject every pending entry with [`Error::Shutdown`] carrying `reason`,
+    /// and refuse new registrations from now on. Returns how many entries
+    /// were rejected.
+    ///
+    /// ```
+    /// use promise_out::{Error, registry::{Registry, RegistryError}};
+    /// use futures::executor::block_on;
+    /// let registry = Registry::<u64, String>::new();
+    /// let reply = registry.register(1).unwrap();
+    /// assert_eq!(1, registry.shutdown("connection closed"));
+    /// assert_eq!(Err(Error::Shutdown("connection closed".into())), block_on(reply));
+    /// assert_eq!(Err(RegistryError::Shutdown), registry.register(2).map(drop));
+    /// ```
+    pub fn shutdown(&self, reason: impl Into<String>) -> usize {
+        let reason = reason.into();
+        let entries = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.shut_down = true;
+            let entries = std::mem::take(&mut inner.entries);
+            for key in entries.keys() {
+                inner.observe(key, &Err(Error::Shutdown(reason.clone())));
+            }
+            // Nothing can settle anymore, so the streams end.
+            inner.observers.clear();
+            entries
+        };
+        let rejected = entries.len();
+        for entry in entries.into_values() {
+            entry.settle(Err(Error::Shutdown(reason.clone())));
+        }
+        rejected
+    }
+}
+
+/// Remove the entry `id` for `key` once its last waiter has been dropped,
+/// reporting it as [`Error::ProducerDropped`] like [`Registry::remove`].
+fn abandon<K: Hash + Eq + Clone, T>(weak: Weak<Mutex<Inner<K, T>>>, key: K, id: u64) {
+    let Some(inner) = weak.upgrade() else {
+        return;
+    };
+    let entry = {
+        let mut inner = inner.lock().unwrap();
+        match inner.entries.get(&key) {
+            Some(entry) if entry.id == id => {
+                inner.observe(&key, &Err(Error::ProducerDropped));
+                inner.entries.remove(&key)
+            }
+            _ => None,
+        }
+    };
+    // Dropped with the lock released.
+    drop(entry);
+}
+
+/// Reject the entry `id` for `key` if it is still pending.
+fn expire<K: Hash + Eq + Clone, T>(weak: Weak<Mutex<Inner<K, T>>>, key: K, id: u64) {
+    let Some(inner) = weak.upgrade() else {
+        return;
+    };
+    let entry = {
+        let mut inner = inner.lock().unwrap();
+        match inner.entries.get(&key) {
+            Some(entry) if entry.id == id => {
+                inner.observe(&key, &Err(Error::Timeout));
+                inner.entries.remove(&key)
+            }
+            _ => None,
+        }
+    };
+    if let Some(entry) = entry {
+        entry.settle(Err(Error::Timeout));
+    }
+}
+
+impl<K, T> Default for Registry<K, T>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    T: Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T> Clone for Registry<K, T> {
+    fn clone(&self) -> Self {
+        Registry {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: Clone + Debug, T> Debug for Registry<K, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Registry")
+            .field("shut_down", &inner.shut_down)
+            .field("pending", &inner.snapshot())
+            .finish()
+    }
+}
+
+impl<T> Future for Consumer<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.consumer).poll(cx).map(Result::flatten)
+    }
+}
+
+impl<T> Envelope for Consumer<T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.consumer.metadata()
+    }
+}
+
+impl<T> Envelope for Shared<T> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.consumer.metadata()
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared {
+            consumer: self.consumer.clone(),
+            waiting: self.waiting.clone(),
+        }
+    }
+}
+
+impl<T> Future for Shared<T> {
+    type Output = Result<Arc<T>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.consumer)
+            .poll(cx)
+            .map(|result| result.and_then(|shared| (*shared).clone()))
+    }
+}
+
+/// Stream for [`Registry::completions`].
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct Completions<K, T> {
+    completed: Arc<Mutex<Completed<K, T>>>,
+}
+
+impl<K, T> Stream for Completions<K, T> {
+    type Item = (K, Result<T, Error>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut completed = self.completed.lock().unwrap();
+        if let Some(settled) = completed.settled.pop_front() {
+            return Poll::Ready(Some(settled));
+        }
+        if completed.closed {
+            return Poll::Ready(None);
+        }
+        completed.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<K, T> Drop for Completions<K, T> {
+    fn drop(&mut self) {
+        let mut completed = self.completed.lock().unwrap();
+        completed.dropped = true;
+        completed.settled.clear();
+    }
+}
+
+/// Future for [`Registry::wait_all`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WaitAll<K, T> {
+    keys: Option<Vec<K>>,
+    values: JoinAll<Shared<T>, Arc<T>>,
+}
+
+impl<K: Debug, T: Debug> Debug for WaitAll<K, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitAll")
+            .field("keys", &self.keys)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl<K, T> Unpin for WaitAll<K, T> {}
+
+impl<K: Hash + Eq, T> Future for WaitAll<K, T> {
+    type Output = Result<HashMap<K, Arc<T>>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let values = match Pin::new(&mut self.values).poll(cx) {
+            Poll::Ready(values) => values?,
+            Poll::Pending => return Poll::Pending,
+        };
+        let keys = self
+            .keys
+            .take()
+            .expect("WaitAll must not be polled after it returned Ready");
+        Poll::Ready(Ok(keys.into_iter().zip(values).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Registry, RegistryError};
+    use crate::Error;
+    use futures::{executor::block_on, FutureExt};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_registry_correlates_out_of_order() {
+        let registry = Registry::<u32, u32>::new();
+        let consumers: Vec<_> = (0..100)
+            .map(|id| (id, registry.register(id).unwrap()))
+            .collect();
+        let transport = registry.clone();
+        let task1 = thread::spawn(move || {
+            for id in (0..100).rev() {
+                if id == 50 {
+                    assert!(transport.remove(&id));
+                } else {
+                    transport.resolve(&id, id * 10).unwrap();
+                }
+            }
+        });
+        for (id, consumer) in consumers {
+            if id == 50 {
+                assert_eq!(Err(Error::ProducerDropped), block_on(consumer));
+            } else {
+                assert_eq!(Ok(id * 10), block_on(consumer));
+            }
+        }
+        task1.join().expect("The task1 thread has panicked");
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_registry_ttl_spares_reused_key() {
+        let registry = Registry::<u32, u32>::new();
+        let first = registry
+            .register_with_ttl(1, Duration::from_millis(10))
+            .unwrap();
+        registry.resolve(&1, 10).unwrap();
+        assert_eq!(Ok(10), block_on(first));
+        // The first entry's timer must not expire the second one.
+        let second = registry.register(1).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        assert!(registry.contains(&1));
+        registry.resolve(&1, 20).unwrap();
+        assert_eq!(Ok(20), block_on(second));
+    }
+
+    #[test]
+    fn test_registry_settled_entries_leave_the_task_queue() {
+        let registry = Registry::<u32, u32>::new().with_ttl(Duration::from_secs(3600));
+        let consumers: Vec<_> = (0..100).map(|id| registry.register(id).unwrap()).collect();
+        assert_eq!(100, registry.inner.lock().unwrap().expiries.len());
+        for id in 0..50 {
+            registry.resolve(&id, id).unwrap();
+        }
+        registry.remove(&50);
+        assert_eq!(49, registry.inner.lock().unwrap().expiries.len());
+        registry.shutdown("done");
+        assert!(registry.inner.lock().unwrap().expiries.is_empty());
+        drop(consumers);
+    }
+
+    #[test]
+    fn test_registry_wait_all_fails_fast() {
+        let registry = Registry::<u32, u32>::new();
+        let _registered = registry.register(3).unwrap();
+        assert_eq!(
+            Err(RegistryError::DuplicateKey),
+            registry.wait_all([1, 2, 3]).map(drop)
+        );
+        assert_eq!(1, registry.len());
+        let mut all = registry.wait_all([1, 2]).unwrap();
+        registry.resolve(&1, 10).unwrap();
+        assert_eq!(None, (&mut all).now_or_never());
+        assert!(registry.remove(&2));
+        assert_eq!(Err(Error::ProducerDropped), block_on(all));
+    }
+
+    #[test]
+    fn test_registry_completions_end_on_shutdown() {
+        use futures::StreamExt;
+        let registry = Registry::<u32, u32>::new();
+        let log = registry.completions();
+        let _removed = registry.register(1).unwrap();
+        let _pending = registry.wait_for(2).unwrap();
+        assert!(registry.remove(&1));
+        registry.shutdown("bye");
+        assert_eq!(
+            vec![
+                (1, Err(Error::ProducerDropped)),
+                (2, Err(Error::Shutdown("bye".into())))
+            ],
+            block_on(log.collect::<Vec<_>>())
+        );
+    }
+
+    #[test]
+    fn test_registry_snapshot() {
+        use super::EntryState;
+        let registry = Registry::<u32, u32>::new();
+        let _registered = registry
+            .register_with_ttl(1, Duration::from_secs(60))
+            .unwrap();
+        let waiters = [registry.wait_for(2).unwrap(), registry.wait_for(2).unwrap()];
+        let snapshot = registry.snapshot();
+        assert_eq!(EntryState::Registered, snapshot[0].state);
+        assert!(snapshot[0].expires_in.unwrap() <= Duration::from_secs(60));
+        assert_eq!(EntryState::Awaited { waiters: 2 }, snapshot[1].state);
+        assert_eq!(None, snapshot[1].expires_in);
+        drop(waiters);
+        assert_eq!(1, registry.snapshot().len());
+        let debug = format!("{registry:?}");
+        assert!(debug.contains("key: 1"), "{debug}");
+    }
+
+    #[test]
+    fn test_registry_dropped_waiters_remove_the_entry() {
+        let registry = Registry::<u32, u32>::new();
+        let first = registry.wait_for(1).unwrap();
+        let second = first.clone();
+        let all = registry.wait_all([1, 2]).unwrap();
+        assert_eq!(2, registry.len());
+        drop((first, second));
+        assert_eq!(2, registry.len());
+        drop(all);
+        assert!(registry.is_empty());
+        let waiter = registry.wait_for(1).unwrap();
+        registry.resolve(&1, 1).unwrap();
+        assert_eq!(1, *block_on(waiter).unwrap());
+    }
+
+    #[test]
+    fn test_registry_supersede_waiters() {
+        let registry = Registry::<u32, u32>::new();
+        let waiter = registry.wait_for(7).unwrap();
+        let (version, retry) = registry.supersede(7).unwrap();
+        assert_eq!(Err(Error::Stale), block_on(waiter));
+        assert_eq!(
+            Err(RegistryError::UnknownKey),
+            registry.resolve_versioned(&8, version, 1)
+        );
+        registry.resolve_versioned(&7, version, 1).unwrap();
+        assert_eq!(Ok(1), block_on(retry));
+        assert_eq!(
+            Err(RegistryError::UnknownKey),
+            registry.resolve_versioned(&7, version, 2)
+        );
+    }
+
+    #[test]
+    fn test_registry_wait_for_before_register() {
+        let registry = Registry::<u32, u32>::new();
+        let waiters: Vec<_> = (0..3).map(|_| registry.wait_for(7).unwrap()).collect();
+        assert_eq!(1, registry.len());
+        assert_eq!(
+            Err(RegistryError::DuplicateKey),
+            registry.register(7).map(drop)
+        );
+        registry.shutdown("bye");
+        for waiter in waiters {
+            assert_eq!(Err(Error::Shutdown("bye".into())), block_on(waiter));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_registry_persist_round_trip() {
+        use crate::envelope::{Envelope, Metadata};
+        let registry = Registry::<String, u32>::new();
+        let metadata = Metadata::new().with_trace_id("4bf92f35");
+        let _pending = registry
+            .register_with_metadata(String::from("pending"), metadata)
+            .unwrap();
+        let _expired =
+            registry.register_with_ttl(String::from("expired"), Duration::from_millis(10));
+        let saved = serde_json::to_string(&registry.persist()).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let restarted = Registry::<String, u32>::new();
+        let entries: Vec<super::PersistedEntry<String>> = serde_json::from_str(&saved).unwrap();
+        let mut consumers = vec![];
+        for entry in entries {
+            consumers.push((entry.is_expired(), restarted.reissue(entry).unwrap()));
+        }
+        let (expired, pending) = (consumers.pop().unwrap(), consumers.pop().unwrap());
+        assert!(expired.0);
+        assert_eq!(Err(Error::Timeout), block_on(expired.1));
+        assert!(!pending.0);
+        assert_eq!(Some("4bf92f35"), pending.1.metadata().unwrap().trace_id());
+        restarted.resolve(&String::from("pending"), 7).unwrap();
+        assert_eq!(Ok(7), block_on(pending.1));
+    }
+}
@@ -0,0 +1,275 @@
+//! retry re-requests a fresh promise when one fails. Promises are one-shot, so
+//! retrying means asking a factory for a new consumer each attempt, optionally
+//! sleeping between attempts according to a [`Backoff`] policy.
+use crate::{pair, timer, Error};
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How long to wait between attempts, and how long an attempt may take.
+///
+/// The delay before the second attempt is `initial`; every later delay is
+/// multiplied by `factor` and capped at `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    factor: u32,
+    max_delay: Duration,
+    timeout: Option<Duration>,
+}
+
+impl Backoff {
+    /// Retry immediately with no delay.
+    pub fn none() -> Self {
+        Self::fixed(Duration::ZERO)
+    }
+
+    /// Always wait `delay` between attempts.
+    pub fn fixed(delay: Duration) -> Self {
+        Backoff {
+            initial: delay,
+            factor: 1,
+            max_delay: delay,
+            timeout: None,
+        }
+    }
+
+    /// Wait `initial` and double the delay after every failed attempt.
+    pub fn exponential(initial: Duration) -> Self {
+        Backoff {
+            initial,
+            factor: 2,
+            max_delay: Duration::MAX,
+            timeout: None,
+        }
+    }
+
+    /// Multiply the delay by `factor` after every failed attempt.
+    pub fn factor(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    /// Never wait longer than `max_delay` between attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Give up on an attempt that has not settled within `timeout`. The
+    /// attempt then fails with [`Error::Timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The delay before attempt number `attempt + 1`, counting from one.
+    fn delay(&self, attempt: u32) -> Duration {
+        let mut delay = self.initial;
+        for _ in 1..attempt {
+            delay = delay.saturating_mul(self.factor);
+            if delay >= self.max_delay {
+                break;
+            }
+        }
+        delay.min(self.max_delay)
+    }
+}
+
+impl Default for Backoff {
+    /// Exponential backoff starting at 10ms.
+    fn default() -> Self {
+        Self::exponential(Duration::from_millis(10))
+    }
+}
+
+/// Await the consumer returned by `factory`, asking for a fresh one if it
+/// fails, for at most `attempts` attempts. The last error is returned if every
+/// attempt fails. Zero attempts is treated as one.
+///
+/// The error type must be able to hold an [`Error`] so a timed out attempt can
+/// be reported.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, pair::Producer, retry::{retry, Backoff}};
+/// use futures::executor::block_on;
+/// use std::time::Duration;
+/// let mut tries = 0;
+/// let result = block_on(retry(3, Backoff::exponential(Duration::from_millis(1)), || {
+///     tries += 1;
+///     let (promise, consumer) = Producer::<u32>::new();
+///     if tries == 3 {
+///         promise.resolve(tries);
+///     }
+///     // Otherwise the promise is dropped and the attempt fails.
+///     consumer
+/// }));
+/// assert_eq!(Ok(3), result);
+/// ```
+pub fn retry<F, Fut, T, E>(attempts: u32, backoff: Backoff, factory: F) -> Retry<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    E: From<Error>,
+{
+    Retry {
+        factory,
+        backoff,
+        attempts: attempts.max(1),
+        attempt: 0,
+        state: State::Start,
+    }
+}
+
+#[derive(Debug)]
+enum State<Fut> {
+    Start,
+    Attempt(Fut, Option<pair::Consumer<()>>),
+    Waiting(pair::Consumer<()>),
+    Done,
+}
+
+/// Future for [`retry`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Retry<F, Fut> {
+    factory: F,
+    backoff: Backoff,
+    attempts: u32,
+    attempt: u32,
+    state: State<Fut>,
+}
+
+impl<F, Fut: Debug> Debug for Retry<F, Fut> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Retry")
+            .field("backoff", &self.backoff)
+            .field("attempts", &self.attempts)
+            .field("attempt", &self.attempt)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl<F, Fut: Unpin> Unpin for Retry<F, Fut> {}
+
+impl<F, Fut, T, E> Future for Retry<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>> + Unpin,
+    E: From<Error>,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Start => {
+                    this.attempt += 1;
+                    let timeout = this.backoff.timeout.map(timer::delay);
+                    this.state = State::Attempt((this.factory)(), timeout);
+                }
+                State::Attempt(future, timeout) => {
+                    let error = match Pin::new(future).poll(cx) {
+                        Poll::Ready(Ok(value)) => {
+                            this.state = State::Done;
+                            return Poll::Ready(Ok(value));
+                        }
+                        Poll::Ready(Err(error)) => error,
+                        Poll::Pending => match timeout.as_mut().map(|t| Pin::new(t).poll(cx)) {
+                            Some(Poll::Ready(_)) => E::from(Error::Timeout),
+                            _ => return Poll::Pending,
+                        },
+                    };
+                    if this.attempt >= this.attempts {
+                        this.state = State::Done;
+                        return Poll::Ready(Err(error));
+                    }
+                    let delay = this.backoff.delay(this.attempt);
+                    this.state = if delay.is_zero() {
+                        State::Start
+                    } else {
+                        State::Waiting(timer::delay(delay))
+                    };
+                }
+                State::Waiting(delay) => match Pin::new(delay).poll(cx) {
+                    Poll::Ready(_) => this.state = State::Start,
+                    Poll::Pending => return Poll::Pending,
+                },
+                State::Done => panic!("Retry must not be polled after it returned Ready"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry, Backoff};
+    use crate::{pair::Producer, Error, Promise};
+    use futures::executor::block_on;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff =
+            Backoff::exponential(Duration::from_millis(10)).max_delay(Duration::from_millis(50));
+        assert_eq!(Duration::from_millis(10), backoff.delay(1));
+        assert_eq!(Duration::from_millis(20), backoff.delay(2));
+        assert_eq!(Duration::from_millis(40), backoff.delay(3));
+        assert_eq!(Duration::from_millis(50), backoff.delay(4));
+        assert_eq!(Duration::from_millis(50), backoff.delay(40));
+        assert_eq!(
+            Duration::from_millis(5),
+            Backoff::fixed(Duration::from_millis(5)).delay(3)
+        );
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let mut tries = 0;
+        let result = block_on(retry(3, Backoff::none(), || {
+            tries += 1;
+            Producer::<u32>::new().1
+        }));
+        assert_eq!(Err(Error::ProducerDropped), result);
+        assert_eq!(3, tries);
+    }
+
+    #[test]
+    fn test_retry_timeout() {
+        let mut producers = Vec::new();
+        let backoff = Backoff::fixed(Duration::from_millis(1)).timeout(Duration::from_millis(10));
+        let result = block_on(retry(2, backoff, || {
+            let (op, op_a) = Producer::<u32>::new();
+            // Keep the producer alive so only the timeout can fail the attempt.
+            producers.push(op);
+            op_a
+        }));
+        assert_eq!(Err(Error::Timeout), result);
+        assert_eq!(2, producers.len());
+    }
+
+    #[test]
+    fn test_retry_resolves_late_attempt() {
+        let mut tries = 0;
+        let backoff = Backoff::exponential(Duration::from_millis(1));
+        let result = block_on(retry(5, backoff, || {
+            tries += 1;
+            let (op, op_a) = Producer::<u32>::new();
+            let tries = tries;
+            thread::spawn(move || {
+                if tries == 4 {
+                    op.resolve(tries);
+                }
+            });
+            op_a
+        }));
+        assert_eq!(Ok(4), result);
+    }
+}
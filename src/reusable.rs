@@ -0,0 +1,178 @@
+//! reusable implements a single-consumer promise that can be re-armed after
+//! each round instead of allocating a new pair. Every round has a generation
+//! number; a consumer from an earlier round fails with [`Error::Stale`] rather
+//! than observing a later round's value.
+use crate::{Error, WakerState};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug)]
+struct Inner<T> {
+    generation: u64,
+    resolved: bool,
+    value: Option<T>,
+    waker: Result<Waker, WakerState>,
+}
+
+impl<T> Inner<T> {
+    fn wake(&mut self, state: WakerState) {
+        if let Ok(waker) = std::mem::replace(&mut self.waker, Err(state)) {
+            waker.wake()
+        }
+    }
+}
+
+/// The producer of a reusable promise. It resolves the current round and
+/// re-arms the promise for the next one.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::reusable::Producer;
+/// use futures::executor::block_on;
+/// let (promise, mut consumer) = Producer::<u32>::new();
+/// for round in 0..3 {
+///     promise.resolve(round).unwrap();
+///     assert_eq!(Ok(round), block_on(consumer));
+///     consumer = promise.rearm();
+/// }
+/// assert_eq!(3, consumer.generation());
+/// ```
+#[derive(Debug)]
+pub struct Producer<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+}
+
+/// The consumer of one round of a reusable promise.
+#[derive(Debug)]
+pub struct Consumer<T> {
+    promise: Arc<Mutex<Inner<T>>>,
+    generation: u64,
+}
+
+impl<T> Producer<T> {
+    /// Return a (producer, consumer) pair for generation zero.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Self, Consumer<T>) {
+        let promise = Arc::new(Mutex::new(Inner {
+            generation: 0,
+            resolved: false,
+            value: None,
+            waker: Err(WakerState::Fresh),
+        }));
+        (
+            Producer {
+                promise: promise.clone(),
+            },
+            Consumer {
+                promise,
+                generation: 0,
+            },
+        )
+    }
+
+    /// Resolve the current round. The value is handed back if this round was
+    /// already resolved.
+    pub fn resolve(&self, value: T) -> Result<(), T> {
+        let mut promise = self.promise.lock().unwrap();
+        if promise.resolved {
+            return Err(value);
+        }
+        promise.resolved = true;
+        promise.value = Some(value);
+        promise.wake(WakerState::Tainted);
+        Ok(())
+    }
+
+    /// Start the next round and return its consumer. A consumer of the
+    /// previous round that is still waiting fails with [`Error::Stale`], and
+    /// an unclaimed value of the previous round is dropped.
+    pub fn rearm(&self) -> Consumer<T> {
+        let mut promise = self.promise.lock().unwrap();
+        promise.generation += 1;
+        promise.resolved = false;
+        promise.value = None;
+        promise.wake(WakerState::Fresh);
+        Consumer {
+            promise: self.promise.clone(),
+            generation: promise.generation,
+        }
+    }
+
+    /// Return the current round's generation.
+    pub fn generation(&self) -> u64 {
+        self.promise.lock().unwrap().generation
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    /// If the current round is unresolved, wake its consumer with an error.
+    fn drop(&mut self) {
+        self.promise.lock().unwrap().wake(WakerState::Tainted);
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Return the generation of the round this consumer waits for.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl<T> Future for Consumer<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut promise = self.promise.lock().unwrap();
+        if promise.generation != self.generation {
+            return Poll::Ready(Err(Error::Stale));
+        }
+        match promise.value.take() {
+            Some(value) => Poll::Ready(Ok(value)),
+            None => match std::mem::replace(&mut promise.waker, Ok(cx.waker().clone())) {
+                Err(WakerState::Tainted) => Poll::Ready(Err(Error::ProducerDropped)),
+                _ => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Producer;
+    use crate::Error;
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_stale_consumer() {
+        let (op, op_a) = Producer::<String>::new();
+        let task1 = thread::spawn(move || block_on(op_a));
+        while op.promise.lock().unwrap().waker.is_err() {
+            thread::yield_now();
+        }
+        let op_b = op.rearm();
+        assert_eq!(
+            Err(Error::Stale),
+            task1.join().expect("The task1 thread has panicked")
+        );
+        op.resolve(String::from("🍓")).unwrap();
+        assert_eq!(Err(String::from("🍓")), op.resolve(String::from("🍓")));
+        assert_eq!(Ok(String::from("🍓")), block_on(op_b));
+    }
+
+    #[test]
+    fn test_producer_dropped() {
+        let (op, op_a) = Producer::<String>::new();
+        let task1 = thread::spawn(move || block_on(op_a));
+        drop(op);
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            task1.join().expect("The task1 thread has panicked")
+        );
+    }
+}
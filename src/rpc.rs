@@ -0,0 +1,447 @@
+//! rpc multiplexes requests over a single transport. Each request is given an
+//! id and registered in a [`Registry`], its frame is written to the
+//! transport's `Sink`, and the frames read from the transport's `Stream` are
+//! matched back to the waiting consumers by id. Requests may time out, and
+//! losing the transport rejects every pending request.
+//!
+//! The [`Client`] issues requests; the [`Driver`] is a future that moves the
+//! frames and must be spawned or polled alongside.
+use crate::registry::{Consumer, Registry, RegistryError};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+/// Turns requests into frames and frames into responses.
+pub trait Codec {
+    /// What the transport carries.
+    type Frame;
+    type Request;
+    type Response;
+
+    /// Return the frame for `request`, tagged with `id`.
+    fn encode(&self, id: u64, request: Self::Request) -> Self::Frame;
+
+    /// Return the id and response carried by `frame`, or `None` for a frame
+    /// that is not a response. Such frames are skipped.
+    fn decode(&self, frame: Self::Frame) -> Option<(u64, Self::Response)>;
+}
+
+#[derive(Debug)]
+struct Outgoing<F> {
+    frames: VecDeque<F>,
+    next_id: u64,
+    waker: Option<Waker>,
+    clients: usize,
+}
+
+struct Shared<C: Codec> {
+    codec: C,
+    outgoing: Mutex<Outgoing<C::Frame>>,
+}
+
+/// Issues requests over the transport. Clones share the transport.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::rpc::{self, Codec};
+/// use futures::{executor::block_on, future, StreamExt};
+/// # use futures::{channel::mpsc, Sink, Stream};
+/// # use std::{pin::Pin, task::{Context, Poll}};
+/// # struct Duplex(mpsc::UnboundedSender<(u64, String)>, mpsc::UnboundedReceiver<(u64, String)>);
+/// # impl Sink<(u64, String)> for Duplex {
+/// #     type Error = mpsc::SendError;
+/// #     fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+/// #         Pin::new(&mut self.0).poll_ready(cx)
+/// #     }
+/// #     fn start_send(mut self: Pin<&mut Self>, item: (u64, String)) -> Result<(), Self::Error> {
+/// #         Pin::new(&mut self.0).start_send(item)
+/// #     }
+/// #     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+/// #         Pin::new(&mut self.0).poll_flush(cx)
+/// #     }
+/// #     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+/// #         Pin::new(&mut self.0).poll_close(cx)
+/// #     }
+/// # }
+/// # impl Stream for Duplex {
+/// #     type Item = (u64, String);
+/// #     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+/// #         self.1.poll_next_unpin(cx)
+/// #     }
+/// # }
+/// # let (to_server, requests) = mpsc::unbounded();
+/// # let (to_client, responses) = mpsc::unbounded();
+/// # let transport = Duplex(to_server, responses);
+/// // Frames are `(id, text)` both ways.
+/// struct Text;
+/// impl Codec for Text {
+///     type Frame = (u64, String);
+///     type Request = String;
+///     type Response = String;
+///     fn encode(&self, id: u64, request: String) -> (u64, String) {
+///         (id, request)
+///     }
+///     fn decode(&self, frame: (u64, String)) -> Option<(u64, String)> {
+///         Some(frame)
+///     }
+/// }
+///
+/// // The other end of `transport` shouts every request back.
+/// let server = requests
+///     .map(|(id, text): (u64, String)| Ok((id, text.to_uppercase())))
+///     .forward(to_client);
+/// let (client, driver) = rpc::new(transport, Text);
+/// let reply = client.call(String::from("hello")).unwrap();
+/// drop(client);
+/// let (reply, (), _) = block_on(future::join3(reply, driver, server));
+/// assert_eq!(Ok(String::from("HELLO")), reply);
+/// ```
+pub struct Client<C: Codec> {
+    shared: Arc<Shared<C>>,
+    registry: Registry<u64, C::Response>,
+}
+
+/// Return a client issuing requests over `transport`, and the driver that
+/// moves its frames.
+pub fn new<Tr, C>(transport: Tr, codec: C) -> (Client<C>, Driver<Tr, C>)
+where
+    C: Codec,
+    C::Response: Send + Sync + 'static,
+    Tr: Sink<C::Frame> + Stream<Item = C::Frame> + Unpin,
+{
+    let shared = Arc::new(Shared {
+        codec,
+        outgoing: Mutex::new(Outgoing {
+            frames: VecDeque::new(),
+            next_id: 0,
+            waker: None,
+            clients: 1,
+        }),
+    });
+    let registry = Registry::new();
+    let driver = Driver {
+        transport,
+        shared: shared.clone(),
+        registry: registry.clone(),
+        flushed: true,
+    };
+    (Client { shared, registry }, driver)
+}
+
+impl<C> Client<C>
+where
+    C: Codec,
+    C::Response: Send + Sync + 'static,
+{
+    /// Send `request` and return the consumer of its response. The consumer
+    /// fails with [`Error::Shutdown`](crate::Error::Shutdown) if the transport is lost first. Fails
+    /// with [`RegistryError::Shutdown`] if it already has been.
+    pub fn call(&self, request: C::Request) -> Result<Consumer<C::Response>, RegistryError> {
+        self.send(request, None)
+    }
+
+    /// Like [`call`](Self::call), but the consumer fails with
+    /// [`Error::Timeout`](crate::Error::Timeout) unless the response arrives within `timeout`.
+    pub fn call_timeout(
+        &self,
+        request: C::Request,
+        timeout: Duration,
+    ) -> Result<Consumer<C::Response>, RegistryError> {
+        self.send(request, Some(timeout))
+    }
+
+    fn send(
+        &self,
+        request: C::Request,
+        timeout: Option<Duration>,
+    ) -> Result<Consumer<C::Response>, RegistryError> {
+        let mut outgoing = self.shared.outgoing.lock().unwrap();
+        let id = outgoing.next_id;
+        outgoing.next_id = outgoing.next_id.wrapping_add(1);
+        let consumer = match timeout {
+            Some(timeout) => self.registry.register_with_ttl(id, timeout)?,
+            None => self.registry.register(id)?,
+        };
+        outgoing
+            .frames
+            .push_back(self.shared.codec.encode(id, request));
+        if let Some(waker) = outgoing.waker.take() {
+            waker.wake()
+        }
+        Ok(consumer)
+    }
+
+    /// Return the number of requests waiting for their response.
+    pub fn pending(&self) -> usize {
+        self.registry.len()
+    }
+}
+
+impl<C: Codec> Clone for Client<C> {
+    fn clone(&self) -> Self {
+        self.shared.outgoing.lock().unwrap().clients += 1;
+        Client {
+            shared: self.shared.clone(),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+impl<C: Codec> Drop for Client<C> {
+    /// Let the driver finish once nothing is left to do.
+    fn drop(&mut self) {
+        let mut outgoing = self.shared.outgoing.lock().unwrap();
+        outgoing.clients -= 1;
+        if let Some(waker) = outgoing.waker.take() {
+            waker.wake()
+        }
+    }
+}
+
+impl<C: Codec> Debug for Client<C>
+where
+    C::Frame: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("outgoing", &self.shared.outgoing)
+            .field("registry", &self.registry)
+            .finish()
+    }
+}
+
+/// Future for [`new`]. It writes queued requests, reads responses, and
+/// settles the matching consumers. It resolves once the transport ends or
+/// fails, or once every client is gone and no request is pending.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Driver<Tr, C: Codec> {
+    transport: Tr,
+    shared: Arc<Shared<C>>,
+    registry: Registry<u64, C::Response>,
+    flushed: bool,
+}
+
+impl<Tr, C> Driver<Tr, C>
+where
+    C: Codec,
+    C::Response: Send + Sync + 'static,
+    Tr: Sink<C::Frame> + Stream<Item = C::Frame> + Unpin,
+{
+    /// Write every queued frame. Returns false if the transport failed.
+    fn poll_write(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        loop {
+            match Pin::new(&mut self.transport).poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => return Poll::Ready(false),
+                Poll::Pending => return Poll::Pending,
+            }
+            let frame = self.shared.outgoing.lock().unwrap().frames.pop_front();
+            let Some(frame) = frame else {
+                break;
+            };
+            if Pin::new(&mut self.transport).start_send(frame).is_err() {
+                return Poll::Ready(false);
+            }
+            self.flushed = false;
+        }
+        if !self.flushed {
+            match Pin::new(&mut self.transport).poll_flush(cx) {
+                Poll::Ready(Ok(())) => self.flushed = true,
+                Poll::Ready(Err(_)) => return Poll::Ready(false),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(true)
+    }
+}
+
+impl<Tr, C> Future for Driver<Tr, C>
+where
+    C: Codec,
+    C::Response: Send + Sync + 'static,
+    Tr: Sink<C::Frame> + Stream<Item = C::Frame> + Unpin,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        this.shared.outgoing.lock().unwrap().waker = Some(cx.waker().clone());
+        if let Poll::Ready(false) = this.poll_write(cx) {
+            this.registry.shutdown("transport failed");
+            return Poll::Ready(());
+        }
+        loop {
+            match Pin::new(&mut this.transport).poll_next(cx) {
+                Poll::Ready(Some(frame)) => {
+                    if let Some((id, response)) = this.shared.codec.decode(frame) {
+                        // A late response for a request that timed out has
+                        // nobody to go to.
+                        let _ = this.registry.resolve(&id, response);
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.registry.shutdown("transport closed");
+                    return Poll::Ready(());
+                }
+                Poll::Pending => break,
+            }
+        }
+        let outgoing = this.shared.outgoing.lock().unwrap();
+        if outgoing.clients == 0 && outgoing.frames.is_empty() && this.registry.is_empty() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl<Tr, C: Codec> Drop for Driver<Tr, C> {
+    /// Fail every pending request, and every later call, as nobody is left
+    /// to send them or read their responses.
+    fn drop(&mut self) {
+        self.registry.shutdown("driver dropped");
+        self.shared.outgoing.lock().unwrap().frames.clear();
+    }
+}
+
+impl<Tr, C: Codec> Unpin for Driver<Tr, C> where Tr: Unpin {}
+
+impl<Tr: Debug, C: Codec> Debug for Driver<Tr, C>
+where
+    C::Frame: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Driver")
+            .field("transport", &self.transport)
+            .field("outgoing", &self.shared.outgoing)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Codec;
+    use crate::{registry::RegistryError, Error};
+    use futures::{channel::mpsc, executor::block_on, Sink, Stream, StreamExt};
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+        thread,
+        time::Duration,
+    };
+
+    struct Text;
+
+    impl Codec for Text {
+        type Frame = (u64, String);
+        type Request = String;
+        type Response = String;
+
+        fn encode(&self, id: u64, request: String) -> (u64, String) {
+            (id, request)
+        }
+
+        fn decode(&self, frame: (u64, String)) -> Option<(u64, String)> {
+            Some(frame)
+        }
+    }
+
+    type Frame = (u64, String);
+
+    struct Duplex(mpsc::UnboundedSender<Frame>, mpsc::UnboundedReceiver<Frame>);
+
+    impl Sink<Frame> for Duplex {
+        type Error = mpsc::SendError;
+
+        fn poll_ready(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Pin::new(&mut self.0).poll_ready(cx)
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: Frame) -> Result<(), Self::Error> {
+            Pin::new(&mut self.0).start_send(item)
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_close(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Pin::new(&mut self.0).poll_close(cx)
+        }
+    }
+
+    impl Stream for Duplex {
+        type Item = Frame;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame>> {
+            self.1.poll_next_unpin(cx)
+        }
+    }
+
+    #[test]
+    fn test_rpc_timeout_and_transport_loss() {
+        let (to_server, mut requests) = mpsc::unbounded();
+        let (to_client, responses) = mpsc::unbounded();
+        let (client, driver) = super::new(Duplex(to_server, responses), Text);
+        let driver = thread::spawn(move || block_on(driver));
+        let slow = client
+            .call_timeout(String::from("slow"), Duration::from_millis(10))
+            .unwrap();
+        let fast = client.call(String::from("fast")).unwrap();
+        let lost = client.call(String::from("lost")).unwrap();
+        let (slow_id, _) = block_on(requests.next()).unwrap();
+        let (fast_id, _) = block_on(requests.next()).unwrap();
+        assert_eq!(Err(Error::Timeout), block_on(slow));
+        // The late response is dropped.
+        to_client
+            .unbounded_send((slow_id, String::from("SLOW")))
+            .unwrap();
+        to_client
+            .unbounded_send((fast_id, String::from("FAST")))
+            .unwrap();
+        assert_eq!(Ok(String::from("FAST")), block_on(fast));
+        assert_eq!(1, client.pending());
+        drop(to_client);
+        assert_eq!(
+            Err(Error::Shutdown(String::from("transport closed"))),
+            block_on(lost)
+        );
+        driver.join().expect("The driver thread has panicked");
+        assert!(client.call(String::from("again")).is_err());
+    }
+
+    #[test]
+    fn test_rpc_driver_dropped() {
+        let (to_server, _requests) = mpsc::unbounded();
+        let (_to_client, responses) = mpsc::unbounded();
+        let (client, driver) = super::new(Duplex(to_server, responses), Text);
+        let pending = client.call(String::from("pending")).unwrap();
+        drop(driver);
+        assert_eq!(
+            Err(Error::Shutdown(String::from("driver dropped"))),
+            block_on(pending)
+        );
+        assert_eq!(
+            Err(RegistryError::Shutdown),
+            client.call(String::from("again")).map(drop)
+        );
+        assert_eq!(0, client.shared.outgoing.lock().unwrap().frames.len());
+    }
+}
@@ -0,0 +1,194 @@
+//! semaphore implements an async counting semaphore. A task that can not get a
+//! permit right away queues a pair producer; releasing a permit resolves the
+//! oldest queued producer with it, so permits are handed out in FIFO order.
+use crate::{pair, Error, Promise};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+#[derive(Debug)]
+struct State {
+    permits: usize,
+    waiters: VecDeque<pair::Producer<Permit>>,
+}
+
+/// A counting semaphore. Clones share the same permits.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::semaphore::Semaphore;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let semaphore = Semaphore::new(1);
+/// let permit = block_on(semaphore.acquire()).unwrap();
+/// let other = semaphore.clone();
+/// let task1 = thread::spawn(move || block_on(async {
+///     let _permit = other.acquire().await.unwrap();
+///     println!("in the critical section");
+/// }));
+/// drop(permit);
+/// task1.join().expect("The task1 thread has panicked.");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Semaphore {
+    state: Arc<Mutex<State>>,
+}
+
+/// A permit of a [`Semaphore`], given back when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    state: Arc<Mutex<State>>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            state: Arc::new(Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Return the number of permits that are not handed out.
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// Return a future that resolves with a permit once one is available and
+    /// every task that asked earlier has been served.
+    pub fn acquire(&self) -> Acquire {
+        let mut state = self.state.lock().unwrap();
+        if state.waiters.is_empty() && state.permits > 0 {
+            state.permits -= 1;
+            return Acquire {
+                permit: Some(self.permit()),
+                waiting: None,
+            };
+        }
+        let (producer, consumer) = pair::Producer::new();
+        state.waiters.push_back(producer);
+        Acquire {
+            permit: None,
+            waiting: Some(consumer),
+        }
+    }
+
+    /// Return a permit if one is available right now and nobody is queued.
+    pub fn try_acquire(&self) -> Option<Permit> {
+        let mut state = self.state.lock().unwrap();
+        if state.waiters.is_empty() && state.permits > 0 {
+            state.permits -= 1;
+            Some(self.permit())
+        } else {
+            None
+        }
+    }
+
+    fn permit(&self) -> Permit {
+        Permit {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Drop for Permit {
+    /// Hand the permit to the oldest waiting task, or give it back.
+    fn drop(&mut self) {
+        let next = {
+            let mut state = self.state.lock().unwrap();
+            // Skip the tasks that stopped waiting.
+            let next = std::iter::from_fn(|| state.waiters.pop_front())
+                .find(|producer| !producer.is_closed());
+            if next.is_none() {
+                state.permits += 1;
+            }
+            next
+        };
+        // Resolved outside the lock: if the consumer goes away in the
+        // meantime, the permit is dropped with it and released again.
+        if let Some(producer) = next {
+            producer.resolve(Permit {
+                state: self.state.clone(),
+            });
+        }
+    }
+}
+
+/// Future for [`Semaphore::acquire`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Acquire {
+    permit: Option<Permit>,
+    waiting: Option<pair::Consumer<Permit>>,
+}
+
+impl Future for Acquire {
+    type Output = Result<Permit, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(permit) = self.permit.take() {
+            return Poll::Ready(Ok(permit));
+        }
+        match self.waiting.as_mut() {
+            Some(waiting) => Pin::new(waiting).poll(cx),
+            None => panic!("Acquire must not be polled after it returned Ready"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Semaphore;
+    use futures::executor::block_on;
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn test_semaphore_fifo() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire().is_none());
+        let order = Arc::new(Mutex::new(vec![]));
+        let waits: Vec<_> = (0..3).map(|_| semaphore.acquire()).collect();
+        let tasks: Vec<_> = waits
+            .into_iter()
+            .enumerate()
+            .map(|(i, wait)| {
+                let order = order.clone();
+                thread::spawn(move || {
+                    let permit = block_on(wait).unwrap();
+                    order.lock().unwrap().push(i);
+                    drop(permit);
+                })
+            })
+            .collect();
+        drop(permit);
+        for task in tasks {
+            task.join().expect("The task thread has panicked");
+        }
+        assert_eq!(vec![0, 1, 2], *order.lock().unwrap());
+        assert_eq!(1, semaphore.available_permits());
+    }
+
+    #[test]
+    fn test_semaphore_cancelled_acquire() {
+        let semaphore = Semaphore::new(1);
+        let permit = block_on(semaphore.acquire()).unwrap();
+        let cancelled = semaphore.acquire();
+        let waiting = semaphore.acquire();
+        drop(cancelled);
+        drop(permit);
+        let permit = block_on(waiting).unwrap();
+        assert_eq!(0, semaphore.available_permits());
+        drop(permit);
+        assert_eq!(1, semaphore.available_permits());
+    }
+}
@@ -0,0 +1,78 @@
+//! settle decides which of several attempts to settle the same promise wins,
+//! for flavors whose producers can be cloned:
+//! [`channel`](crate::channel::Producer::with_policy) and
+//! [`mpmc`](crate::mpmc::Producer::with_policy). A [`SettlePolicy`] judges
+//! each attempt: it can refuse the value, settle the promise with it at once,
+//! or hold it until every producer is gone, so a later attempt can still
+//! replace it. Values that lose are handed back to the party that offered or
+//! displaced them. [`FirstWins`], [`LastWins`], and [`FirstErrorWins`] cover
+//! the common cases.
+
+/// The outcome of an attempt to settle a promise, see [`SettlePolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Refuse the offered value and hand it back.
+    Reject,
+    /// Keep the offered value in place of any held before, which is handed
+    /// back. The promise settles with the held value once every producer is
+    /// gone.
+    Hold,
+    /// Settle the promise with the offered value now, handing back any value
+    /// held before.
+    Settle,
+}
+
+/// Judges the attempts to settle a promise that has not settled yet.
+pub trait SettlePolicy<T> {
+    /// Judge an attempt to settle with `offered`, given the value `held` from
+    /// an earlier attempt, if any.
+    fn judge(&self, held: Option<&T>, offered: &T) -> Verdict;
+}
+
+/// The first attempt settles the promise; later ones are refused.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstWins;
+
+impl<T> SettlePolicy<T> for FirstWins {
+    fn judge(&self, _: Option<&T>, _: &T) -> Verdict {
+        Verdict::Settle
+    }
+}
+
+/// Every attempt replaces the one before, and the promise settles with the
+/// last once every producer is gone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LastWins;
+
+impl<T> SettlePolicy<T> for LastWins {
+    fn judge(&self, _: Option<&T>, _: &T) -> Verdict {
+        Verdict::Hold
+    }
+}
+
+/// The first error settles the promise at once. Otherwise the first success
+/// is held, and the promise settles with it once every producer is gone.
+///
+/// ```
+/// use promise_out::{mpmc::Producer, settle::FirstErrorWins};
+/// use futures::executor::block_on;
+/// let (promise, consumer) = Producer::<Result<u32, &str>>::with_policy(FirstErrorWins);
+/// let replica = promise.clone();
+/// assert_eq!(Ok(None), promise.offer(Ok(1)));
+/// assert_eq!(Err(Ok(2)), promise.offer(Ok(2)));
+/// // The failure displaces the success held so far.
+/// assert_eq!(Ok(Some(Ok(1))), replica.offer(Err("disk full")));
+/// assert_eq!(Err("disk full"), *block_on(consumer).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstErrorWins;
+
+impl<V, E> SettlePolicy<Result<V, E>> for FirstErrorWins {
+    fn judge(&self, held: Option<&Result<V, E>>, offered: &Result<V, E>) -> Verdict {
+        match (held, offered) {
+            (_, Err(_)) => Verdict::Settle,
+            (None, Ok(_)) => Verdict::Hold,
+            (Some(_), Ok(_)) => Verdict::Reject,
+        }
+    }
+}
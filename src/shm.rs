@@ -0,0 +1,383 @@
+//! shm implements a promise resolved through shared memory, for large payloads
+//! passed between processes on the same machine. The producer writes its
+//! value straight into a file-backed region that both processes map, and the
+//! consumer is woken through a futex on that region. The value is never
+//! serialized or copied between the processes.
+//!
+//! The value is a byte buffer of up to the capacity fixed at creation. The
+//! region lives in a file that outlasts both halves, so it can be opened
+//! before or after the producer settles; removing it is left to the caller.
+//! Only Linux is supported, since the wakeup relies on its futexes. A
+//! producer whose process dies without settling cannot be told apart from a
+//! slow one.
+use crate::Error;
+use std::{
+    fmt::Debug,
+    fs::OpenOptions,
+    future::Future,
+    io,
+    ops::Deref,
+    os::fd::AsRawFd,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+const PENDING: u32 = 0;
+const RESOLVED: u32 = 1;
+const DROPPED: u32 = 2;
+
+/// The region starts with the state, the length of the value, and the
+/// capacity, padded to a cache line. The value follows.
+const HEADER: usize = 64;
+
+/// How often a waiting consumer checks whether anyone still wants the value.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A shared mapping of a region's file.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The header is only accessed atomically, and the value is written by the
+// producer alone before it publishes the state with release ordering.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn map(file: &std::fs::File, len: usize) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of an open file of at least `len`
+        // bytes; the kernel picks the address.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    fn state(&self) -> &AtomicU32 {
+        // SAFETY: the mapping is page aligned and longer than the header.
+        unsafe { &*self.ptr.cast::<AtomicU32>() }
+    }
+
+    fn value_len(&self) -> &AtomicU64 {
+        // SAFETY: as above, at an 8 byte aligned offset.
+        unsafe { &*self.ptr.add(8).cast::<AtomicU64>() }
+    }
+
+    fn capacity(&self) -> &AtomicU64 {
+        // SAFETY: as above.
+        unsafe { &*self.ptr.add(16).cast::<AtomicU64>() }
+    }
+
+    fn settle(&self, state: u32) {
+        self.state().store(state, Ordering::Release);
+        // SAFETY: wakes every waiter on the state word of a live mapping.
+        // Without FUTEX_PRIVATE_FLAG so that other processes are woken too.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.state().as_ptr(),
+                libc::FUTEX_WAKE,
+                i32::MAX,
+            );
+        }
+    }
+
+    /// Block while the state is pending, for at most `timeout`.
+    fn wait(&self, timeout: Duration) {
+        let timeout = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        // SAFETY: the futex word belongs to a live mapping; the kernel
+        // returns at once if it no longer holds PENDING.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.state().as_ptr(),
+                libc::FUTEX_WAIT,
+                PENDING,
+                &timeout as *const libc::timespec,
+            );
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly what `map` mapped.
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// The producer of a shared-memory promise.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::shm::{Consumer, Producer};
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let path = std::env::temp_dir().join(format!("promise_out-doc-{}.shm", std::process::id()));
+/// # let _ = std::fs::remove_file(&path);
+/// let promise = Producer::create(&path, 1 << 20).unwrap();
+/// // Typically opened by another process.
+/// let consumer = Consumer::open(&path).unwrap();
+/// thread::spawn(move || {
+///     promise.resolve_with(|region| {
+///         region[..5].copy_from_slice(b"hello");
+///         5
+///     })
+/// });
+/// assert_eq!(b"hello", &*block_on(consumer).unwrap());
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct Producer {
+    mapping: Option<Mapping>,
+}
+
+impl Producer {
+    /// Create the region at `path`, which must not exist yet, with room for a
+    /// value of up to `capacity` bytes.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.set_len((HEADER + capacity) as u64)?;
+        let mapping = Mapping::map(&file, HEADER + capacity)?;
+        mapping.capacity().store(capacity as u64, Ordering::Release);
+        Ok(Producer {
+            mapping: Some(mapping),
+        })
+    }
+
+    /// Return the largest value the region holds.
+    pub fn capacity(&self) -> usize {
+        self.mapping().len - HEADER
+    }
+
+    /// Resolve the promise with `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is longer than the capacity.
+    pub fn resolve(self, value: &[u8]) {
+        self.resolve_with(|region| {
+            region[..value.len()].copy_from_slice(value);
+            value.len()
+        })
+    }
+
+    /// Resolve the promise with the first bytes of the region, after `write`
+    /// has filled them in place and returned how many there are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `write` returns more than the capacity.
+    pub fn resolve_with(mut self, write: impl FnOnce(&mut [u8]) -> usize) {
+        let mapping = self.mapping();
+        // SAFETY: only the producer writes the value, and nobody reads it
+        // before the state is published below.
+        let region = unsafe {
+            std::slice::from_raw_parts_mut(mapping.ptr.add(HEADER), mapping.len - HEADER)
+        };
+        // Should `write` panic, dropping the producer still settles.
+        let len = write(region);
+        assert!(len <= region.len(), "value exceeds the region's capacity");
+        let mapping = self.mapping.take().expect("a producer is settled once");
+        mapping.value_len().store(len as u64, Ordering::Relaxed);
+        mapping.settle(RESOLVED);
+    }
+
+    fn mapping(&self) -> &Mapping {
+        self.mapping.as_ref().expect("a producer is settled once")
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        if let Some(mapping) = self.mapping.take() {
+            mapping.settle(DROPPED);
+        }
+    }
+}
+
+impl Debug for Producer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Producer")
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+struct Shared {
+    mapping: Mapping,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// The consumer of a shared-memory promise. It resolves to the value in
+/// place, or fails with [`Error::ProducerDropped`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer {
+    shared: Arc<Shared>,
+    waiting: bool,
+}
+
+impl Consumer {
+    /// Open the region at `path`, created by [`Producer::create`].
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a promise region",
+            ));
+        }
+        let mapping = Mapping::map(&file, len)?;
+        if mapping.capacity().load(Ordering::Acquire) as usize != len - HEADER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a promise region",
+            ));
+        }
+        Ok(Consumer {
+            shared: Arc::new(Shared {
+                mapping,
+                waker: Mutex::new(None),
+            }),
+            waiting: false,
+        })
+    }
+}
+
+impl Future for Consumer {
+    type Output = Result<Region, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        *self.shared.waker.lock().unwrap() = Some(cx.waker().clone());
+        match self.shared.mapping.state().load(Ordering::Acquire) {
+            RESOLVED => {
+                let len = self.shared.mapping.value_len().load(Ordering::Relaxed) as usize;
+                // The length comes from the file, which anyone may have
+                // written, so it must not reach past the mapping.
+                if len > self.shared.mapping.len - HEADER {
+                    return Poll::Ready(Err(Error::Corrupted));
+                }
+                return Poll::Ready(Ok(Region {
+                    shared: self.shared.clone(),
+                    len,
+                }));
+            }
+            DROPPED => return Poll::Ready(Err(Error::ProducerDropped)),
+            _ => {}
+        }
+        if !self.waiting {
+            self.waiting = true;
+            // The futex blocks, so a thread waits on it for the task.
+            let shared = self.shared.clone();
+            thread::spawn(move || {
+                while shared.mapping.state().load(Ordering::Acquire) == PENDING {
+                    if Arc::strong_count(&shared) == 1 {
+                        return;
+                    }
+                    shared.mapping.wait(POLL_INTERVAL);
+                }
+                if let Some(waker) = shared.waker.lock().unwrap().take() {
+                    waker.wake()
+                }
+            });
+        }
+        Poll::Pending
+    }
+}
+
+impl Debug for Consumer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("state", &self.shared.mapping.state())
+            .finish()
+    }
+}
+
+/// A resolved value, read in place from the shared region.
+pub struct Region {
+    shared: Arc<Shared>,
+    len: usize,
+}
+
+impl Deref for Region {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the producer wrote these bytes before publishing the
+        // resolved state, and never touches them again. `len` was checked to
+        // fit the mapping.
+        unsafe { std::slice::from_raw_parts(self.shared.mapping.ptr.add(HEADER), self.len) }
+    }
+}
+
+impl Debug for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Region").field("len", &self.len).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Consumer, Producer, HEADER, RESOLVED};
+    use crate::Error;
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_shm_producer_dropped() {
+        let path = std::env::temp_dir().join(format!("promise_out-{}.shm", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let promise = Producer::create(&path, 16).unwrap();
+        assert_eq!(16, promise.capacity());
+        let consumer = Consumer::open(&path).unwrap();
+        let late = Consumer::open(&path).unwrap();
+        thread::spawn(move || drop(promise));
+        assert_eq!(Error::ProducerDropped, block_on(consumer).unwrap_err());
+        assert_eq!(Error::ProducerDropped, block_on(late).unwrap_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shm_rejects_a_corrupted_length() {
+        let path = std::env::temp_dir().join(format!("promise_out-{}.bad", std::process::id()));
+        let mut region = vec![0; HEADER + 16];
+        region[..4].copy_from_slice(&RESOLVED.to_ne_bytes());
+        region[8..16].copy_from_slice(&(1u64 << 40).to_ne_bytes());
+        region[16..24].copy_from_slice(&16u64.to_ne_bytes());
+        std::fs::write(&path, region).unwrap();
+        let consumer = Consumer::open(&path).unwrap();
+        assert_eq!(Error::Corrupted, block_on(consumer).unwrap_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+//! singleflight coalesces concurrent requests for the same key. The first
+//! caller for a key becomes the leader and gets the poly producer that does the
+//! work; everyone who asks while it is in flight gets a clone of the same poly
+//! consumer. Once the leader resolves or goes away the key is forgotten, so a
+//! later caller starts a new flight.
+use crate::{poly, Promise};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+type InFlight<K, T> = Arc<Mutex<HashMap<K, poly::Consumer<T>>>>;
+
+/// A set of in-flight requests keyed by `K`. Clones share the same requests.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::singleflight::{Flight, Group};
+/// use futures::executor::block_on;
+/// let group = Group::<u32, String>::new();
+/// let Flight::Leader(leader) = group.call(7) else { unreachable!() };
+/// let Flight::Follower(follower) = group.call(7) else { unreachable!() };
+/// let consumer = leader.consumer();
+/// leader.resolve(String::from("response #7"));
+/// assert_eq!("response #7", *block_on(follower).unwrap());
+/// assert_eq!("response #7", *block_on(consumer).unwrap());
+/// assert!(group.call(7).is_leader());
+/// ```
+#[derive(Debug)]
+pub struct Group<K, T> {
+    in_flight: InFlight<K, T>,
+}
+
+/// The outcome of [`Group::call`].
+#[derive(Debug)]
+pub enum Flight<K: Hash + Eq, T> {
+    /// Nobody was working on the key; the caller has to.
+    Leader(Leader<K, T>),
+    /// Somebody is already working on the key; wait for their result.
+    Follower(poly::Consumer<T>),
+}
+
+/// The caller that does the work for a key. Dropping it without resolving
+/// fails every follower with [`Error::ProducerDropped`](crate::Error::ProducerDropped).
+#[derive(Debug)]
+pub struct Leader<K: Hash + Eq, T> {
+    key: Option<K>,
+    producer: Option<poly::Producer<T>>,
+    consumer: poly::Consumer<T>,
+    in_flight: InFlight<K, T>,
+}
+
+impl<K: Hash + Eq, T> Group<K, T> {
+    pub fn new() -> Self {
+        Group {
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Join the flight for `key`, or start one if there is none.
+    pub fn call(&self, key: K) -> Flight<K, T>
+    where
+        K: Clone,
+    {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(consumer) = in_flight.get(&key) {
+            return Flight::Follower(consumer.clone());
+        }
+        let (producer, consumer) = poly::Producer::new();
+        in_flight.insert(key.clone(), consumer.clone());
+        Flight::Leader(Leader {
+            key: Some(key),
+            producer: Some(producer),
+            consumer,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Return the number of keys in flight.
+    pub fn len(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Hash + Eq, T> Default for Group<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T> Clone for Group<K, T> {
+    fn clone(&self) -> Self {
+        Group {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, T> Flight<K, T> {
+    pub fn is_leader(&self) -> bool {
+        matches!(self, Flight::Leader(_))
+    }
+}
+
+impl<K: Hash + Eq, T> Leader<K, T> {
+    /// Return the key this leader works on.
+    pub fn key(&self) -> &K {
+        self.key.as_ref().unwrap()
+    }
+
+    /// Return a consumer of this flight's result, for the leader itself.
+    pub fn consumer(&self) -> poly::Consumer<T> {
+        self.consumer.clone()
+    }
+
+    /// Resolve the flight for every follower and forget the key.
+    pub fn resolve(mut self, value: T) {
+        self.finish();
+        self.producer.take().unwrap().resolve(value);
+    }
+
+    fn finish(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.in_flight.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl<K: Hash + Eq, T> Drop for Leader<K, T> {
+    /// Forget the key; dropping the producer fails the followers.
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flight, Group};
+    use crate::Error;
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_singleflight_coalesces() {
+        let group = Group::<&str, usize>::new();
+        let mut leaders = vec![];
+        let followers: Vec<_> = (0..4)
+            .filter_map(|_| match group.call("/index.html") {
+                Flight::Leader(leader) => {
+                    leaders.push(leader);
+                    None
+                }
+                Flight::Follower(consumer) => Some(thread::spawn(move || block_on(consumer))),
+            })
+            .collect();
+        assert_eq!(1, leaders.len());
+        assert_eq!(3, followers.len());
+        assert_eq!(1, group.len());
+        leaders.pop().unwrap().resolve(200);
+        assert!(group.is_empty());
+        for follower in followers {
+            assert_eq!(
+                200,
+                *follower
+                    .join()
+                    .expect("The task thread has panicked")
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_singleflight_leader_dropped() {
+        let group = Group::<u32, ()>::new();
+        let leader = group.call(1);
+        let Flight::Follower(follower) = group.call(1) else {
+            panic!("expected a follower");
+        };
+        drop(leader);
+        assert_eq!(Err(Error::ProducerDropped), block_on(follower));
+        assert!(group.call(1).is_leader());
+    }
+}
@@ -0,0 +1,99 @@
+//! slot holds the value of a pair or channel promise. It is an `Option<T>`
+//! whose storage can be handed out uninitialized, so a producer can build a
+//! large value directly in the promise's shared state instead of on its own
+//! stack, from where it would be moved into the `Option` and then out again.
+use core::{fmt::Debug, mem::MaybeUninit};
+
+/// Storage for a value that may not be there yet.
+pub(crate) struct Slot<T> {
+    value: MaybeUninit<T>,
+    full: bool,
+}
+
+impl<T> Slot<T> {
+    pub(crate) const fn empty() -> Self {
+        Slot {
+            value: MaybeUninit::uninit(),
+            full: false,
+        }
+    }
+
+    pub(crate) const fn full(value: T) -> Self {
+        Slot {
+            value: MaybeUninit::new(value),
+            full: true,
+        }
+    }
+
+    /// Store `value`, dropping any value already there.
+    #[cfg(feature = "pair")]
+    pub(crate) fn put(&mut self, value: T) {
+        self.init(|slot| slot.write(value));
+    }
+
+    /// Store the value `init` writes into the uninitialized storage, dropping
+    /// any value already there. `init` proves that it wrote a value by
+    /// returning the reference [`MaybeUninit::write`] gives back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `init` returns a reference to anything but the storage it was
+    /// handed, which leaves the slot empty.
+    pub(crate) fn init(&mut self, init: impl FnOnce(&mut MaybeUninit<T>) -> &mut T) {
+        drop(self.take());
+        let storage = self.value.as_mut_ptr();
+        let written = init(&mut self.value) as *mut T;
+        assert!(
+            core::ptr::eq(storage, written),
+            "resolve_in_place must return the reference MaybeUninit::write returns"
+        );
+        self.full = true;
+    }
+
+    pub(crate) fn take(&mut self) -> Option<T> {
+        if !core::mem::replace(&mut self.full, false) {
+            return None;
+        }
+        // Safety: the flag was set, so the value was initialized, and clearing
+        // it hands the value over to the caller.
+        Some(unsafe { self.value.assume_init_read() })
+    }
+
+    fn get(&self) -> Option<&T> {
+        // Safety: the flag is only set while the value is initialized.
+        self.full.then(|| unsafe { self.value.assume_init_ref() })
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+impl<T: Debug> Debug for Slot<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slot;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_init_writes_in_place_and_drops() {
+        let value = Rc::new(());
+        let mut slot = Slot::empty();
+        slot.init(|storage| storage.write(value.clone()));
+        slot.init(|storage| storage.write(value.clone()));
+        assert_eq!(2, Rc::strong_count(&value));
+        let taken = slot.take();
+        assert!(taken.is_some() && slot.take().is_none());
+        drop(taken);
+        slot.init(|storage| storage.write(value.clone()));
+        drop(slot);
+        assert_eq!(1, Rc::strong_count(&value));
+    }
+}
@@ -0,0 +1,175 @@
+//! stack implements a single-producer, single-consumer promise that allocates
+//! nothing. Its state lives in a [`StackPromise`] provided by the caller, e.g.
+//! on the stack of the task that awaits it, and [`StackPromise::split`] hands
+//! out a producer and a consumer that borrow it. The borrow keeps the slot in
+//! place until both are gone, and since it is exclusive the slot can only have
+//! one promise in flight at a time.
+use crate::{
+    lock::{DefaultRawMutex, RawMutex},
+    Error,
+};
+use core::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use lock_api::Mutex;
+
+/// The storage of a oneshot promise, to be split into borrowed halves.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::stack::StackPromise;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let mut slot = StackPromise::<&str>::new();
+/// let (promise, consumer) = slot.split();
+/// thread::scope(|scope| {
+///     scope.spawn(move || promise.resolve("🍓"));
+///     assert_eq!(Ok("🍓"), block_on(consumer));
+/// });
+/// // Once both halves are gone the slot can be split again.
+/// let (promise, consumer) = slot.split();
+/// drop(promise);
+/// assert!(block_on(consumer).is_err());
+/// ```
+pub struct StackPromise<T, R: RawMutex = DefaultRawMutex> {
+    inner: Mutex<R, Inner<T>>,
+}
+
+impl<T: Debug, R: RawMutex> Debug for StackPromise<T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("StackPromise")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    value: Option<T>,
+    failed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T, R: RawMutex> StackPromise<T, R> {
+    pub const fn new() -> Self {
+        StackPromise {
+            inner: Mutex::const_new(
+                R::INIT,
+                Inner {
+                    value: None,
+                    failed: false,
+                    waker: None,
+                },
+            ),
+        }
+    }
+
+    /// Return the producer and consumer of a new promise kept in this slot.
+    pub fn split(&mut self) -> (Producer<'_, T, R>, Consumer<'_, T, R>) {
+        *self.inner.get_mut() = Inner {
+            value: None,
+            failed: false,
+            waker: None,
+        };
+        (Producer { slot: self }, Consumer { slot: self })
+    }
+}
+
+impl<T, R: RawMutex> Default for StackPromise<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The producer half of a [`StackPromise`].
+pub struct Producer<'a, T, R: RawMutex = DefaultRawMutex> {
+    slot: &'a StackPromise<T, R>,
+}
+
+impl<T: Debug, R: RawMutex> Debug for Producer<'_, T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("slot", &self.slot)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex> Producer<'_, T, R> {
+    pub fn resolve(self, value: T) {
+        let waker = {
+            let mut inner = self.slot.inner.lock();
+            inner.value = Some(value);
+            inner.waker.take()
+        };
+        // The consumer may take the value before a drop could check for it.
+        core::mem::forget(self);
+        if let Some(waker) = waker {
+            waker.wake()
+        }
+    }
+}
+
+impl<T, R: RawMutex> Drop for Producer<'_, T, R> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut inner = self.slot.inner.lock();
+            inner.failed = true;
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake()
+        }
+    }
+}
+
+/// The consumer half of a [`StackPromise`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Consumer<'a, T, R: RawMutex = DefaultRawMutex> {
+    slot: &'a StackPromise<T, R>,
+}
+
+impl<T: Debug, R: RawMutex> Debug for Consumer<'_, T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("slot", &self.slot)
+            .finish()
+    }
+}
+
+impl<T, R: RawMutex> Future for Consumer<'_, T, R> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.slot.inner.lock();
+        if let Some(value) = inner.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if inner.failed {
+            return Poll::Ready(Err(Error::ProducerDropped));
+        }
+        match &inner.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => inner.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StackPromise;
+    use futures::{executor::block_on, FutureExt};
+
+    #[test]
+    fn test_resolve_after_poll() {
+        let mut slot = StackPromise::<u32>::new();
+        let (promise, mut consumer) = slot.split();
+        assert_eq!(None, (&mut consumer).now_or_never());
+        promise.resolve(3);
+        assert_eq!(Ok(3), block_on(consumer));
+    }
+}
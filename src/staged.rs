@@ -0,0 +1,142 @@
+//! staged implements a promise that is resolved in typed stages, such as
+//! `Accepted`, then `Headers`, then `Body`. Every stage is a poly promise, and
+//! the stages are nested in the producer's type so they can only be resolved in
+//! order: resolving a stage hands back the producer of the next one.
+use crate::{poly, Promise};
+
+/// The producer of a staged promise whose current stage resolves with `T`,
+/// followed by the stages of `Next`. The last stage is followed by [`End`].
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::staged::{End, Producer};
+/// use futures::executor::block_on;
+/// use std::thread;
+/// struct Accepted;
+/// type Response = Producer<Accepted, Producer<u16, Producer<String, End>>>;
+///
+/// let (accepted, consumer) = Response::new();
+/// let task1 = thread::spawn(move || block_on(async {
+///     consumer.stage().await.unwrap();
+///     let status = *consumer.next().stage().await.unwrap();
+///     let body = consumer.next().next().stage().await.unwrap();
+///     format!("{status} {body}")
+/// }));
+/// let status = accepted.resolve(Accepted);
+/// let body = status.resolve(200);
+/// body.resolve(String::from("OK"));
+/// assert_eq!("200 OK", task1.join().expect("The task1 thread has panicked."));
+/// ```
+#[derive(Debug)]
+pub struct Producer<T, Next> {
+    stage: poly::Producer<T>,
+    next: Next,
+}
+
+/// The consumer of a staged promise. It may be cloned, and every stage can be
+/// awaited on its own.
+#[derive(Debug)]
+pub struct Consumer<T, Next> {
+    stage: poly::Consumer<T>,
+    next: Next,
+}
+
+/// What follows the last stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct End;
+
+/// A chain of stages that can be created as a (producer, consumer) pair.
+pub trait Stages: Sized {
+    type Consumer;
+
+    fn new() -> (Self, Self::Consumer);
+}
+
+impl Stages for End {
+    type Consumer = End;
+
+    fn new() -> (Self, End) {
+        (End, End)
+    }
+}
+
+impl<T, Next: Stages> Stages for Producer<T, Next> {
+    type Consumer = Consumer<T, Next::Consumer>;
+
+    fn new() -> (Self, Self::Consumer) {
+        let (stage, waiter) = poly::Producer::new();
+        let (next, next_waiter) = Next::new();
+        (
+            Producer { stage, next },
+            Consumer {
+                stage: waiter,
+                next: next_waiter,
+            },
+        )
+    }
+}
+
+impl<T, Next: Stages> Producer<T, Next> {
+    /// Return a (producer, consumer) pair for every stage.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Self, Consumer<T, Next::Consumer>) {
+        <Self as Stages>::new()
+    }
+}
+
+impl<T, Next> Producer<T, Next> {
+    /// Resolve the current stage and return the producer of the next one.
+    /// Dropping a producer fails its stage and every stage after it.
+    pub fn resolve(self, value: T) -> Next {
+        self.stage.resolve(value);
+        self.next
+    }
+
+    /// Return true if every consumer has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.stage.is_closed()
+    }
+}
+
+impl<T, Next> Consumer<T, Next> {
+    /// Return a future of the current stage's value.
+    pub fn stage(&self) -> poly::Consumer<T> {
+        self.stage.clone()
+    }
+
+    /// Return the consumer of the stages after this one.
+    pub fn next(&self) -> &Next {
+        &self.next
+    }
+}
+
+impl<T, Next: Clone> Clone for Consumer<T, Next> {
+    fn clone(&self) -> Self {
+        Consumer {
+            stage: self.stage.clone(),
+            next: self.next.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{End, Producer};
+    use crate::Error;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_staged_producer_dropped_midway() {
+        let (first, consumer) = Producer::<u8, Producer<u16, Producer<u32, End>>>::new();
+        let later = consumer.clone();
+        let second = first.resolve(1);
+        drop(second);
+        assert_eq!(1, *block_on(consumer.stage()).unwrap());
+        assert_eq!(Err(Error::ProducerDropped), block_on(later.next().stage()));
+        assert_eq!(
+            Err(Error::ProducerDropped),
+            block_on(later.next().next().stage())
+        );
+    }
+}
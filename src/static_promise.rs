@@ -0,0 +1,169 @@
+//! static_promise implements a promise that can be a `static` item. The
+//! flavors keep their state in a reference counted allocation, which a
+//! `static` can not make, so a global signal such as a shutdown request would
+//! otherwise need a `OnceLock` around a producer and a consumer. A
+//! [`StaticPromise`] holds its state inline and is built by a `const fn`; it
+//! is resolved once, through a shared reference, and every waiter borrows the
+//! value for as long as the promise lives.
+use crate::lock::{DefaultRawMutex, RawMutex};
+use alloc::vec::Vec;
+use core::{
+    cell::UnsafeCell,
+    fmt::Debug,
+    future::Future,
+    mem::MaybeUninit,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use lock_api::Mutex;
+
+/// A promise that is resolved once and can be waited for by reference.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::static_promise::StaticPromise;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// static SHUTDOWN: StaticPromise<&str> = StaticPromise::new();
+/// let worker = thread::spawn(|| block_on(SHUTDOWN.wait()).len());
+/// assert_eq!(Ok(()), SHUTDOWN.resolve("signal"));
+/// assert_eq!(Err("again"), SHUTDOWN.resolve("again"));
+/// assert_eq!(6, worker.join().expect("The worker thread has panicked."));
+/// assert_eq!(Some(&"signal"), SHUTDOWN.get());
+/// ```
+pub struct StaticPromise<T, R: RawMutex = DefaultRawMutex> {
+    // Written once with the lock held, before `resolved` is set, and never
+    // changed again until the promise is dropped.
+    value: UnsafeCell<MaybeUninit<T>>,
+    resolved: AtomicBool,
+    wakers: Mutex<R, Vec<Waker>>,
+}
+
+// The value is only written before `resolved` is set with release ordering,
+// and only read after it has been seen with acquire ordering.
+unsafe impl<T: Send + Sync, R: RawMutex + Sync> Sync for StaticPromise<T, R> {}
+unsafe impl<T: Send, R: RawMutex + Send> Send for StaticPromise<T, R> {}
+
+impl<T, R: RawMutex> StaticPromise<T, R> {
+    /// Return an unresolved promise.
+    pub const fn new() -> Self {
+        StaticPromise {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            resolved: AtomicBool::new(false),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Resolve the promise and wake its waiters, or return `value` if it was
+    /// already resolved.
+    pub fn resolve(&self, value: T) -> Result<(), T> {
+        let mut wakers = self.wakers.lock();
+        if self.resolved.load(Ordering::Relaxed) {
+            return Err(value);
+        }
+        // Safety: the lock is held and the flag is not set, so nobody else
+        // writes or reads the value.
+        unsafe { (*self.value.get()).write(value) };
+        self.resolved.store(true, Ordering::Release);
+        let wakers = core::mem::take(&mut *wakers);
+        for waker in wakers {
+            waker.wake()
+        }
+        Ok(())
+    }
+
+    /// Return the value if the promise has been resolved.
+    pub fn get(&self) -> Option<&T> {
+        // Safety: once the flag is set, the value is initialized and only
+        // read until the promise is dropped.
+        self.resolved
+            .load(Ordering::Acquire)
+            .then(|| unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Return a future for the value.
+    pub fn wait(&self) -> Wait<'_, T, R> {
+        Wait { promise: self }
+    }
+}
+
+impl<T, R: RawMutex> Default for StaticPromise<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R: RawMutex> Drop for StaticPromise<T, R> {
+    fn drop(&mut self) {
+        if *self.resolved.get_mut() {
+            // Safety: the flag is set, so the value is initialized.
+            unsafe { self.value.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+impl<T: Debug, R: RawMutex> Debug for StaticPromise<T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("StaticPromise")
+            .field("value", &self.get())
+            .finish()
+    }
+}
+
+/// Future for [`StaticPromise::wait`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait<'a, T, R: RawMutex = DefaultRawMutex> {
+    promise: &'a StaticPromise<T, R>,
+}
+
+impl<T: Debug, R: RawMutex> Debug for Wait<'_, T, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Wait")
+            .field("promise", &self.promise)
+            .finish()
+    }
+}
+
+impl<'a, T, R: RawMutex> Future for Wait<'a, T, R> {
+    type Output = &'a T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<&'a T> {
+        let promise = self.promise;
+        if let Some(value) = promise.get() {
+            return Poll::Ready(value);
+        }
+        let mut wakers = promise.wakers.lock();
+        // It may have been resolved while the lock was taken.
+        if let Some(value) = promise.get() {
+            return Poll::Ready(value);
+        }
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StaticPromise;
+    use futures::{executor::block_on, FutureExt};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_resolves_once_and_drops_the_value() {
+        static READY: StaticPromise<u32> = StaticPromise::new();
+        let mut early = READY.wait();
+        assert_eq!(None, (&mut early).now_or_never());
+        assert_eq!(Ok(()), READY.resolve(7));
+        assert_eq!(7, *block_on(early));
+        assert_eq!(Err(8), READY.resolve(8));
+        let value = Arc::new(());
+        let promise: StaticPromise<Arc<()>> = StaticPromise::new();
+        promise.resolve(value.clone()).unwrap();
+        drop(promise);
+        assert_eq!(1, Arc::strong_count(&value));
+    }
+}
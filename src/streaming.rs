@@ -0,0 +1,261 @@
+//! streaming implements a request/response style promise: the producer
+//! resolves a header value, which the consumer receives together with a
+//! `Stream` of body chunks that the producer keeps feeding afterwards. Large
+//! responses can be consumed before they are complete. The body sender is also
+//! a `Sink`, so a body can be fed with `forward` or any other sink plumbing.
+use crate::{pair, Error, Promise};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug)]
+struct BodyInner<C> {
+    chunks: VecDeque<C>,
+    waker: Option<Waker>,
+    finished: bool,
+    sender_dropped: bool,
+    body_dropped: bool,
+}
+
+/// The producer of a header and body.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::streaming::Producer;
+/// use futures::{executor::block_on, StreamExt};
+/// use std::thread;
+/// let (promise, consumer) = Producer::<u16, &str>::new();
+/// let task1 = thread::spawn(move || block_on(async {
+///     let (status, body) = consumer.await.unwrap();
+///     let chunks: Vec<_> = body.map(Result::unwrap).collect().await;
+///     (status, chunks.concat())
+/// }));
+/// let body = promise.resolve(200);
+/// body.send("Hello, ").unwrap();
+/// body.send("dweb").unwrap();
+/// body.finish();
+/// assert_eq!((200, String::from("Hello, dweb")), task1.join().unwrap());
+/// ```
+#[derive(Debug)]
+pub struct Producer<H, C> {
+    header: pair::Producer<(H, Body<C>)>,
+}
+
+/// The consumer of a header and body. It resolves with the header and the
+/// body stream as soon as the header is resolved.
+#[derive(Debug)]
+pub struct Consumer<H, C> {
+    header: pair::Consumer<(H, Body<C>)>,
+}
+
+/// Feeds body chunks to the consumer. Call [`finish`](BodySender::finish)
+/// after the last chunk; dropping the sender without finishing ends the body
+/// with an error.
+#[derive(Debug)]
+pub struct BodySender<C> {
+    body: Arc<Mutex<BodyInner<C>>>,
+}
+
+/// The body chunks, in the order they were sent.
+#[derive(Debug)]
+pub struct Body<C> {
+    body: Arc<Mutex<BodyInner<C>>>,
+}
+
+impl<H, C> Producer<H, C> {
+    /// Return a (producer, consumer) pair.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (Self, Consumer<H, C>) {
+        let (header, waiter) = pair::Producer::new();
+        (Producer { header }, Consumer { header: waiter })
+    }
+
+    /// Resolve the header and return the sender for the body.
+    pub fn resolve(self, header: H) -> BodySender<C> {
+        let body = Arc::new(Mutex::new(BodyInner {
+            chunks: VecDeque::new(),
+            waker: None,
+            finished: false,
+            sender_dropped: false,
+            body_dropped: false,
+        }));
+        self.header.resolve((header, Body { body: body.clone() }));
+        BodySender { body }
+    }
+
+    /// Return true if the consumer has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.header.is_closed()
+    }
+}
+
+impl<H, C> Future for Consumer<H, C> {
+    type Output = Result<(H, Body<C>), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.header).poll(cx)
+    }
+}
+
+impl<C> BodySender<C> {
+    /// Send a chunk. The chunk is handed back if the body has been dropped.
+    pub fn send(&self, chunk: C) -> Result<(), C> {
+        let mut body = self.body.lock().unwrap();
+        if body.body_dropped {
+            return Err(chunk);
+        }
+        body.chunks.push_back(chunk);
+        if let Some(waker) = body.waker.take() {
+            waker.wake()
+        }
+        Ok(())
+    }
+
+    /// End the body after the chunks sent so far.
+    pub fn finish(self) {
+        self.body.lock().unwrap().finished = true;
+    }
+
+    /// Return true if the body has been dropped, so further chunks would go
+    /// unobserved.
+    pub fn is_closed(&self) -> bool {
+        self.body.lock().unwrap().body_dropped
+    }
+}
+
+/// Sending into a dropped body fails with [`Error::ConsumerDropped`], and
+/// closing the sink finishes the body.
+///
+/// ```
+/// use promise_out::streaming::Producer;
+/// use futures::{executor::block_on, stream, StreamExt};
+/// let (promise, consumer) = Producer::<(), u32>::new();
+/// let mut body = promise.resolve(());
+/// block_on(stream::iter([1, 2, 3]).map(Ok).forward(&mut body)).unwrap();
+/// let ((), chunks) = block_on(consumer).unwrap();
+/// assert_eq!(vec![Ok(1), Ok(2), Ok(3)], block_on(chunks.collect::<Vec<_>>()));
+/// ```
+impl<C> Sink<C> for BodySender<C> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.is_closed() {
+            Poll::Ready(Err(Error::ConsumerDropped))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, chunk: C) -> Result<(), Error> {
+        self.send(chunk).map_err(|_| Error::ConsumerDropped)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let mut body = self.body.lock().unwrap();
+        body.finished = true;
+        if let Some(waker) = body.waker.take() {
+            waker.wake()
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C> Drop for BodySender<C> {
+    /// End the body, with an error unless it was finished.
+    fn drop(&mut self) {
+        let mut body = self.body.lock().unwrap();
+        body.sender_dropped = true;
+        if let Some(waker) = body.waker.take() {
+            waker.wake()
+        }
+    }
+}
+
+impl<C> Stream for Body<C> {
+    type Item = Result<C, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut body = self.body.lock().unwrap();
+        if let Some(chunk) = body.chunks.pop_front() {
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+        if body.finished {
+            return Poll::Ready(None);
+        }
+        if body.sender_dropped {
+            // Report the truncated body once, then end the stream.
+            body.finished = true;
+            return Poll::Ready(Some(Err(Error::ProducerDropped)));
+        }
+        body.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<C> Drop for Body<C> {
+    fn drop(&mut self) {
+        let mut body = self.body.lock().unwrap();
+        body.body_dropped = true;
+        body.chunks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Producer;
+    use crate::Error;
+    use futures::{executor::block_on, StreamExt};
+    use std::thread;
+
+    #[test]
+    fn test_streaming_body_truncated() {
+        let (promise, consumer) = Producer::<(), u8>::new();
+        let task1 = thread::spawn(move || {
+            block_on(async {
+                let ((), body) = consumer.await.unwrap();
+                body.collect::<Vec<_>>().await
+            })
+        });
+        let body = promise.resolve(());
+        body.send(1).unwrap();
+        drop(body);
+        assert_eq!(
+            vec![Ok(1), Err(Error::ProducerDropped)],
+            task1.join().expect("The task1 thread has panicked")
+        );
+    }
+
+    #[test]
+    fn test_streaming_body_dropped() {
+        let (promise, consumer) = Producer::<(), u8>::new();
+        let body = promise.resolve(());
+        let ((), stream) = block_on(consumer).unwrap();
+        drop(stream);
+        assert!(body.is_closed());
+        assert_eq!(Err(1), body.send(1));
+    }
+
+    #[test]
+    fn test_streaming_sink_consumer_dropped() {
+        use futures::SinkExt;
+        let (promise, consumer) = Producer::<(), u8>::new();
+        let mut body = promise.resolve(());
+        block_on(SinkExt::send(&mut body, 1)).unwrap();
+        drop(consumer);
+        assert_eq!(
+            Err(Error::ConsumerDropped),
+            block_on(SinkExt::send(&mut body, 2))
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! timer runs the tasks behind the crate's delays and timeouts, such as
+//! [`delay`](crate::delay),
+//! [`Promise::resolve_after`](crate::Promise::resolve_after), and the retry
+//! and debounce deadlines. They go through the [`Timer`] installed with
+//! [`set_timer`], or else [`DefaultTimer`], chosen by feature like
+//! [`DefaultRawMutex`](crate::lock::DefaultRawMutex): by default
+//! [`ThreadTimer`], a single background thread that is started the first time
+//! something is scheduled and sleeps until the next deadline; with the
+//! `async-std` or `smol` feature, the timer of that runtime, so an application
+//! on it runs no extra thread. When both are enabled, `async-std` wins.
+#[cfg(feature = "pair")]
+use crate::{pair, Promise};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Condvar, Mutex, OnceLock},
+    thread,
+    time::Instant,
+};
+
+/// A task to run at a deadline.
+pub type Task = Box<dyn FnOnce() + Send>;
+
+/// Runs tasks once their deadlines have passed.
+pub trait Timer: Send + Sync {
+    /// Run `task` once `deadline` has passed. Tasks should be short, as a
+    /// timer may run them one after another.
+    fn schedule(&self, deadline: Instant, task: Task);
+}
+
+static TIMER: OnceLock<Box<dyn Timer>> = OnceLock::new();
+
+/// Install `timer` as the one the crate's delays and timeouts use, or return
+/// it if a timer is already in use. Call it before anything is scheduled, as
+/// the first deadline otherwise installs [`DefaultTimer`].
+///
+/// ```
+/// use promise_out::timer::{set_timer, Task, Timer};
+/// use std::{thread, time::Instant};
+/// struct SpawnTimer;
+/// impl Timer for SpawnTimer {
+///     fn schedule(&self, deadline: Instant, task: Task) {
+///         thread::spawn(move || {
+///             thread::sleep(deadline.saturating_duration_since(Instant::now()));
+///             task()
+///         });
+///     }
+/// }
+/// assert!(set_timer(SpawnTimer).is_ok());
+/// assert!(set_timer(SpawnTimer).is_err());
+/// ```
+pub fn set_timer<T: Timer + 'static>(timer: T) -> Result<(), T> {
+    let mut timer = Some(timer);
+    TIMER.get_or_init(|| Box::new(timer.take().unwrap()));
+    match timer {
+        None => Ok(()),
+        Some(timer) => Err(timer),
+    }
+}
+
+/// The timer the crate's delays and timeouts use: [`ThreadTimer`], or
+/// [`AsyncStdTimer`] with the `async-std` feature, or else [`SmolTimer`] with
+/// the `smol` feature.
+#[cfg(not(any(feature = "async-std", feature = "smol")))]
+pub type DefaultTimer = ThreadTimer;
+
+/// The timer the crate's delays and timeouts use: [`ThreadTimer`], or
+/// [`AsyncStdTimer`] with the `async-std` feature, or else [`SmolTimer`] with
+/// the `smol` feature.
+#[cfg(feature = "async-std")]
+pub type DefaultTimer = AsyncStdTimer;
+
+/// The timer the crate's delays and timeouts use: [`ThreadTimer`], or
+/// [`AsyncStdTimer`] with the `async-std` feature, or else [`SmolTimer`] with
+/// the `smol` feature.
+#[cfg(all(feature = "smol", not(feature = "async-std")))]
+pub type DefaultTimer = SmolTimer;
+
+/// Runs tasks on the crate's own timer thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadTimer;
+
+impl Timer for ThreadTimer {
+    fn schedule(&self, deadline: Instant, task: Task) {
+        let timer = timer_thread();
+        let mut state = timer.state.lock().unwrap();
+        state.seq += 1;
+        let seq = state.seq;
+        state.entries.push(Entry {
+            deadline,
+            seq,
+            task,
+        });
+        timer.condvar.notify_one();
+    }
+}
+
+/// Runs each task in an async-std task that sleeps until its deadline.
+#[cfg(feature = "async-std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdTimer;
+
+#[cfg(feature = "async-std")]
+impl Timer for AsyncStdTimer {
+    fn schedule(&self, deadline: Instant, task: Task) {
+        // Dropping the handle detaches the task.
+        async_std::task::spawn(async move {
+            async_std::task::sleep(deadline.saturating_duration_since(Instant::now())).await;
+            task()
+        });
+    }
+}
+
+/// Runs each task in a task on smol's global executor that waits on a
+/// `smol::Timer`.
+#[cfg(feature = "smol")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolTimer;
+
+#[cfg(feature = "smol")]
+impl Timer for SmolTimer {
+    fn schedule(&self, deadline: Instant, task: Task) {
+        smol::spawn(async move {
+            smol::Timer::at(deadline).await;
+            task()
+        })
+        .detach();
+    }
+}
+
+struct Entry {
+    deadline: Instant,
+    seq: u64,
+    task: Task,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    /// Reversed so the `BinaryHeap` pops the earliest deadline first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    entries: BinaryHeap<Entry>,
+    seq: u64,
+}
+
+struct TimerThread {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+fn timer_thread() -> &'static TimerThread {
+    static THREAD: OnceLock<TimerThread> = OnceLock::new();
+    THREAD.get_or_init(|| {
+        thread::Builder::new()
+            .name("promise_out-timer".into())
+            .spawn(run)
+            .expect("failed to spawn the timer thread");
+        TimerThread {
+            state: Mutex::new(State::default()),
+            condvar: Condvar::new(),
+        }
+    })
+}
+
+fn run() {
+    let timer = timer_thread();
+    let mut state = timer.state.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while state
+            .entries
+            .peek()
+            .is_some_and(|entry| entry.deadline <= now)
+        {
+            due.push(state.entries.pop().unwrap().task);
+        }
+        if !due.is_empty() {
+            drop(state);
+            for task in due {
+                // A panicking task must not take the thread, and every later
+                // deadline, down with it. The panic hook has reported it.
+                let _ = panic::catch_unwind(AssertUnwindSafe(task));
+            }
+            state = timer.state.lock().unwrap();
+            continue;
+        }
+        state = match state.entries.peek() {
+            Some(entry) => {
+                let timeout = entry.deadline.saturating_duration_since(now);
+                timer.condvar.wait_timeout(state, timeout).unwrap().0
+            }
+            None => timer.condvar.wait(state).unwrap(),
+        };
+    }
+}
+
+/// Run `task` on the installed timer once `deadline` has passed.
+pub(crate) fn schedule(deadline: Instant, task: impl FnOnce() + Send + 'static) {
+    TIMER
+        .get_or_init(|| Box::new(DefaultTimer::default()))
+        .schedule(deadline, Box::new(task))
+}
+
+/// Return a consumer that resolves once `duration` has elapsed.
+#[cfg(feature = "pair")]
+pub(crate) fn delay(duration: std::time::Duration) -> pair::Consumer<()> {
+    let (producer, consumer) = pair::Producer::new();
+    schedule(Instant::now() + duration, move || producer.resolve(()));
+    consumer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ThreadTimer, Timer};
+    use std::{
+        sync::mpsc,
+        time::{Duration, Instant},
+    };
+
+    /// Schedule two tasks, the later one first, and check they run in order.
+    fn runs_in_deadline_order(timer: impl Timer) {
+        let (sender, ran) = mpsc::channel();
+        let now = Instant::now();
+        for (n, after) in [(2, 40), (1, 10)] {
+            let sender = sender.clone();
+            timer.schedule(
+                now + Duration::from_millis(after),
+                Box::new(move || sender.send(n).unwrap()),
+            );
+        }
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Ok(1), ran.recv_timeout(timeout));
+        assert_eq!(Ok(2), ran.recv_timeout(timeout));
+        assert!(now.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_a_panicking_task_does_not_stop_the_thread() {
+        let (sender, ran) = mpsc::channel();
+        let now = Instant::now();
+        ThreadTimer.schedule(now, Box::new(|| panic!("task panicked")));
+        ThreadTimer.schedule(
+            now + Duration::from_millis(10),
+            Box::new(move || sender.send(()).unwrap()),
+        );
+        assert_eq!(Ok(()), ran.recv_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_timers_run_in_deadline_order() {
+        runs_in_deadline_order(ThreadTimer);
+        #[cfg(feature = "async-std")]
+        runs_in_deadline_order(super::AsyncStdTimer);
+        #[cfg(feature = "smol")]
+        runs_in_deadline_order(super::SmolTimer);
+    }
+}
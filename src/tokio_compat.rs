@@ -0,0 +1,197 @@
+//! tokio_compat bridges the flavors and tokio's `oneshot`, `watch`, and
+//! `Notify`, so a codebase can adopt promises one module at a time and keep
+//! tokio's types at the boundaries. Each bridge spawns a small task on the
+//! current tokio runtime that forwards the value, and that task ends as soon
+//! as the side it forwards to is dropped, so an abandoned bridge does not
+//! hold on to anything. Every function panics outside a tokio runtime, like
+//! `tokio::spawn`.
+//!
+//! ```
+//! use promise_out::{pair, tokio_compat, Promise};
+//! let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! runtime.block_on(async {
+//!     let (sender, receiver) = tokio::sync::oneshot::channel();
+//!     let consumer: pair::Consumer<u32> = tokio_compat::consumer_from_oneshot(receiver);
+//!     sender.send(1).unwrap();
+//!     assert_eq!(Ok(1), consumer.await);
+//!     let (promise, consumer) = pair::Producer::<u32>::new();
+//!     let receiver = tokio_compat::oneshot_from_consumer(consumer);
+//!     promise.resolve(2);
+//!     assert_eq!(Ok(2), receiver.await);
+//! });
+//! ```
+use crate::{pair, poly, Promise};
+use std::{
+    future::{poll_fn, Future},
+    pin::pin,
+    sync::Arc,
+    task::Poll,
+};
+use tokio::sync::{oneshot, watch, Notify};
+
+/// Run `work` until it finishes or `closed` does, whichever is first.
+async fn until<F: Future>(closed: impl Future, work: F) -> Option<F::Output> {
+    let mut closed = pin!(closed);
+    let mut work = pin!(work);
+    poll_fn(|cx| {
+        if closed.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        work.as_mut().poll(cx).map(Some)
+    })
+    .await
+}
+
+/// Return a consumer of what is sent on `receiver`. It fails with
+/// [`Error::ProducerDropped`](crate::Error::ProducerDropped) if the sender is
+/// dropped without sending.
+pub fn consumer_from_oneshot<T: Send + 'static>(
+    receiver: oneshot::Receiver<T>,
+) -> pair::Consumer<T> {
+    let (promise, consumer) = pair::Producer::new();
+    tokio::spawn(async move {
+        if let Some(Ok(value)) = until(promise.closed(), receiver).await {
+            promise.resolve(value)
+        }
+    });
+    consumer
+}
+
+/// Return a receiver of the value `consumer` resolves with. The sender is
+/// dropped if the promise fails.
+pub fn oneshot_from_consumer<T: Send + 'static>(
+    consumer: pair::Consumer<T>,
+) -> oneshot::Receiver<T> {
+    let (mut sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let closed = poll_fn(|cx| sender.poll_closed(cx));
+        if let Some(Ok(value)) = until(closed, consumer).await {
+            let _ = sender.send(value);
+        }
+    });
+    receiver
+}
+
+/// Return a producer whose value is sent on `sender`. The producer sees the
+/// promise closed once the receiver is dropped.
+pub fn producer_from_oneshot<T: Send + 'static>(
+    mut sender: oneshot::Sender<T>,
+) -> pair::Producer<T> {
+    let (promise, consumer) = pair::Producer::new();
+    tokio::spawn(async move {
+        let closed = poll_fn(|cx| sender.poll_closed(cx));
+        if let Some(Ok(value)) = until(closed, consumer).await {
+            let _ = sender.send(value);
+        }
+    });
+    promise
+}
+
+/// Return a sender whose value resolves `producer`. Dropping the sender
+/// without sending drops the producer, which fails its consumer.
+pub fn oneshot_from_producer<T: Send + 'static>(producer: pair::Producer<T>) -> oneshot::Sender<T> {
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        if let Some(Ok(value)) = until(producer.closed(), receiver).await {
+            producer.resolve(value)
+        }
+    });
+    sender
+}
+
+/// Return a watch of `consumer`, which holds `None` until the promise is
+/// resolved and its value then. The sender is dropped if the promise fails,
+/// so `changed()` returns an error.
+pub fn watch_from_consumer<T: Send + Sync + 'static>(
+    consumer: poly::Consumer<T>,
+) -> watch::Receiver<Option<Arc<T>>> {
+    let (sender, receiver) = watch::channel(None);
+    tokio::spawn(async move {
+        if let Some(Ok(value)) = until(sender.closed(), consumer).await {
+            sender.send_replace(Some(value));
+        }
+    });
+    receiver
+}
+
+/// Return a consumer of the first value of `receiver`, the current one
+/// included, for which `ready` returns true. It fails with
+/// [`Error::ProducerDropped`](crate::Error::ProducerDropped) if the sender is
+/// dropped first.
+pub fn consumer_from_watch<T, F>(mut receiver: watch::Receiver<T>, ready: F) -> poly::Consumer<T>
+where
+    T: Clone + Send + Sync + 'static,
+    F: FnMut(&T) -> bool + Send + 'static,
+{
+    let (promise, consumer) = poly::Producer::new();
+    tokio::spawn(async move {
+        let value = until(promise.closed(), async {
+            receiver.wait_for(ready).await.map(|value| value.clone())
+        })
+        .await;
+        if let Some(Ok(value)) = value {
+            promise.resolve(value)
+        }
+    });
+    consumer
+}
+
+/// Return a consumer that resolves at the next `notify_waiters()` or
+/// `notify_one()` of `notify`.
+pub fn consumer_from_notify(notify: Arc<Notify>) -> poly::Consumer<()> {
+    let (promise, consumer) = poly::Producer::new();
+    let notified = notify.notified_owned();
+    tokio::spawn(async move {
+        if until(promise.closed(), notified).await.is_some() {
+            promise.resolve(())
+        }
+    });
+    consumer
+}
+
+/// Call `notify_waiters()` on `notify` once `consumer` settles, whether it
+/// resolves or fails.
+pub fn notify_on_settle<F>(consumer: F, notify: Arc<Notify>)
+where
+    F: Future + Send + 'static,
+{
+    tokio::spawn(async move {
+        consumer.await;
+        notify.notify_waiters();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        consumer_from_watch, oneshot_from_producer, producer_from_oneshot, watch_from_consumer,
+    };
+    use crate::{poly, Error, Promise};
+    use tokio::sync::{oneshot, watch};
+
+    #[test]
+    fn test_bridges_forward_and_close() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (sender, receiver) = oneshot::channel::<u32>();
+            let promise = producer_from_oneshot(sender);
+            drop(receiver);
+            promise.closed().await;
+            let (promise, consumer) = crate::pair::Producer::<u32>::new();
+            drop(oneshot_from_producer(promise));
+            assert_eq!(Err(Error::ProducerDropped), consumer.await);
+            let (promise, consumer) = poly::Producer::<u32>::new();
+            let mut watched = watch_from_consumer(consumer);
+            promise.resolve(3);
+            watched.changed().await.unwrap();
+            assert_eq!(Some(3), watched.borrow().as_deref().copied());
+            let (sender, receiver) = watch::channel(0);
+            let consumer = consumer_from_watch(receiver, |&n| n >= 2);
+            sender.send(1).unwrap();
+            sender.send(2).unwrap();
+            assert_eq!(2, *consumer.await.unwrap());
+        });
+    }
+}
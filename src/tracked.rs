@@ -0,0 +1,116 @@
+//! tracked implements the opt-in `tracked` feature. Every promise of the pair,
+//! poly, channel, and mpmc flavors is then entered in a global table, and
+//! [`shutdown`] fails every consumer still waiting with [`Error::Shutdown`], a
+//! last resort so that no task hangs on a promise while the process exits.
+//! Without the feature the table does not exist and promises pay nothing.
+use crate::Error;
+use core::task::{Context, Poll};
+#[cfg(feature = "tracked")]
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::Waker,
+};
+
+/// The reason given to the consumers failed by [`shutdown`].
+#[cfg(feature = "tracked")]
+const REASON: &str = "process shutting down";
+
+#[cfg(feature = "tracked")]
+static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "tracked")]
+static TABLE: Mutex<Vec<Weak<Slot>>> = Mutex::new(Vec::new());
+
+/// The wakers of the tasks waiting on one promise.
+#[cfg(feature = "tracked")]
+#[derive(Debug, Default)]
+struct Slot {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// A promise's entry in the global table. Without the `tracked` feature it is
+/// empty and never reports a shutdown.
+#[derive(Debug)]
+pub(crate) struct Tracked {
+    #[cfg(feature = "tracked")]
+    slot: Arc<Slot>,
+}
+
+impl Tracked {
+    pub(crate) fn new() -> Self {
+        #[cfg(feature = "tracked")]
+        {
+            let slot = Arc::new(Slot::default());
+            let mut table = TABLE.lock().unwrap();
+            // Drop the entries of dropped promises before growing.
+            if table.len() == table.capacity() {
+                table.retain(|slot| slot.strong_count() > 0);
+            }
+            table.push(Arc::downgrade(&slot));
+            Tracked { slot }
+        }
+        #[cfg(not(feature = "tracked"))]
+        Tracked {}
+    }
+
+    /// Return the error to fail a pending consumer with once [`shutdown`] has
+    /// been called, or else arrange for `cx` to be woken when it is.
+    #[cfg_attr(not(feature = "tracked"), allow(unused_variables))]
+    pub(crate) fn poll_shutdown(&self, cx: &mut Context<'_>) -> Poll<Error> {
+        #[cfg(feature = "tracked")]
+        {
+            if SHUT_DOWN.load(Ordering::Acquire) {
+                return Poll::Ready(Error::Shutdown(String::from(REASON)));
+            }
+            let mut wakers = self.slot.wakers.lock().unwrap();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            drop(wakers);
+            // A shutdown may have taken the wakers just before ours went in.
+            if SHUT_DOWN.load(Ordering::Acquire) {
+                return Poll::Ready(Error::Shutdown(String::from(REASON)));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Fail every pending consumer, now and from now on, with
+/// [`Error::Shutdown`]. Consumers whose promise has already been resolved
+/// still get their value.
+///
+/// ```
+/// use promise_out::{Error, Promise, pair::Producer};
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let (_forgotten, consumer) = Producer::<u32>::new();
+/// let task = thread::spawn(move || block_on(consumer));
+/// promise_out::shutdown();
+/// assert!(matches!(task.join().unwrap(), Err(Error::Shutdown(_))));
+/// ```
+#[cfg(feature = "tracked")]
+pub fn shutdown() {
+    SHUT_DOWN.store(true, Ordering::Release);
+    let slots: Vec<_> = TABLE
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for slot in slots {
+        let wakers = std::mem::take(&mut *slot.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake()
+        }
+    }
+}
+
+/// Return true once [`shutdown`] has been called.
+#[cfg(feature = "tracked")]
+pub fn is_shut_down() -> bool {
+    SHUT_DOWN.load(Ordering::Acquire)
+}
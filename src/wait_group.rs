@@ -0,0 +1,163 @@
+//! wait_group implements a Go style WaitGroup. Each [`Guard`] counts as one
+//! outstanding task, and [`WaitGroup::wait`] resolves once every guard has been
+//! dropped. While any guard is alive the group holds a poly promise that the
+//! last guard resolves.
+use crate::{poly, Error, Promise};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+#[derive(Debug, Default)]
+struct State {
+    count: usize,
+    // Present while count > 0.
+    round: Option<(poly::Producer<()>, poly::Consumer<()>)>,
+}
+
+/// A group of outstanding tasks to wait for.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::wait_group::WaitGroup;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let group = WaitGroup::new();
+/// for i in 0..3 {
+///     let guard = group.guard();
+///     thread::spawn(move || {
+///         println!("task {i} done");
+///         drop(guard);
+///     });
+/// }
+/// block_on(group.wait()).unwrap();
+/// assert_eq!(0, group.count());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WaitGroup {
+    state: Arc<Mutex<State>>,
+}
+
+/// One outstanding task of a [`WaitGroup`]. Cloning it adds another task.
+#[derive(Debug)]
+pub struct Guard {
+    state: Arc<Mutex<State>>,
+}
+
+impl WaitGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a task to the group, finished when the returned guard is dropped.
+    pub fn guard(&self) -> Guard {
+        add(&self.state);
+        Guard {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Return the number of outstanding tasks.
+    pub fn count(&self) -> usize {
+        self.state.lock().unwrap().count
+    }
+
+    /// Return a future that resolves once there are no outstanding tasks.
+    /// Tasks added after the count reaches zero start a new round and are
+    /// not waited for.
+    pub fn wait(&self) -> Wait {
+        let state = self.state.lock().unwrap();
+        Wait {
+            done: state.round.as_ref().map(|(_, done)| done.clone()),
+        }
+    }
+}
+
+fn add(state: &Mutex<State>) {
+    let mut state = state.lock().unwrap();
+    state.count += 1;
+    if state.round.is_none() {
+        state.round = Some(poly::Producer::new());
+    }
+}
+
+impl Clone for Guard {
+    fn clone(&self) -> Self {
+        add(&self.state);
+        Guard {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let round = {
+            let mut state = self.state.lock().unwrap();
+            state.count -= 1;
+            if state.count > 0 {
+                return;
+            }
+            state.round.take()
+        };
+        if let Some((producer, _)) = round {
+            producer.resolve(());
+        }
+    }
+}
+
+/// Future for [`WaitGroup::wait`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Wait {
+    // None if there were no outstanding tasks.
+    done: Option<poly::Consumer<()>>,
+}
+
+impl Future for Wait {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.done.as_mut() {
+            None => Poll::Ready(Ok(())),
+            Some(done) => Pin::new(done).poll(cx).map_ok(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WaitGroup;
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_wait_group_dynamic_tasks() {
+        let group = WaitGroup::new();
+        assert_eq!(Ok(()), block_on(group.wait()));
+        let guard = group.guard();
+        let wait = group.wait();
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let guard = guard.clone();
+                thread::spawn(move || drop(guard))
+            })
+            .collect();
+        drop(guard);
+        assert_eq!(Ok(()), block_on(wait));
+        assert_eq!(0, group.count());
+        for task in tasks {
+            task.join().expect("The task thread has panicked");
+        }
+
+        // A new round starts after the count reached zero.
+        let guard = group.guard();
+        let wait = group.wait();
+        let task1 = thread::spawn(move || block_on(wait));
+        drop(guard);
+        assert_eq!(Ok(()), task1.join().expect("The task1 thread has panicked"));
+    }
+}
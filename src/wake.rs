@@ -0,0 +1,114 @@
+//! wake selects how a [`poly`](crate::poly) producer wakes its consumers when
+//! it settles. By default they are woken inline, with the promise's lock held,
+//! which is cheapest for a handful of consumers. With thousands of them, a
+//! resolver would run every wake in its own call stack while the lock is held,
+//! so [`WakeStrategy::Deferred`] collects the wakers and wakes them after the
+//! lock is released, and [`WakeStrategy::spawn`] hands the batch to a
+//! function, e.g. one that queues it on the application's executor.
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt::Debug, task::Waker};
+
+/// How the wakers of a promise are run when it settles.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, poly::Producer, wake::WakeStrategy};
+/// use futures::executor::block_on;
+/// use std::{sync::mpsc, thread};
+/// let (batches, queue) = mpsc::channel();
+/// let (promise, consumer) = Producer::<u8>::new();
+/// promise.set_wake_strategy(WakeStrategy::spawn(move |wakers| {
+///     batches.send(wakers).unwrap();
+/// }));
+/// thread::spawn(move || {
+///     for waker in queue.recv().unwrap() {
+///         waker.wake()
+///     }
+/// });
+/// promise.resolve(1);
+/// assert_eq!(1, *block_on(consumer).unwrap());
+/// ```
+#[derive(Clone, Default)]
+pub enum WakeStrategy {
+    /// Wake each consumer in turn with the lock held.
+    #[default]
+    Inline,
+    /// Collect the wakers and wake them once the lock is released.
+    Deferred,
+    /// Hand the collected wakers to a function once the lock is released.
+    Spawn(Arc<dyn Fn(Vec<Waker>) + Send + Sync>),
+}
+
+impl WakeStrategy {
+    /// Return a strategy that passes every batch of wakers to `spawn`.
+    pub fn spawn(spawn: impl Fn(Vec<Waker>) + Send + Sync + 'static) -> Self {
+        WakeStrategy::Spawn(Arc::new(spawn))
+    }
+
+    /// Run a batch of wakers collected with the lock held.
+    pub(crate) fn dispatch(&self, wakers: Vec<Waker>) {
+        match self {
+            WakeStrategy::Spawn(spawn) if !wakers.is_empty() => spawn(wakers),
+            WakeStrategy::Spawn(_) => {}
+            WakeStrategy::Inline | WakeStrategy::Deferred => {
+                for waker in wakers {
+                    waker.wake()
+                }
+            }
+        }
+    }
+}
+
+impl Debug for WakeStrategy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WakeStrategy::Inline => f.write_str("Inline"),
+            WakeStrategy::Deferred => f.write_str("Deferred"),
+            WakeStrategy::Spawn(_) => f.write_str("Spawn"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::WakeStrategy;
+    use crate::{
+        poly::{Consumer, Producer},
+        Promise,
+    };
+    use futures::executor::block_on;
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Wake, Waker},
+    };
+
+    /// A waker that locks the promise it waits for, which would deadlock if
+    /// it were woken with the lock held.
+    struct Relock(Consumer<u8>, AtomicBool);
+
+    impl Wake for Relock {
+        fn wake(self: Arc<Self>) {
+            self.0.id();
+            self.1.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_deferred_wakes_after_unlock() {
+        let (promise, mut consumer) = Producer::<u8>::new();
+        promise.set_wake_strategy(WakeStrategy::Deferred);
+        let relock = Arc::new(Relock(consumer.clone(), AtomicBool::new(false)));
+        let waker = Waker::from(relock.clone());
+        let poll = Pin::new(&mut consumer).poll(&mut Context::from_waker(&waker));
+        assert!(poll.is_pending());
+        promise.resolve(9);
+        assert!(relock.1.load(Ordering::Relaxed));
+        assert_eq!(9, *block_on(consumer).unwrap());
+    }
+}
@@ -0,0 +1,51 @@
+//! wasm bridges consumers and JavaScript promises for the dweb browser side.
+//! [`promise_from_consumer`] hands a consumer to JavaScript as a
+//! `js_sys::Promise`, and [`consumer_from_promise`] awaits a JavaScript
+//! promise as a pair consumer. The browser runs one thread, so the pair uses
+//! [`LocalRawMutex`], and settling goes through the microtask queue that
+//! `wasm-bindgen-futures` drives.
+//!
+//! ```no_run
+//! use promise_out::{pair, wasm, Promise};
+//! use wasm_bindgen::JsValue;
+//! let (promise, consumer) = pair::Producer::<u32>::new();
+//! let js_promise = wasm::promise_from_consumer(consumer);
+//! promise.resolve(1);
+//! let consumer = wasm::consumer_from_promise(js_promise);
+//! wasm_bindgen_futures::spawn_local(async move {
+//!     assert_eq!(Ok(Ok(JsValue::from(1))), consumer.await);
+//! });
+//! ```
+use crate::{lock::LocalRawMutex, pair, Promise};
+use js_sys::Promise as JsPromise;
+use std::{fmt::Display, future::Future};
+use wasm_bindgen::{JsError, JsValue};
+use wasm_bindgen_futures::{future_to_promise, spawn_local, JsFuture};
+
+/// Return a JavaScript promise that settles like `consumer`: it fulfills with
+/// the value converted to a `JsValue`, or rejects with a JavaScript `Error`
+/// carrying the consumer's error message. The consumer is polled on the
+/// browser's event loop.
+pub fn promise_from_consumer<F, T, E>(consumer: F) -> JsPromise
+where
+    F: Future<Output = Result<T, E>> + 'static,
+    T: Into<JsValue>,
+    E: Display,
+{
+    future_to_promise(async move {
+        consumer
+            .await
+            .map(Into::into)
+            .map_err(|error| JsError::new(&error.to_string()).into())
+    })
+}
+
+/// Return a consumer of `promise`. It resolves with `Ok` of the value the
+/// promise fulfills with, or with `Err` of the reason it rejects with.
+pub fn consumer_from_promise(
+    promise: JsPromise,
+) -> pair::Consumer<Result<JsValue, JsValue>, LocalRawMutex> {
+    let (producer, consumer) = pair::Producer::new();
+    spawn_local(async move { producer.resolve(JsFuture::from(promise).await) });
+    consumer
+}
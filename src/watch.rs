@@ -0,0 +1,240 @@
+//! watch implements a latest-value promise. The sender may update the value
+//! any number of times and every receiver can read the latest snapshot or wait
+//! for the next change. Each change is a poly promise, so waking the receivers
+//! is exactly the poly wake-all.
+use crate::{poly, Error, Promise};
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+#[derive(Debug)]
+struct Shared<T> {
+    value: Arc<T>,
+    version: u64,
+    // Resolved by the next send, or dropped along with the sender.
+    next: Option<poly::Producer<()>>,
+    changed: poly::Consumer<()>,
+}
+
+/// The updating half of a watch. It can not be cloned.
+#[derive(Debug)]
+pub struct Sender<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// The observing half of a watch. It may be cloned; every clone tracks which
+/// version it has seen on its own.
+#[derive(Debug)]
+pub struct Receiver<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    seen: u64,
+}
+
+/// Return a (sender, receiver) pair watching `initial`.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::watch;
+/// use futures::executor::block_on;
+/// use std::thread;
+/// let (sender, mut receiver) = watch::channel("debug");
+/// let task1 = thread::spawn(move || block_on(async {
+///     receiver.changed().await.unwrap();
+///     *receiver.borrow()
+/// }));
+/// sender.send("info");
+/// assert_eq!("info", task1.join().expect("The task1 thread has panicked."));
+/// ```
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let (next, changed) = poly::Producer::new();
+    let shared = Arc::new(Mutex::new(Shared {
+        value: Arc::new(initial),
+        version: 0,
+        next: Some(next),
+        changed,
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, seen: 0 },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Replace the value and wake every receiver waiting for a change.
+    pub fn send(&self, value: T) {
+        let (next, changed) = poly::Producer::new();
+        let previous = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.value = Arc::new(value);
+            shared.version += 1;
+            shared.changed = changed;
+            shared.next.replace(next)
+        };
+        if let Some(previous) = previous {
+            previous.resolve(());
+        }
+    }
+
+    /// Return the latest value.
+    pub fn borrow(&self) -> Arc<T> {
+        self.shared.lock().unwrap().value.clone()
+    }
+
+    /// Return a new receiver that has seen the current value.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let seen = self.shared.lock().unwrap().version;
+        Receiver {
+            shared: self.shared.clone(),
+            seen,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    /// Wake every receiver waiting for a change with an error.
+    fn drop(&mut self) {
+        let next = self.shared.lock().unwrap().next.take();
+        drop(next);
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Return the latest value without marking it as seen.
+    pub fn borrow(&self) -> Arc<T> {
+        self.shared.lock().unwrap().value.clone()
+    }
+
+    /// Return the latest value and mark it as seen.
+    pub fn borrow_and_update(&mut self) -> Arc<T> {
+        let shared = self.shared.lock().unwrap();
+        self.seen = shared.version;
+        shared.value.clone()
+    }
+
+    /// Return true if the value changed since this receiver last saw it.
+    pub fn has_changed(&self) -> bool {
+        self.shared.lock().unwrap().version != self.seen
+    }
+
+    /// Wait until the value differs from the one this receiver last saw, and
+    /// mark the new value as seen. Fails once the sender has been dropped and
+    /// there is no unseen value left.
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed {
+            receiver: self,
+            waiting: None,
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+/// Future for [`Receiver::changed`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Changed<'a, T> {
+    receiver: &'a mut Receiver<T>,
+    waiting: Option<poly::Consumer<()>>,
+}
+
+impl<T: Debug> Debug for Changed<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Changed")
+            .field("receiver", &self.receiver)
+            .finish()
+    }
+}
+
+impl<T> Future for Changed<'_, T> {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            {
+                let shared = this.receiver.shared.lock().unwrap();
+                if shared.version != this.receiver.seen {
+                    this.receiver.seen = shared.version;
+                    return Poll::Ready(Ok(()));
+                }
+                if this.waiting.is_none() {
+                    if shared.next.is_none() {
+                        return Poll::Ready(Err(Error::ProducerDropped));
+                    }
+                    this.waiting = Some(shared.changed.clone());
+                }
+            }
+            match Pin::new(this.waiting.as_mut().unwrap()).poll(cx) {
+                // Look at the version again; an error means the sender is gone.
+                Poll::Ready(_) => this.waiting = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+    use crate::Error;
+    use futures::executor::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_watch_latest_value() {
+        let (sender, mut receiver) = channel(0);
+        let mut receiver2 = receiver.clone();
+        sender.send(1);
+        sender.send(2);
+        assert!(receiver.has_changed());
+        assert_eq!(Ok(()), block_on(receiver.changed()));
+        assert_eq!(2, *receiver.borrow());
+        assert!(!receiver.has_changed());
+        assert_eq!(2, *receiver2.borrow_and_update());
+        assert!(!receiver2.has_changed());
+        assert_eq!(2, *sender.borrow());
+    }
+
+    #[test]
+    fn test_watch_many_receivers() {
+        let (sender, receiver) = channel(String::from("a"));
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let mut receiver = receiver.clone();
+                thread::spawn(move || {
+                    block_on(receiver.changed()).unwrap();
+                    receiver.borrow()
+                })
+            })
+            .collect();
+        sender.send(String::from("b"));
+        for task in tasks {
+            assert_eq!("b", *task.join().expect("The task thread has panicked"));
+        }
+    }
+
+    #[test]
+    fn test_watch_sender_dropped() {
+        let (sender, mut receiver) = channel(0);
+        let task1 = thread::spawn(move || block_on(receiver.changed()));
+        let mut receiver2 = sender.subscribe();
+        sender.send(1);
+        drop(sender);
+        let _ = task1.join().expect("The task1 thread has panicked");
+        assert_eq!(Ok(()), block_on(receiver2.changed()));
+        assert_eq!(Err(Error::ProducerDropped), block_on(receiver2.changed()));
+    }
+}
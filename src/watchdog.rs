@@ -0,0 +1,204 @@
+//! watchdog rejects consumers whose producer has been orphaned. A consumer is
+//! normally failed when its producer is dropped, but a producer that was
+//! leaked, parked in a long-lived structure, or lost in a panic may never be
+//! dropped. Instead, a [`Watched`] consumer is tied to an [`Owner`], such as
+//! the thread or task that is meant to settle it, and fails with
+//! [`Error::Shutdown`] as soon as the owner goes away, however its producer
+//! fares.
+use crate::{
+    envelope::{Envelope, Metadata},
+    Error,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+thread_local! {
+    static THREAD: Owner = const {
+        Owner {
+            watches: Mutex::new(Vec::new()),
+            thread: true,
+        }
+    };
+}
+
+/// Whether the owner of a watched consumer is gone, and the consumer's waker
+/// to wake when it goes.
+#[derive(Debug, Default)]
+struct Watch {
+    reason: Option<String>,
+    waker: Option<Waker>,
+}
+
+impl Watch {
+    fn orphan(&mut self, reason: &str) {
+        self.reason = Some(String::from(reason));
+        if let Some(waker) = self.waker.take() {
+            waker.wake()
+        }
+    }
+}
+
+/// Owns the producers of the consumers it watches. Dropping it, e.g. when
+/// the task or thread holding it finishes or panics, fails every watched
+/// consumer that is still pending.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{pair::Producer, watchdog::Owner, Error, Promise};
+/// use futures::executor::block_on;
+/// use std::{sync::Mutex, thread};
+/// static STASH: Mutex<Vec<Producer<u32>>> = Mutex::new(Vec::new());
+/// let owner = Owner::new();
+/// let (promise, consumer) = Producer::<u32>::new();
+/// let consumer = owner.watch(consumer);
+/// let worker = thread::spawn(move || {
+///     let _owner = owner;
+///     STASH.lock().unwrap().push(promise);
+///     panic!("worker failed");
+/// });
+/// assert!(worker.join().is_err());
+/// assert_eq!(Err(Error::Shutdown("owner panicked".into())), block_on(consumer));
+/// ```
+#[derive(Debug)]
+pub struct Owner {
+    watches: Mutex<Vec<Weak<Mutex<Watch>>>>,
+    /// True for the owner standing for the current thread.
+    thread: bool,
+}
+
+impl Owner {
+    pub fn new() -> Self {
+        Owner {
+            watches: Mutex::new(vec![]),
+            thread: false,
+        }
+    }
+
+    /// Tie `consumer` to this owner.
+    pub fn watch<C>(&self, consumer: C) -> Watched<C> {
+        let watch = Arc::new(Mutex::new(Watch::default()));
+        let mut watches = self.watches.lock().unwrap();
+        // Drop the entries of finished consumers before growing.
+        if watches.len() == watches.capacity() {
+            watches.retain(|watch| watch.strong_count() > 0);
+        }
+        watches.push(Arc::downgrade(&watch));
+        Watched { consumer, watch }
+    }
+}
+
+impl Default for Owner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        let reason = if self.thread {
+            "owner thread exited"
+        } else if thread::panicking() {
+            "owner panicked"
+        } else {
+            "owner dropped"
+        };
+        let watches = std::mem::take(&mut *self.watches.lock().unwrap());
+        for watch in watches.iter().filter_map(Weak::upgrade) {
+            watch.lock().unwrap().orphan(reason);
+        }
+    }
+}
+
+/// Tie `consumer` to the current thread, which is meant to settle it. The
+/// consumer fails once the thread exits, whether it returns or panics.
+///
+/// ```
+/// use promise_out::{pair::Producer, watchdog, Error, Promise};
+/// use futures::executor::block_on;
+/// use std::{sync::mpsc, thread};
+/// let (sender, receiver) = mpsc::channel();
+/// thread::spawn(move || {
+///     let (promise, consumer) = Producer::<u32>::new();
+///     sender.send(watchdog::watch(consumer)).unwrap();
+///     // The producer leaks instead of being dropped.
+///     std::mem::forget(promise);
+/// });
+/// let consumer = receiver.recv().unwrap();
+/// assert_eq!(Err(Error::Shutdown("owner thread exited".into())), block_on(consumer));
+/// ```
+pub fn watch<C>(consumer: C) -> Watched<C> {
+    THREAD.with(|owner| owner.watch(consumer))
+}
+
+/// A consumer tied to an [`Owner`]. It returns what the consumer returns,
+/// unless the owner goes away while it is pending.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Watched<C> {
+    consumer: C,
+    watch: Arc<Mutex<Watch>>,
+}
+
+impl<C> Watched<C> {
+    /// Return the watched consumer.
+    pub fn into_inner(self) -> C {
+        self.consumer
+    }
+}
+
+impl<C, T> Future for Watched<C>
+where
+    C: Future<Output = Result<T, Error>> + Unpin,
+{
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(result) = Pin::new(&mut this.consumer).poll(cx) {
+            return Poll::Ready(result);
+        }
+        let mut watch = this.watch.lock().unwrap();
+        match &watch.reason {
+            Some(reason) => Poll::Ready(Err(Error::Shutdown(reason.clone()))),
+            None => {
+                watch.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<C: Envelope> Envelope for Watched<C> {
+    fn metadata(&self) -> Option<Arc<Metadata>> {
+        self.consumer.metadata()
+    }
+}
+
+#[cfg(all(test, feature = "pair"))]
+mod tests {
+    use super::Owner;
+    use crate::{pair, Promise};
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_settled_before_owner_dropped() {
+        let owner = Owner::new();
+        let (promise, consumer) = pair::Producer::<u32>::new();
+        let consumer = owner.watch(consumer);
+        let (_leaked, unsettled) = pair::Producer::<u32>::new();
+        let unsettled = owner.watch(unsettled);
+        promise.resolve(1);
+        drop(owner);
+        assert_eq!(Ok(1), block_on(consumer));
+        assert_eq!(
+            Err(crate::Error::Shutdown("owner dropped".into())),
+            block_on(unsettled)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! waterfall chains async steps over promises. Each step runs once the stage
+//! before it has resolved, and its result settles the producer paired with it,
+//! so every stage of the chain can be observed through its own consumer. The
+//! first step to fail aborts the rest: their producers are dropped and the
+//! chain resolves with the error.
+use crate::pair;
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A declarative chain of async steps, itself a future resolving with the
+/// result of the last step.
+///
+/// # Examples
+///
+/// ```
+/// use promise_out::{Promise, pair::Producer, waterfall::Waterfall};
+/// use futures::executor::block_on;
+/// let (request, consumer) = Producer::<u32>::new();
+/// let (parsed, parsed_consumer) = Producer::<u32>::new();
+/// let (reply, reply_consumer) = Producer::<String>::new();
+/// let chain = Waterfall::new(consumer)
+///     .step(parsed, |n| async move { Ok(n * 2) })
+///     .step(reply, |n| async move { Ok(format!("{n}!")) });
+/// request.resolve(21);
+/// assert_eq!(Ok(String::from("42!")), block_on(chain));
+/// assert_eq!(Ok(42), block_on(parsed_consumer));
+/// assert_eq!(Ok(String::from("42!")), block_on(reply_consumer));
+/// ```
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Waterfall<T, E> {
+    driver: Pin<Box<dyn Future<Output = Result<T, E>> + Send>>,
+}
+
+impl<T: Send + 'static, E: Send + 'static> Waterfall<T, E> {
+    /// Start a chain from the first stage, typically a consumer.
+    pub fn new<Fut>(first: Fut) -> Self
+    where
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        Waterfall {
+            driver: Box::pin(first),
+        }
+    }
+
+    /// Run `step` with the value of the previous stage once it resolves, and
+    /// settle `producer` with a clone of its result. If this or an earlier
+    /// step fails, `producer` is dropped instead.
+    pub fn step<U, F, Fut>(self, producer: pair::Producer<U>, step: F) -> Waterfall<U, E>
+    where
+        U: Clone + Send + 'static,
+        F: FnOnce(T) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<U, E>> + Send,
+    {
+        let previous = self.driver;
+        Waterfall {
+            driver: Box::pin(async move {
+                let value = step(previous.await?).await?;
+                producer.resolve(value.clone());
+                Ok(value)
+            }),
+        }
+    }
+}
+
+impl<T, E> Debug for Waterfall<T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Waterfall").finish_non_exhaustive()
+    }
+}
+
+impl<T, E> Future for Waterfall<T, E> {
+    type Output = Result<T, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.driver.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Waterfall;
+    use crate::{pair, Error, Promise};
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_waterfall_aborts_on_rejection() {
+        let (request, consumer) = pair::Producer::<u32>::new();
+        let (checked, checked_consumer) = pair::Producer::<u32>::new();
+        let (stored, stored_consumer) = pair::Producer::<u32>::new();
+        let chain = Waterfall::new(consumer)
+            .step(checked, |n| async move {
+                if n > 10 {
+                    Err(Error::Stale)
+                } else {
+                    Ok(n)
+                }
+            })
+            .step(stored, |_| async { panic!("step after a rejection ran") });
+        request.resolve(11);
+        assert_eq!(Err(Error::Stale), block_on(chain));
+        assert_eq!(Err(Error::ProducerDropped), block_on(checked_consumer));
+        assert_eq!(Err(Error::ProducerDropped), block_on(stored_consumer));
+    }
+}
@@ -0,0 +1,67 @@
+//! Runs the flavors as embassy tasks. Embassy's executor never returns, so
+//! this test has no harness: it exits once the checks pass, and a watchdog
+//! thread fails it if they hang.
+use embassy_executor::{Executor, Spawner};
+use promise_out::{heapless, pair, poly, stack::StackPromise, Promise};
+use std::{process, thread, time::Duration};
+
+static READING: heapless::Slot<u32, 2> = heapless::Slot::new();
+
+#[embassy_executor::task]
+async fn sensor(promise: pair::Producer<u32>) {
+    promise.resolve(42);
+}
+
+#[embassy_executor::task]
+async fn broadcast(promise: poly::Producer<u32>) {
+    promise.resolve(7);
+}
+
+#[embassy_executor::task]
+async fn sample(promise: heapless::Producer<'static, u32, 2>) {
+    promise.resolve(512);
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn listen(consumer: poly::Consumer<u32>, reply: pair::Producer<u32>) {
+    reply.resolve(*consumer.await.unwrap());
+}
+
+#[embassy_executor::task]
+async fn run(spawner: Spawner) {
+    let (promise, consumer) = pair::Producer::<u32>::new();
+    spawner.spawn(sensor(promise)).unwrap();
+    assert_eq!(Ok(42), consumer.await);
+
+    let (promise, consumer) = poly::Producer::<u32>::new();
+    let (first, first_reply) = pair::Producer::<u32>::new();
+    let (second, second_reply) = pair::Producer::<u32>::new();
+    spawner.spawn(listen(consumer.clone(), first)).unwrap();
+    spawner.spawn(listen(consumer, second)).unwrap();
+    spawner.spawn(broadcast(promise)).unwrap();
+    assert_eq!(Ok(7), first_reply.await);
+    assert_eq!(Ok(7), second_reply.await);
+
+    let (promise, consumer) = READING.split().unwrap();
+    let other = consumer.try_clone().unwrap();
+    spawner.spawn(sample(promise)).unwrap();
+    assert_eq!(Ok(512), consumer.await);
+    assert_eq!(Ok(512), other.await);
+
+    let mut slot = StackPromise::<u32>::new();
+    let (promise, consumer) = slot.split();
+    promise.resolve(1);
+    assert_eq!(Ok(1), consumer.await);
+
+    process::exit(0);
+}
+
+fn main() {
+    thread::spawn(|| {
+        thread::sleep(Duration::from_secs(10));
+        eprintln!("the embassy tasks hung");
+        process::exit(1);
+    });
+    let executor = Box::leak(Box::new(Executor::new()));
+    executor.run(|spawner| spawner.spawn(run(spawner)).unwrap());
+}
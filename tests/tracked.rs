@@ -0,0 +1,35 @@
+#![cfg(all(
+    feature = "tracked",
+    feature = "pair",
+    feature = "poly",
+    feature = "channel"
+))]
+use futures::executor::block_on;
+use promise_out::{channel, mpmc, pair, poly, Error, Promise};
+use std::thread;
+
+#[test]
+fn test_shutdown_fails_every_pending_consumer() {
+    let (_pair, pair_consumer) = pair::Producer::<u32>::new();
+    let (_poly, poly_consumer) = poly::Producer::<u32>::new();
+    let (_channel, channel_consumer) = channel::Producer::<u32>::new();
+    let (_mpmc, mpmc_consumer) = mpmc::Producer::<u32>::new();
+    let (resolved, resolved_consumer) = pair::Producer::<u32>::new();
+    resolved.resolve(1);
+    let tasks = vec![
+        thread::spawn(move || block_on(pair_consumer).map(drop)),
+        thread::spawn(move || block_on(poly_consumer).map(drop)),
+        thread::spawn(move || block_on(channel_consumer).map(drop)),
+        thread::spawn(move || block_on(mpmc_consumer).map(drop)),
+        thread::spawn(move || block_on(pair::Consumer::<u32>::never()).map(drop)),
+    ];
+    assert!(!promise_out::is_shut_down());
+    promise_out::shutdown();
+    let shutdown = Error::Shutdown(String::from("process shutting down"));
+    for task in tasks {
+        assert_eq!(Err(shutdown.clone()), task.join().unwrap());
+    }
+    assert_eq!(Ok(1), block_on(resolved_consumer));
+    let (_late, late_consumer) = poly::Producer::<u32>::new();
+    assert_eq!(Err(shutdown), block_on(late_consumer));
+}